@@ -0,0 +1,22 @@
+#![no_main]
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use terminus_store::structure::AdjacencyList;
+
+fuzz_target!(|data: (Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>)| {
+    let (nums, bits, blocks, sblocks) = data;
+    if let Ok(aj) = AdjacencyList::parse(
+        Bytes::from(nums),
+        Bytes::from(bits),
+        Bytes::from(blocks),
+        Bytes::from(sblocks),
+    ) {
+        let _ = aj.iter().count();
+        for left in 1..=aj.left_count() as u64 {
+            let _ = aj.get(left).iter().count();
+        }
+        for pos in 0..aj.right_count() as u64 {
+            let _ = aj.pair_at_pos(pos);
+        }
+    }
+});
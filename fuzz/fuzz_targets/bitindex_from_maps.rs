@@ -0,0 +1,31 @@
+#![no_main]
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use terminus_store::structure::BitIndex;
+
+fuzz_target!(|data: (Vec<u8>, Vec<u8>, Vec<u8>)| {
+    let (bits, blocks, sblocks) = data;
+    if let Ok(index) =
+        BitIndex::from_maps(Bytes::from(bits), Bytes::from(blocks), Bytes::from(sblocks))
+    {
+        if index.is_empty() {
+            return;
+        }
+
+        let len = index.len() as u64;
+        for i in 0..len {
+            let _ = index.get(i);
+            let _ = index.rank0(i);
+            let _ = index.rank1(i);
+        }
+
+        let ones = index.rank1(len - 1);
+        let zeros = index.rank0(len - 1);
+        for rank in 1..=ones + 1 {
+            let _ = index.select1(rank);
+        }
+        for rank in 1..=zeros + 1 {
+            let _ = index.select0(rank);
+        }
+    }
+});
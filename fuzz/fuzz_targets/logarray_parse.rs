@@ -0,0 +1,14 @@
+#![no_main]
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use terminus_store::structure::LogArray;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(logarray) = LogArray::parse(Bytes::copy_from_slice(data)) {
+        let _ = logarray.iter().count();
+        if !logarray.is_empty() {
+            let slice = logarray.slice(1, logarray.len() - 1);
+            let _ = slice.iter().count();
+        }
+    }
+});
@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use std::io;
+use terminus_store::storage::directory::pack_layer_parents;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = pack_layer_parents(io::Cursor::new(data));
+});
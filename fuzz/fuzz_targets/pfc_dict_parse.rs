@@ -0,0 +1,19 @@
+#![no_main]
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+use terminus_store::structure::PfcDict;
+
+fuzz_target!(|data: (Vec<u8>, Vec<u8>)| {
+    let (blocks, offsets) = data;
+    let dict = PfcDict::parse(Bytes::from(blocks), Bytes::from(offsets));
+    if let Ok(dict) = dict.and_then(|dict| dict.validate().map(|_| dict)) {
+        for (ix, s) in dict.strings().enumerate() {
+            assert_eq!(Some(&s), dict.get(ix).as_ref());
+            assert_eq!(Some(s.len()), dict.entry(ix).map(|e| e.len()));
+            let _ = dict.id(&s);
+        }
+        let _ = dict.entries().count();
+        let _ = dict.get(dict.len());
+        let _ = dict.id("");
+    }
+});
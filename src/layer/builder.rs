@@ -1,8 +1,9 @@
 use std::io;
 
-use futures::stream::TryStreamExt;
+use futures::stream::{Stream, TryStreamExt};
 use rayon::prelude::*;
 
+use super::internal::triple_filter_key;
 use super::layer::*;
 use crate::storage::*;
 use crate::structure::util;
@@ -376,3 +377,23 @@ pub async fn build_indexes<FLoad: 'static + FileLoad, F: 'static + FileLoad + Fi
 
     Ok(())
 }
+
+/// Build the Bloom filter over the triples of a layer.
+///
+/// The filter is sized for `triple_count` triples, which should be
+/// the amount of triples in the stream.
+pub async fn build_triple_filter<
+    S: Stream<Item = io::Result<(u64, u64, u64)>> + Unpin,
+    F: 'static + FileStore,
+>(
+    mut triples: S,
+    triple_count: usize,
+    filter_file: F,
+) -> io::Result<()> {
+    let mut filter = BloomFilterBuilder::new(triple_count);
+    while let Some((subject, predicate, object)) = triples.try_next().await? {
+        filter.add(triple_filter_key(subject, predicate, object));
+    }
+
+    filter.finalize(filter_file).await
+}
@@ -2,8 +2,8 @@ use std::io;
 
 use super::internal::*;
 use crate::layer::builder::{build_indexes, TripleFileBuilder};
-use crate::layer::id_map::{construct_idmaps, construct_idmaps_upto};
-use crate::layer::Layer;
+use crate::layer::id_map::{build_idmap, construct_idmaps, construct_idmaps_upto};
+use crate::layer::{IdTriple, Layer};
 use crate::storage::*;
 use crate::structure::*;
 
@@ -62,6 +62,40 @@ pub async fn delta_rollup<F: 'static + FileLoad + FileStore>(
 ) -> io::Result<()> {
     dictionary_rollup(layer, &files).await?;
 
+    triple_rollup(layer, files).await
+}
+
+/// Roll up the given layer like `delta_rollup`, leaving out all dictionary entries that no remaining triple uses.
+///
+/// The ids of the remaining entries do not change. The ids of the
+/// entries that were left out are not reused by layers built on top of
+/// the rollup.
+pub async fn compacted_delta_rollup<F: 'static + FileLoad + FileStore>(
+    layer: &InternalLayer,
+    files: BaseLayerFiles<F>,
+) -> io::Result<()> {
+    let mut used = UsedIds::new(0, layer.node_and_value_count(), 0, layer.predicate_count());
+    for triple in layer.triples() {
+        used.mark(triple);
+    }
+
+    compacted_dictionary_rollup(
+        &layer.immediate_layers(),
+        used,
+        files.node_dictionary_files.clone(),
+        files.predicate_dictionary_files.clone(),
+        files.value_dictionary_files.clone(),
+        files.id_map_files.clone(),
+    )
+    .await?;
+
+    triple_rollup(layer, files).await
+}
+
+async fn triple_rollup<F: 'static + FileLoad + FileStore>(
+    layer: &InternalLayer,
+    files: BaseLayerFiles<F>,
+) -> io::Result<()> {
     let counts = layer.all_counts();
 
     let mut builder = TripleFileBuilder::new(
@@ -93,6 +127,52 @@ pub async fn delta_rollup_upto<F: 'static + FileLoad + FileStore>(
 ) -> io::Result<()> {
     dictionary_rollup_upto(layer, upto, &files).await?;
 
+    triple_rollup_upto(layer, upto, files).await
+}
+
+/// Roll up the given layer like `delta_rollup_upto`, leaving out all dictionary entries that neither the additions nor the removals of the rollup use.
+///
+/// The ids of the remaining entries do not change. The ids of the
+/// entries that were left out are not reused by layers built on top of
+/// the rollup.
+pub async fn compacted_delta_rollup_upto<F: 'static + FileLoad + FileStore>(
+    layer: &InternalLayer,
+    upto: [u32; 5],
+    files: ChildLayerFiles<F>,
+) -> io::Result<()> {
+    let layers = layer.immediate_layers_upto(upto);
+    let node_value_offset = layers[0].parent_node_value_count();
+    let predicate_offset = layers[0].parent_predicate_count();
+    let mut used = UsedIds::new(
+        node_value_offset,
+        layer.node_and_value_count(),
+        predicate_offset,
+        layer.predicate_count(),
+    );
+    for (_, triple) in
+        InternalTripleStackIterator::from_layer_stack(layer, upto).expect("upto not found")
+    {
+        used.mark(triple);
+    }
+
+    compacted_dictionary_rollup(
+        &layers,
+        used,
+        files.node_dictionary_files.clone(),
+        files.predicate_dictionary_files.clone(),
+        files.value_dictionary_files.clone(),
+        files.id_map_files.clone(),
+    )
+    .await?;
+
+    triple_rollup_upto(layer, upto, files).await
+}
+
+async fn triple_rollup_upto<F: 'static + FileLoad + FileStore>(
+    layer: &InternalLayer,
+    upto: [u32; 5],
+    files: ChildLayerFiles<F>,
+) -> io::Result<()> {
     let counts = layer.all_counts();
 
     let mut pos_builder = TripleFileBuilder::new(
@@ -148,6 +228,187 @@ pub async fn delta_rollup_upto<F: 'static + FileLoad + FileStore>(
     .await
 }
 
+/// Squash the given layer and all its ancestors into a new base layer.
+///
+/// Unlike a rollup, the new layer gets ids of its own, and all strings
+/// that are no longer used are left out. The dictionaries of the stack
+/// are merged directly and the triples are remapped to the new ids, so
+/// nothing is converted to strings along the way.
+pub async fn squash_layer<F: 'static + FileLoad + FileStore>(
+    layer: &InternalLayer,
+    files: BaseLayerFiles<F>,
+) -> io::Result<()> {
+    let mut used = UsedIds::new(0, layer.node_and_value_count(), 0, layer.predicate_count());
+    for triple in layer.triples() {
+        used.mark(triple);
+    }
+
+    let layers = layer.immediate_layers();
+    let node_ids = merge_dictionary_stack(
+        layers
+            .iter()
+            .map(|l| l.node_dict_entries_zero_index())
+            .collect(),
+        |id| used.node_values[id as usize],
+        files.node_dictionary_files.clone(),
+    )
+    .await?;
+    let value_ids = merge_dictionary_stack(
+        layers
+            .iter()
+            .map(|l| l.value_dict_entries_zero_index())
+            .collect(),
+        |id| used.node_values[id as usize],
+        files.value_dictionary_files.clone(),
+    )
+    .await?;
+    let predicate_ids = merge_dictionary_stack(
+        layers
+            .iter()
+            .map(|l| l.predicate_dict_entries_zero_index())
+            .collect(),
+        |id| used.predicates[id as usize],
+        files.predicate_dictionary_files.clone(),
+    )
+    .await?;
+
+    // map the ids of the stack to the position of their entries in the
+    // new dictionaries, where values come after the nodes
+    let mut node_value_map = vec![0; used.node_values.len()];
+    for (new_id, id) in node_ids.iter().chain(value_ids.iter()).enumerate() {
+        node_value_map[*id as usize] = new_id as u64 + 1;
+    }
+    let mut predicate_map = vec![0; used.predicates.len()];
+    for (new_id, id) in predicate_ids.iter().enumerate() {
+        predicate_map[*id as usize] = new_id as u64 + 1;
+    }
+
+    let mut builder = BaseLayerFileBuilderPhase2::new(
+        files,
+        node_ids.len(),
+        predicate_ids.len(),
+        value_ids.len(),
+    );
+
+    // the new ids are in dictionary order rather than in stack order, so
+    // the subjects are visited in the order of their new ids, and only the
+    // triples of a single subject have to be sorted again
+    let mut subject_triples = Vec::new();
+    for (new_subject, subject) in node_ids.iter().enumerate() {
+        subject_triples.extend(layer.triples_s(subject + 1).map(|t| {
+            (
+                predicate_map[t.predicate as usize - 1],
+                node_value_map[t.object as usize - 1],
+            )
+        }));
+        subject_triples.sort_unstable();
+        for (predicate, object) in subject_triples.drain(..) {
+            builder
+                .add_triple(new_subject as u64 + 1, predicate, object)
+                .await?;
+        }
+    }
+
+    builder.finalize().await
+}
+
+/// Keeps track of which of the ids introduced by the rolled up layers are used by a triple.
+struct UsedIds {
+    node_value_offset: u64,
+    node_values: Vec<bool>,
+    predicate_offset: u64,
+    predicates: Vec<bool>,
+}
+
+impl UsedIds {
+    fn new(
+        node_value_offset: usize,
+        node_value_count: usize,
+        predicate_offset: usize,
+        predicate_count: usize,
+    ) -> Self {
+        Self {
+            node_value_offset: node_value_offset as u64,
+            node_values: vec![false; node_value_count - node_value_offset],
+            predicate_offset: predicate_offset as u64,
+            predicates: vec![false; predicate_count - predicate_offset],
+        }
+    }
+
+    fn mark(&mut self, triple: IdTriple) {
+        // ids at or below the offset belong to layers that are not rolled up
+        for id in [triple.subject, triple.object] {
+            if id > self.node_value_offset {
+                self.node_values[(id - 1 - self.node_value_offset) as usize] = true;
+            }
+        }
+        if triple.predicate > self.predicate_offset {
+            self.predicates[(triple.predicate - 1 - self.predicate_offset) as usize] = true;
+        }
+    }
+}
+
+/// Build the dictionaries and id maps of a rollup over the given layers, leaving out the unused entries.
+async fn compacted_dictionary_rollup<F: 'static + FileLoad + FileStore>(
+    layers: &[&InternalLayer],
+    used: UsedIds,
+    node_dictionary_files: DictionaryFiles<F>,
+    predicate_dictionary_files: DictionaryFiles<F>,
+    value_dictionary_files: DictionaryFiles<F>,
+    id_map_files: IdMapFiles<F>,
+) -> io::Result<()> {
+    let node_value_offset = used.node_value_offset;
+    let predicate_offset = used.predicate_offset;
+    let node_ids = merge_dictionary_stack(
+        layers
+            .iter()
+            .map(|l| l.node_dict_entries_zero_index())
+            .collect(),
+        |id| used.node_values[(id - node_value_offset) as usize],
+        node_dictionary_files,
+    )
+    .await?;
+    let value_ids = merge_dictionary_stack(
+        layers
+            .iter()
+            .map(|l| l.value_dict_entries_zero_index())
+            .collect(),
+        |id| used.node_values[(id - node_value_offset) as usize],
+        value_dictionary_files,
+    )
+    .await?;
+    let predicate_ids = merge_dictionary_stack(
+        layers
+            .iter()
+            .map(|l| l.predicate_dict_entries_zero_index())
+            .collect(),
+        |id| used.predicates[(id - predicate_offset) as usize],
+        predicate_dictionary_files,
+    )
+    .await?;
+
+    let node_value_ids = node_ids
+        .into_iter()
+        .chain(value_ids)
+        .map(|id| id - node_value_offset)
+        .collect();
+    build_idmap(
+        node_value_ids,
+        used.node_values.len(),
+        id_map_files.node_value_idmap_files,
+    )
+    .await?;
+    build_idmap(
+        predicate_ids
+            .into_iter()
+            .map(|id| id - predicate_offset)
+            .collect(),
+        used.predicates.len(),
+        id_map_files.predicate_idmap_files,
+    )
+    .await
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -330,4 +591,387 @@ mod tests {
             assert!(delta_layer2.string_triple_exists(&t));
         }
     }
+
+    #[tokio::test]
+    async fn compacted_rollup_keeps_ids() {
+        let (_, _, layer) = build_three_layers().await.unwrap();
+
+        let delta_files = base_layer_memory_files();
+        compacted_delta_rollup(&layer, delta_files.clone())
+            .await
+            .unwrap();
+
+        let delta_layer: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([0, 0, 0, 0, 4], &delta_files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        assert_eq!(
+            layer.triples().collect::<Vec<_>>(),
+            delta_layer.triples().collect::<Vec<_>>()
+        );
+        for t in layer.triples() {
+            assert_eq!(
+                layer.id_triple_to_string(&t),
+                delta_layer.id_triple_to_string(&t)
+            );
+        }
+
+        // 'hates' is no longer used by any triple
+        let hates = layer.predicate_id("hates").unwrap();
+        assert_eq!(None, delta_layer.predicate_id("hates"));
+        assert_eq!(None, delta_layer.id_predicate(hates));
+        assert_eq!(
+            layer.predicate_count() - 1,
+            delta_layer.predicate_dict_len()
+        );
+        // but its id is not given out again
+        assert_eq!(
+            layer.node_and_value_count(),
+            delta_layer.node_and_value_count()
+        );
+        assert_eq!(layer.predicate_count(), delta_layer.predicate_count());
+    }
+
+    #[tokio::test]
+    async fn compacted_rollup_id_map_is_wider_than_its_dictionaries() {
+        let base_files = base_layer_memory_files();
+        let mut builder = SimpleLayerBuilder::new([0, 0, 0, 0, 1], base_files.clone());
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.add_string_triple(StringTriple::new_value("pig", "says", "oink"));
+        builder.commit().await.unwrap();
+        let base_layer: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([0, 0, 0, 0, 1], &base_files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        let child_files = child_layer_memory_files();
+        let mut builder = SimpleLayerBuilder::from_parent(
+            [0, 0, 0, 0, 2],
+            base_layer.clone(),
+            child_files.clone(),
+        );
+        builder.remove_string_triple(StringTriple::new_value("pig", "says", "oink"));
+        builder.commit().await.unwrap();
+        let child_layer: Arc<InternalLayer> = Arc::new(
+            ChildLayer::load_from_files([0, 0, 0, 0, 2], base_layer, &child_files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        // two of the four node and value ids are left out, which makes
+        // the dictionaries too small to tell the width of the id map
+        let delta_files = base_layer_memory_files();
+        compacted_delta_rollup(&child_layer, delta_files.clone())
+            .await
+            .unwrap();
+        let delta_layer: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([0, 0, 0, 0, 3], &delta_files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        assert_eq!(
+            2,
+            delta_layer.node_dict_len() + delta_layer.value_dict_len()
+        );
+        assert_eq!(4, delta_layer.node_and_value_count());
+        for t in child_layer.triples() {
+            assert_eq!(
+                child_layer.id_triple_to_string(&t),
+                delta_layer.id_triple_to_string(&t)
+            );
+        }
+        assert_eq!(None, delta_layer.subject_id("pig"));
+    }
+
+    #[tokio::test]
+    async fn child_of_compacted_rollup_gets_new_ids() {
+        let (_, _, layer) = build_three_layers().await.unwrap();
+        let hates = layer.predicate_id("hates").unwrap();
+
+        let delta_files = base_layer_memory_files();
+        compacted_delta_rollup(&layer, delta_files.clone())
+            .await
+            .unwrap();
+        let delta_layer: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([0, 0, 0, 0, 4], &delta_files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        let child_files = child_layer_memory_files();
+        let mut builder = SimpleLayerBuilder::from_parent(
+            [0, 0, 0, 0, 5],
+            delta_layer.clone(),
+            child_files.clone(),
+        );
+        builder.add_string_triple(StringTriple::new_node("duck", "hates", "pig"));
+        builder.commit().await.unwrap();
+        let child_layer: Arc<InternalLayer> = Arc::new(
+            ChildLayer::load_from_files([0, 0, 0, 0, 5], delta_layer.clone(), &child_files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        let new_hates = child_layer.predicate_id("hates").unwrap();
+        assert!(new_hates > hates);
+        assert_eq!(
+            Some("hates".to_string()),
+            child_layer.id_predicate(new_hates)
+        );
+        assert_eq!(None, child_layer.id_predicate(hates));
+        assert!(child_layer.string_triple_exists(&StringTriple::new_node("duck", "hates", "pig")));
+        assert!(child_layer.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+    }
+
+    #[tokio::test]
+    async fn compacted_rollup_two_of_three_layers() {
+        let (base_layer, _, child_layer) = build_three_layers().await.unwrap();
+
+        let delta_files = child_layer_memory_files();
+        compacted_delta_rollup_upto(&child_layer, [0, 0, 0, 0, 1], delta_files.clone())
+            .await
+            .unwrap();
+
+        let delta_layer: Arc<InternalLayer> = Arc::new(
+            ChildLayer::load_from_files([0, 0, 0, 0, 4], base_layer, &delta_files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        assert_eq!(
+            child_layer.triples().collect::<Vec<_>>(),
+            delta_layer.triples().collect::<Vec<_>>()
+        );
+        for t in child_layer.triples() {
+            assert_eq!(
+                child_layer.id_triple_to_string(&t),
+                delta_layer.id_triple_to_string(&t)
+            );
+        }
+
+        let change_expected: Vec<_> =
+            InternalTripleStackIterator::from_layer_stack(&*child_layer, [0, 0, 0, 0, 1])
+                .unwrap()
+                .collect();
+        let change_actual: Vec<_> =
+            InternalTripleStackIterator::from_layer_stack(&*delta_layer, [0, 0, 0, 0, 1])
+                .unwrap()
+                .collect();
+        assert_eq!(change_expected, change_actual);
+
+        // 'hates' comes from the base layer, which was not rolled up
+        assert!(delta_layer.predicate_id("hates").is_some());
+        assert_eq!(
+            child_layer.node_and_value_count(),
+            delta_layer.node_and_value_count()
+        );
+    }
+
+    #[tokio::test]
+    async fn rollup_of_compacted_rollup_keeps_ids() {
+        let (base_layer, _, child_layer) = build_three_layers().await.unwrap();
+
+        let delta1_files = child_layer_memory_files();
+        compacted_delta_rollup_upto(&child_layer, [0, 0, 0, 0, 1], delta1_files.clone())
+            .await
+            .unwrap();
+        let delta_layer1: Arc<InternalLayer> = Arc::new(
+            ChildLayer::load_from_files([0, 0, 0, 0, 4], base_layer, &delta1_files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        let delta2_files = base_layer_memory_files();
+        delta_rollup(&delta_layer1, delta2_files.clone())
+            .await
+            .unwrap();
+        let delta_layer2: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([0, 0, 0, 0, 5], &delta2_files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        assert_eq!(
+            child_layer.triples().collect::<Vec<_>>(),
+            delta_layer2.triples().collect::<Vec<_>>()
+        );
+        for t in child_layer.triples() {
+            assert_eq!(
+                child_layer.id_triple_to_string(&t),
+                delta_layer2.id_triple_to_string(&t)
+            );
+        }
+        assert_eq!(
+            child_layer.node_and_value_count(),
+            delta_layer2.node_and_value_count()
+        );
+    }
+
+    /// Build a base layer from the string triples of the given layer, the way squashing used to.
+    async fn rebuild_from_strings(layer: &InternalLayer) -> Arc<InternalLayer> {
+        let files = base_layer_memory_files();
+        let mut builder = SimpleLayerBuilder::new([0, 0, 0, 1, 0], files.clone());
+        for t in layer.triples() {
+            builder.add_string_triple(layer.id_triple_to_string(&t).unwrap());
+        }
+        builder.commit().await.unwrap();
+
+        Arc::new(
+            BaseLayer::load_from_files([0, 0, 0, 1, 0], &files)
+                .await
+                .unwrap()
+                .into(),
+        )
+    }
+
+    #[tokio::test]
+    async fn squash_three_layers() {
+        let (_, _, layer) = build_three_layers().await.unwrap();
+
+        let squash_files = base_layer_memory_files();
+        squash_layer(&layer, squash_files.clone()).await.unwrap();
+        let squashed: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([0, 0, 0, 0, 4], &squash_files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        let expected = rebuild_from_strings(&layer).await;
+        assert_eq!(
+            expected.triples().collect::<Vec<_>>(),
+            squashed.triples().collect::<Vec<_>>()
+        );
+        for t in squashed.triples() {
+            assert_eq!(
+                expected.id_triple_to_string(&t),
+                squashed.id_triple_to_string(&t)
+            );
+        }
+        assert_eq!(None, squashed.predicate_id("hates"));
+        assert_eq!(
+            expected.node_and_value_count(),
+            squashed.node_and_value_count()
+        );
+        assert_eq!(expected.predicate_count(), squashed.predicate_count());
+    }
+
+    #[tokio::test]
+    async fn squash_reorders_strings_added_by_children() {
+        let base_files = base_layer_memory_files();
+        let mut builder = SimpleLayerBuilder::new([0, 0, 0, 0, 1], base_files.clone());
+        builder.add_string_triple(StringTriple::new_node("cow", "likes", "duck"));
+        builder.add_string_triple(StringTriple::new_node("duck", "likes", "cow"));
+        builder.commit().await.unwrap();
+        let base_layer: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([0, 0, 0, 0, 1], &base_files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        let child_files = child_layer_memory_files();
+        let mut builder = SimpleLayerBuilder::from_parent(
+            [0, 0, 0, 0, 2],
+            base_layer.clone(),
+            child_files.clone(),
+        );
+        builder.add_string_triple(StringTriple::new_node("ant", "likes", "cow"));
+        builder.add_string_triple(StringTriple::new_node("cow", "likes", "bee"));
+        builder.add_string_triple(StringTriple::new_node("cow", "hates", "ant"));
+        builder.commit().await.unwrap();
+        let child_layer: Arc<InternalLayer> = Arc::new(
+            ChildLayer::load_from_files([0, 0, 0, 0, 2], base_layer, &child_files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        let squash_files = base_layer_memory_files();
+        squash_layer(&child_layer, squash_files.clone())
+            .await
+            .unwrap();
+        let squashed: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([0, 0, 0, 0, 3], &squash_files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        let expected = rebuild_from_strings(&child_layer).await;
+        assert_eq!(
+            expected.triples().collect::<Vec<_>>(),
+            squashed.triples().collect::<Vec<_>>()
+        );
+        for t in squashed.triples() {
+            assert_eq!(
+                expected.id_triple_to_string(&t),
+                squashed.id_triple_to_string(&t)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn squash_on_top_of_compacted_rollup() {
+        let (_, _, layer) = build_three_layers().await.unwrap();
+
+        let delta_files = base_layer_memory_files();
+        compacted_delta_rollup(&layer, delta_files.clone())
+            .await
+            .unwrap();
+        let delta_layer: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([0, 0, 0, 0, 4], &delta_files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        let child_files = child_layer_memory_files();
+        let mut builder = SimpleLayerBuilder::from_parent(
+            [0, 0, 0, 0, 5],
+            delta_layer.clone(),
+            child_files.clone(),
+        );
+        builder.add_string_triple(StringTriple::new_node("duck", "hates", "pig"));
+        builder.remove_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.commit().await.unwrap();
+        let child_layer: Arc<InternalLayer> = Arc::new(
+            ChildLayer::load_from_files([0, 0, 0, 0, 5], delta_layer, &child_files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        let squash_files = base_layer_memory_files();
+        squash_layer(&child_layer, squash_files.clone())
+            .await
+            .unwrap();
+        let squashed: Arc<InternalLayer> = Arc::new(
+            BaseLayer::load_from_files([0, 0, 0, 0, 6], &squash_files)
+                .await
+                .unwrap()
+                .into(),
+        );
+
+        let expected = rebuild_from_strings(&child_layer).await;
+        assert_eq!(
+            expected.triples().collect::<Vec<_>>(),
+            squashed.triples().collect::<Vec<_>>()
+        );
+        assert_eq!(None, squashed.object_value_id("moo"));
+        assert!(squashed.string_triple_exists(&StringTriple::new_node("duck", "hates", "pig")));
+    }
 }
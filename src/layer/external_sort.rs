@@ -0,0 +1,489 @@
+//! External merge sort over temporary files.
+//!
+//! Items are collected in memory until an estimated memory limit is
+//! reached. They are then sorted, deduplicated and written to a
+//! temporary file as a run. On finish, all runs are merged back into
+//! a single sorted and deduplicated stream.
+//!
+//! Runs are kept in levels. Once a level holds `MAX_OPEN_RUNS` runs,
+//! they are merged into a single run of the next level, so every item
+//! is rewritten once per level rather than once per new run.
+use super::layer::*;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+
+/// The maximum amount of runs a sorter keeps around at each level
+/// before merging them into a single run of the next level.
+const MAX_OPEN_RUNS: usize = 128;
+
+/// An item that can be written to, and read back from, a run file.
+pub(crate) trait SpillItem: Ord + Send + Sized {
+    /// An estimate of the amount of memory this item occupies.
+    fn estimated_size(&self) -> usize;
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()>;
+    fn read_from<R: Read>(r: &mut R) -> io::Result<Self>;
+}
+
+fn write_string<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+    w.write_u64::<BigEndian>(s.len() as u64)?;
+    w.write_all(s.as_bytes())
+}
+
+fn read_string<R: Read>(r: &mut R) -> io::Result<String> {
+    let len = r.read_u64::<BigEndian>()? as usize;
+    let mut bytes = vec![0; len];
+    r.read_exact(&mut bytes)?;
+
+    String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+impl SpillItem for String {
+    fn estimated_size(&self) -> usize {
+        std::mem::size_of::<String>() + self.len()
+    }
+
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_string(w, self)
+    }
+
+    fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        read_string(r)
+    }
+}
+
+impl SpillItem for IdTriple {
+    fn estimated_size(&self) -> usize {
+        std::mem::size_of::<IdTriple>()
+    }
+
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_u64::<BigEndian>(self.subject)?;
+        w.write_u64::<BigEndian>(self.predicate)?;
+        w.write_u64::<BigEndian>(self.object)
+    }
+
+    fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let subject = r.read_u64::<BigEndian>()?;
+        let predicate = r.read_u64::<BigEndian>()?;
+        let object = r.read_u64::<BigEndian>()?;
+
+        Ok(IdTriple::new(subject, predicate, object))
+    }
+}
+
+const RESOLVED: u8 = 0;
+const UNRESOLVED: u8 = 1;
+const UNRESOLVED_VALUE: u8 = 2;
+
+fn write_possibly_resolved_string<W: Write>(
+    w: &mut W,
+    p: &PossiblyResolved<String>,
+) -> io::Result<()> {
+    match p {
+        PossiblyResolved::Resolved(id) => {
+            w.write_u8(RESOLVED)?;
+            w.write_u64::<BigEndian>(*id)
+        }
+        PossiblyResolved::Unresolved(s) => {
+            w.write_u8(UNRESOLVED)?;
+            write_string(w, s)
+        }
+    }
+}
+
+fn read_possibly_resolved_string<R: Read>(r: &mut R) -> io::Result<PossiblyResolved<String>> {
+    match r.read_u8()? {
+        RESOLVED => Ok(PossiblyResolved::Resolved(r.read_u64::<BigEndian>()?)),
+        UNRESOLVED => Ok(PossiblyResolved::Unresolved(read_string(r)?)),
+        tag => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected tag {} in run file", tag),
+        )),
+    }
+}
+
+impl SpillItem for PartiallyResolvedTriple {
+    fn estimated_size(&self) -> usize {
+        let mut size = std::mem::size_of::<PartiallyResolvedTriple>();
+        if let PossiblyResolved::Unresolved(s) = &self.subject {
+            size += s.len();
+        }
+        if let PossiblyResolved::Unresolved(p) = &self.predicate {
+            size += p.len();
+        }
+        match &self.object {
+            PossiblyResolved::Unresolved(ObjectType::Node(o))
+            | PossiblyResolved::Unresolved(ObjectType::Value(o)) => size += o.len(),
+            PossiblyResolved::Resolved(_) => {}
+        }
+
+        size
+    }
+
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_possibly_resolved_string(w, &self.subject)?;
+        write_possibly_resolved_string(w, &self.predicate)?;
+        match &self.object {
+            PossiblyResolved::Resolved(id) => {
+                w.write_u8(RESOLVED)?;
+                w.write_u64::<BigEndian>(*id)
+            }
+            PossiblyResolved::Unresolved(ObjectType::Node(n)) => {
+                w.write_u8(UNRESOLVED)?;
+                write_string(w, n)
+            }
+            PossiblyResolved::Unresolved(ObjectType::Value(v)) => {
+                w.write_u8(UNRESOLVED_VALUE)?;
+                write_string(w, v)
+            }
+        }
+    }
+
+    fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let subject = read_possibly_resolved_string(r)?;
+        let predicate = read_possibly_resolved_string(r)?;
+        let object = match r.read_u8()? {
+            RESOLVED => PossiblyResolved::Resolved(r.read_u64::<BigEndian>()?),
+            UNRESOLVED => PossiblyResolved::Unresolved(ObjectType::Node(read_string(r)?)),
+            UNRESOLVED_VALUE => PossiblyResolved::Unresolved(ObjectType::Value(read_string(r)?)),
+            tag => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected tag {} in run file", tag),
+                ))
+            }
+        };
+
+        Ok(PartiallyResolvedTriple {
+            subject,
+            predicate,
+            object,
+        })
+    }
+}
+
+fn create_temp_file(temp_dir: Option<&Path>) -> io::Result<File> {
+    match temp_dir {
+        Some(dir) => tempfile::tempfile_in(dir),
+        None => tempfile::tempfile(),
+    }
+}
+
+/// A sorted sequence of items stored in an anonymous temporary file.
+pub(crate) struct Run {
+    file: File,
+    len: usize,
+}
+
+impl Run {
+    /// Returns an iterator over the items in this run.
+    pub fn read<T: SpillItem>(self) -> RunReader<T> {
+        RunReader {
+            reader: BufReader::new(self.file),
+            remaining: self.len,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+/// Writes items to a new run.
+///
+/// The caller is responsible for pushing items in order.
+pub(crate) struct RunWriter<T: SpillItem> {
+    writer: BufWriter<File>,
+    len: usize,
+    _phantom: PhantomData<T>,
+}
+
+impl<T: SpillItem> RunWriter<T> {
+    pub fn new(temp_dir: Option<&Path>) -> io::Result<Self> {
+        Ok(RunWriter {
+            writer: BufWriter::new(create_temp_file(temp_dir)?),
+            len: 0,
+            _phantom: PhantomData,
+        })
+    }
+
+    pub fn push(&mut self, item: &T) -> io::Result<()> {
+        item.write_to(&mut self.writer)?;
+        self.len += 1;
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<Run> {
+        let mut file = self.writer.into_inner().map_err(|e| e.into_error())?;
+        file.seek(SeekFrom::Start(0))?;
+
+        Ok(Run {
+            file,
+            len: self.len,
+        })
+    }
+}
+
+/// An iterator over the items in a run.
+pub(crate) struct RunReader<T: SpillItem> {
+    reader: BufReader<File>,
+    remaining: usize,
+    _phantom: PhantomData<T>,
+}
+
+impl<T: SpillItem> Iterator for RunReader<T> {
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<io::Result<T>> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        self.remaining -= 1;
+        Some(T::read_from(&mut self.reader))
+    }
+}
+
+/// A sorter which spills sorted runs to temporary files once its memory limit is hit.
+pub(crate) struct ExternalSorter<T: SpillItem> {
+    memory_limit: usize,
+    temp_dir: Option<PathBuf>,
+    buffer: Vec<T>,
+    buffered: usize,
+    /// Runs by level, where a run of level n+1 is the merge of `MAX_OPEN_RUNS` runs of level n.
+    levels: Vec<Vec<Run>>,
+}
+
+impl<T: SpillItem> ExternalSorter<T> {
+    pub fn new(memory_limit: usize, temp_dir: Option<PathBuf>) -> Self {
+        ExternalSorter {
+            memory_limit,
+            temp_dir,
+            buffer: Vec::new(),
+            buffered: 0,
+            levels: Vec::new(),
+        }
+    }
+
+    /// Set the directory in which temporary files are created.
+    pub fn set_temp_dir(&mut self, temp_dir: Option<PathBuf>) {
+        self.temp_dir = temp_dir;
+    }
+
+    /// Returns true if this sorter has written any runs to disk.
+    pub fn has_spilled(&self) -> bool {
+        self.levels.iter().any(|level| !level.is_empty())
+    }
+
+    /// Add an item, spilling to disk if the memory limit is reached.
+    pub fn push(&mut self, item: T) -> io::Result<()> {
+        self.buffered += item.estimated_size();
+        self.buffer.push(item);
+
+        if self.buffered >= self.memory_limit {
+            self.spill()?;
+        }
+
+        Ok(())
+    }
+
+    /// Write all buffered items to disk as a new run.
+    pub fn spill(&mut self) -> io::Result<()> {
+        let items = std::mem::take(&mut self.buffer);
+        self.buffered = 0;
+
+        self.write_run(items)
+    }
+
+    /// Sort and deduplicate the given items, and write them to disk as a new run.
+    pub fn write_run(&mut self, mut items: Vec<T>) -> io::Result<()> {
+        if items.is_empty() {
+            return Ok(());
+        }
+
+        items.par_sort_unstable();
+        items.dedup();
+
+        let mut writer = RunWriter::new(self.temp_dir.as_deref())?;
+        for item in items.iter() {
+            writer.push(item)?;
+        }
+        let run = writer.finish()?;
+
+        self.add_run(0, run)
+    }
+
+    /// Add a run to the given level, merging the level into the next one once it is full.
+    fn add_run(&mut self, level: usize, run: Run) -> io::Result<()> {
+        if self.levels.len() <= level {
+            self.levels.push(Vec::new());
+        }
+        self.levels[level].push(run);
+
+        if self.levels[level].len() >= MAX_OPEN_RUNS {
+            let merged = self.merge_level(level)?;
+            self.add_run(level + 1, merged)?;
+        }
+
+        Ok(())
+    }
+
+    fn merge_level(&mut self, level: usize) -> io::Result<Run> {
+        let runs = std::mem::take(&mut self.levels[level]);
+        let mut writer = RunWriter::new(self.temp_dir.as_deref())?;
+        for item in MergedRuns::<T>::new(runs)? {
+            writer.push(&item?)?;
+        }
+
+        writer.finish()
+    }
+
+    /// Returns a sorted and deduplicated iterator over everything that was pushed into this sorter.
+    pub fn finish(mut self) -> io::Result<MergedRuns<T>> {
+        self.spill()?;
+
+        // every level holds fewer than MAX_OPEN_RUNS runs, but all of
+        // them together might not, so merge the lowest levels upwards
+        // until they do
+        let mut level = 0;
+        while self.levels.iter().map(|l| l.len()).sum::<usize>() > MAX_OPEN_RUNS {
+            if self.levels[level].len() > 1 {
+                let merged = self.merge_level(level)?;
+                self.add_run(level + 1, merged)?;
+            }
+            level += 1;
+        }
+
+        MergedRuns::new(self.levels.into_iter().flatten().collect())
+    }
+}
+
+/// An iterator merging several runs into a single sorted and deduplicated stream.
+pub(crate) struct MergedRuns<T: SpillItem> {
+    readers: Vec<RunReader<T>>,
+    heap: BinaryHeap<Reverse<(T, usize)>>,
+}
+
+impl<T: SpillItem> MergedRuns<T> {
+    fn new(runs: Vec<Run>) -> io::Result<Self> {
+        let mut merged = MergedRuns {
+            readers: runs.into_iter().map(|run| run.read()).collect(),
+            heap: BinaryHeap::new(),
+        };
+
+        for index in 0..merged.readers.len() {
+            merged.refill(index)?;
+        }
+
+        Ok(merged)
+    }
+
+    fn refill(&mut self, index: usize) -> io::Result<()> {
+        if let Some(item) = self.readers[index].next() {
+            self.heap.push(Reverse((item?, index)));
+        }
+
+        Ok(())
+    }
+
+    fn next_item(&mut self) -> io::Result<Option<T>> {
+        let (item, index) = match self.heap.pop() {
+            None => return Ok(None),
+            Some(Reverse(entry)) => entry,
+        };
+        self.refill(index)?;
+
+        // skip over equal items coming from other runs
+        while matches!(self.heap.peek(), Some(Reverse((next, _))) if *next == item) {
+            let Reverse((_, index)) = self.heap.pop().unwrap();
+            self.refill(index)?;
+        }
+
+        Ok(Some(item))
+    }
+}
+
+impl<T: SpillItem> Iterator for MergedRuns<T> {
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<io::Result<T>> {
+        self.next_item().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort_strings_across_runs() {
+        let mut sorter = ExternalSorter::new(0, None);
+        for s in &["duck", "cow", "pig", "cow", "horse", "duck"] {
+            sorter.push(s.to_string()).unwrap();
+        }
+        assert!(sorter.has_spilled());
+
+        let result: Vec<String> = sorter.finish().unwrap().map(|s| s.unwrap()).collect();
+        assert_eq!(vec!["cow", "duck", "horse", "pig"], result);
+    }
+
+    #[test]
+    fn sort_more_runs_than_open_limit() {
+        let mut sorter = ExternalSorter::new(0, None);
+        for i in (0..MAX_OPEN_RUNS as u64 * 3).rev() {
+            sorter.push(IdTriple::new(i % 100, 1, 1)).unwrap();
+        }
+
+        let result: Vec<IdTriple> = sorter.finish().unwrap().map(|t| t.unwrap()).collect();
+        let expected: Vec<IdTriple> = (0..100).map(|i| IdTriple::new(i, 1, 1)).collect();
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn runs_are_merged_per_level() {
+        let mut sorter = ExternalSorter::new(0, None);
+        for i in 0..(MAX_OPEN_RUNS * MAX_OPEN_RUNS + 1) as u64 {
+            sorter.push(IdTriple::new(i, 1, 1)).unwrap();
+        }
+        // a full level 0 became a level 1 run every MAX_OPEN_RUNS items,
+        // and a full level 1 became a single level 2 run
+        assert_eq!(3, sorter.levels.len());
+        assert_eq!(1, sorter.levels[0].len());
+        assert_eq!(0, sorter.levels[1].len());
+        assert_eq!(1, sorter.levels[2].len());
+
+        let result: Vec<IdTriple> = sorter.finish().unwrap().map(|t| t.unwrap()).collect();
+        let expected: Vec<IdTriple> = (0..(MAX_OPEN_RUNS * MAX_OPEN_RUNS + 1) as u64)
+            .map(|i| IdTriple::new(i, 1, 1))
+            .collect();
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn roundtrip_partially_resolved_triples() {
+        let triples = vec![
+            StringTriple::new_node("cow", "likes", "duck").to_unresolved(),
+            StringTriple::new_value("cow", "says", "moo").to_unresolved(),
+            IdTriple::new(1, 2, 3).to_resolved(),
+            PartiallyResolvedTriple {
+                subject: PossiblyResolved::Resolved(5),
+                predicate: PossiblyResolved::Unresolved("says".to_string()),
+                object: PossiblyResolved::Unresolved(ObjectType::Value("oink".to_string())),
+            },
+        ];
+
+        let mut sorter = ExternalSorter::new(usize::MAX, None);
+        sorter.write_run(triples.clone()).unwrap();
+        sorter.write_run(triples.clone()).unwrap();
+
+        let mut expected = triples;
+        expected.sort();
+        let result: Vec<_> = sorter.finish().unwrap().map(|t| t.unwrap()).collect();
+        assert_eq!(expected, result);
+    }
+}
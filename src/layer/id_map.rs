@@ -1,6 +1,6 @@
 #![allow(dead_code)]
 use super::*;
-use crate::storage::{BitIndexMaps, FileLoad, FileStore, IdMapFiles};
+use crate::storage::{BitIndexFiles, BitIndexMaps, FileLoad, FileStore, IdMapFiles};
 use crate::structure::util::sorted_iterator;
 use crate::structure::*;
 use std::convert::TryInto;
@@ -18,11 +18,14 @@ impl Default for IdMap {
 }
 
 impl IdMap {
-    pub fn from_maps(maps: BitIndexMaps, width: u8) -> Self {
-        let bitindex = BitIndex::from_maps(maps.bits_map, maps.blocks_map, maps.sblocks_map);
-        let id_wtree = WaveletTree::from_parts(bitindex, width);
-
-        Self::from_parts(Some(id_wtree))
+    pub fn from_maps(maps: BitIndexMaps) -> io::Result<Self> {
+        let bitindex = BitIndex::from_maps(maps.bits_map, maps.blocks_map, maps.sblocks_map)?;
+        let width = idmap_width(bitindex.len()).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "id map has an unexpected size")
+        })?;
+        let id_wtree = WaveletTree::from_parts(bitindex, width)?;
+
+        Ok(Self::from_parts(Some(id_wtree)))
     }
 
     pub fn from_parts(id_wtree: Option<WaveletTree>) -> Self {
@@ -42,6 +45,15 @@ impl IdMap {
             .unwrap_or(id)
     }
 
+    /// The amount of ids in this map, or 0 if it maps every id to itself.
+    pub fn len(&self) -> usize {
+        self.id_wtree.as_ref().map(|wtree| wtree.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn inner_to_outer(&self, id: u64) -> u64 {
         self.id_wtree
             .as_ref()
@@ -114,29 +126,73 @@ async fn construct_idmaps_from_slice<F: 'static + FileLoad + FileStore>(
 
     let sorted_node_iter = sorted_iterator(node_iters, entry_comparator);
     let sorted_value_iter = sorted_iterator(value_iters, entry_comparator);
-    let sorted_node_value_iter = sorted_node_iter.chain(sorted_value_iter);
-    let sorted_predicate_iter = sorted_iterator(predicate_iters, entry_comparator);
-
-    let node_value_width = util::calculate_width(
-        (layers.last().unwrap().node_and_value_count() - node_value_offset) as u64,
-    );
-    let node_value_build_task = tokio::spawn(build_wavelet_tree_from_iter(
-        node_value_width,
-        sorted_node_value_iter.map(move |(id, _)| id - node_value_offset as u64),
-        idmap_files.node_value_idmap_files.bits_file,
-        idmap_files.node_value_idmap_files.blocks_file,
-        idmap_files.node_value_idmap_files.sblocks_file,
+    let node_value_ids: Vec<_> = sorted_node_iter
+        .chain(sorted_value_iter)
+        .map(|(id, _)| id - node_value_offset as u64)
+        .collect();
+    let predicate_ids: Vec<_> = sorted_iterator(predicate_iters, entry_comparator)
+        .map(|(id, _)| id - predicate_offset as u64)
+        .collect();
+
+    let node_value_build_task = tokio::spawn(build_idmap(
+        node_value_ids,
+        layers.last().unwrap().node_and_value_count() - node_value_offset,
+        idmap_files.node_value_idmap_files,
     ));
-    let predicate_width =
-        util::calculate_width((layers.last().unwrap().predicate_count() - predicate_offset) as u64);
-    let predicate_build_task = tokio::spawn(build_wavelet_tree_from_iter(
-        predicate_width,
-        sorted_predicate_iter.map(move |(id, _)| id - predicate_offset as u64),
-        idmap_files.predicate_idmap_files.bits_file,
-        idmap_files.predicate_idmap_files.blocks_file,
-        idmap_files.predicate_idmap_files.sblocks_file,
+    let predicate_build_task = tokio::spawn(build_idmap(
+        predicate_ids,
+        layers.last().unwrap().predicate_count() - predicate_offset,
+        idmap_files.predicate_idmap_files,
     ));
 
     node_value_build_task.await??;
     predicate_build_task.await?
 }
+
+/// Returns the width of an id map that is stored in the given amount of bits.
+///
+/// A map of `n` ids is `calculate_width(n)` bits wide. This can't be
+/// derived from the dictionaries of a layer, as the map of a compacted
+/// rollup covers more ids than its dictionaries hold.
+fn idmap_width(bit_count: usize) -> Option<u8> {
+    if bit_count == 0 {
+        return Some(0);
+    }
+
+    (1..=64).find(|&width| {
+        bit_count.is_multiple_of(width as usize)
+            && util::calculate_width((bit_count / width as usize) as u64) == width
+    })
+}
+
+/// Build an id map from the outer ids of the entries of a rolled up dictionary, in dictionary order.
+///
+/// Ids below `count` that belong to none of the entries are put at the
+/// end of the map, past the end of the dictionary. This happens when
+/// unused entries were left out of the dictionary. The map then still
+/// covers all `count` ids, so that the rollup introduces as many ids as
+/// the layers it replaces.
+pub(crate) async fn build_idmap<F: 'static + FileLoad + FileStore>(
+    ids: Vec<u64>,
+    count: usize,
+    files: BitIndexFiles<F>,
+) -> io::Result<()> {
+    let mut present = vec![false; count];
+    for &id in ids.iter() {
+        present[id as usize] = true;
+    }
+    let missing = present
+        .into_iter()
+        .enumerate()
+        .filter(|(_, present)| !present)
+        .map(|(id, _)| id as u64);
+
+    build_wavelet_tree_from_iter(
+        util::calculate_width(count as u64),
+        ids.into_iter().chain(missing),
+        files.bits_file,
+        files.blocks_file,
+        files.sblocks_file,
+    )
+    .await
+}
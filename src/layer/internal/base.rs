@@ -37,6 +37,8 @@ pub struct BaseLayer {
     o_ps_adjacency_list: AdjacencyList,
 
     predicate_wavelet_tree: WaveletTree,
+
+    triple_filter: Option<BloomFilter>,
 }
 
 impl BaseLayer {
@@ -45,67 +47,60 @@ impl BaseLayer {
         files: &BaseLayerFiles<F>,
     ) -> io::Result<Self> {
         let maps = files.map_all().await?;
-        Ok(Self::load(name, maps))
+        Self::load(name, maps)
     }
 
-    pub fn load(name: [u32; 5], maps: BaseLayerMaps) -> BaseLayer {
+    pub fn load(name: [u32; 5], maps: BaseLayerMaps) -> io::Result<BaseLayer> {
         let node_dictionary = PfcDict::parse(
             maps.node_dictionary_maps.blocks_map,
             maps.node_dictionary_maps.offsets_map,
-        )
-        .unwrap();
+        )?;
         let predicate_dictionary = PfcDict::parse(
             maps.predicate_dictionary_maps.blocks_map,
             maps.predicate_dictionary_maps.offsets_map,
-        )
-        .unwrap();
+        )?;
         let value_dictionary = PfcDict::parse(
             maps.value_dictionary_maps.blocks_map,
             maps.value_dictionary_maps.offsets_map,
-        )
-        .unwrap();
+        )?;
 
         let node_value_idmap = match maps.id_map_maps.node_value_idmap_maps {
             None => IdMap::default(),
-            Some(maps) => IdMap::from_maps(
-                maps,
-                util::calculate_width((node_dictionary.len() + value_dictionary.len()) as u64),
-            ),
+            Some(maps) => IdMap::from_maps(maps)?,
         };
 
         let predicate_idmap = match maps.id_map_maps.predicate_idmap_maps {
             None => IdMap::default(),
-            Some(map) => IdMap::from_maps(
-                map,
-                util::calculate_width(predicate_dictionary.len() as u64),
-            ),
+            Some(map) => IdMap::from_maps(map)?,
         };
 
-        let subjects = maps.subjects_map.map(|subjects_map| {
-            MonotonicLogArray::from_logarray(LogArray::parse(subjects_map).unwrap())
-        });
-        let objects = maps.objects_map.map(|objects_map| {
-            MonotonicLogArray::from_logarray(LogArray::parse(objects_map).unwrap())
-        });
+        let subjects = maps
+            .subjects_map
+            .map(|subjects_map| LogArray::parse(subjects_map).map(MonotonicLogArray::from_logarray))
+            .transpose()?;
+        let objects = maps
+            .objects_map
+            .map(|objects_map| LogArray::parse(objects_map).map(MonotonicLogArray::from_logarray))
+            .transpose()?;
 
         let s_p_adjacency_list = AdjacencyList::parse(
             maps.s_p_adjacency_list_maps.nums_map,
             maps.s_p_adjacency_list_maps.bitindex_maps.bits_map,
             maps.s_p_adjacency_list_maps.bitindex_maps.blocks_map,
             maps.s_p_adjacency_list_maps.bitindex_maps.sblocks_map,
-        );
+        )?;
         let sp_o_adjacency_list = AdjacencyList::parse(
             maps.sp_o_adjacency_list_maps.nums_map,
             maps.sp_o_adjacency_list_maps.bitindex_maps.bits_map,
             maps.sp_o_adjacency_list_maps.bitindex_maps.blocks_map,
             maps.sp_o_adjacency_list_maps.bitindex_maps.sblocks_map,
-        );
+        )?;
         let o_ps_adjacency_list = AdjacencyList::parse(
             maps.o_ps_adjacency_list_maps.nums_map,
             maps.o_ps_adjacency_list_maps.bitindex_maps.bits_map,
             maps.o_ps_adjacency_list_maps.bitindex_maps.blocks_map,
             maps.o_ps_adjacency_list_maps.bitindex_maps.sblocks_map,
-        );
+        )?;
 
         let predicate_wavelet_tree_width = s_p_adjacency_list.nums().width();
         let predicate_wavelet_tree = WaveletTree::from_parts(
@@ -113,11 +108,13 @@ impl BaseLayer {
                 maps.predicate_wavelet_tree_maps.bits_map,
                 maps.predicate_wavelet_tree_maps.blocks_map,
                 maps.predicate_wavelet_tree_maps.sblocks_map,
-            ),
+            )?,
             predicate_wavelet_tree_width,
-        );
+        )?;
+
+        let triple_filter = maps.triple_filter_map.map(BloomFilter::parse).transpose()?;
 
-        BaseLayer {
+        Ok(BaseLayer {
             name,
             node_dictionary,
             predicate_dictionary,
@@ -135,7 +132,9 @@ impl BaseLayer {
             o_ps_adjacency_list,
 
             predicate_wavelet_tree,
-        }
+
+            triple_filter,
+        })
     }
 }
 
@@ -227,6 +226,10 @@ impl InternalLayerImpl for BaseLayer {
     fn neg_objects(&self) -> Option<&MonotonicLogArray> {
         None
     }
+
+    fn triple_filter(&self) -> Option<&BloomFilter> {
+        self.triple_filter.as_ref()
+    }
 }
 
 /// A builder for a base layer.
@@ -374,7 +377,7 @@ pub struct BaseLayerFileBuilderPhase2<F: 'static + FileLoad + FileStore> {
 }
 
 impl<F: 'static + FileLoad + FileStore> BaseLayerFileBuilderPhase2<F> {
-    fn new(
+    pub(crate) fn new(
         files: BaseLayerFiles<F>,
 
         num_nodes: usize,
@@ -424,8 +427,21 @@ impl<F: 'static + FileLoad + FileStore> BaseLayerFileBuilderPhase2<F> {
         let o_ps_adjacency_list_files = self.files.o_ps_adjacency_list_files;
         let predicate_wavelet_tree_files = self.files.predicate_wavelet_tree_files;
 
+        let triple_filter_file = self.files.triple_filter_file;
+
         self.builder.finalize().await?;
 
+        let (triple_count, _) =
+            logarray_file_get_length_and_width(sp_o_adjacency_list_files.nums_file.clone()).await?;
+        let filter_task = tokio::spawn(build_triple_filter(
+            open_base_triple_stream(
+                s_p_adjacency_list_files.clone(),
+                sp_o_adjacency_list_files.clone(),
+            ),
+            triple_count as usize,
+            triple_filter_file,
+        ));
+
         build_indexes(
             s_p_adjacency_list_files,
             sp_o_adjacency_list_files,
@@ -433,7 +449,9 @@ impl<F: 'static + FileLoad + FileStore> BaseLayerFileBuilderPhase2<F> {
             None,
             predicate_wavelet_tree_files,
         )
-        .await
+        .await?;
+
+        filter_task.await?
     }
 }
 
@@ -50,6 +50,8 @@ pub struct ChildLayer {
 
     pos_predicate_wavelet_tree: WaveletTree,
     neg_predicate_wavelet_tree: WaveletTree,
+
+    triple_filter: Option<BloomFilter>,
 }
 
 impl ChildLayer {
@@ -59,90 +61,83 @@ impl ChildLayer {
         files: &ChildLayerFiles<F>,
     ) -> io::Result<Self> {
         let maps = files.map_all().await?;
-        Ok(Self::load(name, parent, maps))
+        Self::load(name, parent, maps)
     }
 
-    pub fn load(name: [u32; 5], parent: Arc<InternalLayer>, maps: ChildLayerMaps) -> ChildLayer {
+    pub fn load(
+        name: [u32; 5],
+        parent: Arc<InternalLayer>,
+        maps: ChildLayerMaps,
+    ) -> io::Result<ChildLayer> {
         let node_dictionary = PfcDict::parse(
             maps.node_dictionary_maps.blocks_map,
             maps.node_dictionary_maps.offsets_map,
-        )
-        .unwrap();
+        )?;
         let predicate_dictionary = PfcDict::parse(
             maps.predicate_dictionary_maps.blocks_map,
             maps.predicate_dictionary_maps.offsets_map,
-        )
-        .unwrap();
+        )?;
         let value_dictionary = PfcDict::parse(
             maps.value_dictionary_maps.blocks_map,
             maps.value_dictionary_maps.offsets_map,
-        )
-        .unwrap();
+        )?;
 
         let parent_node_value_count = parent.node_and_value_count();
         let parent_predicate_count = parent.predicate_count();
 
         let node_value_idmap = match maps.id_map_maps.node_value_idmap_maps {
             None => IdMap::default(),
-            Some(maps) => IdMap::from_maps(
-                maps,
-                util::calculate_width((node_dictionary.len() + value_dictionary.len()) as u64),
-            ),
+            Some(maps) => IdMap::from_maps(maps)?,
         };
 
         let predicate_idmap = match maps.id_map_maps.predicate_idmap_maps {
             None => IdMap::default(),
-            Some(map) => IdMap::from_maps(
-                map,
-                util::calculate_width(predicate_dictionary.len() as u64),
-            ),
+            Some(map) => IdMap::from_maps(map)?,
         };
 
         let pos_subjects =
-            MonotonicLogArray::from_logarray(LogArray::parse(maps.pos_subjects_map).unwrap());
-        let pos_objects =
-            MonotonicLogArray::from_logarray(LogArray::parse(maps.pos_objects_map).unwrap());
+            MonotonicLogArray::from_logarray(LogArray::parse(maps.pos_subjects_map)?);
+        let pos_objects = MonotonicLogArray::from_logarray(LogArray::parse(maps.pos_objects_map)?);
         let neg_subjects =
-            MonotonicLogArray::from_logarray(LogArray::parse(maps.neg_subjects_map).unwrap());
-        let neg_objects =
-            MonotonicLogArray::from_logarray(LogArray::parse(maps.neg_objects_map).unwrap());
+            MonotonicLogArray::from_logarray(LogArray::parse(maps.neg_subjects_map)?);
+        let neg_objects = MonotonicLogArray::from_logarray(LogArray::parse(maps.neg_objects_map)?);
 
         let pos_s_p_adjacency_list = AdjacencyList::parse(
             maps.pos_s_p_adjacency_list_maps.nums_map,
             maps.pos_s_p_adjacency_list_maps.bitindex_maps.bits_map,
             maps.pos_s_p_adjacency_list_maps.bitindex_maps.blocks_map,
             maps.pos_s_p_adjacency_list_maps.bitindex_maps.sblocks_map,
-        );
+        )?;
         let pos_sp_o_adjacency_list = AdjacencyList::parse(
             maps.pos_sp_o_adjacency_list_maps.nums_map,
             maps.pos_sp_o_adjacency_list_maps.bitindex_maps.bits_map,
             maps.pos_sp_o_adjacency_list_maps.bitindex_maps.blocks_map,
             maps.pos_sp_o_adjacency_list_maps.bitindex_maps.sblocks_map,
-        );
+        )?;
         let pos_o_ps_adjacency_list = AdjacencyList::parse(
             maps.pos_o_ps_adjacency_list_maps.nums_map,
             maps.pos_o_ps_adjacency_list_maps.bitindex_maps.bits_map,
             maps.pos_o_ps_adjacency_list_maps.bitindex_maps.blocks_map,
             maps.pos_o_ps_adjacency_list_maps.bitindex_maps.sblocks_map,
-        );
+        )?;
         let neg_s_p_adjacency_list = AdjacencyList::parse(
             maps.neg_s_p_adjacency_list_maps.nums_map,
             maps.neg_s_p_adjacency_list_maps.bitindex_maps.bits_map,
             maps.neg_s_p_adjacency_list_maps.bitindex_maps.blocks_map,
             maps.neg_s_p_adjacency_list_maps.bitindex_maps.sblocks_map,
-        );
+        )?;
         let neg_sp_o_adjacency_list = AdjacencyList::parse(
             maps.neg_sp_o_adjacency_list_maps.nums_map,
             maps.neg_sp_o_adjacency_list_maps.bitindex_maps.bits_map,
             maps.neg_sp_o_adjacency_list_maps.bitindex_maps.blocks_map,
             maps.neg_sp_o_adjacency_list_maps.bitindex_maps.sblocks_map,
-        );
+        )?;
         let neg_o_ps_adjacency_list = AdjacencyList::parse(
             maps.neg_o_ps_adjacency_list_maps.nums_map,
             maps.neg_o_ps_adjacency_list_maps.bitindex_maps.bits_map,
             maps.neg_o_ps_adjacency_list_maps.bitindex_maps.blocks_map,
             maps.neg_o_ps_adjacency_list_maps.bitindex_maps.sblocks_map,
-        );
+        )?;
 
         let pos_predicate_wavelet_tree_width = pos_s_p_adjacency_list.nums().width();
         let pos_predicate_wavelet_tree = WaveletTree::from_parts(
@@ -150,9 +145,9 @@ impl ChildLayer {
                 maps.pos_predicate_wavelet_tree_maps.bits_map,
                 maps.pos_predicate_wavelet_tree_maps.blocks_map,
                 maps.pos_predicate_wavelet_tree_maps.sblocks_map,
-            ),
+            )?,
             pos_predicate_wavelet_tree_width,
-        );
+        )?;
 
         let neg_predicate_wavelet_tree_width = neg_s_p_adjacency_list.nums().width();
         let neg_predicate_wavelet_tree = WaveletTree::from_parts(
@@ -160,11 +155,13 @@ impl ChildLayer {
                 maps.neg_predicate_wavelet_tree_maps.bits_map,
                 maps.neg_predicate_wavelet_tree_maps.blocks_map,
                 maps.neg_predicate_wavelet_tree_maps.sblocks_map,
-            ),
+            )?,
             neg_predicate_wavelet_tree_width,
-        );
+        )?;
+
+        let triple_filter = maps.triple_filter_map.map(BloomFilter::parse).transpose()?;
 
-        ChildLayer {
+        Ok(ChildLayer {
             name,
             parent,
 
@@ -193,7 +190,9 @@ impl ChildLayer {
 
             pos_predicate_wavelet_tree,
             neg_predicate_wavelet_tree,
-        }
+
+            triple_filter,
+        })
     }
 }
 
@@ -285,6 +284,10 @@ impl InternalLayerImpl for ChildLayer {
     fn neg_objects(&self) -> Option<&MonotonicLogArray> {
         Some(&self.neg_objects)
     }
+
+    fn triple_filter(&self) -> Option<&BloomFilter> {
+        self.triple_filter.as_ref()
+    }
 }
 
 /// A builder for a child layer.
@@ -614,6 +617,30 @@ impl<F: 'static + FileLoad + FileStore + Clone + Send + Sync> ChildLayerFileBuil
         pos_task.await??;
         neg_task.await??;
 
+        // the filter covers both additions and removals
+        let (pos_count, _) = logarray_file_get_length_and_width(
+            self.files.pos_sp_o_adjacency_list_files.nums_file.clone(),
+        )
+        .await?;
+        let (neg_count, _) = logarray_file_get_length_and_width(
+            self.files.neg_sp_o_adjacency_list_files.nums_file.clone(),
+        )
+        .await?;
+        let filter_task = tokio::spawn(build_triple_filter(
+            open_child_triple_stream(
+                self.files.pos_subjects_file.clone(),
+                self.files.pos_s_p_adjacency_list_files.clone(),
+                self.files.pos_sp_o_adjacency_list_files.clone(),
+            )
+            .chain(open_child_triple_stream(
+                self.files.neg_subjects_file.clone(),
+                self.files.neg_s_p_adjacency_list_files.clone(),
+                self.files.neg_sp_o_adjacency_list_files.clone(),
+            )),
+            (pos_count + neg_count) as usize,
+            self.files.triple_filter_file,
+        ));
+
         let pos_indexes_task = tokio::spawn(build_indexes(
             self.files.pos_s_p_adjacency_list_files,
             self.files.pos_sp_o_adjacency_list_files,
@@ -631,6 +658,7 @@ impl<F: 'static + FileLoad + FileStore + Clone + Send + Sync> ChildLayerFileBuil
 
         pos_indexes_task.await??;
         neg_indexes_task.await??;
+        filter_task.await??;
 
         Ok(())
     }
@@ -785,6 +813,55 @@ pub mod tests {
         child_layer_memory_files()
     }
 
+    /// Build a base layer from `base` and a child layer on top of it
+    /// that adds `additions` and removes `removals`, returning the child.
+    pub(crate) async fn layer_stack_from_triples(
+        base: Vec<StringTriple>,
+        additions: Vec<StringTriple>,
+        removals: Vec<StringTriple>,
+    ) -> Arc<InternalLayer> {
+        let store = MemoryLayerStore::new();
+        let mut builder = store.create_base_layer().await.unwrap();
+        let base_name = builder.name();
+        for triple in base {
+            builder.add_string_triple(triple);
+        }
+        builder.commit_boxed().await.unwrap();
+
+        builder = store.create_child_layer(base_name).await.unwrap();
+        let child_name = builder.name();
+        for triple in additions {
+            builder.add_string_triple(triple);
+        }
+        for triple in removals {
+            builder.remove_string_triple(triple);
+        }
+        builder.commit_boxed().await.unwrap();
+
+        store.get_layer(child_name).await.unwrap().unwrap()
+    }
+
+    /// A small base layer with a child that both adds and removes triples.
+    pub(crate) async fn example_layer_stack() -> Arc<InternalLayer> {
+        layer_stack_from_triples(
+            vec![
+                StringTriple::new_value("cow", "says", "moo"),
+                StringTriple::new_value("duck", "says", "quack"),
+                StringTriple::new_node("cow", "likes", "duck"),
+                StringTriple::new_node("duck", "hates", "cow"),
+            ],
+            vec![
+                StringTriple::new_value("horse", "says", "neigh"),
+                StringTriple::new_node("horse", "likes", "cow"),
+            ],
+            vec![
+                StringTriple::new_node("duck", "hates", "cow"),
+                StringTriple::new_value("cow", "says", "moo"),
+            ],
+        )
+        .await
+    }
+
     #[tokio::test]
     async fn empty_child_layer_equivalent_to_parent() {
         let base_layer = example_base_layer().await;
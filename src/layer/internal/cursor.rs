@@ -0,0 +1,425 @@
+//! Seekable cursors over the triples of a layer stack.
+//!
+//! The iterators returned by `Layer` methods like `triples_s` are
+//! positioned once, when they are created. Cursors can be repositioned
+//! any number of times, both forward and backward, which makes them
+//! suitable for merge joins and leapfrog joins, where one cursor
+//! repeatedly catches up with another.
+use super::object_iterator::*;
+use super::subject_iterator::*;
+use super::InternalLayerImpl;
+use crate::layer::IdTriple;
+use std::sync::Arc;
+
+type SeekFn = Arc<dyn Fn(IdTriple) -> Box<dyn Iterator<Item = IdTriple> + Send> + Send + Sync>;
+
+/// Triples in cursor order, taken from a function that starts an iteration at any triple.
+///
+/// This backs the cursors of layers that don't have the index
+/// structures of an internal layer. Every seek starts a new iteration.
+struct SeekFnIterator {
+    seek_fn: SeekFn,
+    start: IdTriple,
+    consumed: usize,
+    iter: Box<dyn Iterator<Item = IdTriple> + Send>,
+}
+
+impl SeekFnIterator {
+    fn new(seek_fn: SeekFn, start: IdTriple) -> Self {
+        let iter = seek_fn(start);
+        SeekFnIterator {
+            seek_fn,
+            start,
+            consumed: 0,
+            iter,
+        }
+    }
+
+    fn seek(&mut self, start: IdTriple) {
+        *self = Self::new(self.seek_fn.clone(), start);
+    }
+}
+
+impl Clone for SeekFnIterator {
+    fn clone(&self) -> Self {
+        // the iteration can't be cloned, so the clone starts over and skips what was already consumed
+        SeekFnIterator {
+            seek_fn: self.seek_fn.clone(),
+            start: self.start,
+            consumed: self.consumed,
+            iter: Box::new((self.seek_fn)(self.start).skip(self.consumed)),
+        }
+    }
+}
+
+impl Iterator for SeekFnIterator {
+    type Item = IdTriple;
+
+    fn next(&mut self) -> Option<IdTriple> {
+        let triple = self.iter.next()?;
+        self.consumed += 1;
+
+        Some(triple)
+    }
+}
+
+#[derive(Clone)]
+enum SubjectCursorInner {
+    Layer(InternalTripleSubjectIterator),
+    SeekFn(SeekFnIterator),
+}
+
+/// A cursor over all triples in a layer stack, ordered by subject, predicate and object.
+#[derive(Clone)]
+pub struct SubjectCursor {
+    inner: SubjectCursorInner,
+    peeked: Option<IdTriple>,
+}
+
+impl SubjectCursor {
+    pub fn from_layer<T: 'static + InternalLayerImpl>(layer: &T) -> Self {
+        SubjectCursor {
+            inner: SubjectCursorInner::Layer(InternalTripleSubjectIterator::from_layer(layer)),
+            peeked: None,
+        }
+    }
+
+    /// Create a cursor from a function returning all triples equal to or greater than the given triple, ordered by subject, predicate and object.
+    ///
+    /// Every seek calls the function again, so this is only as
+    /// efficient as the function is at finding its starting point.
+    pub fn from_seek_fn<
+        F: 'static + Fn(IdTriple) -> Box<dyn Iterator<Item = IdTriple> + Send> + Send + Sync,
+    >(
+        seek_fn: F,
+    ) -> Self {
+        SubjectCursor {
+            inner: SubjectCursorInner::SeekFn(SeekFnIterator::new(
+                Arc::new(seek_fn),
+                IdTriple::new(0, 0, 0),
+            )),
+            peeked: None,
+        }
+    }
+
+    /// Return the next triple without advancing the cursor.
+    pub fn peek(&mut self) -> Option<&IdTriple> {
+        if self.peeked.is_none() {
+            self.peeked = self.next_inner();
+        }
+
+        self.peeked.as_ref()
+    }
+
+    /// Move the cursor to the first triple with a subject equal to or greater than the given subject.
+    pub fn seek_subject(&mut self, subject: u64) {
+        self.peeked = None;
+        match &mut self.inner {
+            SubjectCursorInner::Layer(inner) => inner.seek_subject_ref(subject),
+            SubjectCursorInner::SeekFn(inner) => inner.seek(IdTriple::new(subject, 0, 0)),
+        }
+    }
+
+    /// Move the cursor to the first triple with a subject and predicate equal to or greater than the given subject and predicate.
+    pub fn seek_subject_predicate(&mut self, subject: u64, predicate: u64) {
+        self.peeked = None;
+        match &mut self.inner {
+            SubjectCursorInner::Layer(inner) => {
+                inner.seek_subject_predicate_ref(subject, predicate)
+            }
+            SubjectCursorInner::SeekFn(inner) => inner.seek(IdTriple::new(subject, predicate, 0)),
+        }
+    }
+
+    /// Move the cursor to the first triple equal to or greater than the given triple.
+    pub fn seek(&mut self, triple: IdTriple) {
+        self.seek_subject_predicate(triple.subject, triple.predicate);
+        while self.peek().map(|t| *t < triple).unwrap_or(false) {
+            self.peeked = None;
+        }
+    }
+
+    fn next_inner(&mut self) -> Option<IdTriple> {
+        match &mut self.inner {
+            SubjectCursorInner::Layer(inner) => inner.next(),
+            SubjectCursorInner::SeekFn(inner) => inner.next(),
+        }
+    }
+}
+
+impl Iterator for SubjectCursor {
+    type Item = IdTriple;
+
+    fn next(&mut self) -> Option<IdTriple> {
+        match self.peeked.take() {
+            Some(triple) => Some(triple),
+            None => self.next_inner(),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum ObjectCursorInner {
+    Layer(InternalTripleObjectIterator),
+    SeekFn(SeekFnIterator),
+}
+
+/// A cursor over all triples in a layer stack, ordered by object, subject and predicate.
+#[derive(Clone)]
+pub struct ObjectCursor {
+    inner: ObjectCursorInner,
+    peeked: Option<IdTriple>,
+}
+
+impl ObjectCursor {
+    pub fn from_layer<T: 'static + InternalLayerImpl>(layer: &T) -> Self {
+        ObjectCursor {
+            inner: ObjectCursorInner::Layer(InternalTripleObjectIterator::from_layer(layer)),
+            peeked: None,
+        }
+    }
+
+    /// Create a cursor from a function returning all triples equal to or greater than the given triple, ordered by object, subject and predicate.
+    ///
+    /// Every seek calls the function again, so this is only as
+    /// efficient as the function is at finding its starting point.
+    pub fn from_seek_fn<
+        F: 'static + Fn(IdTriple) -> Box<dyn Iterator<Item = IdTriple> + Send> + Send + Sync,
+    >(
+        seek_fn: F,
+    ) -> Self {
+        ObjectCursor {
+            inner: ObjectCursorInner::SeekFn(SeekFnIterator::new(
+                Arc::new(seek_fn),
+                IdTriple::new(0, 0, 0),
+            )),
+            peeked: None,
+        }
+    }
+
+    /// Return the next triple without advancing the cursor.
+    pub fn peek(&mut self) -> Option<&IdTriple> {
+        if self.peeked.is_none() {
+            self.peeked = self.next_inner();
+        }
+
+        self.peeked.as_ref()
+    }
+
+    /// Move the cursor to the first triple with an object equal to or greater than the given object.
+    pub fn seek_object(&mut self, object: u64) {
+        self.peeked = None;
+        match &mut self.inner {
+            ObjectCursorInner::Layer(inner) => inner.seek_object_ref(object),
+            ObjectCursorInner::SeekFn(inner) => inner.seek(IdTriple::new(0, 0, object)),
+        }
+    }
+
+    fn next_inner(&mut self) -> Option<IdTriple> {
+        match &mut self.inner {
+            ObjectCursorInner::Layer(inner) => inner.next(),
+            ObjectCursorInner::SeekFn(inner) => inner.next(),
+        }
+    }
+}
+
+impl Iterator for ObjectCursor {
+    type Item = IdTriple;
+
+    fn next(&mut self) -> Option<IdTriple> {
+        match self.peeked.take() {
+            Some(triple) => Some(triple),
+            None => self.next_inner(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layer::child::tests::{example_layer_stack, layer_stack_from_triples};
+    use crate::layer::*;
+
+    fn to_strings(layer: &dyn Layer, triples: impl Iterator<Item = IdTriple>) -> Vec<StringTriple> {
+        triples
+            .map(|t| layer.id_triple_to_string(&t).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn subject_cursor_seeks_and_peeks() {
+        let layer = example_layer_stack().await;
+        let cow = layer.subject_id("cow").unwrap();
+        let duck = layer.subject_id("duck").unwrap();
+        let horse = layer.subject_id("horse").unwrap();
+        let says = layer.predicate_id("says").unwrap();
+
+        let mut cursor = layer.subject_cursor();
+        assert_eq!(
+            to_strings(&*layer, layer.triples()),
+            to_strings(&*layer, cursor.clone())
+        );
+
+        cursor.seek_subject(horse);
+        let first = *cursor.peek().unwrap();
+        assert_eq!(horse, first.subject);
+        assert_eq!(Some(first), cursor.next());
+
+        // seeking backward, past the removed triple of the cow
+        cursor.seek_subject(cow);
+        assert_eq!(
+            vec![StringTriple::new_node("cow", "likes", "duck")],
+            to_strings(&*layer, cursor.by_ref().take(1))
+        );
+
+        cursor.seek_subject_predicate(duck, says);
+        assert_eq!(
+            vec![StringTriple::new_value("duck", "says", "quack")],
+            to_strings(&*layer, cursor.by_ref().take(1))
+        );
+
+        cursor.seek(IdTriple::new(horse, says, 0));
+        assert_eq!(
+            to_strings(&*layer, layer.triples_sp(horse, says)),
+            to_strings(&*layer, cursor.by_ref())
+        );
+        assert_eq!(None, cursor.peek());
+    }
+
+    #[tokio::test]
+    async fn object_cursor_seeks_and_peeks() {
+        let layer = example_layer_stack().await;
+        let cow = layer.object_node_id("cow").unwrap();
+        let duck = layer.object_node_id("duck").unwrap();
+
+        let mut cursor = layer.object_cursor();
+        cursor.seek_object(duck);
+        assert_eq!(duck, cursor.peek().unwrap().object);
+        cursor.seek_object(cow);
+        assert_eq!(
+            vec![StringTriple::new_node("horse", "likes", "cow")],
+            to_strings(&*layer, cursor.by_ref().take_while(|t| t.object == cow))
+        );
+    }
+
+    #[tokio::test]
+    async fn distinct_entries_skip_removed_triples() {
+        let layer = example_layer_stack().await;
+        let subjects: Vec<_> = layer
+            .subjects()
+            .map(|s| layer.id_subject(s).unwrap())
+            .collect();
+        assert_eq!(vec!["cow", "duck", "horse"], subjects);
+
+        let mut predicates: Vec<_> = layer
+            .predicates()
+            .map(|p| layer.id_predicate(p).unwrap())
+            .collect();
+        predicates.sort();
+        assert_eq!(vec!["likes", "says"], predicates);
+
+        let mut nodes: Vec<_> = layer
+            .node_objects()
+            .map(|o| layer.id_object(o).unwrap())
+            .collect();
+        nodes.sort();
+        assert_eq!(
+            vec![
+                ObjectType::Node("cow".to_string()),
+                ObjectType::Node("duck".to_string())
+            ],
+            nodes
+        );
+
+        // moo was removed
+        let mut values: Vec<_> = layer
+            .value_objects()
+            .map(|o| layer.id_object(o).unwrap())
+            .collect();
+        values.sort();
+        assert_eq!(
+            vec![
+                ObjectType::Value("neigh".to_string()),
+                ObjectType::Value("quack".to_string())
+            ],
+            values
+        );
+        assert_eq!(4, layer.objects().count());
+
+        let entries: Vec<_> = layer.node_and_value_ids().collect();
+        let mut expected: Vec<_> = layer.subjects().chain(layer.objects()).collect();
+        expected.sort_unstable();
+        expected.dedup();
+        assert_eq!(expected, entries);
+        assert_eq!(5, entries.len());
+    }
+
+    #[tokio::test]
+    async fn leapfrog_intersection() {
+        let layer = example_layer_stack().await;
+        let likes = layer.predicate_id("likes").unwrap();
+        let says = layer.predicate_id("says").unwrap();
+
+        // subjects that both like something and say something
+        let mut left = layer.subject_cursor();
+        let mut right = layer.subject_cursor();
+        let mut subject = 0;
+        let mut result = Vec::new();
+        loop {
+            left.seek_subject_predicate(subject, likes);
+            let l = match left.peek() {
+                Some(t) if t.predicate == likes => t.subject,
+                Some(t) => {
+                    subject = t.subject + 1;
+                    continue;
+                }
+                None => break,
+            };
+            right.seek_subject_predicate(l, says);
+            match right.peek() {
+                Some(t) if t.subject == l && t.predicate == says => {
+                    result.push(l);
+                    subject = l + 1;
+                }
+                Some(t) => subject = t.subject,
+                None => break,
+            }
+        }
+
+        let names: Vec<_> = result
+            .into_iter()
+            .map(|s| layer.id_subject(s).unwrap())
+            .collect();
+        assert_eq!(vec!["horse".to_string()], names);
+    }
+
+    #[tokio::test]
+    async fn split_triples_cover_all_subjects() {
+        let base = (0..100)
+            .map(|i| StringTriple::new_value(&format!("s{}", i), "p", &format!("{}", i)))
+            .collect();
+        let layer = layer_stack_from_triples(
+            base,
+            vec![StringTriple::new_node("s3", "q", "s4")],
+            vec![StringTriple::new_value("s50", "p", "50")],
+        )
+        .await;
+
+        let expected: Vec<_> = layer.triples().collect();
+        for parts in 1..8 {
+            let ranges = layer.subject_ranges(parts);
+            assert!(!ranges.is_empty() && ranges.len() <= parts);
+            assert_eq!(0, ranges[0].start);
+            assert_eq!(u64::MAX, ranges[ranges.len() - 1].end);
+            for pair in ranges.windows(2) {
+                assert_eq!(pair[0].end, pair[1].start);
+            }
+
+            let split = layer.triples_split(parts);
+            assert_eq!(ranges.len(), split.len());
+            let triples: Vec<_> = split.into_iter().flatten().collect();
+            assert_eq!(expected, triples);
+        }
+
+        assert_eq!(4, layer.subject_ranges(4).len());
+    }
+}
@@ -1,21 +1,26 @@
 pub mod base;
 pub mod child;
+mod cursor;
 mod object_iterator;
 mod predicate_iterator;
 pub mod rollup;
+mod sample;
 mod subject_iterator;
 
 use super::id_map::*;
 use super::layer::*;
+use super::scan::*;
 use crate::structure::*;
 use std::convert::TryInto;
-use std::ops::Deref;
+use std::ops::{Deref, Range};
 
 pub use base::*;
 pub use child::*;
+pub use cursor::*;
 pub use object_iterator::*;
 pub use predicate_iterator::*;
 pub use rollup::*;
+pub use sample::*;
 pub use subject_iterator::*;
 
 /*
@@ -80,6 +85,9 @@ pub trait InternalLayerImpl {
     fn neg_subjects(&self) -> Option<&MonotonicLogArray>;
     fn neg_objects(&self) -> Option<&MonotonicLogArray>;
 
+    /// The filter over the additions and removals of this layer, if one was stored with it.
+    fn triple_filter(&self) -> Option<&BloomFilter>;
+
     fn predicate_dict_get(&self, id: usize) -> Option<String> {
         self.predicate_dictionary().get(id)
     }
@@ -116,6 +124,23 @@ pub trait InternalLayerImpl {
         self.value_dictionary().get(id)
     }
 
+    /// The amount of node and value ids introduced by this layer.
+    ///
+    /// This is the size of the node and value dictionaries, unless this
+    /// is a compacted rollup. Such a rollup leaves unused entries out of
+    /// its dictionaries, but still covers their ids in its id map.
+    fn layer_node_value_count(&self) -> usize {
+        std::cmp::max(
+            self.node_dict_len() + self.value_dict_len(),
+            self.node_value_id_map().len(),
+        )
+    }
+
+    /// The amount of predicate ids introduced by this layer.
+    fn layer_predicate_count(&self) -> usize {
+        std::cmp::max(self.predicate_dict_len(), self.predicate_id_map().len())
+    }
+
     fn node_dict_entries_zero_index(&self) -> Box<dyn Iterator<Item = (u64, PfcDictEntry)> + Send> {
         let parent_node_value_count = self.parent_node_value_count();
         let node_value_id_map = self.node_value_id_map().clone();
@@ -170,6 +195,14 @@ pub trait InternalLayerImpl {
         )
     }
 
+    /// Returns false if this layer definitely doesn't add or remove the given triple.
+    fn internal_triple_may_exist(&self, subject: u64, predicate: u64, object: u64) -> bool {
+        match self.triple_filter() {
+            Some(filter) => filter.may_contain(triple_filter_key(subject, predicate, object)),
+            None => true,
+        }
+    }
+
     fn internal_triple_addition_exists(&self, subject: u64, predicate: u64, object: u64) -> bool {
         layer_triple_exists(
             self.pos_subjects(),
@@ -326,6 +359,18 @@ pub trait InternalLayerImpl {
         )
     }
 
+    fn internal_triple_additions_po(
+        &self,
+        predicate: u64,
+        object: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        Box::new(InternalTriplePredicateObjectIterator::new(
+            self.internal_triple_additions_by_object(),
+            predicate,
+            object,
+        ))
+    }
+
     fn internal_triple_additions_by_object(&self) -> OptInternalLayerTripleObjectIterator {
         OptInternalLayerTripleObjectIterator(Some(InternalLayerTripleObjectIterator::new(
             self.pos_subjects().cloned(),
@@ -343,6 +388,18 @@ pub trait InternalLayerImpl {
         )
     }
 
+    fn internal_triple_removals_po(
+        &self,
+        predicate: u64,
+        object: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        Box::new(InternalTriplePredicateObjectIterator::new(
+            self.internal_triple_removals_by_object(),
+            predicate,
+            object,
+        ))
+    }
+
     fn internal_triple_removals_by_object(&self) -> OptInternalLayerTripleObjectIterator {
         OptInternalLayerTripleObjectIterator(
             match (
@@ -376,6 +433,93 @@ pub trait InternalLayerImpl {
                 .unwrap_or(0)
     }
 
+    fn internal_triple_layer_addition_count_s(&self, subject: u64) -> usize {
+        layer_triple_count_s(
+            self.pos_subjects(),
+            self.pos_s_p_adjacency_list(),
+            self.pos_sp_o_adjacency_list(),
+            subject,
+        )
+    }
+
+    fn internal_triple_layer_removal_count_s(&self, subject: u64) -> usize {
+        match (
+            self.neg_s_p_adjacency_list(),
+            self.neg_sp_o_adjacency_list(),
+        ) {
+            (Some(neg_s_p_adjacency_list), Some(neg_sp_o_adjacency_list)) => layer_triple_count_s(
+                self.neg_subjects(),
+                neg_s_p_adjacency_list,
+                neg_sp_o_adjacency_list,
+                subject,
+            ),
+            _ => 0,
+        }
+    }
+
+    fn internal_triple_layer_addition_count_sp(&self, subject: u64, predicate: u64) -> usize {
+        layer_triple_count_sp(
+            self.pos_subjects(),
+            self.pos_s_p_adjacency_list(),
+            self.pos_sp_o_adjacency_list(),
+            subject,
+            predicate,
+        )
+    }
+
+    fn internal_triple_layer_removal_count_sp(&self, subject: u64, predicate: u64) -> usize {
+        match (
+            self.neg_s_p_adjacency_list(),
+            self.neg_sp_o_adjacency_list(),
+        ) {
+            (Some(neg_s_p_adjacency_list), Some(neg_sp_o_adjacency_list)) => layer_triple_count_sp(
+                self.neg_subjects(),
+                neg_s_p_adjacency_list,
+                neg_sp_o_adjacency_list,
+                subject,
+                predicate,
+            ),
+            _ => 0,
+        }
+    }
+
+    fn internal_triple_layer_addition_count_p(&self, predicate: u64) -> usize {
+        layer_triple_count_p(
+            self.pos_predicate_wavelet_tree(),
+            self.pos_sp_o_adjacency_list(),
+            predicate,
+        )
+    }
+
+    fn internal_triple_layer_removal_count_p(&self, predicate: u64) -> usize {
+        match (
+            self.neg_predicate_wavelet_tree(),
+            self.neg_sp_o_adjacency_list(),
+        ) {
+            (Some(neg_predicate_wavelet_tree), Some(neg_sp_o_adjacency_list)) => {
+                layer_triple_count_p(
+                    neg_predicate_wavelet_tree,
+                    neg_sp_o_adjacency_list,
+                    predicate,
+                )
+            }
+            _ => 0,
+        }
+    }
+
+    fn internal_triple_layer_addition_count_o(&self, object: u64) -> usize {
+        layer_triple_count_o(self.pos_objects(), self.pos_o_ps_adjacency_list(), object)
+    }
+
+    fn internal_triple_layer_removal_count_o(&self, object: u64) -> usize {
+        match self.neg_o_ps_adjacency_list() {
+            Some(neg_o_ps_adjacency_list) => {
+                layer_triple_count_o(self.neg_objects(), neg_o_ps_adjacency_list, object)
+            }
+            None => 0,
+        }
+    }
+
     fn internal_triple_layer_removal_count(&self) -> usize {
         match self.neg_sp_o_adjacency_list() {
             None => 0,
@@ -396,13 +540,11 @@ impl<T: 'static + InternalLayerImpl + Send + Sync + Clone> Layer for T {
     }
 
     fn node_and_value_count(&self) -> usize {
-        self.parent_node_value_count()
-            + self.node_dictionary().len()
-            + self.value_dictionary().len()
+        self.parent_node_value_count() + self.layer_node_value_count()
     }
 
     fn predicate_count(&self) -> usize {
-        self.parent_predicate_count() + self.predicate_dictionary().len()
+        self.parent_predicate_count() + self.layer_predicate_count()
     }
 
     fn subject_id<'a>(&'a self, subject: &str) -> Option<u64> {
@@ -484,9 +626,7 @@ impl<T: 'static + InternalLayerImpl + Send + Sync + Clone> Layer for T {
         let mut parent_count = self.node_and_value_count() as u64;
         while let Some(current_layer) = current_option {
             if let Some(parent) = current_layer.immediate_parent() {
-                parent_count = parent_count
-                    - current_layer.node_dict_len() as u64
-                    - current_layer.value_dict_len() as u64;
+                parent_count -= current_layer.layer_node_value_count() as u64;
                 if corrected_id >= parent_count as u64 {
                     // subject, if it exists, is in this layer
                     corrected_id -= parent_count;
@@ -517,7 +657,7 @@ impl<T: 'static + InternalLayerImpl + Send + Sync + Clone> Layer for T {
         while let Some(current_layer) = current_option {
             let mut corrected_id = id - 1;
             if let Some(parent) = current_layer.immediate_parent() {
-                parent_count -= current_layer.predicate_dict_len() as u64;
+                parent_count -= current_layer.layer_predicate_count() as u64;
                 if corrected_id >= parent_count as u64 {
                     // subject, if it exists, is in this layer
                     corrected_id -= parent_count;
@@ -540,45 +680,148 @@ impl<T: 'static + InternalLayerImpl + Send + Sync + Clone> Layer for T {
     }
 
     fn id_object(&self, id: u64) -> Option<ObjectType> {
-        if id == 0 {
-            return None;
+        let (layer, inner_id) = locate_object(self, id)?;
+        if inner_id >= layer.node_dict_len() as u64 {
+            // object, if it exists, must be a value
+            layer
+                .value_dict_get(
+                    (inner_id - layer.node_dict_len() as u64)
+                        .try_into()
+                        .unwrap(),
+                )
+                .map(ObjectType::Value)
+        } else {
+            layer
+                .node_dict_get(inner_id.try_into().unwrap())
+                .map(ObjectType::Node)
         }
-        let mut corrected_id = id - 1;
-        let mut current_option: Option<&dyn InternalLayerImpl> = Some(self);
-        let mut parent_count = self.node_and_value_count() as u64;
-        while let Some(current_layer) = current_option {
-            if let Some(parent) = current_layer.immediate_parent() {
-                parent_count = parent_count
-                    - current_layer.node_dict_len() as u64
-                    - current_layer.value_dict_len() as u64;
+    }
 
-                if corrected_id >= parent_count {
-                    // object, if it exists, is in this layer
-                    corrected_id -= parent_count;
-                } else {
-                    current_option = Some(parent);
-                    continue;
-                }
-            }
+    fn id_object_is_value(&self, id: u64) -> Option<bool> {
+        object_is_value(self, id)
+    }
 
-            corrected_id = current_layer
-                .node_value_id_map()
-                .outer_to_inner(corrected_id);
+    fn subject_ids(&self, subjects: &[&str]) -> Vec<Option<u64>> {
+        stack_dict_ids(
+            self,
+            subjects,
+            |layer| layer.parent_node_value_count() as u64,
+            |layer, strings| {
+                let id_map = layer.node_value_id_map();
+                layer
+                    .node_dictionary()
+                    .id_many(strings)
+                    .into_iter()
+                    .map(|id| id.map(|id| id_map.inner_to_outer(id)))
+                    .collect()
+            },
+        )
+    }
 
-            if corrected_id >= current_layer.node_dict_len() as u64 {
-                // object, if it exists, must be a value
-                corrected_id -= current_layer.node_dict_len() as u64;
-                return current_layer
-                    .value_dict_get(corrected_id.try_into().unwrap())
-                    .map(ObjectType::Value);
-            } else {
-                return current_layer
-                    .node_dict_get(corrected_id.try_into().unwrap())
-                    .map(ObjectType::Node);
-            }
-        }
+    fn predicate_ids(&self, predicates: &[&str]) -> Vec<Option<u64>> {
+        stack_dict_ids(
+            self,
+            predicates,
+            |layer| layer.parent_predicate_count() as u64,
+            |layer, strings| {
+                let id_map = layer.predicate_id_map();
+                layer
+                    .predicate_dictionary()
+                    .id_many(strings)
+                    .into_iter()
+                    .map(|id| id.map(|id| id_map.inner_to_outer(id)))
+                    .collect()
+            },
+        )
+    }
 
-        None
+    fn object_value_ids(&self, objects: &[&str]) -> Vec<Option<u64>> {
+        stack_dict_ids(
+            self,
+            objects,
+            |layer| layer.parent_node_value_count() as u64,
+            |layer, strings| {
+                let id_map = layer.node_value_id_map();
+                let node_count = layer.node_dict_len() as u64;
+                layer
+                    .value_dictionary()
+                    .id_many(strings)
+                    .into_iter()
+                    .map(|id| id.map(|id| id_map.inner_to_outer(id + node_count)))
+                    .collect()
+            },
+        )
+    }
+
+    fn id_subjects(&self, ids: &[u64]) -> Vec<Option<String>> {
+        stack_dict_get_many(
+            self,
+            ids,
+            |layer| layer.parent_node_value_count() as u64,
+            |layer, ids| {
+                let id_map = layer.node_value_id_map();
+                let inner_ids: Vec<_> = ids
+                    .iter()
+                    .map(|&id| id_map.outer_to_inner(id).try_into().unwrap())
+                    .collect();
+                layer.node_dictionary().get_many(&inner_ids)
+            },
+        )
+    }
+
+    fn id_predicates(&self, ids: &[u64]) -> Vec<Option<String>> {
+        stack_dict_get_many(
+            self,
+            ids,
+            |layer| layer.parent_predicate_count() as u64,
+            |layer, ids| {
+                let id_map = layer.predicate_id_map();
+                let inner_ids: Vec<_> = ids
+                    .iter()
+                    .map(|&id| id_map.outer_to_inner(id).try_into().unwrap())
+                    .collect();
+                layer.predicate_dictionary().get_many(&inner_ids)
+            },
+        )
+    }
+
+    fn id_objects(&self, ids: &[u64]) -> Vec<Option<ObjectType>> {
+        stack_dict_get_many(
+            self,
+            ids,
+            |layer| layer.parent_node_value_count() as u64,
+            |layer, ids| {
+                let id_map = layer.node_value_id_map();
+                let node_count = layer.node_dict_len();
+                // split the ids into nodes and values, remembering their position
+                let mut node_ids = Vec::new();
+                let mut value_ids = Vec::new();
+                let mut is_value = Vec::with_capacity(ids.len());
+                for &id in ids {
+                    let inner_id: usize = id_map.outer_to_inner(id).try_into().unwrap();
+                    if inner_id < node_count {
+                        node_ids.push(inner_id);
+                        is_value.push(false);
+                    } else {
+                        value_ids.push(inner_id - node_count);
+                        is_value.push(true);
+                    }
+                }
+
+                let mut nodes = layer.node_dictionary().get_many(&node_ids).into_iter();
+                let mut values = layer.value_dictionary().get_many(&value_ids).into_iter();
+                is_value
+                    .into_iter()
+                    .map(|is_value| {
+                        if is_value {
+                            values.next().unwrap().map(ObjectType::Value)
+                        } else {
+                            nodes.next().unwrap().map(ObjectType::Node)
+                        }
+                    })
+                    .collect()
+            },
+        )
     }
 
     fn clone_boxed(&self) -> Box<dyn Layer> {
@@ -612,14 +855,15 @@ impl<T: 'static + InternalLayerImpl + Send + Sync + Clone> Layer for T {
     }
 
     fn all_counts(&self) -> LayerCounts {
+        // ids of entries left out by compaction are counted as values
         let mut node_count = self.node_dict_len();
-        let mut predicate_count = self.predicate_dict_len();
-        let mut value_count = self.value_dict_len();
+        let mut predicate_count = self.layer_predicate_count();
+        let mut value_count = self.layer_node_value_count() - self.node_dict_len();
         let mut parent_option = self.immediate_parent();
         while let Some(parent) = parent_option {
             node_count += parent.node_dict_len();
-            predicate_count += parent.predicate_dict_len();
-            value_count += parent.value_dict_len();
+            predicate_count += parent.layer_predicate_count();
+            value_count += parent.layer_node_value_count() - parent.node_dict_len();
             parent_option = parent.immediate_parent();
         }
         LayerCounts {
@@ -634,25 +878,29 @@ impl<T: 'static + InternalLayerImpl + Send + Sync + Clone> Layer for T {
             return false;
         }
 
-        if self.internal_triple_addition_exists(subject, predicate, object) {
-            true
-        } else if self.internal_triple_removal_exists(subject, predicate, object) {
-            false
-        } else {
-            let mut parent_opt = self.immediate_parent();
-            while parent_opt.is_some() {
-                let parent = parent_opt.unwrap();
+        // layers whose filter rules out the triple are skipped without a lookup
+        if self.internal_triple_may_exist(subject, predicate, object) {
+            if self.internal_triple_addition_exists(subject, predicate, object) {
+                return true;
+            } else if self.internal_triple_removal_exists(subject, predicate, object) {
+                return false;
+            }
+        }
+
+        let mut parent_opt = self.immediate_parent();
+        while let Some(parent) = parent_opt {
+            if parent.internal_triple_may_exist(subject, predicate, object) {
                 if parent.internal_triple_addition_exists(subject, predicate, object) {
                     return true;
                 } else if parent.internal_triple_removal_exists(subject, predicate, object) {
                     return false;
                 }
-
-                parent_opt = parent.immediate_parent();
             }
 
-            false
+            parent_opt = parent.immediate_parent();
         }
+
+        false
     }
 
     fn triples(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
@@ -690,6 +938,110 @@ impl<T: 'static + InternalLayerImpl + Send + Sync + Clone> Layer for T {
                 .take_while(move |t| t.object == object),
         )
     }
+
+    fn triples_po(&self, predicate: u64, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        Box::new(InternalTriplePredicateObjectIterator::new(
+            InternalTripleObjectIterator::from_layer(self),
+            predicate,
+            object,
+        ))
+    }
+
+    fn triples_after(
+        &self,
+        token: &ScanToken,
+    ) -> Result<Box<dyn Iterator<Item = IdTriple> + Send>, ScanTokenError> {
+        if token.layer() != Layer::name(self) {
+            return Err(ScanTokenError::WrongLayer);
+        }
+
+        let scan = token.scan();
+        let last = token.last();
+        let triples: Box<dyn Iterator<Item = IdTriple> + Send> = match scan {
+            TripleScan::All | TripleScan::S(_) | TripleScan::Sp(_, _) => Box::new(
+                InternalTripleSubjectIterator::from_layer(self)
+                    .seek_subject_predicate(last.subject, last.predicate),
+            ),
+            TripleScan::P(predicate) => Box::new(
+                InternalTriplePredicateIterator::from_layer(self, predicate)
+                    .seek_subject(last.subject),
+            ),
+            TripleScan::O(_) | TripleScan::Po(_, _) => Box::new(
+                InternalTripleObjectIterator::from_layer(self).seek_object_subject_predicate(
+                    last.object,
+                    last.subject,
+                    last.predicate,
+                ),
+            ),
+        };
+
+        Ok(Box::new(
+            triples
+                .skip_while(move |t| scan.not_after(t, &last))
+                .take_while(move |t| scan.within(t))
+                .filter(move |t| scan.contains(t)),
+        ))
+    }
+
+    fn count_s(&self, subject: u64) -> usize {
+        stack_triple_count(self, |layer| {
+            (
+                layer.internal_triple_layer_addition_count_s(subject),
+                layer.internal_triple_layer_removal_count_s(subject),
+            )
+        })
+    }
+
+    fn count_sp(&self, subject: u64, predicate: u64) -> usize {
+        stack_triple_count(self, |layer| {
+            (
+                layer.internal_triple_layer_addition_count_sp(subject, predicate),
+                layer.internal_triple_layer_removal_count_sp(subject, predicate),
+            )
+        })
+    }
+
+    fn count_p(&self, predicate: u64) -> usize {
+        stack_triple_count(self, |layer| {
+            (
+                layer.internal_triple_layer_addition_count_p(predicate),
+                layer.internal_triple_layer_removal_count_p(predicate),
+            )
+        })
+    }
+
+    fn count_o(&self, object: u64) -> usize {
+        stack_triple_count(self, |layer| {
+            (
+                layer.internal_triple_layer_addition_count_o(object),
+                layer.internal_triple_layer_removal_count_o(object),
+            )
+        })
+    }
+
+    fn subject_cursor(&self) -> SubjectCursor {
+        SubjectCursor::from_layer(self)
+    }
+
+    fn object_cursor(&self) -> ObjectCursor {
+        ObjectCursor::from_layer(self)
+    }
+
+    fn subject_ranges(&self, parts: usize) -> Vec<Range<u64>> {
+        stack_subject_ranges(self, parts)
+    }
+
+    fn triple_sampler(&self) -> TripleSampler {
+        TripleSampler::from_layer(self)
+    }
+
+    fn triple_sampler_s(&self, subject: u64) -> TripleSampler {
+        TripleSampler::from_layer_s(self, subject)
+    }
+
+    fn triple_sampler_p(&self, predicate: u64) -> TripleSampler {
+        TripleSampler::from_layer_p(self, predicate)
+    }
 }
 
 #[derive(Clone)]
@@ -863,6 +1215,14 @@ impl InternalLayerImpl for InternalLayer {
     fn neg_objects(&self) -> Option<&MonotonicLogArray> {
         (&**self).neg_objects()
     }
+    fn triple_filter(&self) -> Option<&BloomFilter> {
+        (**self).triple_filter()
+    }
+}
+
+/// The key under which a triple is stored in the triple filter of a layer.
+pub(crate) fn triple_filter_key(subject: u64, predicate: u64, object: u64) -> u64 {
+    mix_u64(mix_u64(mix_u64(subject) ^ predicate) ^ object)
 }
 
 pub(crate) fn layer_triple_exists(
@@ -925,6 +1285,374 @@ pub(crate) fn layer_triple_exists(
     false
 }
 
+fn stack_node_value_count(layer: &dyn InternalLayerImpl) -> u64 {
+    (layer.parent_node_value_count() + layer.layer_node_value_count()) as u64
+}
+
+/// Find the layer in the stack that introduced the given object id, returning that layer and the object's position in its node and value dictionaries.
+fn locate_object(layer: &dyn InternalLayerImpl, id: u64) -> Option<(&dyn InternalLayerImpl, u64)> {
+    if id == 0 {
+        return None;
+    }
+    let mut corrected_id = id - 1;
+    let mut current_layer = layer;
+    let mut parent_count = stack_node_value_count(layer);
+    while let Some(parent) = current_layer.immediate_parent() {
+        parent_count -= current_layer.layer_node_value_count() as u64;
+
+        if corrected_id >= parent_count {
+            // object, if it exists, is in this layer
+            corrected_id -= parent_count;
+            break;
+        }
+
+        current_layer = parent;
+    }
+
+    let inner_id = current_layer
+        .node_value_id_map()
+        .outer_to_inner(corrected_id);
+
+    Some((current_layer, inner_id))
+}
+
+/// Returns true if the given object id refers to a value, false if it refers to a node, and None if it is out of range or was left out of a compacted rollup.
+///
+/// Unlike `id_object`, this does not do any dictionary lookups.
+pub(crate) fn object_is_value(layer: &dyn InternalLayerImpl, id: u64) -> Option<bool> {
+    if id > stack_node_value_count(layer) {
+        return None;
+    }
+    let (layer, inner_id) = locate_object(layer, id)?;
+    if inner_id >= (layer.node_dict_len() + layer.value_dict_len()) as u64 {
+        // the entry was left out by compaction
+        return None;
+    }
+
+    Some(inner_id >= layer.node_dict_len() as u64)
+}
+
+/// Resolve many strings to ids in a stack of dictionaries, doing one bulk lookup per layer.
+///
+/// `lookup` returns the ids of the given strings within a single
+/// layer, and `parent_count` the amount of ids that come before that
+/// layer. Strings that are found in a layer are not looked up in its
+/// ancestors.
+fn stack_dict_ids<'a>(
+    layer: &'a dyn InternalLayerImpl,
+    strings: &[&str],
+    parent_count: impl Fn(&'a dyn InternalLayerImpl) -> u64,
+    lookup: impl Fn(&'a dyn InternalLayerImpl, &[&str]) -> Vec<Option<u64>>,
+) -> Vec<Option<u64>> {
+    let mut result = vec![None; strings.len()];
+    let mut pending: Vec<usize> = (0..strings.len()).collect();
+    let mut layer_opt = Some(layer);
+    while let Some(layer) = layer_opt {
+        if pending.is_empty() {
+            break;
+        }
+
+        let pending_strings: Vec<_> = pending.iter().map(|&index| strings[index]).collect();
+        let offset = parent_count(layer);
+        let mut remaining = Vec::new();
+        for (index, id) in pending.into_iter().zip(lookup(layer, &pending_strings)) {
+            match id {
+                Some(id) => result[index] = Some(1 + id + offset),
+                None => remaining.push(index),
+            }
+        }
+        pending = remaining;
+
+        layer_opt = layer
+            .immediate_parent()
+            .map(|p| p as &dyn InternalLayerImpl);
+    }
+
+    result
+}
+
+/// Resolve many ids to strings in a stack of dictionaries, doing one bulk lookup per layer.
+///
+/// Every id is assigned to the layer that introduced it. `get_many`
+/// then receives the ids for a layer, with the ids of its ancestors
+/// already subtracted, and returns their strings.
+fn stack_dict_get_many<'a, T: Clone>(
+    layer: &'a dyn InternalLayerImpl,
+    ids: &[u64],
+    parent_count: impl Fn(&'a dyn InternalLayerImpl) -> u64,
+    get_many: impl Fn(&'a dyn InternalLayerImpl, &[u64]) -> Vec<Option<T>>,
+) -> Vec<Option<T>> {
+    // the layers from the bottom of the stack to the top, along with the amount of ids before them
+    let mut layers = Vec::new();
+    let mut layer_opt = Some(layer);
+    while let Some(layer) = layer_opt {
+        layers.push((layer, parent_count(layer)));
+        layer_opt = layer
+            .immediate_parent()
+            .map(|p| p as &dyn InternalLayerImpl);
+    }
+    layers.reverse();
+
+    let mut per_layer: Vec<(Vec<usize>, Vec<u64>)> = vec![(Vec::new(), Vec::new()); layers.len()];
+    for (index, &id) in ids.iter().enumerate() {
+        if id == 0 {
+            continue;
+        }
+
+        let layer_index = layers.partition_point(|(_, offset)| *offset < id) - 1;
+        let (indexes, layer_ids) = &mut per_layer[layer_index];
+        indexes.push(index);
+        layer_ids.push(id - 1 - layers[layer_index].1);
+    }
+
+    let mut result = vec![None; ids.len()];
+    for ((layer, _), (indexes, layer_ids)) in layers.into_iter().zip(per_layer) {
+        if layer_ids.is_empty() {
+            continue;
+        }
+
+        for (index, value) in indexes.into_iter().zip(get_many(layer, &layer_ids)) {
+            result[index] = value;
+        }
+    }
+
+    result
+}
+
+/// Sum the additions and removals returned by `count` for every layer in the stack, returning the amount of triples that remain.
+///
+/// Every layer only adds triples that do not exist in its parent, and
+/// only removes triples that do, so the difference is exact.
+fn stack_triple_count<'a>(
+    layer: &'a dyn InternalLayerImpl,
+    count: impl Fn(&'a dyn InternalLayerImpl) -> (usize, usize),
+) -> usize {
+    let mut additions = 0;
+    let mut removals = 0;
+    let mut layer_opt = Some(layer);
+    while let Some(layer) = layer_opt {
+        let (layer_additions, layer_removals) = count(layer);
+        additions += layer_additions;
+        removals += layer_removals;
+
+        layer_opt = layer
+            .immediate_parent()
+            .map(|p| p as &dyn InternalLayerImpl);
+    }
+
+    additions - removals
+}
+
+/// Returns the positions in the right side of the adjacency list for the given (1-based) left index, as an exclusive range.
+fn adjacency_range(adjacency_list: &AdjacencyList, index: u64) -> (u64, u64) {
+    let start = adjacency_list.offset_for(index);
+    let end = if index >= adjacency_list.left_count() as u64 {
+        adjacency_list.right_count() as u64
+    } else {
+        adjacency_list.offset_for(index + 1)
+    };
+
+    (start, end)
+}
+
+fn layer_subject_position(
+    subjects: Option<&MonotonicLogArray>,
+    s_p_adjacency_list: &AdjacencyList,
+    subject: u64,
+) -> Option<u64> {
+    if subject == 0 {
+        return None;
+    }
+
+    match subjects {
+        None => {
+            if subject > s_p_adjacency_list.left_count() as u64 {
+                None
+            } else {
+                Some(subject - 1)
+            }
+        }
+        Some(subjects) => subjects.index_of(subject).map(|pos| pos as u64),
+    }
+}
+
+/// Returns the positions in the sp_o adjacency list of a single layer that hold the triples with the given subject, as an exclusive range.
+fn layer_subject_range(
+    subjects: Option<&MonotonicLogArray>,
+    s_p_adjacency_list: &AdjacencyList,
+    sp_o_adjacency_list: &AdjacencyList,
+    subject: u64,
+) -> Option<(u64, u64)> {
+    let s_position = layer_subject_position(subjects, s_p_adjacency_list, subject)?;
+
+    let (s_p_start, s_p_end) = adjacency_range(s_p_adjacency_list, s_position + 1);
+    if s_p_start == s_p_end
+        || (s_p_end - s_p_start == 1 && s_p_adjacency_list.num_at_pos(s_p_start) == 0)
+    {
+        // no predicates, or the placeholder predicate of a subject without triples
+        return None;
+    }
+
+    let (sp_o_start, _) = adjacency_range(sp_o_adjacency_list, s_p_start + 1);
+    let (_, sp_o_end) = adjacency_range(sp_o_adjacency_list, s_p_end);
+
+    Some((sp_o_start, sp_o_end))
+}
+
+/// Count the triples in a single layer with the given subject.
+pub(crate) fn layer_triple_count_s(
+    subjects: Option<&MonotonicLogArray>,
+    s_p_adjacency_list: &AdjacencyList,
+    sp_o_adjacency_list: &AdjacencyList,
+    subject: u64,
+) -> usize {
+    match layer_subject_range(subjects, s_p_adjacency_list, sp_o_adjacency_list, subject) {
+        Some((sp_o_start, sp_o_end)) => (sp_o_end - sp_o_start) as usize,
+        None => 0,
+    }
+}
+
+/// Decode the triple stored at the given position of the sp_o adjacency list of a single layer.
+///
+/// The predicate or object is 0 if the position holds a placeholder.
+pub(crate) fn layer_triple_at_pos(
+    subjects: Option<&MonotonicLogArray>,
+    s_p_adjacency_list: &AdjacencyList,
+    sp_o_adjacency_list: &AdjacencyList,
+    sp_o_position: u64,
+) -> IdTriple {
+    let (s_p_index, object) = sp_o_adjacency_list.pair_at_pos(sp_o_position);
+    let (s_index, predicate) = s_p_adjacency_list.pair_at_pos(s_p_index - 1);
+    let subject = match subjects {
+        Some(subjects) => subjects.entry((s_index - 1).try_into().unwrap()),
+        None => s_index,
+    };
+
+    IdTriple::new(subject, predicate, object)
+}
+
+/// Split the subjects of a layer stack into at most `parts` consecutive ranges.
+///
+/// The boundaries are taken from the layer with the most additions,
+/// so that the ranges hold roughly the same amount of triples.
+fn stack_subject_ranges(layer: &dyn InternalLayerImpl, parts: usize) -> Vec<Range<u64>> {
+    let mut largest = layer;
+    let mut layer_opt = layer.immediate_parent();
+    while let Some(layer) = layer_opt {
+        if layer.pos_sp_o_adjacency_list().right_count()
+            > largest.pos_sp_o_adjacency_list().right_count()
+        {
+            largest = layer;
+        }
+
+        layer_opt = layer.immediate_parent();
+    }
+
+    let sp_o_adjacency_list = largest.pos_sp_o_adjacency_list();
+    let len = sp_o_adjacency_list.right_count() as u64;
+    let parts = if len == 0 { 1 } else { parts.max(1) as u64 };
+    let mut ranges = Vec::with_capacity(parts as usize);
+    let mut start = 0;
+    for part in 1..parts {
+        let boundary = layer_triple_at_pos(
+            largest.pos_subjects(),
+            largest.pos_s_p_adjacency_list(),
+            sp_o_adjacency_list,
+            part * len / parts,
+        )
+        .subject;
+        if boundary > start {
+            ranges.push(start..boundary);
+            start = boundary;
+        }
+    }
+    ranges.push(start..u64::MAX);
+
+    ranges
+}
+
+/// Count the triples in a single layer with the given subject and predicate.
+pub(crate) fn layer_triple_count_sp(
+    subjects: Option<&MonotonicLogArray>,
+    s_p_adjacency_list: &AdjacencyList,
+    sp_o_adjacency_list: &AdjacencyList,
+    subject: u64,
+    predicate: u64,
+) -> usize {
+    if predicate == 0 {
+        return 0;
+    }
+
+    let s_position = match layer_subject_position(subjects, s_p_adjacency_list, subject) {
+        Some(s_position) => s_position,
+        None => return 0,
+    };
+
+    let (s_p_start, s_p_end) = adjacency_range(s_p_adjacency_list, s_position + 1);
+    match (s_p_start..s_p_end).find(|&pos| s_p_adjacency_list.num_at_pos(pos) == predicate) {
+        Some(s_p_position) => {
+            let (sp_o_start, sp_o_end) = adjacency_range(sp_o_adjacency_list, s_p_position + 1);
+            (sp_o_end - sp_o_start) as usize
+        }
+        None => 0,
+    }
+}
+
+/// Count the triples in a single layer with the given predicate.
+pub(crate) fn layer_triple_count_p(
+    predicate_wavelet_tree: &WaveletTree,
+    sp_o_adjacency_list: &AdjacencyList,
+    predicate: u64,
+) -> usize {
+    if predicate == 0 {
+        return 0;
+    }
+
+    match predicate_wavelet_tree.lookup(predicate) {
+        Some(lookup) => lookup
+            .iter()
+            .map(|s_p_position| {
+                let (sp_o_start, sp_o_end) = adjacency_range(sp_o_adjacency_list, s_p_position + 1);
+                (sp_o_end - sp_o_start) as usize
+            })
+            .sum(),
+        None => 0,
+    }
+}
+
+/// Count the triples in a single layer with the given object.
+pub(crate) fn layer_triple_count_o(
+    objects: Option<&MonotonicLogArray>,
+    o_ps_adjacency_list: &AdjacencyList,
+    object: u64,
+) -> usize {
+    if object == 0 {
+        return 0;
+    }
+
+    let o_position = match objects {
+        None => {
+            if object > o_ps_adjacency_list.left_count() as u64 {
+                return 0;
+            }
+
+            object - 1
+        }
+        Some(objects) => match objects.index_of(object) {
+            Some(pos) => pos as u64,
+            None => return 0,
+        },
+    };
+
+    let (o_ps_start, o_ps_end) = adjacency_range(o_ps_adjacency_list, o_position + 1);
+    if o_ps_end - o_ps_start == 1 && o_ps_adjacency_list.num_at_pos(o_ps_start) == 0 {
+        // the placeholder of an object that is not used in this layer
+        return 0;
+    }
+
+    (o_ps_end - o_ps_start) as usize
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -975,6 +1703,182 @@ mod tests {
         assert_eq!(1, layer.triple_layer_removal_count().unwrap());
     }
 
+    #[test]
+    fn counts_match_iterators_across_stack() {
+        let store = open_sync_memory_store();
+        let base_layer = create_base_layer(&store);
+        let builder = base_layer.open_write().unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("horse", "says", "neigh"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("horse", "likes", "duck"))
+            .unwrap();
+        let child_layer = builder.commit().unwrap();
+
+        let builder = child_layer.open_write().unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        let layer = builder.commit().unwrap();
+
+        let node_count = layer.node_and_value_count() as u64;
+        let predicate_count = layer.predicate_count() as u64;
+        for id in 0..=node_count + 1 {
+            assert_eq!(layer.triples_s(id).count(), layer.count_s(id));
+            assert_eq!(layer.triples_o(id).count(), layer.count_o(id));
+            for predicate in 0..=predicate_count + 1 {
+                assert_eq!(
+                    layer.triples_sp(id, predicate).count(),
+                    layer.count_sp(id, predicate)
+                );
+            }
+        }
+        for predicate in 0..=predicate_count + 1 {
+            assert_eq!(layer.triples_p(predicate).count(), layer.count_p(predicate));
+        }
+
+        let duck = layer.object_node_id("duck").unwrap();
+        assert_eq!(1, layer.count_o(duck));
+        let says = layer.predicate_id("says").unwrap();
+        assert_eq!(3, layer.count_p(says));
+    }
+
+    #[test]
+    fn bulk_lookups_match_single_lookups() {
+        let store = open_sync_memory_store();
+        let base_layer = create_base_layer(&store);
+        let builder = base_layer.open_write().unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("horse", "says", "neigh"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("horse", "hates", "cow"))
+            .unwrap();
+        let child_layer = builder.commit().unwrap();
+        let builder = child_layer.open_write().unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "eats", "moo"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        let layer = builder.commit().unwrap();
+
+        let strings = vec![
+            "pig", "cow", "moo", "neigh", "says", "horse", "duck", "eats", "likes", "unknown",
+            "quack", "cow", "hates",
+        ];
+        let single =
+            |f: &dyn Fn(&str) -> Option<u64>| -> Vec<_> { strings.iter().map(|s| f(s)).collect() };
+        assert_eq!(
+            single(&|s| layer.subject_id(s)),
+            layer.subject_ids(&strings)
+        );
+        assert_eq!(
+            single(&|s| layer.object_node_id(s)),
+            layer.object_node_ids(&strings)
+        );
+        assert_eq!(
+            single(&|s| layer.object_value_id(s)),
+            layer.object_value_ids(&strings)
+        );
+        assert_eq!(
+            single(&|s| layer.predicate_id(s)),
+            layer.predicate_ids(&strings)
+        );
+
+        let node_count = layer.node_and_value_count() as u64;
+        let ids: Vec<u64> = (0..=node_count + 2).rev().collect();
+        let expected: Vec<_> = ids.iter().map(|&id| layer.id_subject(id)).collect();
+        assert_eq!(expected, layer.id_subjects(&ids));
+        let expected: Vec<_> = ids.iter().map(|&id| layer.id_object(id)).collect();
+        assert_eq!(expected, layer.id_objects(&ids));
+        let ids: Vec<u64> = (0..=layer.predicate_count() as u64 + 2).rev().collect();
+        let expected: Vec<_> = ids.iter().map(|&id| layer.id_predicate(id)).collect();
+        assert_eq!(expected, layer.id_predicates(&ids));
+
+        let triples: Vec<_> = layer.triples().collect();
+        let expected: Vec<_> = triples
+            .iter()
+            .map(|t| layer.id_triple_to_string(t))
+            .collect();
+        assert_eq!(expected, layer.id_triples_to_strings(&triples));
+        assert_eq!(
+            vec![None],
+            layer.id_triples_to_strings(&[IdTriple::new(1, 1000, 1)])
+        );
+    }
+
+    #[tokio::test]
+    async fn triple_filters_cover_layer_changes() {
+        use crate::storage::memory::MemoryLayerStore;
+        use crate::storage::LayerStore;
+
+        let store = MemoryLayerStore::new();
+        let mut builder = store.create_base_layer().await.unwrap();
+        let mut name = builder.name();
+        for i in 0..10 {
+            builder.add_string_triple(StringTriple::new_node(
+                &format!("s{}", i),
+                "p",
+                &format!("s{}", (i + 1) % 10),
+            ));
+        }
+        builder.commit_boxed().await.unwrap();
+        for i in 0..10 {
+            builder = store.create_child_layer(name).await.unwrap();
+            name = builder.name();
+            builder.remove_string_triple(StringTriple::new_node(
+                &format!("s{}", i),
+                "p",
+                &format!("s{}", (i + 1) % 10),
+            ));
+            builder.add_string_triple(StringTriple::new_node(
+                &format!("s{}", i),
+                "q",
+                &format!("s{}", (i + 2) % 10),
+            ));
+            builder.commit_boxed().await.unwrap();
+        }
+        let layer = store.get_layer(name).await.unwrap().unwrap();
+
+        for l in layer.immediate_layers() {
+            assert!(l.triple_filter().is_some());
+            for triple in l
+                .internal_triple_additions()
+                .chain(l.internal_triple_removals())
+            {
+                assert!(l.internal_triple_may_exist(
+                    triple.subject,
+                    triple.predicate,
+                    triple.object
+                ));
+            }
+        }
+
+        let triples: std::collections::HashSet<_> = layer.triples().collect();
+        assert_eq!(10, triples.len());
+        let node_count = layer.node_and_value_count() as u64;
+        let predicate_count = layer.predicate_count() as u64;
+        for subject in 0..=node_count {
+            for predicate in 0..=predicate_count {
+                for object in 0..=node_count {
+                    assert_eq!(
+                        triples.contains(&IdTriple::new(subject, predicate, object)),
+                        layer.triple_exists(subject, predicate, object)
+                    );
+                }
+            }
+        }
+    }
+
     use crate::layer::base::tests::*;
     #[tokio::test]
     async fn base_layer_with_gaps_addition_count() {
@@ -1007,5 +1911,14 @@ mod tests {
             .unwrap();
 
         assert_eq!(1, layer.internal_triple_layer_addition_count());
+
+        assert_eq!(0, layer.count_s(1));
+        assert_eq!(1, layer.count_s(3));
+        assert_eq!(1, layer.count_sp(3, 3));
+        assert_eq!(0, layer.count_sp(3, 1));
+        assert_eq!(0, layer.count_p(1));
+        assert_eq!(1, layer.count_p(3));
+        assert_eq!(0, layer.count_o(1));
+        assert_eq!(1, layer.count_o(3));
     }
 }
@@ -60,6 +60,56 @@ impl InternalLayerTripleObjectIterator {
         }
     }
 
+    /// Move the iterator to the first triple equal to or greater than the given object, subject and predicate, in that order.
+    pub fn seek_object_subject_predicate_ref(&mut self, object: u64, subject: u64, predicate: u64) {
+        self.seek_object_ref(object);
+        if self.o_ps_position >= self.o_ps_adjacency_list.right_count() as u64 {
+            return;
+        }
+
+        let current_object = match self.objects.as_ref() {
+            Some(objects) => objects.entry(self.o_position.try_into().unwrap()),
+            None => self.o_position + 1,
+        };
+        if current_object != object {
+            // we are already past the given object
+            return;
+        }
+
+        // the subject-predicate pairs of an object are ordered, so we can do a binary search
+        let (start, end) = super::adjacency_range(&self.o_ps_adjacency_list, self.o_position + 1);
+        let mut low = start;
+        let mut high = end;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let sp_pair_num = self.o_ps_adjacency_list.num_at_pos(mid);
+            let before = if sp_pair_num == 0 {
+                true
+            } else {
+                let (mapped_subject, mid_predicate) =
+                    self.s_p_adjacency_list.pair_at_pos(sp_pair_num - 1);
+                let mid_subject = match self.subjects.as_ref() {
+                    Some(subjects) => subjects.entry(mapped_subject as usize - 1),
+                    None => mapped_subject,
+                };
+
+                (mid_subject, mid_predicate) < (subject, predicate)
+            };
+
+            if before {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+
+        self.o_ps_position = low;
+        if low == end {
+            // we moved past the end of this object
+            self.o_position += 1;
+        }
+    }
+
     pub fn peek(&mut self) -> Option<&IdTriple> {
         self.peeked = self.next();
 
@@ -113,6 +163,7 @@ impl Iterator for InternalLayerTripleObjectIterator {
     }
 }
 
+#[derive(Clone)]
 pub struct OptInternalLayerTripleObjectIterator(pub Option<InternalLayerTripleObjectIterator>);
 
 impl OptInternalLayerTripleObjectIterator {
@@ -126,6 +177,12 @@ impl OptInternalLayerTripleObjectIterator {
         }
     }
 
+    pub fn seek_object_subject_predicate_ref(&mut self, object: u64, subject: u64, predicate: u64) {
+        if let Some(i) = self.0.as_mut() {
+            i.seek_object_subject_predicate_ref(object, subject, predicate)
+        }
+    }
+
     pub fn peek(&mut self) -> Option<&IdTriple> {
         self.0.as_mut().and_then(|i| i.peek())
     }
@@ -142,6 +199,7 @@ impl Iterator for OptInternalLayerTripleObjectIterator {
     }
 }
 
+#[derive(Clone)]
 pub struct InternalTripleObjectIterator {
     positives: Vec<OptInternalLayerTripleObjectIterator>,
     negatives: Vec<OptInternalLayerTripleObjectIterator>,
@@ -170,6 +228,12 @@ impl InternalTripleObjectIterator {
     }
 
     pub fn seek_object(mut self, object: u64) -> Self {
+        self.seek_object_ref(object);
+
+        self
+    }
+
+    pub fn seek_object_ref(&mut self, object: u64) {
         for p in self.positives.iter_mut() {
             p.seek_object_ref(object);
         }
@@ -177,9 +241,29 @@ impl InternalTripleObjectIterator {
         for n in self.negatives.iter_mut() {
             n.seek_object_ref(object);
         }
+    }
+
+    /// Move the iterator to the first triple equal to or greater than the given object, subject and predicate, in that order.
+    pub fn seek_object_subject_predicate(
+        mut self,
+        object: u64,
+        subject: u64,
+        predicate: u64,
+    ) -> Self {
+        self.seek_object_subject_predicate_ref(object, subject, predicate);
 
         self
     }
+
+    pub fn seek_object_subject_predicate_ref(&mut self, object: u64, subject: u64, predicate: u64) {
+        for p in self.positives.iter_mut() {
+            p.seek_object_subject_predicate_ref(object, subject, predicate);
+        }
+
+        for n in self.negatives.iter_mut() {
+            n.seek_object_subject_predicate_ref(object, subject, predicate);
+        }
+    }
 }
 
 impl Iterator for InternalTripleObjectIterator {
@@ -223,6 +307,90 @@ impl Iterator for InternalTripleObjectIterator {
     }
 }
 
+/// An object iterator that can jump to a subject-predicate pair within an object.
+pub trait SeekObjectSubjectPredicate: Iterator<Item = IdTriple> {
+    fn seek_object_subject_predicate_ref(&mut self, object: u64, subject: u64, predicate: u64);
+}
+
+impl SeekObjectSubjectPredicate for InternalLayerTripleObjectIterator {
+    fn seek_object_subject_predicate_ref(&mut self, object: u64, subject: u64, predicate: u64) {
+        InternalLayerTripleObjectIterator::seek_object_subject_predicate_ref(
+            self, object, subject, predicate,
+        )
+    }
+}
+
+impl SeekObjectSubjectPredicate for OptInternalLayerTripleObjectIterator {
+    fn seek_object_subject_predicate_ref(&mut self, object: u64, subject: u64, predicate: u64) {
+        OptInternalLayerTripleObjectIterator::seek_object_subject_predicate_ref(
+            self, object, subject, predicate,
+        )
+    }
+}
+
+impl SeekObjectSubjectPredicate for InternalTripleObjectIterator {
+    fn seek_object_subject_predicate_ref(&mut self, object: u64, subject: u64, predicate: u64) {
+        InternalTripleObjectIterator::seek_object_subject_predicate_ref(
+            self, object, subject, predicate,
+        )
+    }
+}
+
+/// Iterator over the triples with a given predicate and object.
+///
+/// The subject-predicate pairs of an object are ordered by subject
+/// first, so the triples for the predicate are spread out over the
+/// object's range. Rather than looking at every pair, this jumps
+/// straight to the predicate within each subject.
+#[derive(Clone)]
+pub struct InternalTriplePredicateObjectIterator<I> {
+    iter: I,
+    predicate: u64,
+    object: u64,
+    subject: u64,
+    done: bool,
+}
+
+impl<I: SeekObjectSubjectPredicate> InternalTriplePredicateObjectIterator<I> {
+    pub fn new(iter: I, predicate: u64, object: u64) -> Self {
+        Self {
+            iter,
+            predicate,
+            object,
+            subject: 0,
+            done: predicate == 0 || object == 0,
+        }
+    }
+}
+
+impl<I: SeekObjectSubjectPredicate> Iterator for InternalTriplePredicateObjectIterator<I> {
+    type Item = IdTriple;
+
+    fn next(&mut self) -> Option<IdTriple> {
+        while !self.done {
+            self.iter
+                .seek_object_subject_predicate_ref(self.object, self.subject, self.predicate);
+            match self.iter.next() {
+                Some(t) if t.object == self.object => {
+                    if t.predicate < self.predicate {
+                        // this subject may still have the predicate further on
+                        self.subject = t.subject;
+                        continue;
+                    }
+
+                    self.subject = t.subject + 1;
+                    if t.predicate == self.predicate {
+                        return Some(t);
+                    }
+                }
+                _ => self.done = true,
+            }
+        }
+
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -410,6 +578,21 @@ mod tests {
         assert!(triples.is_empty());
     }
 
+    #[tokio::test]
+    async fn object_additions_iterator_for_predicate_object() {
+        let base_layer = example_base_layer().await;
+
+        let triples: Vec<_> = base_layer.internal_triple_additions_po(1, 5).collect();
+
+        let expected = vec![IdTriple::new(2, 1, 5), IdTriple::new(4, 1, 5)];
+
+        assert_eq!(expected, triples);
+        assert!(base_layer
+            .internal_triple_additions_po(3, 5)
+            .next()
+            .is_none());
+    }
+
     #[tokio::test]
     async fn combined_iterator_for_object() {
         let store = MemoryLayerStore::new();
@@ -467,5 +650,79 @@ mod tests {
         ];
 
         assert_eq!(expected, triples);
+
+        let predicate_id = layer.predicate_id("likes").unwrap();
+        let triples: Vec<_> = layer
+            .triples_po(predicate_id, object_id)
+            .map(|t| layer.id_triple_to_string(&t).unwrap())
+            .collect();
+
+        let expected = vec![
+            StringTriple::new_node("duck", "likes", "cow"),
+            StringTriple::new_node("horse", "likes", "cow"),
+        ];
+
+        assert_eq!(expected, triples);
+    }
+
+    #[tokio::test]
+    async fn predicate_object_iterator_skips_other_predicates() {
+        let store = MemoryLayerStore::new();
+        let mut builder = store.create_base_layer().await.unwrap();
+        let base_name = builder.name();
+
+        for subject in 0..20 {
+            for predicate in 0..5 {
+                if (subject + predicate) % 3 != 0 {
+                    builder.add_string_triple(StringTriple::new_node(
+                        &format!("s{}", subject),
+                        &format!("p{}", predicate),
+                        "o",
+                    ));
+                }
+            }
+        }
+        builder.commit_boxed().await.unwrap();
+
+        builder = store.create_child_layer(base_name).await.unwrap();
+        let child_name = builder.name();
+        for subject in 0..20 {
+            if subject % 4 == 0 {
+                builder.remove_string_triple(StringTriple::new_node(
+                    &format!("s{}", subject),
+                    "p1",
+                    "o",
+                ));
+            }
+        }
+        builder.commit_boxed().await.unwrap();
+
+        let base_layer = store.get_layer(base_name).await.unwrap().unwrap();
+        let layer = store.get_layer(child_name).await.unwrap().unwrap();
+        let object = layer.object_node_id("o").unwrap();
+        for predicate in 0..5 {
+            let predicate = layer.predicate_id(&format!("p{}", predicate)).unwrap();
+
+            let expected: Vec<_> = layer
+                .triples_o(object)
+                .filter(|t| t.predicate == predicate)
+                .collect();
+            assert!(!expected.is_empty());
+            assert_eq!(
+                expected,
+                layer.triples_po(predicate, object).collect::<Vec<_>>()
+            );
+
+            let expected: Vec<_> = base_layer
+                .internal_triple_additions_o(object)
+                .filter(|t| t.predicate == predicate)
+                .collect();
+            assert_eq!(
+                expected,
+                base_layer
+                    .internal_triple_additions_po(predicate, object)
+                    .collect::<Vec<_>>()
+            );
+        }
     }
 }
@@ -51,6 +51,27 @@ impl InternalLayerTriplePredicateIterator {
         true
     }
 
+    /// Move the iterator to the first triple with a subject equal to or greater than the given subject.
+    pub fn seek_subject_ref(&mut self, subject: u64) {
+        self.peeked = None;
+        self.sp_boundary = true;
+
+        // the positions in the lookup are ordered by subject, so we can do a binary search
+        let mut low = 0;
+        let mut high = self.len as u64;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let s_p_pos = self.predicate_wavelet_lookup.entry(mid.try_into().unwrap());
+            if self.subject_iterator.subject_at_s_p_pos(s_p_pos) < subject {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+
+        self.predicate_pos = low;
+    }
+
     pub fn peek(&mut self) -> Option<&IdTriple> {
         self.peeked = self.next();
 
@@ -101,6 +122,12 @@ pub struct OptInternalLayerTriplePredicateIterator(
 );
 
 impl OptInternalLayerTriplePredicateIterator {
+    pub fn seek_subject_ref(&mut self, subject: u64) {
+        if let Some(i) = self.0.as_mut() {
+            i.seek_subject_ref(subject)
+        }
+    }
+
     pub fn peek(&mut self) -> Option<&IdTriple> {
         self.0.as_mut().and_then(|i| i.peek())
     }
@@ -143,6 +170,19 @@ impl InternalTriplePredicateIterator {
             negatives,
         }
     }
+
+    /// Move the iterator to the first triple with a subject equal to or greater than the given subject.
+    pub fn seek_subject(mut self, subject: u64) -> Self {
+        for p in self.positives.iter_mut() {
+            p.seek_subject_ref(subject);
+        }
+
+        for n in self.negatives.iter_mut() {
+            n.seek_subject_ref(subject);
+        }
+
+        self
+    }
 }
 
 impl Iterator for InternalTriplePredicateIterator {
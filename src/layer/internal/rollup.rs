@@ -122,4 +122,8 @@ impl InternalLayerImpl for RollupLayer {
     fn neg_objects(&self) -> Option<&MonotonicLogArray> {
         self.internal.neg_objects()
     }
+
+    fn triple_filter(&self) -> Option<&BloomFilter> {
+        self.internal.triple_filter()
+    }
 }
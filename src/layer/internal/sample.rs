@@ -0,0 +1,373 @@
+//! Uniform random sampling of the triples in a layer stack.
+//!
+//! A sample is drawn by picking a random position among the additions
+//! of every layer in the stack and decoding the triple stored there,
+//! using the random access operations of the adjacency lists. If the
+//! triple was removed again by a layer further up the stack, or if
+//! the position holds the placeholder of a subject without triples,
+//! the sample is rejected and a new position is drawn.
+//!
+//! Every triple in the stack is stored as an addition in exactly one
+//! layer that is not followed by a removal of that triple, so each
+//! triple is equally likely to be returned.
+use super::*;
+use rand::Rng;
+use std::sync::Arc;
+
+#[derive(Clone)]
+struct SampleLists {
+    subjects: Option<MonotonicLogArray>,
+    s_p_adjacency_list: AdjacencyList,
+    sp_o_adjacency_list: AdjacencyList,
+}
+
+impl SampleLists {
+    fn triple_at_pos(&self, sp_o_position: u64) -> Option<IdTriple> {
+        let triple = layer_triple_at_pos(
+            self.subjects.as_ref(),
+            &self.s_p_adjacency_list,
+            &self.sp_o_adjacency_list,
+            sp_o_position,
+        );
+        if triple.predicate == 0 || triple.object == 0 {
+            return None;
+        }
+
+        Some(triple)
+    }
+
+    fn contains(&self, triple: IdTriple) -> bool {
+        layer_triple_exists(
+            self.subjects.as_ref(),
+            &self.s_p_adjacency_list,
+            &self.sp_o_adjacency_list,
+            triple.subject,
+            triple.predicate,
+            triple.object,
+        )
+    }
+}
+
+#[derive(Clone)]
+struct SampleSource {
+    /// The position of the layer in the stack, counting from the top.
+    depth: usize,
+    additions: SampleLists,
+    /// Ranges of sp_o positions to sample from, as (start, end, offset), where offset is the amount of positions in all ranges before this one.
+    ranges: Vec<(u64, u64, u64)>,
+    size: u64,
+}
+
+impl SampleSource {
+    fn new(depth: usize, additions: SampleLists, position_ranges: Vec<(u64, u64)>) -> Self {
+        let mut ranges = Vec::with_capacity(position_ranges.len());
+        let mut size = 0;
+        for (start, end) in position_ranges {
+            if start < end {
+                ranges.push((start, end, size));
+                size += end - start;
+            }
+        }
+
+        SampleSource {
+            depth,
+            additions,
+            ranges,
+            size,
+        }
+    }
+
+    fn triple_at(&self, offset: u64) -> Option<IdTriple> {
+        let index = self.ranges.partition_point(|&(_, _, o)| o <= offset) - 1;
+        let (start, _, range_offset) = self.ranges[index];
+
+        self.additions.triple_at_pos(start + offset - range_offset)
+    }
+}
+
+#[derive(Clone)]
+struct StackSampler {
+    sources: Vec<SampleSource>,
+    /// The amount of positions in all sources before each source.
+    offsets: Vec<u64>,
+    size: u64,
+    removals: Vec<Option<SampleLists>>,
+}
+
+impl StackSampler {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> IdTriple {
+        loop {
+            let offset = rng.gen_range(0..self.size);
+            let index = self.offsets.partition_point(|&o| o <= offset) - 1;
+            let source = &self.sources[index];
+
+            if let Some(triple) = source.triple_at(offset - self.offsets[index]) {
+                let removed = self.removals[..source.depth]
+                    .iter()
+                    .flatten()
+                    .any(|removals| removals.contains(triple));
+
+                if !removed {
+                    return triple;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+enum SamplerInner {
+    Stack(StackSampler),
+    Triples(Arc<[IdTriple]>),
+}
+
+/// A sampler drawing uniformly random triples from a layer stack.
+///
+/// Samplers are created through `Layer::triple_sampler` and its
+/// variants, and draw samples with replacement.
+#[derive(Clone)]
+pub struct TripleSampler {
+    inner: SamplerInner,
+    triple_count: usize,
+}
+
+impl TripleSampler {
+    fn new<'a>(
+        layer: &'a dyn InternalLayerImpl,
+        triple_count: usize,
+        position_ranges: impl Fn(&'a dyn InternalLayerImpl) -> Vec<(u64, u64)>,
+    ) -> Self {
+        let mut sources = Vec::new();
+        let mut offsets = Vec::new();
+        let mut size = 0;
+        let mut removals = Vec::new();
+
+        let mut layer_opt = Some(layer);
+        while let Some(layer) = layer_opt {
+            let additions = SampleLists {
+                subjects: layer.pos_subjects().cloned(),
+                s_p_adjacency_list: layer.pos_s_p_adjacency_list().clone(),
+                sp_o_adjacency_list: layer.pos_sp_o_adjacency_list().clone(),
+            };
+            let source = SampleSource::new(removals.len(), additions, position_ranges(layer));
+            if source.size != 0 {
+                offsets.push(size);
+                size += source.size;
+                sources.push(source);
+            }
+
+            removals.push(
+                match (
+                    layer.neg_s_p_adjacency_list(),
+                    layer.neg_sp_o_adjacency_list(),
+                ) {
+                    (Some(s_p_adjacency_list), Some(sp_o_adjacency_list)) => Some(SampleLists {
+                        subjects: layer.neg_subjects().cloned(),
+                        s_p_adjacency_list: s_p_adjacency_list.clone(),
+                        sp_o_adjacency_list: sp_o_adjacency_list.clone(),
+                    }),
+                    _ => None,
+                },
+            );
+
+            layer_opt = layer
+                .immediate_parent()
+                .map(|p| p as &dyn InternalLayerImpl);
+        }
+
+        TripleSampler {
+            inner: SamplerInner::Stack(StackSampler {
+                sources,
+                offsets,
+                size,
+                removals,
+            }),
+            triple_count,
+        }
+    }
+
+    /// Create a sampler over the given triples.
+    ///
+    /// This is meant for layers without the adjacency lists of an
+    /// internal layer, which have to collect their triples up front.
+    pub fn from_triples(triples: Vec<IdTriple>) -> Self {
+        TripleSampler {
+            triple_count: triples.len(),
+            inner: SamplerInner::Triples(triples.into()),
+        }
+    }
+
+    /// Create a sampler over all triples in the given layer stack.
+    pub fn from_layer<T: 'static + InternalLayerImpl>(layer: &T) -> Self {
+        let triple_count = stack_triple_count(layer, |layer| {
+            (
+                layer.internal_triple_layer_addition_count(),
+                layer.internal_triple_layer_removal_count(),
+            )
+        });
+
+        Self::new(layer, triple_count, |layer| {
+            vec![(0, layer.pos_sp_o_adjacency_list().right_count() as u64)]
+        })
+    }
+
+    /// Create a sampler over the triples with the given subject in the given layer stack.
+    pub fn from_layer_s<T: 'static + InternalLayerImpl>(layer: &T, subject: u64) -> Self {
+        let triple_count = stack_triple_count(layer, |layer| {
+            (
+                layer.internal_triple_layer_addition_count_s(subject),
+                layer.internal_triple_layer_removal_count_s(subject),
+            )
+        });
+
+        Self::new(layer, triple_count, |layer| {
+            layer_subject_range(
+                layer.pos_subjects(),
+                layer.pos_s_p_adjacency_list(),
+                layer.pos_sp_o_adjacency_list(),
+                subject,
+            )
+            .into_iter()
+            .collect()
+        })
+    }
+
+    /// Create a sampler over the triples with the given predicate in the given layer stack.
+    ///
+    /// This visits every subject-predicate pair with this predicate
+    /// once, in order to weigh them by their amount of objects.
+    pub fn from_layer_p<T: 'static + InternalLayerImpl>(layer: &T, predicate: u64) -> Self {
+        let triple_count = stack_triple_count(layer, |layer| {
+            (
+                layer.internal_triple_layer_addition_count_p(predicate),
+                layer.internal_triple_layer_removal_count_p(predicate),
+            )
+        });
+
+        Self::new(layer, triple_count, |layer| {
+            if predicate == 0 {
+                return Vec::new();
+            }
+
+            let sp_o_adjacency_list = layer.pos_sp_o_adjacency_list();
+            match layer.pos_predicate_wavelet_tree().lookup(predicate) {
+                Some(lookup) => lookup
+                    .iter()
+                    .map(|s_p_position| adjacency_range(sp_o_adjacency_list, s_p_position + 1))
+                    .collect(),
+                None => Vec::new(),
+            }
+        })
+    }
+
+    /// The amount of triples this sampler draws from.
+    pub fn triple_count(&self) -> usize {
+        self.triple_count
+    }
+
+    /// Returns true if there are no triples to draw from.
+    pub fn is_empty(&self) -> bool {
+        self.triple_count == 0
+    }
+
+    /// Draw a single triple, or None if there are no triples to draw from.
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<IdTriple> {
+        if self.is_empty() {
+            return None;
+        }
+
+        match &self.inner {
+            SamplerInner::Stack(stack) => Some(stack.sample(rng)),
+            SamplerInner::Triples(triples) => Some(triples[rng.gen_range(0..triples.len())]),
+        }
+    }
+
+    /// An endless iterator of samples, or an empty one if there are no triples to draw from.
+    pub fn samples<R: Rng>(self, rng: R) -> TripleSamples<R> {
+        TripleSamples { sampler: self, rng }
+    }
+}
+
+/// An iterator drawing random triples from a `TripleSampler`.
+pub struct TripleSamples<R> {
+    sampler: TripleSampler,
+    rng: R,
+}
+
+impl<R: Rng> Iterator for TripleSamples<R> {
+    type Item = IdTriple;
+
+    fn next(&mut self) -> Option<IdTriple> {
+        self.sampler.sample(&mut self.rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layer::child::tests::example_layer_stack;
+    use crate::layer::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::collections::HashMap;
+
+    fn sample_counts(sampler: TripleSampler, amount: usize) -> HashMap<IdTriple, usize> {
+        let mut counts = HashMap::new();
+        for triple in sampler.samples(StdRng::seed_from_u64(42)).take(amount) {
+            *counts.entry(triple).or_insert(0) += 1;
+        }
+
+        counts
+    }
+
+    fn assert_uniform(counts: &HashMap<IdTriple, usize>, expected: Vec<IdTriple>, amount: usize) {
+        let mut sampled: Vec<_> = counts.keys().cloned().collect();
+        sampled.sort();
+        assert_eq!(expected, sampled);
+
+        let mean = amount / expected.len();
+        for count in counts.values() {
+            assert!(*count > mean * 3 / 4 && *count < mean * 5 / 4);
+        }
+    }
+
+    #[tokio::test]
+    async fn sample_all_triples_uniformly() {
+        let layer = example_layer_stack().await;
+        let sampler = layer.triple_sampler();
+        assert_eq!(layer.triple_count(), sampler.triple_count());
+
+        let counts = sample_counts(sampler, 4000);
+        assert_uniform(&counts, layer.triples().collect(), 4000);
+    }
+
+    #[tokio::test]
+    async fn sample_triples_by_subject_and_predicate() {
+        let layer = example_layer_stack().await;
+        let duck = layer.subject_id("duck").unwrap();
+        let cow = layer.subject_id("cow").unwrap();
+        let likes = layer.predicate_id("likes").unwrap();
+
+        let counts = sample_counts(layer.triple_sampler_s(duck), 2000);
+        assert_uniform(&counts, layer.triples_s(duck).collect(), 2000);
+
+        let counts = sample_counts(layer.triple_sampler_p(likes), 3000);
+        assert_uniform(&counts, layer.triples_p(likes).collect(), 3000);
+
+        // the only triple of the cow that is left was added in the base layer
+        let counts = sample_counts(layer.triple_sampler_s(cow), 100);
+        assert_uniform(&counts, layer.triples_s(cow).collect(), 100);
+    }
+
+    #[tokio::test]
+    async fn sample_without_triples() {
+        let layer = example_layer_stack().await;
+        let hates = layer.predicate_id("hates").unwrap();
+
+        let sampler = layer.triple_sampler_p(hates);
+        assert!(sampler.is_empty());
+        assert_eq!(None, sampler.sample(&mut StdRng::seed_from_u64(42)));
+        assert_eq!(0, sampler.samples(StdRng::seed_from_u64(42)).count());
+
+        assert!(layer.triple_sampler_s(1000).is_empty());
+    }
+}
@@ -94,8 +94,13 @@ impl InternalLayerTripleSubjectIterator {
             self.s_p_position = self.s_p_adjacency_list.right_count() as u64;
             self.sp_o_position = self.sp_o_adjacency_list.right_count() as u64;
         } else {
+            let subject_found = match self.subjects.as_ref() {
+                None => true,
+                Some(subjects) => subjects.entry(self.s_position.try_into().unwrap()) == subject,
+            };
             let mut s_p_position = self.s_p_adjacency_list.offset_for(self.s_position + 1);
-            while self.s_p_adjacency_list.num_at_pos(s_p_position) < predicate {
+            // if this layer doesn't contain the subject, we are at the start of a later subject, which is where we stop
+            while subject_found && self.s_p_adjacency_list.num_at_pos(s_p_position) < predicate {
                 s_p_position += 1;
 
                 if self.s_p_adjacency_list.bit_at_pos(s_p_position - 1) {
@@ -106,7 +111,11 @@ impl InternalLayerTripleSubjectIterator {
                 }
             }
             self.s_p_position = s_p_position;
-            self.sp_o_position = self.sp_o_adjacency_list.offset_for(self.s_p_position + 1);
+            if self.s_p_position >= self.s_p_adjacency_list.right_count() as u64 {
+                self.sp_o_position = self.sp_o_adjacency_list.right_count() as u64;
+            } else {
+                self.sp_o_position = self.sp_o_adjacency_list.offset_for(self.s_p_position + 1);
+            }
         }
     }
 
@@ -118,6 +127,15 @@ impl InternalLayerTripleSubjectIterator {
         self.sp_o_position = self.sp_o_adjacency_list.offset_for(self.s_p_position + 1);
     }
 
+    /// Returns the subject of the pair at the given position in the s_p adjacency list.
+    pub fn subject_at_s_p_pos(&self, pos: u64) -> u64 {
+        let s_index = self.s_p_adjacency_list.left_at_pos(pos);
+        match self.subjects.as_ref() {
+            Some(subjects) => subjects.entry((s_index - 1).try_into().unwrap()),
+            None => s_index,
+        }
+    }
+
     pub fn peek(&mut self) -> Option<&IdTriple> {
         self.peeked = self.next();
 
@@ -243,6 +261,12 @@ impl InternalTripleSubjectIterator {
     }
 
     pub fn seek_subject(mut self, subject: u64) -> Self {
+        self.seek_subject_ref(subject);
+
+        self
+    }
+
+    pub fn seek_subject_ref(&mut self, subject: u64) {
         for p in self.positives.iter_mut() {
             p.seek_subject_ref(subject);
         }
@@ -250,11 +274,15 @@ impl InternalTripleSubjectIterator {
         for n in self.negatives.iter_mut() {
             n.seek_subject_ref(subject);
         }
+    }
+
+    pub fn seek_subject_predicate(mut self, subject: u64, predicate: u64) -> Self {
+        self.seek_subject_predicate_ref(subject, predicate);
 
         self
     }
 
-    pub fn seek_subject_predicate(mut self, subject: u64, predicate: u64) -> Self {
+    pub fn seek_subject_predicate_ref(&mut self, subject: u64, predicate: u64) {
         for p in self.positives.iter_mut() {
             p.seek_subject_predicate_ref(subject, predicate);
         }
@@ -262,8 +290,6 @@ impl InternalTripleSubjectIterator {
         for n in self.negatives.iter_mut() {
             n.seek_subject_predicate_ref(subject, predicate);
         }
-
-        self
     }
 }
 
@@ -1,6 +1,11 @@
 //! Common data structures and traits for all layer types.
+use super::internal::{ObjectCursor, SubjectCursor, TripleSampler};
+use super::scan::{ScanToken, ScanTokenError, TripleScan};
+use crate::query::{PropertyPath, Traversal};
 use std::collections::HashMap;
 use std::hash::Hash;
+use std::ops::Range;
+use std::sync::Arc;
 
 /// A layer containing dictionary entries and triples.
 ///
@@ -33,6 +38,50 @@ pub trait Layer: Send + Sync {
     fn id_predicate(&self, id: u64) -> Option<String>;
     /// The object corresponding to a numerical id, or None if it cannot be found.
     fn id_object(&self, id: u64) -> Option<ObjectType>;
+    /// Returns true if the given object id refers to a value, false if it refers to a node, or None if it cannot be found.
+    ///
+    /// Internal layers answer this without looking up the object in a dictionary.
+    fn id_object_is_value(&self, id: u64) -> Option<bool> {
+        self.id_object(id)
+            .map(|object| matches!(object, ObjectType::Value(_)))
+    }
+
+    /// The numerical ids of many subjects, in the order of the given subjects.
+    ///
+    /// Internal layers resolve all subjects in one pass over the
+    /// dictionary of every layer, which is a lot faster than resolving
+    /// them one by one.
+    fn subject_ids(&self, subjects: &[&str]) -> Vec<Option<u64>> {
+        subjects.iter().map(|s| self.subject_id(s)).collect()
+    }
+    /// The numerical ids of many predicates, in the order of the given predicates.
+    fn predicate_ids(&self, predicates: &[&str]) -> Vec<Option<u64>> {
+        predicates.iter().map(|p| self.predicate_id(p)).collect()
+    }
+    /// The numerical ids of many node objects, in the order of the given node objects.
+    fn object_node_ids(&self, objects: &[&str]) -> Vec<Option<u64>> {
+        // nodes share their ids between subject and object position
+        self.subject_ids(objects)
+    }
+    /// The numerical ids of many value objects, in the order of the given value objects.
+    fn object_value_ids(&self, objects: &[&str]) -> Vec<Option<u64>> {
+        objects.iter().map(|o| self.object_value_id(o)).collect()
+    }
+    /// The subjects corresponding to many numerical ids, in the order of the given ids.
+    ///
+    /// Internal layers group the ids by the layer whose dictionary
+    /// they are in, and decode every dictionary block at most once.
+    fn id_subjects(&self, ids: &[u64]) -> Vec<Option<String>> {
+        ids.iter().map(|&id| self.id_subject(id)).collect()
+    }
+    /// The predicates corresponding to many numerical ids, in the order of the given ids.
+    fn id_predicates(&self, ids: &[u64]) -> Vec<Option<String>> {
+        ids.iter().map(|&id| self.id_predicate(id)).collect()
+    }
+    /// The objects corresponding to many numerical ids, in the order of the given ids.
+    fn id_objects(&self, ids: &[u64]) -> Vec<Option<ObjectType>> {
+        ids.iter().map(|&id| self.id_object(id)).collect()
+    }
 
     /// Create a struct with all the counts
     fn all_counts(&self) -> LayerCounts;
@@ -83,6 +132,132 @@ pub trait Layer: Send + Sync {
 
     fn triples_o(&self, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send>;
 
+    /// Iterator over all triples with the given predicate and object, ordered by subject.
+    fn triples_po(&self, predicate: u64, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        Box::new(
+            self.triples_o(object)
+                .filter(move |t| t.predicate == predicate),
+        )
+    }
+
+    /// Iterator over the triples of the given scan.
+    fn triples_scan(&self, scan: TripleScan) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        match scan {
+            TripleScan::All => self.triples(),
+            TripleScan::S(subject) => self.triples_s(subject),
+            TripleScan::Sp(subject, predicate) => self.triples_sp(subject, predicate),
+            TripleScan::P(predicate) => self.triples_p(predicate),
+            TripleScan::O(object) => self.triples_o(object),
+            TripleScan::Po(predicate, object) => self.triples_po(predicate, object),
+        }
+    }
+
+    /// Iterator over the remaining triples of a scan, starting right after the last triple recorded in the token.
+    ///
+    /// Internal layers seek to the starting position directly, so
+    /// resuming a scan does not go over the triples that came before.
+    /// The token has to be created for this layer.
+    fn triples_after(
+        &self,
+        token: &ScanToken,
+    ) -> Result<Box<dyn Iterator<Item = IdTriple> + Send>, ScanTokenError> {
+        if token.layer() != self.name() {
+            return Err(ScanTokenError::WrongLayer);
+        }
+
+        let scan = token.scan();
+        let last = token.last();
+        Ok(Box::new(
+            self.triples_scan(scan)
+                .skip_while(move |t| scan.not_after(t, &last)),
+        ))
+    }
+
+    /// Returns the amount of triples with the given subject.
+    ///
+    /// Internal layers compute this from the indexes of every layer in
+    /// the stack, without iterating over the triples themselves.
+    fn count_s(&self, subject: u64) -> usize {
+        self.triples_s(subject).count()
+    }
+
+    /// Returns the amount of triples with the given subject and predicate.
+    fn count_sp(&self, subject: u64, predicate: u64) -> usize {
+        self.triples_sp(subject, predicate).count()
+    }
+
+    /// Returns the amount of triples with the given predicate.
+    fn count_p(&self, predicate: u64) -> usize {
+        self.triples_p(predicate).count()
+    }
+
+    /// Returns the amount of triples with the given object.
+    fn count_o(&self, object: u64) -> usize {
+        self.triples_o(object).count()
+    }
+
+    /// A seekable cursor over all triples, ordered by subject, predicate and object.
+    ///
+    /// By default, every seek skips over the triples of `triples`
+    /// until it reaches its starting point.
+    fn subject_cursor(&self) -> SubjectCursor {
+        let layer = self.clone_boxed();
+        SubjectCursor::from_seek_fn(move |start| {
+            Box::new(layer.triples().skip_while(move |t| *t < start))
+        })
+    }
+
+    /// A seekable cursor over all triples, ordered by object, subject and predicate.
+    ///
+    /// By default, every seek goes over the objects from its starting
+    /// point on, sorting the triples of each one.
+    fn object_cursor(&self) -> ObjectCursor {
+        let layer: Arc<dyn Layer> = self.clone_boxed().into();
+        ObjectCursor::from_seek_fn(move |start| {
+            let layer = layer.clone();
+            let key = |t: &IdTriple| (t.object, t.subject, t.predicate);
+            Box::new(
+                (start.object.max(1)..=layer.node_and_value_count() as u64)
+                    .flat_map(move |object| {
+                        let mut triples: Vec<_> = layer.triples_o(object).collect();
+                        triples.sort_unstable_by_key(key);
+                        triples
+                    })
+                    .skip_while(move |t| key(t) < key(&start)),
+            )
+        })
+    }
+
+    /// Split the subject ids into at most `parts` disjoint, consecutive ranges, which together cover all subjects.
+    ///
+    /// The ranges are chosen so that they hold roughly the same
+    /// amount of triples, which makes them suitable for distributing
+    /// a scan over multiple threads. By default, all subjects are in
+    /// a single range.
+    fn subject_ranges(&self, _parts: usize) -> Vec<Range<u64>> {
+        vec![Range {
+            start: 0,
+            end: u64::MAX,
+        }]
+    }
+
+    /// A sampler that draws uniformly random triples from this layer.
+    ///
+    /// By default, the sampler holds a copy of all triples.
+    fn triple_sampler(&self) -> TripleSampler {
+        TripleSampler::from_triples(self.triples().collect())
+    }
+
+    /// A sampler that draws uniformly random triples with the given subject.
+    fn triple_sampler_s(&self, subject: u64) -> TripleSampler {
+        TripleSampler::from_triples(self.triples_s(subject).collect())
+    }
+
+    /// A sampler that draws uniformly random triples with the given predicate.
+    fn triple_sampler_p(&self, predicate: u64) -> TripleSampler {
+        TripleSampler::from_triples(self.triples_p(predicate).collect())
+    }
+
     /// Convert all known strings in the given string triple to ids.
     fn string_triple_to_partially_resolved(&self, triple: StringTriple) -> PartiallyResolvedTriple {
         PartiallyResolvedTriple {
@@ -120,6 +295,30 @@ pub trait Layer: Send + Sync {
         })
     }
 
+    /// Convert many id triples to their string versions at once, in the order of the given triples.
+    ///
+    /// This uses the bulk dictionary lookups, so it is a lot faster
+    /// than converting the triples one by one.
+    fn id_triples_to_strings(&self, triples: &[IdTriple]) -> Vec<Option<StringTriple>> {
+        let subjects = self.id_subjects(&triples.iter().map(|t| t.subject).collect::<Vec<_>>());
+        let predicates =
+            self.id_predicates(&triples.iter().map(|t| t.predicate).collect::<Vec<_>>());
+        let objects = self.id_objects(&triples.iter().map(|t| t.object).collect::<Vec<_>>());
+
+        subjects
+            .into_iter()
+            .zip(predicates)
+            .zip(objects)
+            .map(|((subject, predicate), object)| {
+                Some(StringTriple {
+                    subject: subject?,
+                    predicate: predicate?,
+                    object: object?,
+                })
+            })
+            .collect()
+    }
+
     /// Returns the total amount of triple additions in this layer and all its parents.
     fn triple_addition_count(&self) -> usize;
 
@@ -130,6 +329,107 @@ pub trait Layer: Send + Sync {
     fn triple_count(&self) -> usize {
         self.triple_addition_count() - self.triple_removal_count()
     }
+
+    /// Start a breadth-first traversal of the graph from the given node id.
+    ///
+    /// The traversal holds its own clone of this layer.
+    fn traverse(&self, start: u64) -> Traversal {
+        Traversal::new(self.clone_boxed().into(), start)
+    }
+
+    /// Iterator over the distinct node ids reachable from the given node id through a property path.
+    fn path_targets(
+        &self,
+        start: u64,
+        path: &PropertyPath,
+    ) -> Box<dyn Iterator<Item = u64> + Send> {
+        Box::new(path.targets(self.clone_boxed().into(), start))
+    }
+
+    /// Iterator over all triples with a subject in the given range, ordered by subject, predicate and object.
+    fn triples_subject_range(
+        &self,
+        subjects: Range<u64>,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        let mut cursor = self.subject_cursor();
+        cursor.seek_subject(subjects.start);
+        Box::new(cursor.take_while(move |t| t.subject < subjects.end))
+    }
+
+    /// Split all triples into at most `parts` iterators over disjoint subject ranges.
+    ///
+    /// Each iterator can be consumed on its own thread, for example
+    /// through rayon's `into_par_iter()`.
+    fn triples_split(&self, parts: usize) -> Vec<Box<dyn Iterator<Item = IdTriple> + Send>> {
+        self.subject_ranges(parts)
+            .into_iter()
+            .map(|range| self.triples_subject_range(range))
+            .collect()
+    }
+
+    /// Iterator over the ids of all subjects that have at least one triple, in ascending order.
+    fn subjects(&self) -> Box<dyn Iterator<Item = u64> + Send> {
+        let mut cursor = self.subject_cursor();
+        Box::new(std::iter::from_fn(move || {
+            let subject = cursor.peek()?.subject;
+            cursor.seek_subject(subject + 1);
+
+            Some(subject)
+        }))
+    }
+
+    /// Iterator over the ids of all predicates that are used in at least one triple, in ascending order.
+    fn predicates(&self) -> Box<dyn Iterator<Item = u64> + Send> {
+        let layer = self.clone_boxed();
+        Box::new((1..=self.predicate_count() as u64).filter(move |p| layer.count_p(*p) != 0))
+    }
+
+    /// Iterator over the ids of all objects that are used in at least one triple, in ascending order.
+    fn objects(&self) -> Box<dyn Iterator<Item = u64> + Send> {
+        let mut cursor = self.object_cursor();
+        Box::new(std::iter::from_fn(move || {
+            let object = cursor.peek()?.object;
+            cursor.seek_object(object + 1);
+
+            Some(object)
+        }))
+    }
+
+    /// Iterator over the ids of all node objects that are used in at least one triple, in ascending order.
+    fn node_objects(&self) -> Box<dyn Iterator<Item = u64> + Send> {
+        let layer = self.clone_boxed();
+        Box::new(
+            self.objects()
+                .filter(move |o| layer.id_object_is_value(*o) == Some(false)),
+        )
+    }
+
+    /// Iterator over the ids of all value objects that are used in at least one triple, in ascending order.
+    fn value_objects(&self) -> Box<dyn Iterator<Item = u64> + Send> {
+        let layer = self.clone_boxed();
+        Box::new(
+            self.objects()
+                .filter(move |o| layer.id_object_is_value(*o) == Some(true)),
+        )
+    }
+
+    /// Iterator over the ids of all node and value dictionary entries that are used in at least one triple, as either subject or object, in ascending order.
+    fn node_and_value_ids(&self) -> Box<dyn Iterator<Item = u64> + Send> {
+        let mut subjects = self.subjects().peekable();
+        let mut objects = self.objects().peekable();
+        Box::new(std::iter::from_fn(move || {
+            match (subjects.peek().copied(), objects.peek().copied()) {
+                (Some(s), Some(o)) if s == o => {
+                    subjects.next();
+                    objects.next()
+                }
+                (Some(s), Some(o)) if s < o => subjects.next(),
+                (_, Some(_)) => objects.next(),
+                (Some(_), None) => subjects.next(),
+                (None, None) => None,
+            }
+        }))
+    }
 }
 
 pub struct LayerCounts {
@@ -331,6 +631,7 @@ pub enum ObjectType {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::layer::child::tests::example_layer_stack;
     use crate::layer::internal::base::tests::base_layer_files;
     use crate::layer::internal::base::BaseLayer;
     use crate::layer::internal::child::tests::child_layer_files;
@@ -339,6 +640,80 @@ mod tests {
     use crate::layer::simple_builder::{LayerBuilder, SimpleLayerBuilder};
     use std::sync::Arc;
 
+    /// A layer that only implements the required methods of `Layer`, to exercise the defaults.
+    #[derive(Clone)]
+    struct DefaultsOnly(Arc<InternalLayer>);
+
+    impl Layer for DefaultsOnly {
+        fn name(&self) -> [u32; 5] {
+            Layer::name(&*self.0)
+        }
+        fn parent_name(&self) -> Option<[u32; 5]> {
+            self.0.parent_name()
+        }
+        fn node_and_value_count(&self) -> usize {
+            self.0.node_and_value_count()
+        }
+        fn predicate_count(&self) -> usize {
+            self.0.predicate_count()
+        }
+        fn subject_id(&self, subject: &str) -> Option<u64> {
+            self.0.subject_id(subject)
+        }
+        fn predicate_id(&self, predicate: &str) -> Option<u64> {
+            self.0.predicate_id(predicate)
+        }
+        fn object_node_id(&self, object: &str) -> Option<u64> {
+            self.0.object_node_id(object)
+        }
+        fn object_value_id(&self, object: &str) -> Option<u64> {
+            self.0.object_value_id(object)
+        }
+        fn id_subject(&self, id: u64) -> Option<String> {
+            self.0.id_subject(id)
+        }
+        fn id_predicate(&self, id: u64) -> Option<String> {
+            self.0.id_predicate(id)
+        }
+        fn id_object(&self, id: u64) -> Option<ObjectType> {
+            self.0.id_object(id)
+        }
+        fn all_counts(&self) -> LayerCounts {
+            self.0.all_counts()
+        }
+        fn clone_boxed(&self) -> Box<dyn Layer> {
+            Box::new(self.clone())
+        }
+        fn triple_exists(&self, subject: u64, predicate: u64, object: u64) -> bool {
+            self.0.triple_exists(subject, predicate, object)
+        }
+        fn triples(&self) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+            self.0.triples()
+        }
+        fn triples_s(&self, subject: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+            self.0.triples_s(subject)
+        }
+        fn triples_sp(
+            &self,
+            subject: u64,
+            predicate: u64,
+        ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+            self.0.triples_sp(subject, predicate)
+        }
+        fn triples_p(&self, predicate: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+            self.0.triples_p(predicate)
+        }
+        fn triples_o(&self, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+            self.0.triples_o(object)
+        }
+        fn triple_addition_count(&self) -> usize {
+            self.0.triple_addition_count()
+        }
+        fn triple_removal_count(&self) -> usize {
+            self.0.triple_removal_count()
+        }
+    }
+
     #[tokio::test]
     async fn find_triple_after_adjacent_removal() {
         let files = base_layer_files();
@@ -445,4 +820,164 @@ mod tests {
 
         assert_eq!(vec![StringTriple::new_value("cow", "says", "moo")], triples);
     }
+
+    #[tokio::test]
+    async fn default_cursors_match_the_layer_cursors() {
+        let layer = example_layer_stack().await;
+        let defaults = DefaultsOnly(layer.clone());
+
+        let expected: Vec<_> = layer.subject_cursor().collect();
+        assert_eq!(expected, defaults.subject_cursor().collect::<Vec<_>>());
+        let expected: Vec<_> = layer.object_cursor().collect();
+        assert_eq!(expected, defaults.object_cursor().collect::<Vec<_>>());
+
+        let cow = layer.subject_id("cow").unwrap();
+        let likes = layer.predicate_id("likes").unwrap();
+        let mut expected = layer.subject_cursor();
+        let mut cursor = defaults.subject_cursor();
+        expected.seek_subject_predicate(cow, likes);
+        cursor.seek_subject_predicate(cow, likes);
+        assert_eq!(expected.peek(), cursor.peek());
+        assert_eq!(expected.collect::<Vec<_>>(), cursor.collect::<Vec<_>>());
+
+        let mut expected = layer.object_cursor();
+        let mut cursor = defaults.object_cursor();
+        expected.seek_object(cow);
+        cursor.seek_object(cow);
+        assert_eq!(expected.collect::<Vec<_>>(), cursor.collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn default_samplers_draw_from_the_same_triples() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+        use std::collections::BTreeSet;
+
+        let layer = example_layer_stack().await;
+        let defaults = DefaultsOnly(layer.clone());
+        let says = layer.predicate_id("says").unwrap();
+        let horse = layer.subject_id("horse").unwrap();
+
+        for (sampler, expected) in [
+            (defaults.triple_sampler(), layer.triples()),
+            (defaults.triple_sampler_s(horse), layer.triples_s(horse)),
+            (defaults.triple_sampler_p(says), layer.triples_p(says)),
+        ] {
+            let expected: BTreeSet<_> = expected.collect();
+            assert_eq!(expected.len(), sampler.triple_count());
+            let sampled: BTreeSet<_> = sampler
+                .samples(StdRng::seed_from_u64(42))
+                .take(100)
+                .collect();
+            assert_eq!(expected, sampled);
+        }
+    }
+
+    #[tokio::test]
+    async fn default_triples_po_matches_the_layer() {
+        let layer = example_layer_stack().await;
+        let defaults = DefaultsOnly(layer.clone());
+
+        for object in 1..=layer.node_and_value_count() as u64 {
+            for predicate in 1..=layer.predicate_count() as u64 {
+                assert_eq!(
+                    layer.triples_po(predicate, object).collect::<Vec<_>>(),
+                    defaults.triples_po(predicate, object).collect::<Vec<_>>()
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn default_counts_match_the_layer() {
+        let layer = example_layer_stack().await;
+        let defaults = DefaultsOnly(layer.clone());
+
+        for id in 1..=layer.node_and_value_count() as u64 {
+            assert_eq!(layer.count_s(id), defaults.count_s(id));
+            assert_eq!(layer.count_o(id), defaults.count_o(id));
+            for predicate in 1..=layer.predicate_count() as u64 {
+                assert_eq!(
+                    layer.count_sp(id, predicate),
+                    defaults.count_sp(id, predicate)
+                );
+            }
+        }
+        for predicate in 1..=layer.predicate_count() as u64 {
+            assert_eq!(layer.count_p(predicate), defaults.count_p(predicate));
+        }
+    }
+
+    #[tokio::test]
+    async fn default_id_object_is_value_matches_the_layer() {
+        let layer = example_layer_stack().await;
+        let defaults = DefaultsOnly(layer.clone());
+
+        for id in 0..=layer.node_and_value_count() as u64 + 1 {
+            assert_eq!(
+                layer.id_object_is_value(id),
+                defaults.id_object_is_value(id)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn default_subject_ranges_split_into_one_range() {
+        let layer = example_layer_stack().await;
+        let defaults = DefaultsOnly(layer.clone());
+
+        assert_eq!(vec![0..u64::MAX], defaults.subject_ranges(4));
+        let split: Vec<_> = defaults.triples_split(4).into_iter().flatten().collect();
+        assert_eq!(layer.triples().collect::<Vec<_>>(), split);
+    }
+
+    #[tokio::test]
+    async fn default_triples_after_resumes_every_scan() {
+        let layer = example_layer_stack().await;
+        let defaults = DefaultsOnly(layer.clone());
+        let cow = layer.subject_id("cow").unwrap();
+        let likes = layer.predicate_id("likes").unwrap();
+
+        for scan in [
+            TripleScan::All,
+            TripleScan::S(cow),
+            TripleScan::Sp(cow, likes),
+            TripleScan::P(likes),
+            TripleScan::O(cow),
+            TripleScan::Po(likes, cow),
+        ] {
+            let triples: Vec<_> = defaults.triples_scan(scan).collect();
+            for (ix, last) in triples.iter().enumerate() {
+                let token = ScanToken::new(defaults.name(), scan, *last).unwrap();
+                let rest: Vec<_> = defaults.triples_after(&token).unwrap().collect();
+                assert_eq!(triples[ix + 1..], rest[..]);
+                assert_eq!(
+                    layer.triples_after(&token).unwrap().collect::<Vec<_>>(),
+                    rest
+                );
+            }
+        }
+
+        let token = ScanToken::new([0; 5], TripleScan::All, IdTriple::new(1, 1, 1)).unwrap();
+        assert!(defaults.triples_after(&token).is_err());
+    }
+
+    #[tokio::test]
+    async fn default_bulk_lookups_match_the_layer() {
+        let layer = example_layer_stack().await;
+        let defaults = DefaultsOnly(layer.clone());
+
+        let names = ["cow", "horse", "pig", "says", "likes", "moo", "neigh"];
+        assert_eq!(layer.subject_ids(&names), defaults.subject_ids(&names));
+        assert_eq!(layer.predicate_ids(&names), defaults.predicate_ids(&names));
+        assert_eq!(
+            layer.object_value_ids(&names),
+            defaults.object_value_ids(&names)
+        );
+
+        let ids: Vec<u64> = (0..=layer.node_and_value_count() as u64 + 1).collect();
+        assert_eq!(layer.id_subjects(&ids), defaults.id_subjects(&ids));
+        assert_eq!(layer.id_predicates(&ids), defaults.id_predicates(&ids));
+        assert_eq!(layer.id_objects(&ids), defaults.id_objects(&ids));
+    }
 }
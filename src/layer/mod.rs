@@ -5,13 +5,22 @@
 //! set. On top of that, each layer stores additions and removals.
 mod builder;
 mod delta;
+mod external_sort;
 mod id_map;
 mod internal;
 mod layer;
+mod scan;
 mod simple_builder;
+mod spilling_builder;
+mod statistics;
+mod stream;
 
 pub use delta::*;
 pub use id_map::*;
 pub use internal::*;
 pub use layer::*;
+pub use scan::*;
 pub use simple_builder::*;
+pub use spilling_builder::*;
+pub use statistics::*;
+pub use stream::*;
@@ -0,0 +1,319 @@
+//! Continuation tokens for resuming triple scans.
+//!
+//! Triple iterators borrow from the layer they were created from, so
+//! they cannot be kept around between requests. A `ScanToken` instead
+//! records which scan was done on which layer, and the last triple
+//! that was returned. `Layer::triples_after` uses it to seek straight
+//! to the next triple, without going over the triples before it.
+//!
+//! Tokens can be converted to and from strings. These strings are
+//! meant to be handed out as opaque page tokens.
+use super::layer::IdTriple;
+use crate::storage::{name_to_string, string_to_name};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// One of the triple scans of a layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TripleScan {
+    /// All triples, as returned by `Layer::triples`.
+    All,
+    /// The triples with the given subject, as returned by `Layer::triples_s`.
+    S(u64),
+    /// The triples with the given subject and predicate, as returned by `Layer::triples_sp`.
+    Sp(u64, u64),
+    /// The triples with the given predicate, as returned by `Layer::triples_p`.
+    P(u64),
+    /// The triples with the given object, as returned by `Layer::triples_o`.
+    O(u64),
+    /// The triples with the given predicate and object, as returned by `Layer::triples_po`.
+    Po(u64, u64),
+}
+
+impl TripleScan {
+    /// Returns true if the given triple is part of this scan.
+    pub fn contains(&self, triple: &IdTriple) -> bool {
+        match *self {
+            TripleScan::All => true,
+            TripleScan::S(subject) => triple.subject == subject,
+            TripleScan::Sp(subject, predicate) => {
+                triple.subject == subject && triple.predicate == predicate
+            }
+            TripleScan::P(predicate) => triple.predicate == predicate,
+            TripleScan::O(object) => triple.object == object,
+            TripleScan::Po(predicate, object) => {
+                triple.predicate == predicate && triple.object == object
+            }
+        }
+    }
+
+    /// Returns true if the given triple comes before or at the given position, in the order of this scan.
+    pub(crate) fn not_after(&self, triple: &IdTriple, position: &IdTriple) -> bool {
+        match self {
+            TripleScan::O(_) | TripleScan::Po(_, _) => {
+                (triple.object, triple.subject, triple.predicate)
+                    <= (position.object, position.subject, position.predicate)
+            }
+            _ => triple <= position,
+        }
+    }
+
+    /// Returns true if the given triple, which comes after the start of the scan, is not past the end of the scan.
+    pub(crate) fn within(&self, triple: &IdTriple) -> bool {
+        match *self {
+            TripleScan::All | TripleScan::P(_) => true,
+            TripleScan::S(subject) => triple.subject == subject,
+            TripleScan::Sp(subject, predicate) => {
+                triple.subject == subject && triple.predicate == predicate
+            }
+            TripleScan::O(object) | TripleScan::Po(_, object) => triple.object == object,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            TripleScan::All => "all",
+            TripleScan::S(_) => "s",
+            TripleScan::Sp(_, _) => "sp",
+            TripleScan::P(_) => "p",
+            TripleScan::O(_) => "o",
+            TripleScan::Po(_, _) => "po",
+        }
+    }
+
+    fn from_kind(kind: &str, last: &IdTriple) -> Option<Self> {
+        match kind {
+            "all" => Some(TripleScan::All),
+            "s" => Some(TripleScan::S(last.subject)),
+            "sp" => Some(TripleScan::Sp(last.subject, last.predicate)),
+            "p" => Some(TripleScan::P(last.predicate)),
+            "o" => Some(TripleScan::O(last.object)),
+            "po" => Some(TripleScan::Po(last.predicate, last.object)),
+            _ => None,
+        }
+    }
+}
+
+/// The position of a triple scan, from which a later scan on the same layer can resume.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanToken {
+    layer: [u32; 5],
+    scan: TripleScan,
+    last: IdTriple,
+}
+
+impl ScanToken {
+    /// Create a token for a scan on the given layer that stopped after returning the given triple.
+    pub fn new(layer: [u32; 5], scan: TripleScan, last: IdTriple) -> Result<Self, ScanTokenError> {
+        if !scan.contains(&last) {
+            return Err(ScanTokenError::TripleNotInScan);
+        }
+
+        Ok(ScanToken { layer, scan, last })
+    }
+
+    /// The name of the layer this token was created for.
+    pub fn layer(&self) -> [u32; 5] {
+        self.layer
+    }
+
+    /// The scan this token continues.
+    pub fn scan(&self) -> TripleScan {
+        self.scan
+    }
+
+    /// The last triple returned by the scan before this token was created.
+    pub fn last(&self) -> IdTriple {
+        self.last
+    }
+}
+
+impl fmt::Display for ScanToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}-{}-{}-{}",
+            name_to_string(self.layer),
+            self.scan.kind(),
+            self.last.subject,
+            self.last.predicate,
+            self.last.object
+        )
+    }
+}
+
+impl FromStr for ScanToken {
+    type Err = ScanTokenError;
+
+    fn from_str(s: &str) -> Result<Self, ScanTokenError> {
+        let parts: Vec<_> = s.split('-').collect();
+        if parts.len() != 5 {
+            return Err(ScanTokenError::Malformed);
+        }
+
+        let layer = string_to_name(parts[0]).map_err(|_| ScanTokenError::Malformed)?;
+        let mut ids = [0; 3];
+        for (id, part) in ids.iter_mut().zip(&parts[2..]) {
+            *id = part.parse().map_err(|_| ScanTokenError::Malformed)?;
+        }
+        let last = IdTriple::new(ids[0], ids[1], ids[2]);
+        let scan = TripleScan::from_kind(parts[1], &last).ok_or(ScanTokenError::Malformed)?;
+
+        ScanToken::new(layer, scan, last)
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ScanTokenError {
+    #[error("scan token is malformed")]
+    Malformed,
+    #[error("last triple of the scan token is not part of its scan")]
+    TripleNotInScan,
+    #[error("scan token was created for a different layer")]
+    WrongLayer,
+}
+
+impl From<ScanTokenError> for io::Error {
+    fn from(err: ScanTokenError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::child::tests::layer_stack_from_triples;
+    use crate::layer::*;
+    use std::sync::Arc;
+
+    async fn example_layer() -> Arc<InternalLayer> {
+        let mut base = Vec::new();
+        let mut additions = Vec::new();
+        let mut removals = Vec::new();
+        for i in 0..20 {
+            for j in 0..4 {
+                base.push(StringTriple::new_node(
+                    &format!("s{}", i),
+                    &format!("p{}", j),
+                    &format!("s{}", (i + j) % 5),
+                ));
+            }
+            base.push(StringTriple::new_value(
+                &format!("s{}", i),
+                "p0",
+                &format!("{}", i % 3),
+            ));
+            if i % 3 == 0 {
+                removals.push(StringTriple::new_node(
+                    &format!("s{}", i),
+                    "p1",
+                    &format!("s{}", (i + 1) % 5),
+                ));
+            }
+            additions.push(StringTriple::new_node(&format!("s{}", i), "p4", "s2"));
+        }
+
+        layer_stack_from_triples(base, additions, removals).await
+    }
+
+    fn paged(layer: &InternalLayer, scan: TripleScan, page_size: usize) -> Vec<IdTriple> {
+        let mut result = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let page: Vec<_> = match token {
+                None => layer.triples_scan(scan).take(page_size).collect(),
+                Some(token) => layer
+                    .triples_after(&token.parse().unwrap())
+                    .unwrap()
+                    .take(page_size)
+                    .collect(),
+            };
+            match page.last() {
+                None => return result,
+                Some(last) => {
+                    token = Some(
+                        ScanToken::new(Layer::name(layer), scan, *last)
+                            .unwrap()
+                            .to_string(),
+                    )
+                }
+            }
+            result.extend(page);
+        }
+    }
+
+    #[tokio::test]
+    async fn resume_every_scan() {
+        let layer = example_layer().await;
+        let s3 = layer.subject_id("s3").unwrap();
+        let p0 = layer.predicate_id("p0").unwrap();
+        let p1 = layer.predicate_id("p1").unwrap();
+        let p4 = layer.predicate_id("p4").unwrap();
+        let o2 = layer.object_node_id("s2").unwrap();
+
+        let scans = vec![
+            TripleScan::All,
+            TripleScan::S(s3),
+            TripleScan::Sp(s3, p0),
+            TripleScan::P(p1),
+            TripleScan::P(p4),
+            TripleScan::O(o2),
+            TripleScan::Po(p1, o2),
+        ];
+        for scan in scans {
+            let expected: Vec<_> = layer.triples_scan(scan).collect();
+            assert!(!expected.is_empty());
+            for page_size in 1..4 {
+                assert_eq!(expected, paged(&layer, scan, page_size));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn resume_on_other_layer_fails() {
+        let layer = example_layer().await;
+        let token =
+            ScanToken::new([1, 2, 3, 4, 5], TripleScan::All, IdTriple::new(1, 1, 1)).unwrap();
+
+        assert_eq!(
+            ScanTokenError::WrongLayer,
+            layer.triples_after(&token).err().unwrap()
+        );
+    }
+
+    #[test]
+    fn token_roundtrip() {
+        let token = ScanToken::new(
+            [1, 2, 3, 4, 5],
+            TripleScan::Po(7, 42),
+            IdTriple::new(3, 7, 42),
+        )
+        .unwrap();
+
+        let parsed: ScanToken = token.to_string().parse().unwrap();
+        assert_eq!(token, parsed);
+    }
+
+    #[test]
+    fn reject_bad_tokens() {
+        assert_eq!(
+            Err(ScanTokenError::TripleNotInScan),
+            ScanToken::new([1, 2, 3, 4, 5], TripleScan::S(2), IdTriple::new(3, 7, 42))
+        );
+
+        let token = ScanToken::new([1, 2, 3, 4, 5], TripleScan::All, IdTriple::new(3, 7, 42))
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            Err(ScanTokenError::Malformed),
+            token.replace("-all-", "-q-").parse::<ScanToken>()
+        );
+        assert_eq!(
+            Err(ScanTokenError::Malformed),
+            token.replace("-42", "-x").parse::<ScanToken>()
+        );
+        assert_eq!(Err(ScanTokenError::Malformed), "".parse::<ScanToken>());
+    }
+}
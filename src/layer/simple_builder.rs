@@ -124,42 +124,8 @@ impl<F: 'static + FileLoad + FileStore + Clone> LayerBuilder for SimpleLayerBuil
         } = self;
 
         let (mut additions, mut removals) = rayon::join(
-            || {
-                let mut additions: Vec<_> = match parent.as_ref() {
-                    None => additions
-                        .into_iter()
-                        .map(|triple| triple.to_unresolved())
-                        .collect(),
-                    Some(parent) => additions
-                        .into_par_iter()
-                        .map(move |triple| parent.string_triple_to_partially_resolved(triple))
-                        .collect(),
-                };
-
-                additions.extend(id_additions.into_iter().map(|triple| triple.to_resolved()));
-                additions.par_sort_unstable();
-                additions.dedup();
-
-                additions
-            },
-            || {
-                let mut removals: Vec<_> = match parent.as_ref() {
-                    None => removals
-                        .into_iter()
-                        .map(|triple| triple.to_unresolved())
-                        .collect(),
-                    Some(parent) => removals
-                        .into_par_iter()
-                        .map(move |triple| parent.string_triple_to_partially_resolved(triple))
-                        .collect(),
-                };
-
-                removals.extend(id_removals.into_iter().map(|triple| triple.to_resolved()));
-                removals.par_sort_unstable();
-                removals.dedup();
-
-                removals
-            },
+            || resolve_triples(parent.as_ref(), additions, id_additions),
+            || resolve_triples(parent.as_ref(), removals, id_removals),
         );
 
         // there's now a sorted list of additions and a sorted list of
@@ -282,6 +248,32 @@ impl<F: 'static + FileLoad + FileStore + Clone> LayerBuilder for SimpleLayerBuil
     }
 }
 
+/// Convert the given string and id triples into a sorted and
+/// deduplicated list of partially resolved triples, resolving as much
+/// as possible using the parent.
+pub(crate) fn resolve_triples(
+    parent: Option<&Arc<dyn Layer>>,
+    triples: Vec<StringTriple>,
+    id_triples: Vec<IdTriple>,
+) -> Vec<PartiallyResolvedTriple> {
+    let mut resolved: Vec<_> = match parent {
+        None => triples
+            .into_iter()
+            .map(|triple| triple.to_unresolved())
+            .collect(),
+        Some(parent) => triples
+            .into_par_iter()
+            .map(move |triple| parent.string_triple_to_partially_resolved(triple))
+            .collect(),
+    };
+
+    resolved.extend(id_triples.into_iter().map(|triple| triple.to_resolved()));
+    resolved.par_sort_unstable();
+    resolved.dedup();
+
+    resolved
+}
+
 fn zero_equivalents(
     additions: &mut [PartiallyResolvedTriple],
     removals: &mut [PartiallyResolvedTriple],
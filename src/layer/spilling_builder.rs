@@ -0,0 +1,681 @@
+//! memory-bounded builder frontend for constructing new layers
+//!
+//! `SimpleLayerBuilder` keeps every added and removed triple in
+//! memory until commit, which is not an option for very large
+//! layers. The layer builder implemented here keeps track of an
+//! estimate of how much memory its buffered triples take up. Once a
+//! configurable limit is hit, the buffered triples are resolved
+//! against the parent, sorted, and written to a temporary file as a
+//! sorted run.
+//!
+//! On commit, these runs are merged and streamed into the dictionary
+//! builders and triple builders of `BaseLayerFileBuilder` or
+//! `ChildLayerFileBuilder`. Any intermediate data that might not fit
+//! in memory (unknown strings, and the final id triples) goes through
+//! an external sort as well.
+//!
+//! If the limit is never reached, committing works exactly like
+//! committing a `SimpleLayerBuilder`.
+use super::external_sort::*;
+use super::internal::*;
+use super::layer::*;
+use super::simple_builder::*;
+use crate::storage::*;
+use crate::structure::PfcDict;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::future::{self, Future};
+use tokio::sync::mpsc;
+
+/// A layer builder which spills to temporary files once its memory limit is hit.
+///
+/// If spilling fails, all further triples are dropped, and the error
+/// is returned on commit.
+pub struct SpillingLayerBuilder<F: 'static + FileLoad + FileStore + Clone> {
+    name: [u32; 5],
+    parent: Option<Arc<dyn Layer>>,
+    files: LayerFiles<F>,
+    memory_limit: usize,
+    temp_dir: Option<PathBuf>,
+    additions: Vec<StringTriple>,
+    id_additions: Vec<IdTriple>,
+    removals: Vec<StringTriple>,
+    id_removals: Vec<IdTriple>,
+    buffered: usize,
+    addition_runs: ExternalSorter<PartiallyResolvedTriple>,
+    removal_runs: ExternalSorter<PartiallyResolvedTriple>,
+    error: Option<io::Error>,
+}
+
+impl<F: 'static + FileLoad + FileStore + Clone> SpillingLayerBuilder<F> {
+    fn new_internal(
+        name: [u32; 5],
+        parent: Option<Arc<dyn Layer>>,
+        files: LayerFiles<F>,
+        memory_limit: usize,
+    ) -> Self {
+        Self {
+            name,
+            parent,
+            files,
+            memory_limit,
+            temp_dir: None,
+            additions: Vec::new(),
+            id_additions: Vec::new(),
+            removals: Vec::new(),
+            id_removals: Vec::new(),
+            buffered: 0,
+            addition_runs: ExternalSorter::new(memory_limit, None),
+            removal_runs: ExternalSorter::new(memory_limit, None),
+            error: None,
+        }
+    }
+
+    /// Construct a layer builder for a base layer.
+    ///
+    /// `memory_limit` is the approximate amount of bytes of triple
+    /// data this builder keeps in memory before spilling.
+    pub fn new(name: [u32; 5], files: BaseLayerFiles<F>, memory_limit: usize) -> Self {
+        Self::new_internal(name, None, LayerFiles::Base(files), memory_limit)
+    }
+
+    /// Construct a layer builder for a child layer.
+    ///
+    /// `memory_limit` is the approximate amount of bytes of triple
+    /// data this builder keeps in memory before spilling.
+    pub fn from_parent(
+        name: [u32; 5],
+        parent: Arc<dyn Layer>,
+        files: ChildLayerFiles<F>,
+        memory_limit: usize,
+    ) -> Self {
+        Self::new_internal(name, Some(parent), LayerFiles::Child(files), memory_limit)
+    }
+
+    /// Create temporary files in the given directory, rather than in the system's default temporary directory.
+    pub fn with_temp_dir<P: Into<PathBuf>>(mut self, temp_dir: P) -> Self {
+        let temp_dir = temp_dir.into();
+        self.addition_runs.set_temp_dir(Some(temp_dir.clone()));
+        self.removal_runs.set_temp_dir(Some(temp_dir.clone()));
+        self.temp_dir = Some(temp_dir);
+
+        self
+    }
+
+    /// Returns true if this builder has written any triples to temporary files.
+    pub fn has_spilled(&self) -> bool {
+        self.addition_runs.has_spilled() || self.removal_runs.has_spilled()
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        let additions = std::mem::take(&mut self.additions);
+        let id_additions = std::mem::take(&mut self.id_additions);
+        let removals = std::mem::take(&mut self.removals);
+        let id_removals = std::mem::take(&mut self.id_removals);
+        self.buffered = 0;
+
+        let parent = self.parent.as_ref();
+        let (additions, removals) = rayon::join(
+            || resolve_triples(parent, additions, id_additions),
+            || resolve_triples(parent, removals, id_removals),
+        );
+
+        self.addition_runs.write_run(additions)?;
+        self.removal_runs.write_run(removals)
+    }
+
+    fn spill_if_needed(&mut self) {
+        if self.buffered >= self.memory_limit && self.error.is_none() {
+            if let Err(e) = self.spill() {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    fn into_simple_builder(self) -> SimpleLayerBuilder<F> {
+        let mut builder = match self.parent {
+            None => SimpleLayerBuilder::new(self.name, self.files.into_base()),
+            Some(parent) => {
+                SimpleLayerBuilder::from_parent(self.name, parent, self.files.into_child())
+            }
+        };
+
+        for triple in self.additions {
+            builder.add_string_triple(triple);
+        }
+        for triple in self.id_additions {
+            builder.add_id_triple(triple);
+        }
+        for triple in self.removals {
+            builder.remove_string_triple(triple);
+        }
+        for triple in self.id_removals {
+            builder.remove_id_triple(triple);
+        }
+
+        builder
+    }
+
+    /// Merge all runs, crossing off triples that are both added and removed.
+    ///
+    /// This reads and writes temporary files, so it has to run on the
+    /// blocking thread pool.
+    fn merge_runs(mut self) -> io::Result<MergedSpill<F>> {
+        self.spill()?;
+
+        let SpillingLayerBuilder {
+            parent,
+            files,
+            memory_limit,
+            temp_dir,
+            addition_runs,
+            removal_runs,
+            ..
+        } = self;
+
+        let mut additions = RunWriter::new(temp_dir.as_deref())?;
+        let mut removals = RunWriter::new(temp_dir.as_deref())?;
+        let mut nodes = ExternalSorter::new(memory_limit / 3, temp_dir.clone());
+        let mut predicates = ExternalSorter::new(memory_limit / 3, temp_dir.clone());
+        let mut values = ExternalSorter::new(memory_limit / 3, temp_dir.clone());
+
+        // Both merged streams are sorted, so we can walk them side by
+        // side to cross off triples that are both added and removed
+        // in this builder. Removals that aren't fully resolved at
+        // this point refer to triples the parent doesn't know about,
+        // so those are no-ops as well. A base layer has no use for
+        // removals at all.
+        let mut keep_removal = |removal: PartiallyResolvedTriple| -> io::Result<()> {
+            match (parent.is_some(), removal.as_resolved()) {
+                (true, Some(removal)) => removals.push(&removal),
+                _ => Ok(()),
+            }
+        };
+
+        let mut removal_iter = removal_runs.finish()?;
+        let mut next_removal = removal_iter.next().transpose()?;
+        for addition in addition_runs.finish()? {
+            let addition = addition?;
+            while matches!(&next_removal, Some(r) if *r < addition) {
+                keep_removal(next_removal.take().unwrap())?;
+                next_removal = removal_iter.next().transpose()?;
+            }
+
+            if next_removal.as_ref() == Some(&addition) {
+                next_removal = removal_iter.next().transpose()?;
+                continue;
+            }
+
+            if let PossiblyResolved::Unresolved(subject) = &addition.subject {
+                nodes.push(subject.clone())?;
+            }
+            if let PossiblyResolved::Unresolved(predicate) = &addition.predicate {
+                predicates.push(predicate.clone())?;
+            }
+            match &addition.object {
+                PossiblyResolved::Unresolved(ObjectType::Node(node)) => nodes.push(node.clone())?,
+                PossiblyResolved::Unresolved(ObjectType::Value(value)) => {
+                    values.push(value.clone())?
+                }
+                PossiblyResolved::Resolved(_) => {}
+            }
+
+            additions.push(&addition)?;
+        }
+        while let Some(removal) = next_removal {
+            keep_removal(removal)?;
+            next_removal = removal_iter.next().transpose()?;
+        }
+
+        Ok(MergedSpill {
+            parent,
+            files,
+            memory_limit,
+            temp_dir,
+            nodes,
+            predicates,
+            values,
+            additions: additions.finish()?,
+            removals: removals.finish()?,
+        })
+    }
+
+    async fn commit_spilled(self) -> io::Result<()> {
+        let MergedSpill {
+            parent,
+            files,
+            memory_limit,
+            temp_dir,
+            nodes,
+            predicates,
+            values,
+            additions,
+            removals,
+        } = tokio::task::spawn_blocking(move || self.merge_runs()).await??;
+
+        let mut nodes = read_blocking(move || nodes.finish());
+        let mut predicates = read_blocking(move || predicates.finish());
+        let mut values = read_blocking(move || values.finish());
+
+        // time to build things
+        match parent {
+            Some(parent) => {
+                let files = files.into_child();
+                let mut builder = ChildLayerFileBuilder::from_files(parent.clone(), &files);
+
+                while let Some(batch) = nodes.recv().await {
+                    for node in batch? {
+                        builder.add_node(&node).await?;
+                    }
+                }
+                while let Some(batch) = predicates.recv().await {
+                    for predicate in batch? {
+                        builder.add_predicate(&predicate).await?;
+                    }
+                }
+                while let Some(batch) = values.recv().await {
+                    for value in batch? {
+                        builder.add_value(&value).await?;
+                    }
+                }
+
+                let mut builder = builder.into_phase2().await?;
+
+                let counts = parent.all_counts();
+                let resolver = DictionaryResolver::load(
+                    &files.node_dictionary_files,
+                    &files.predicate_dictionary_files,
+                    &files.value_dictionary_files,
+                    counts.node_count as u64 + counts.value_count as u64,
+                    counts.predicate_count as u64,
+                )
+                .await?;
+
+                let mut additions =
+                    read_blocking(move || resolver.resolve_all(additions, memory_limit, temp_dir));
+                while let Some(batch) = additions.recv().await {
+                    for triple in batch? {
+                        builder
+                            .add_triple(triple.subject, triple.predicate, triple.object)
+                            .await?;
+                    }
+                }
+                let mut removals = read_blocking(move || Ok(removals.read::<IdTriple>()));
+                while let Some(batch) = removals.recv().await {
+                    for triple in batch? {
+                        builder
+                            .remove_triple(triple.subject, triple.predicate, triple.object)
+                            .await?;
+                    }
+                }
+
+                builder.finalize().await
+            }
+            None => {
+                let files = files.into_base();
+                let mut builder = BaseLayerFileBuilder::from_files(&files);
+
+                while let Some(batch) = nodes.recv().await {
+                    for node in batch? {
+                        builder.add_node(&node).await?;
+                    }
+                }
+                while let Some(batch) = predicates.recv().await {
+                    for predicate in batch? {
+                        builder.add_predicate(&predicate).await?;
+                    }
+                }
+                while let Some(batch) = values.recv().await {
+                    for value in batch? {
+                        builder.add_value(&value).await?;
+                    }
+                }
+
+                let mut builder = builder.into_phase2().await?;
+
+                let resolver = DictionaryResolver::load(
+                    &files.node_dictionary_files,
+                    &files.predicate_dictionary_files,
+                    &files.value_dictionary_files,
+                    0,
+                    0,
+                )
+                .await?;
+
+                let mut additions =
+                    read_blocking(move || resolver.resolve_all(additions, memory_limit, temp_dir));
+                while let Some(batch) = additions.recv().await {
+                    for triple in batch? {
+                        builder
+                            .add_triple(triple.subject, triple.predicate, triple.object)
+                            .await?;
+                    }
+                }
+
+                builder.finalize().await
+            }
+        }
+    }
+}
+
+/// Everything left of a spilling builder once its runs have been merged.
+struct MergedSpill<F: 'static + FileLoad + FileStore + Clone> {
+    parent: Option<Arc<dyn Layer>>,
+    files: LayerFiles<F>,
+    memory_limit: usize,
+    temp_dir: Option<PathBuf>,
+    nodes: ExternalSorter<String>,
+    predicates: ExternalSorter<String>,
+    values: ExternalSorter<String>,
+    additions: Run,
+    removals: Run,
+}
+
+/// The amount of items handed over at once from the blocking thread pool to the layer file builders.
+const READ_BATCH_SIZE: usize = 1024;
+
+/// Read the items of an iterator over temporary files on the blocking thread pool, handing them over in batches.
+///
+/// The iterator is opened on the blocking thread pool as well. The
+/// first error ends the reading.
+fn read_blocking<T, I, O>(open: O) -> mpsc::Receiver<io::Result<Vec<T>>>
+where
+    T: 'static + Send,
+    I: Iterator<Item = io::Result<T>>,
+    O: 'static + FnOnce() -> io::Result<I> + Send,
+{
+    let (sender, receiver) = mpsc::channel(1);
+    tokio::task::spawn_blocking(move || {
+        let mut iter = match open() {
+            Ok(iter) => iter,
+            Err(e) => {
+                let _ = sender.blocking_send(Err(e));
+                return;
+            }
+        };
+
+        loop {
+            let batch: io::Result<Vec<T>> = iter.by_ref().take(READ_BATCH_SIZE).collect();
+            match batch {
+                Ok(batch) if batch.is_empty() => break,
+                Ok(batch) => {
+                    if sender.blocking_send(Ok(batch)).is_err() {
+                        // the commit was abandoned
+                        break;
+                    }
+                }
+                Err(e) => {
+                    let _ = sender.blocking_send(Err(e));
+                    break;
+                }
+            }
+        }
+    });
+
+    receiver
+}
+
+fn string_triple_size(triple: &StringTriple) -> usize {
+    let object_len = match &triple.object {
+        ObjectType::Node(o) | ObjectType::Value(o) => o.len(),
+    };
+
+    std::mem::size_of::<StringTriple>() + triple.subject.len() + triple.predicate.len() + object_len
+}
+
+impl<F: 'static + FileLoad + FileStore + Clone> LayerBuilder for SpillingLayerBuilder<F> {
+    fn name(&self) -> [u32; 5] {
+        self.name
+    }
+
+    fn parent(&self) -> Option<Arc<dyn Layer>> {
+        self.parent.clone()
+    }
+
+    fn add_string_triple(&mut self, triple: StringTriple) {
+        if self.error.is_some() {
+            return;
+        }
+        self.buffered += string_triple_size(&triple);
+        self.additions.push(triple);
+        self.spill_if_needed();
+    }
+
+    fn add_id_triple(&mut self, triple: IdTriple) {
+        if self.error.is_some() {
+            return;
+        }
+        self.buffered += std::mem::size_of::<IdTriple>();
+        self.id_additions.push(triple);
+        self.spill_if_needed();
+    }
+
+    fn remove_string_triple(&mut self, triple: StringTriple) {
+        if self.error.is_some() {
+            return;
+        }
+        self.buffered += string_triple_size(&triple);
+        self.removals.push(triple);
+        self.spill_if_needed();
+    }
+
+    fn remove_id_triple(&mut self, triple: IdTriple) {
+        if self.error.is_some() {
+            return;
+        }
+        self.buffered += std::mem::size_of::<IdTriple>();
+        self.id_removals.push(triple);
+        self.spill_if_needed();
+    }
+
+    fn commit(mut self) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        if let Some(e) = self.error.take() {
+            return Box::pin(future::err(e));
+        }
+
+        if !self.has_spilled() {
+            return self.into_simple_builder().commit();
+        }
+
+        Box::pin(self.commit_spilled())
+    }
+
+    fn commit_boxed(self: Box<Self>) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        let builder = *self;
+        builder.commit()
+    }
+}
+
+/// Resolves the strings in partially resolved triples using freshly built dictionaries.
+struct DictionaryResolver {
+    nodes: PfcDict,
+    predicates: PfcDict,
+    values: PfcDict,
+    node_offset: u64,
+    predicate_offset: u64,
+}
+
+impl DictionaryResolver {
+    async fn load<F: 'static + FileLoad + FileStore>(
+        node_files: &DictionaryFiles<F>,
+        predicate_files: &DictionaryFiles<F>,
+        value_files: &DictionaryFiles<F>,
+        node_offset: u64,
+        predicate_offset: u64,
+    ) -> io::Result<Self> {
+        let node_maps = node_files.map_all().await?;
+        let predicate_maps = predicate_files.map_all().await?;
+        let value_maps = value_files.map_all().await?;
+
+        Ok(DictionaryResolver {
+            nodes: PfcDict::parse(node_maps.blocks_map, node_maps.offsets_map)?,
+            predicates: PfcDict::parse(predicate_maps.blocks_map, predicate_maps.offsets_map)?,
+            values: PfcDict::parse(value_maps.blocks_map, value_maps.offsets_map)?,
+            node_offset,
+            predicate_offset,
+        })
+    }
+
+    fn lookup(dict: &PfcDict, s: &str, offset: u64) -> io::Result<u64> {
+        dict.id(s).map(|id| id + 1 + offset).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("string {:?} missing from layer dictionary", s),
+            )
+        })
+    }
+
+    fn resolve(&self, triple: &PartiallyResolvedTriple) -> io::Result<IdTriple> {
+        let subject = match triple.subject.as_ref() {
+            PossiblyResolved::Unresolved(s) => Self::lookup(&self.nodes, s, self.node_offset)?,
+            PossiblyResolved::Resolved(id) => id,
+        };
+        let predicate = match triple.predicate.as_ref() {
+            PossiblyResolved::Unresolved(p) => {
+                Self::lookup(&self.predicates, p, self.predicate_offset)?
+            }
+            PossiblyResolved::Resolved(id) => id,
+        };
+        let object = match triple.object.as_ref() {
+            PossiblyResolved::Unresolved(ObjectType::Node(n)) => {
+                Self::lookup(&self.nodes, n, self.node_offset)?
+            }
+            PossiblyResolved::Unresolved(ObjectType::Value(v)) => {
+                Self::lookup(&self.values, v, self.node_offset + self.nodes.len() as u64)?
+            }
+            PossiblyResolved::Resolved(id) => id,
+        };
+
+        Ok(IdTriple::new(subject, predicate, object))
+    }
+
+    /// Resolve all triples in the given run, returning them as a sorted stream of id triples.
+    fn resolve_all(
+        &self,
+        run: Run,
+        memory_limit: usize,
+        temp_dir: Option<PathBuf>,
+    ) -> io::Result<MergedRuns<IdTriple>> {
+        let mut sorter = ExternalSorter::new(memory_limit, temp_dir);
+        for triple in run.read::<PartiallyResolvedTriple>() {
+            sorter.push(self.resolve(&triple?)?)?;
+        }
+
+        sorter.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::internal::InternalLayer;
+    use crate::storage::memory::*;
+
+    async fn build_base_layer(memory_limit: usize) -> Arc<InternalLayer> {
+        let name = [1, 2, 3, 4, 5];
+        let files = base_layer_memory_files();
+        let mut builder = SpillingLayerBuilder::new(name, files.clone(), memory_limit);
+
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.add_string_triple(StringTriple::new_value("pig", "says", "oink"));
+        builder.add_string_triple(StringTriple::new_value("duck", "says", "quack"));
+        builder.add_string_triple(StringTriple::new_node("cow", "likes", "duck"));
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.add_string_triple(StringTriple::new_value("crow", "says", "caw"));
+        builder.remove_string_triple(StringTriple::new_value("crow", "says", "caw"));
+        assert_eq!(memory_limit == 0, builder.has_spilled());
+
+        builder.commit().await.unwrap();
+
+        let layer = BaseLayer::load_from_files(name, &files).await.unwrap();
+        Arc::new(layer.into())
+    }
+
+    fn string_triples(layer: &InternalLayer) -> Vec<StringTriple> {
+        layer
+            .triples()
+            .map(|t| layer.id_triple_to_string(&t).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn spilled_base_layer_matches_in_memory_base_layer() {
+        let spilled = build_base_layer(0).await;
+        let in_memory = build_base_layer(usize::MAX).await;
+
+        assert_eq!(string_triples(&in_memory), string_triples(&spilled));
+        assert_eq!(4, spilled.triple_count());
+        assert!(spilled.string_triple_exists(&StringTriple::new_node("cow", "likes", "duck")));
+        assert!(!spilled.string_triple_exists(&StringTriple::new_value("crow", "says", "caw")));
+    }
+
+    async fn build_child_layer(
+        base_layer: Arc<InternalLayer>,
+        memory_limit: usize,
+    ) -> Arc<InternalLayer> {
+        let name = [0, 0, 0, 0, 1];
+        let files = child_layer_memory_files();
+        let cow = base_layer.subject_id("cow").unwrap();
+        let says = base_layer.predicate_id("says").unwrap();
+        let moo = base_layer.object_value_id("moo").unwrap();
+        let mut builder = SpillingLayerBuilder::from_parent(
+            name,
+            base_layer.clone(),
+            files.clone(),
+            memory_limit,
+        );
+
+        builder.add_string_triple(StringTriple::new_value("horse", "says", "neigh"));
+        builder.add_string_triple(StringTriple::new_node("horse", "likes", "cow"));
+        builder.add_string_triple(StringTriple::new_node("cow", "hates", "horse"));
+        builder.remove_string_triple(StringTriple::new_value("duck", "says", "quack"));
+        builder.remove_string_triple(StringTriple::new_value("crow", "says", "caw"));
+        builder.remove_id_triple(IdTriple::new(cow, says, moo));
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.remove_string_triple(StringTriple::new_value("pig", "says", "oink"));
+
+        builder.commit().await.unwrap();
+
+        let layer = ChildLayer::load_from_files(name, base_layer, &files)
+            .await
+            .unwrap();
+        Arc::new(layer.into())
+    }
+
+    #[tokio::test]
+    async fn spilled_child_layer_matches_in_memory_child_layer() {
+        let base_layer = build_base_layer(usize::MAX).await;
+        let spilled = build_child_layer(base_layer.clone(), 0).await;
+        let in_memory = build_child_layer(base_layer, usize::MAX).await;
+
+        assert_eq!(string_triples(&in_memory), string_triples(&spilled));
+        assert!(spilled.string_triple_exists(&StringTriple::new_value("horse", "says", "neigh")));
+        assert!(spilled.string_triple_exists(&StringTriple::new_node("cow", "hates", "horse")));
+        assert!(spilled.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+        assert!(!spilled.string_triple_exists(&StringTriple::new_value("duck", "says", "quack")));
+        assert!(!spilled.string_triple_exists(&StringTriple::new_value("pig", "says", "oink")));
+        assert_eq!(2, spilled.internal_triple_removals().count());
+    }
+
+    #[tokio::test]
+    async fn failed_spill_stops_buffering() {
+        let files = base_layer_memory_files();
+        let mut builder = SpillingLayerBuilder::new([1, 2, 3, 4, 5], files, 0)
+            .with_temp_dir("/nonexistent/terminus-store/temp");
+
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        assert!(builder.error.is_some());
+
+        builder.add_string_triple(StringTriple::new_value("pig", "says", "oink"));
+        builder.add_id_triple(IdTriple::new(1, 1, 1));
+        builder.remove_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.remove_id_triple(IdTriple::new(1, 1, 1));
+        assert_eq!(0, builder.buffered);
+        assert!(builder.additions.is_empty() && builder.removals.is_empty());
+
+        assert!(builder.commit().await.is_err());
+    }
+}
@@ -0,0 +1,541 @@
+//! Statistics about the contents of layers, for use in query planning.
+//!
+//! Every layer stores statistics about its own additions and removals,
+//! broken down by predicate. When statistics for a layer are requested,
+//! the statistics of all layers in its stack are combined.
+use super::layer::{IdTriple, Layer};
+use super::{object_is_value, InternalLayer};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+
+/// Statistics about the triples with a particular predicate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PredicateStatistics {
+    /// The amount of triples with this predicate.
+    pub triple_count: usize,
+    /// The amount of distinct subjects used with this predicate.
+    pub distinct_subjects: usize,
+    /// The amount of distinct objects used with this predicate.
+    pub distinct_objects: usize,
+    /// The amount of triples with this predicate whose object is a node.
+    pub node_objects: usize,
+    /// The amount of triples with this predicate whose object is a value.
+    pub value_objects: usize,
+}
+
+/// Per-predicate statistics about the additions and removals of a single layer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LayerChangeStatistics {
+    additions: BTreeMap<u64, PredicateStatistics>,
+    removals: BTreeMap<u64, PredicateStatistics>,
+}
+
+const STATISTICS_VERSION: &str = "1";
+
+impl LayerChangeStatistics {
+    /// Compute statistics from the additions and removals of a layer.
+    ///
+    /// Both iterators have to be ordered by subject, as the triple
+    /// iterators of a layer are. `layer` is the layer these changes
+    /// belong to, and `parent` its parent, if it has one.
+    ///
+    /// The distinct subject and object counts only include the
+    /// subjects and objects whose use with a predicate starts or ends
+    /// in this layer, so that they can be summed over a stack.
+    pub fn from_triples(
+        additions: impl Iterator<Item = IdTriple>,
+        removals: impl Iterator<Item = IdTriple>,
+        layer: &InternalLayer,
+        parent: Option<&InternalLayer>,
+    ) -> Self {
+        let is_value = |id| object_is_value(layer, id).unwrap_or(false);
+        Self {
+            // a subject or object is new if the parent doesn't use it with the predicate yet
+            additions: predicate_statistics(
+                additions,
+                &is_value,
+                |subject, predicate| match parent {
+                    Some(parent) => parent.triples_sp(subject, predicate).next().is_none(),
+                    None => true,
+                },
+                |triple| {
+                    let new = match parent {
+                        Some(parent) => parent
+                            .triples_po(triple.predicate, triple.object)
+                            .next()
+                            .is_none(),
+                        None => true,
+                    };
+
+                    // all triples with this predicate and object are additions now, so count only the first
+                    new && layer.triples_po(triple.predicate, triple.object).next() == Some(triple)
+                },
+            ),
+            // a subject or object is gone if this layer doesn't use it with the predicate anymore
+            removals: predicate_statistics(
+                removals,
+                &is_value,
+                |subject, predicate| layer.triples_sp(subject, predicate).next().is_none(),
+                |triple| {
+                    let gone = layer
+                        .triples_po(triple.predicate, triple.object)
+                        .next()
+                        .is_none();
+
+                    // all triples with this predicate and object in the parent are removals now, so count only the first
+                    gone && parent.and_then(|parent| {
+                        parent.triples_po(triple.predicate, triple.object).next()
+                    }) == Some(triple)
+                },
+            ),
+        }
+    }
+
+    /// Statistics about the triples added by this layer, by predicate.
+    pub fn additions(&self) -> &BTreeMap<u64, PredicateStatistics> {
+        &self.additions
+    }
+
+    /// Statistics about the triples removed by this layer, by predicate.
+    pub fn removals(&self) -> &BTreeMap<u64, PredicateStatistics> {
+        &self.removals
+    }
+
+    /// Serialize these statistics into the format of the layer statistics file.
+    ///
+    /// The format is textual. The first line is a version number,
+    /// followed by one line per predicate, starting with `+` for
+    /// additions or `-` for removals.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = format!("{}\n", STATISTICS_VERSION);
+        for (sign, statistics) in &[("+", &self.additions), ("-", &self.removals)] {
+            for (predicate, s) in statistics.iter() {
+                result.push_str(&format!(
+                    "{} {} {} {} {} {} {}\n",
+                    sign,
+                    predicate,
+                    s.triple_count,
+                    s.distinct_subjects,
+                    s.distinct_objects,
+                    s.node_objects,
+                    s.value_objects
+                ));
+            }
+        }
+
+        result.into_bytes()
+    }
+
+    /// Parse statistics from the contents of a layer statistics file.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let s = std::str::from_utf8(bytes)
+            .map_err(|e| invalid(format!("statistics file is not valid utf-8: {}", e)))?;
+        let mut lines = s.lines();
+        match lines.next() {
+            Some(STATISTICS_VERSION) => {}
+            version => {
+                return Err(invalid(format!(
+                    "unsupported statistics file version {:?}",
+                    version
+                )))
+            }
+        }
+
+        let mut result = Self::default();
+        for line in lines {
+            let fields: Vec<&str> = line.split(' ').collect();
+            let numbers: Vec<u64> = fields
+                .iter()
+                .skip(1)
+                .map(|n| n.parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| invalid(format!("invalid statistics line {:?}", line)))?;
+            if numbers.len() != 6 {
+                return Err(invalid(format!("invalid statistics line {:?}", line)));
+            }
+
+            let statistics = PredicateStatistics {
+                triple_count: numbers[1] as usize,
+                distinct_subjects: numbers[2] as usize,
+                distinct_objects: numbers[3] as usize,
+                node_objects: numbers[4] as usize,
+                value_objects: numbers[5] as usize,
+            };
+            match fields[0] {
+                "+" => result.additions.insert(numbers[0], statistics),
+                "-" => result.removals.insert(numbers[0], statistics),
+                _ => return Err(invalid(format!("invalid statistics line {:?}", line))),
+            };
+        }
+
+        Ok(result)
+    }
+}
+
+fn predicate_statistics<F: Fn(u64) -> bool>(
+    triples: impl Iterator<Item = IdTriple>,
+    is_value: &F,
+    counts_subject: impl Fn(u64, u64) -> bool,
+    counts_object: impl Fn(IdTriple) -> bool,
+) -> BTreeMap<u64, PredicateStatistics> {
+    let mut result: BTreeMap<u64, PredicateStatistics> = BTreeMap::new();
+    let mut last_subjects: HashMap<u64, u64> = HashMap::new();
+    for triple in triples {
+        let statistics = result.entry(triple.predicate).or_default();
+        statistics.triple_count += 1;
+
+        // triples are ordered by subject, so every subject only comes by once per predicate
+        if last_subjects.insert(triple.predicate, triple.subject) != Some(triple.subject)
+            && counts_subject(triple.subject, triple.predicate)
+        {
+            statistics.distinct_subjects += 1;
+        }
+
+        // objects are not ordered, so it is up to counts_object to count each of them once
+        if counts_object(triple) {
+            statistics.distinct_objects += 1;
+        }
+
+        if is_value(triple.object) {
+            statistics.value_objects += 1;
+        } else {
+            statistics.node_objects += 1;
+        }
+    }
+
+    result
+}
+
+/// Statistics about a layer and all its ancestors.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerStatistics {
+    stack_depth: usize,
+    loaded_depth: usize,
+    rolled_up: bool,
+    triple_count: usize,
+    predicates: BTreeMap<u64, PredicateStatistics>,
+}
+
+impl LayerStatistics {
+    /// Combine the statistics of every layer in a stack, ordered from the base layer up, into statistics for the top layer.
+    ///
+    /// This returns an error if a layer removes more than its stack
+    /// holds, which means the stored statistics are corrupt.
+    pub(crate) fn combine(
+        changes: &[LayerChangeStatistics],
+        layer: &InternalLayer,
+    ) -> io::Result<Self> {
+        let mut predicates: BTreeMap<u64, PredicateStatistics> = BTreeMap::new();
+        for change in changes {
+            for (predicate, added) in change.additions.iter() {
+                let statistics = predicates.entry(*predicate).or_default();
+                statistics.triple_count += added.triple_count;
+                statistics.distinct_subjects += added.distinct_subjects;
+                statistics.distinct_objects += added.distinct_objects;
+                statistics.node_objects += added.node_objects;
+                statistics.value_objects += added.value_objects;
+            }
+
+            for (predicate, removed) in change.removals.iter() {
+                // a layer only removes triples that exist in its parent
+                let statistics = predicates.entry(*predicate).or_default();
+                let subtract = |count: usize, removed: usize| {
+                    count.checked_sub(removed).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "statistics remove more triples with predicate {} than there are",
+                                predicate
+                            ),
+                        )
+                    })
+                };
+                statistics.triple_count = subtract(statistics.triple_count, removed.triple_count)?;
+                statistics.distinct_subjects =
+                    subtract(statistics.distinct_subjects, removed.distinct_subjects)?;
+                statistics.distinct_objects =
+                    subtract(statistics.distinct_objects, removed.distinct_objects)?;
+                statistics.node_objects = subtract(statistics.node_objects, removed.node_objects)?;
+                statistics.value_objects =
+                    subtract(statistics.value_objects, removed.value_objects)?;
+            }
+        }
+
+        predicates.retain(|_, statistics| statistics.triple_count != 0);
+
+        let mut loaded_depth = 1;
+        let mut parent = layer.immediate_parent();
+        while let Some(p) = parent {
+            loaded_depth += 1;
+            parent = p.immediate_parent();
+        }
+
+        Ok(Self {
+            stack_depth: changes.len(),
+            loaded_depth,
+            rolled_up: layer.is_rollup(),
+            triple_count: predicates.values().map(|s| s.triple_count).sum(),
+            predicates,
+        })
+    }
+
+    /// The amount of layers in the stack, including this layer.
+    pub fn stack_depth(&self) -> usize {
+        self.stack_depth
+    }
+
+    /// The amount of layers that queries have to go through.
+    ///
+    /// This is the same as the stack depth, unless part of the stack
+    /// has been rolled up.
+    pub fn loaded_depth(&self) -> usize {
+        self.loaded_depth
+    }
+
+    /// Returns true if this layer is loaded from a rollup.
+    pub fn is_rolled_up(&self) -> bool {
+        self.rolled_up
+    }
+
+    /// The amount of triples in this layer stack.
+    pub fn triple_count(&self) -> usize {
+        self.triple_count
+    }
+
+    /// Statistics for the given predicate, or None if no triples use it.
+    pub fn predicate(&self, predicate: u64) -> Option<&PredicateStatistics> {
+        self.predicates.get(&predicate)
+    }
+
+    /// Iterator over the statistics of every predicate in use, ordered by predicate id.
+    pub fn predicates(&self) -> impl Iterator<Item = (u64, &PredicateStatistics)> {
+        self.predicates.iter().map(|(p, s)| (*p, s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::{InternalLayerImpl, Layer, StringTriple};
+    use crate::storage::memory::MemoryLayerStore;
+    use crate::storage::LayerStore;
+    use crate::store::sync::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn change_statistics_roundtrip() {
+        let store = MemoryLayerStore::new();
+        let mut builder = store.create_base_layer().await.unwrap();
+        let base_name = builder.name();
+        builder.add_string_triple(StringTriple::new_node("cow", "likes", "duck"));
+        builder.add_string_triple(StringTriple::new_node("cow", "likes", "horse"));
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.add_string_triple(StringTriple::new_node("duck", "likes", "duck"));
+        builder.add_string_triple(StringTriple::new_value("calf", "says", "moo"));
+        builder.commit_boxed().await.unwrap();
+
+        let mut builder = store.create_child_layer(base_name).await.unwrap();
+        let child_name = builder.name();
+        builder.remove_string_triple(StringTriple::new_value("calf", "says", "moo"));
+        builder.commit_boxed().await.unwrap();
+
+        let base = store.get_layer(base_name).await.unwrap().unwrap();
+        let child = store.get_layer(child_name).await.unwrap().unwrap();
+        let statistics = LayerChangeStatistics::from_triples(
+            base.internal_triple_additions(),
+            base.internal_triple_removals(),
+            &base,
+            None,
+        );
+
+        let likes = base.predicate_id("likes").unwrap();
+        assert_eq!(
+            Some(&PredicateStatistics {
+                triple_count: 3,
+                distinct_subjects: 2,
+                distinct_objects: 2,
+                node_objects: 3,
+                value_objects: 0,
+            }),
+            statistics.additions().get(&likes)
+        );
+        assert!(statistics.removals().is_empty());
+
+        // the cow still says moo, so the object stays in use
+        let says = base.predicate_id("says").unwrap();
+        let child_statistics = LayerChangeStatistics::from_triples(
+            child.internal_triple_additions(),
+            child.internal_triple_removals(),
+            &child,
+            Some(&base),
+        );
+        assert_eq!(
+            Some(&PredicateStatistics {
+                triple_count: 1,
+                distinct_subjects: 1,
+                distinct_objects: 0,
+                node_objects: 0,
+                value_objects: 1,
+            }),
+            child_statistics.removals().get(&says)
+        );
+
+        for statistics in &[statistics, child_statistics] {
+            let bytes = statistics.to_bytes();
+            assert_eq!(
+                *statistics,
+                LayerChangeStatistics::from_bytes(&bytes).unwrap()
+            );
+        }
+        assert!(LayerChangeStatistics::from_bytes(b"2\n").is_err());
+        assert!(LayerChangeStatistics::from_bytes(b"1\n+ 1 2\n").is_err());
+    }
+
+    fn statistics_across_stack(store: SyncStore) {
+        let builder = store.create_base_layer().unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        let base = builder.commit().unwrap();
+
+        let builder = base.open_write().unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("horse", "likes", "cow"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("horse", "says", "neigh"))
+            .unwrap();
+        builder.commit_no_load().unwrap();
+        let child = store.get_layer_from_id(builder.name()).unwrap().unwrap();
+
+        let statistics = child.statistics().unwrap();
+        assert_eq!(2, statistics.stack_depth());
+        assert_eq!(2, statistics.loaded_depth());
+        assert!(!statistics.is_rolled_up());
+        assert_eq!(child.triple_count(), statistics.triple_count());
+
+        let says = child.predicate_id("says").unwrap();
+        assert_eq!(
+            Some(&PredicateStatistics {
+                triple_count: 2,
+                distinct_subjects: 2,
+                distinct_objects: 2,
+                node_objects: 0,
+                value_objects: 2,
+            }),
+            statistics.predicate(says)
+        );
+        let likes = child.predicate_id("likes").unwrap();
+        assert_eq!(2, statistics.predicate(likes).unwrap().node_objects);
+        assert_eq!(2, statistics.predicates().count());
+
+        child.rollup().unwrap();
+        let rolled_up = store.get_layer_from_id(child.name()).unwrap().unwrap();
+        let rolled_up_statistics = rolled_up.statistics().unwrap();
+        assert_eq!(2, rolled_up_statistics.stack_depth());
+        assert_eq!(1, rolled_up_statistics.loaded_depth());
+        assert!(rolled_up_statistics.is_rolled_up());
+        assert_eq!(
+            statistics.predicates().collect::<Vec<_>>(),
+            rolled_up_statistics.predicates().collect::<Vec<_>>()
+        );
+    }
+
+    fn distinct_counts_follow_removals(store: SyncStore) {
+        let builder = store.create_base_layer().unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "duck"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "horse"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("duck", "likes", "cow"))
+            .unwrap();
+        let base = builder.commit().unwrap();
+
+        let builder = base.open_write().unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("duck", "likes", "cow"))
+            .unwrap();
+        let child = builder.commit().unwrap();
+
+        let builder = child.open_write().unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("cow", "likes", "pig"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("pig", "likes", "duck"))
+            .unwrap();
+        builder.commit_no_load().unwrap();
+        let grandchild = store.get_layer_from_id(builder.name()).unwrap().unwrap();
+
+        let likes = child.predicate_id("likes").unwrap();
+        assert_eq!(
+            Some(&PredicateStatistics {
+                triple_count: 2,
+                distinct_subjects: 1,
+                distinct_objects: 2,
+                node_objects: 2,
+                value_objects: 0,
+            }),
+            child.statistics().unwrap().predicate(likes)
+        );
+        assert_eq!(
+            Some(&PredicateStatistics {
+                triple_count: 4,
+                distinct_subjects: 2,
+                distinct_objects: 3,
+                node_objects: 4,
+                value_objects: 0,
+            }),
+            grandchild.statistics().unwrap().predicate(likes)
+        );
+    }
+
+    #[test]
+    fn memory_distinct_counts_follow_removals() {
+        distinct_counts_follow_removals(open_sync_memory_store());
+    }
+
+    #[test]
+    fn directory_distinct_counts_follow_removals() {
+        let dir = tempdir().unwrap();
+        distinct_counts_follow_removals(open_sync_directory_store(dir.path()));
+    }
+
+    #[test]
+    fn memory_statistics_across_stack() {
+        statistics_across_stack(open_sync_memory_store());
+    }
+
+    #[test]
+    fn directory_statistics_across_stack() {
+        let dir = tempdir().unwrap();
+        statistics_across_stack(open_sync_directory_store(dir.path()));
+    }
+
+    #[tokio::test]
+    async fn combine_statistics_that_remove_too_much_errors() {
+        let layer = crate::layer::child::tests::example_layer_stack().await;
+        let changes = vec![
+            LayerChangeStatistics::from_bytes(b"1\n+ 1 2 2 2 0 2\n").unwrap(),
+            LayerChangeStatistics::from_bytes(b"1\n- 1 3 1 1 0 1\n").unwrap(),
+        ];
+
+        let error = LayerStatistics::combine(&changes, &layer).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    }
+}
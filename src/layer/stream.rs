@@ -0,0 +1,153 @@
+//! Asynchronous streams over triple iterators.
+//!
+//! The triple iterators of a layer do all their work synchronously.
+//! A `TripleStream` drives such an iterator on tokio's blocking
+//! thread pool and hands over the triples in bounded batches, so that
+//! long scans do not hold up the runtime's worker threads.
+use super::layer::IdTriple;
+use futures::stream::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+
+/// The amount of triples that are handed over from the iterator to the stream at once.
+pub const DEFAULT_TRIPLE_BATCH_SIZE: usize = 1024;
+
+/// A stream of triples, produced by an iterator running on the blocking thread pool.
+///
+/// At most two batches are produced ahead of the consumer. Dropping
+/// the stream stops the iterator, even in the middle of a batch.
+pub struct TripleStream {
+    receiver: mpsc::Receiver<Vec<IdTriple>>,
+    batch: std::vec::IntoIter<IdTriple>,
+}
+
+impl TripleStream {
+    /// Create a stream over the triples of the given iterator, using the default batch size.
+    ///
+    /// This must be called from within a tokio runtime.
+    pub fn new<I: 'static + Iterator<Item = IdTriple> + Send>(iter: I) -> Self {
+        Self::with_batch_size(iter, DEFAULT_TRIPLE_BATCH_SIZE)
+    }
+
+    /// Create a stream over the triples of the given iterator, which hands over triples in batches of `batch_size`.
+    ///
+    /// This must be called from within a tokio runtime.
+    pub fn with_batch_size<I: 'static + Iterator<Item = IdTriple> + Send>(
+        mut iter: I,
+        batch_size: usize,
+    ) -> Self {
+        let batch_size = batch_size.max(1);
+        let (sender, receiver) = mpsc::channel(1);
+        tokio::task::spawn_blocking(move || loop {
+            let mut batch = Vec::with_capacity(batch_size.min(DEFAULT_TRIPLE_BATCH_SIZE));
+            while batch.len() < batch_size && !sender.is_closed() {
+                match iter.next() {
+                    Some(triple) => batch.push(triple),
+                    None => break,
+                }
+            }
+            if batch.is_empty() || sender.blocking_send(batch).is_err() {
+                // either we're done, or the stream was dropped
+                break;
+            }
+        });
+
+        TripleStream {
+            receiver,
+            batch: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl Stream for TripleStream {
+    type Item = IdTriple;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<IdTriple>> {
+        loop {
+            if let Some(triple) = self.batch.next() {
+                return Poll::Ready(Some(triple));
+            }
+
+            match self.receiver.poll_recv(cx) {
+                Poll::Ready(Some(batch)) => self.batch = batch.into_iter(),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::*;
+    use crate::storage::memory::*;
+    use crate::storage::LayerStore;
+    use futures::stream::StreamExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn stream_yields_all_triples() {
+        let store = MemoryLayerStore::new();
+        let mut builder = store.create_base_layer().await.unwrap();
+        let name = builder.name();
+        for i in 0..100 {
+            builder.add_string_triple(StringTriple::new_value(
+                &format!("s{}", i),
+                "p",
+                &format!("{}", i),
+            ));
+        }
+        builder.commit_boxed().await.unwrap();
+        let layer = store.get_layer(name).await.unwrap().unwrap();
+
+        let streamed: Vec<_> = TripleStream::with_batch_size(layer.triples(), 7)
+            .collect()
+            .await;
+        assert_eq!(layer.triples().collect::<Vec<_>>(), streamed);
+    }
+
+    #[tokio::test]
+    async fn dropped_stream_stops_iterator() {
+        let produced = Arc::new(AtomicUsize::new(0));
+        let counter = produced.clone();
+        let iter = (1..).map(move |i| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            IdTriple::new(i, 1, 1)
+        });
+
+        let mut stream = TripleStream::with_batch_size(iter, 10);
+        let first: Vec<_> = stream.by_ref().take(15).collect().await;
+        assert_eq!(15, first.len());
+        drop(stream);
+
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let count = produced.load(Ordering::SeqCst);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(count, produced.load(Ordering::SeqCst));
+        assert!(count <= 50);
+    }
+
+    #[tokio::test]
+    async fn dropped_stream_stops_iterator_within_a_batch() {
+        let produced = Arc::new(AtomicUsize::new(0));
+        let counter = produced.clone();
+        let iter = (1..).map(move |i| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            IdTriple::new(i, 1, 1)
+        });
+
+        let stream = TripleStream::with_batch_size(iter, 1_000_000);
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        drop(stream);
+
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let count = produced.load(Ordering::SeqCst);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(count, produced.load(Ordering::SeqCst));
+        assert!(count < 1_000_000);
+    }
+}
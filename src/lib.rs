@@ -33,6 +33,8 @@ extern crate lazy_static;
 
 pub mod layer;
 //pub mod logging;
+pub mod query;
+pub mod rdf;
 pub mod storage;
 pub mod store;
 pub mod structure;
@@ -0,0 +1,937 @@
+//! Basic graph pattern evaluation.
+use crate::layer::{IdTriple, Layer, ObjectType};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::iter::{self, Peekable};
+use std::sync::Arc;
+
+/// A term in a triple pattern.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PatternTerm {
+    /// A variable, to be bound by the query.
+    Variable(String),
+    /// A node. In predicate position, this is the predicate string.
+    Node(String),
+    /// A value. Values only ever match in object position.
+    Value(String),
+    /// A numerical id, as returned by the layer this pattern is
+    /// evaluated against. In predicate position, this is a predicate
+    /// id. Elsewhere, it is a node or value id.
+    Id(u64),
+}
+
+impl PatternTerm {
+    /// Construct a variable term.
+    pub fn variable<S: Into<String>>(name: S) -> Self {
+        PatternTerm::Variable(name.into())
+    }
+
+    /// Construct a node term.
+    pub fn node<S: Into<String>>(node: S) -> Self {
+        PatternTerm::Node(node.into())
+    }
+
+    /// Construct a value term.
+    pub fn value<S: Into<String>>(value: S) -> Self {
+        PatternTerm::Value(value.into())
+    }
+
+    /// The name of this term if it is a variable, or None otherwise.
+    pub fn variable_name(&self) -> Option<&str> {
+        match self {
+            PatternTerm::Variable(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+/// A triple pattern, matching all triples that agree with its terms.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TriplePattern {
+    pub subject: PatternTerm,
+    pub predicate: PatternTerm,
+    pub object: PatternTerm,
+}
+
+impl TriplePattern {
+    /// Construct a new triple pattern.
+    pub fn new(subject: PatternTerm, predicate: PatternTerm, object: PatternTerm) -> Self {
+        TriplePattern {
+            subject,
+            predicate,
+            object,
+        }
+    }
+
+    fn terms(&self) -> [&PatternTerm; 3] {
+        [&self.subject, &self.predicate, &self.object]
+    }
+}
+
+/// The id a variable is bound to.
+///
+/// Subjects and objects share an id space, while predicates have
+/// their own. A variable that appears in both predicate and
+/// subject or object position is matched by comparing strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BindingId {
+    /// A node or value id.
+    Object(u64),
+    /// A predicate id.
+    Predicate(u64),
+}
+
+impl BindingId {
+    /// Convert this binding to a string, returning None if the id is not known to the layer.
+    ///
+    /// Predicates are returned as nodes.
+    pub fn to_object(self, layer: &dyn Layer) -> Option<ObjectType> {
+        match self {
+            BindingId::Object(id) => layer.id_object(id),
+            BindingId::Predicate(id) => layer.id_predicate(id).map(ObjectType::Node),
+        }
+    }
+
+    fn node_string(self, layer: &dyn Layer) -> Option<String> {
+        match self.to_object(layer) {
+            Some(ObjectType::Node(node)) => Some(node),
+            _ => None,
+        }
+    }
+
+    /// The id of this binding in the given position, translating
+    /// between predicate and object ids when needed.
+    fn id_in_position(self, layer: &dyn Layer, position: Position) -> Option<u64> {
+        match (self, position) {
+            (BindingId::Object(id), Position::Subject)
+            | (BindingId::Object(id), Position::Object) => Some(id),
+            (BindingId::Predicate(id), Position::Predicate) => Some(id),
+            (BindingId::Object(_), Position::Predicate) => self
+                .node_string(layer)
+                .and_then(|node| layer.predicate_id(&node)),
+            (BindingId::Predicate(_), Position::Subject) => self
+                .node_string(layer)
+                .and_then(|node| layer.subject_id(&node)),
+            (BindingId::Predicate(_), Position::Object) => self
+                .node_string(layer)
+                .and_then(|node| layer.object_node_id(&node)),
+        }
+    }
+}
+
+/// One solution of a basic graph pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Solution {
+    variables: Arc<[String]>,
+    bindings: Vec<BindingId>,
+}
+
+impl Solution {
+    /// The variables of the pattern, in order of first appearance.
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    /// The bindings of all variables, in the same order as `variables`.
+    pub fn ids(&self) -> &[BindingId] {
+        &self.bindings
+    }
+
+    /// The binding of the given variable, or None if the pattern has no such variable.
+    pub fn get(&self, variable: &str) -> Option<BindingId> {
+        self.variables
+            .iter()
+            .position(|v| v == variable)
+            .map(|index| self.bindings[index])
+    }
+
+    /// The binding of the given variable as a string.
+    pub fn get_string(&self, layer: &dyn Layer, variable: &str) -> Option<ObjectType> {
+        self.get(variable).and_then(|id| id.to_object(layer))
+    }
+
+    /// Convert all bindings to strings, returning None if any of the ids could not be converted.
+    pub fn to_strings(&self, layer: &dyn Layer) -> Option<HashMap<String, ObjectType>> {
+        self.variables
+            .iter()
+            .zip(self.bindings.iter())
+            .map(|(variable, id)| id.to_object(layer).map(|object| (variable.clone(), object)))
+            .collect()
+    }
+}
+
+/// The index used to find the triples matching a pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessPath {
+    /// All positions are known, so only existence is checked.
+    Exists,
+    /// Lookup by subject and predicate.
+    SubjectPredicate,
+    /// Lookup by subject.
+    Subject,
+    /// Lookup by object, narrowed down by predicate if it is known.
+    Object,
+    /// Lookup by predicate.
+    Predicate,
+    /// A scan over all triples.
+    All,
+}
+
+impl AccessPath {
+    fn for_bound(subject: bool, predicate: bool, object: bool) -> Self {
+        match (subject, predicate, object) {
+            (true, true, true) => AccessPath::Exists,
+            (true, true, false) => AccessPath::SubjectPredicate,
+            (true, false, _) => AccessPath::Subject,
+            (false, _, true) => AccessPath::Object,
+            (false, true, false) => AccessPath::Predicate,
+            (false, false, false) => AccessPath::All,
+        }
+    }
+
+    /// Rough selectivity of this access path, lower is better.
+    fn cost(self, bound_count: usize) -> usize {
+        match self {
+            AccessPath::Exists => 0,
+            AccessPath::SubjectPredicate => 1,
+            AccessPath::Subject => 4 - bound_count,
+            AccessPath::Object => 6 - bound_count,
+            AccessPath::Predicate => 6,
+            AccessPath::All => 7,
+        }
+    }
+}
+
+/// How a pattern is combined with the solutions of the patterns before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    /// The first pattern, which is scanned on its own.
+    Scan,
+    /// For every solution so far, the index is queried with the bound variables filled in.
+    NestedLoop,
+    /// The pattern is scanned on its own and merged with the previous
+    /// pattern, as both are sorted on their shared subject variable.
+    Merge,
+}
+
+/// One step in the evaluation of a basic graph pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlanStep {
+    /// The index of the pattern in the basic graph pattern.
+    pub pattern: usize,
+    /// The index used to find matching triples.
+    pub access: AccessPath,
+    /// How this step joins with the previous ones.
+    pub join: JoinKind,
+}
+
+/// A conjunction of triple patterns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicGraphPattern {
+    patterns: Vec<TriplePattern>,
+    variables: Arc<[String]>,
+}
+
+impl BasicGraphPattern {
+    /// Construct a basic graph pattern from the given triple patterns.
+    pub fn new(patterns: Vec<TriplePattern>) -> Self {
+        let mut variables: Vec<String> = Vec::new();
+        for term in patterns.iter().flat_map(|p| p.terms().to_vec()) {
+            if let Some(name) = term.variable_name() {
+                if !variables.iter().any(|v| v == name) {
+                    variables.push(name.to_string());
+                }
+            }
+        }
+
+        BasicGraphPattern {
+            patterns,
+            variables: variables.into(),
+        }
+    }
+
+    /// The triple patterns in this basic graph pattern.
+    pub fn patterns(&self) -> &[TriplePattern] {
+        &self.patterns
+    }
+
+    /// The variables in this basic graph pattern, in order of first appearance.
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    /// Decide on the order in which patterns are evaluated, and how.
+    ///
+    /// Patterns are picked greedily. At each step, the pattern that
+    /// can use the most selective index given the variables bound so
+    /// far is picked, avoiding patterns that share no variables with
+    /// the ones before it. If the first two patterns share only their
+    /// subject variable, they are merge joined.
+    pub fn plan(&self) -> Vec<PlanStep> {
+        self.plan_with_bound(&HashSet::new())
+    }
+
+    /// Plan the evaluation of this pattern, given variables that are
+    /// bound before it is evaluated.
+    fn plan_with_bound(&self, initially_bound: &HashSet<&str>) -> Vec<PlanStep> {
+        let mut bound: HashSet<&str> = initially_bound.clone();
+        let mut remaining: Vec<usize> = (0..self.patterns.len()).collect();
+        let mut steps = Vec::with_capacity(self.patterns.len());
+
+        while !remaining.is_empty() {
+            let (remaining_index, access, _) = remaining
+                .iter()
+                .enumerate()
+                .map(|(remaining_index, &pattern_index)| {
+                    let pattern = &self.patterns[pattern_index];
+                    let (access, bound_count) = access_for(pattern, &bound);
+                    let mut cost = access.cost(bound_count);
+                    let terms = pattern.terms();
+                    let mut variables = terms.iter().filter_map(|t| t.variable_name());
+                    if !bound.is_empty()
+                        && variables.clone().next().is_some()
+                        && !variables.any(|v| bound.contains(v))
+                    {
+                        // avoid cross products
+                        cost += 10;
+                    }
+
+                    (remaining_index, access, cost)
+                })
+                .min_by_key(|(_, _, cost)| *cost)
+                .unwrap();
+
+            let pattern_index = remaining.remove(remaining_index);
+            let join = if steps.is_empty() && initially_bound.is_empty() {
+                JoinKind::Scan
+            } else {
+                JoinKind::NestedLoop
+            };
+            steps.push(PlanStep {
+                pattern: pattern_index,
+                access,
+                join,
+            });
+            for term in self.patterns[pattern_index].terms().iter() {
+                if let Some(name) = term.variable_name() {
+                    bound.insert(name);
+                }
+            }
+        }
+
+        if steps.len() >= 2 && initially_bound.is_empty() {
+            let first = &self.patterns[steps[0].pattern];
+            let second = &self.patterns[steps[1].pattern];
+            if let Some(access) = merge_access(first, second, steps[0].access) {
+                steps[1].access = access;
+                steps[1].join = JoinKind::Merge;
+            }
+        }
+
+        steps
+    }
+
+    /// Evaluate this pattern against the given layer, streaming back all solutions.
+    pub fn solutions<'a>(&self, layer: &'a dyn Layer) -> Solutions<'a> {
+        let variables = self.variables.clone();
+        let width = variables.len();
+        let inner = self.extend(
+            layer,
+            &variables,
+            &HashSet::new(),
+            Box::new(iter::once(vec![None; width])),
+        );
+
+        Solutions {
+            layer,
+            variables,
+            inner,
+        }
+    }
+
+    /// Extend each of the given partial solutions with the solutions of this pattern.
+    ///
+    /// Partial solutions hold a binding for each of the given
+    /// variables, which have to include all variables of this
+    /// pattern. Variables that are bound in every partial solution
+    /// can be passed in as `bound`, so that they are taken into
+    /// account when planning.
+    pub(crate) fn extend<'a>(
+        &self,
+        layer: &'a dyn Layer,
+        variables: &[String],
+        bound: &HashSet<&str>,
+        input: PartialIterator<'a>,
+    ) -> PartialIterator<'a> {
+        let compiled: Option<Vec<CompiledPattern>> = self
+            .patterns
+            .iter()
+            .map(|pattern| CompiledPattern::compile(layer, pattern, variables))
+            .collect();
+
+        let compiled = match compiled {
+            Some(compiled) => compiled,
+            // a constant is missing from the layer, so nothing can match
+            None => return Box::new(iter::empty()),
+        };
+
+        let plan = self.plan_with_bound(bound);
+        let mut inner = input;
+        let mut steps = &plan[..];
+        if steps.len() >= 2 && steps[1].join == JoinKind::Merge {
+            let first = compiled[steps[0].pattern];
+            let second = compiled[steps[1].pattern];
+            // Whatever is bound in a partial solution, both scans
+            // remain sorted by subject, so each partial solution can
+            // be merged on its own.
+            inner = Box::new(inner.flat_map(move |partial| MergeJoin {
+                layer,
+                left_pattern: first,
+                right_pattern: second,
+                left: first.scan(layer, &partial).peekable(),
+                right: second.scan(layer, &partial).peekable(),
+                base: partial,
+                pending: VecDeque::new(),
+            }));
+            steps = &steps[2..];
+        }
+
+        for step in steps {
+            let pattern = compiled[step.pattern];
+            inner = Box::new(inner.flat_map(move |partial| {
+                let triples = pattern.scan(layer, &partial);
+                triples.filter_map(move |triple| pattern.bind(layer, triple, &partial))
+            }));
+        }
+
+        inner
+    }
+}
+
+/// Returns the access path for a pattern given the bound variables,
+/// and the amount of positions that are known.
+fn access_for(pattern: &TriplePattern, bound: &HashSet<&str>) -> (AccessPath, usize) {
+    let known = |term: &PatternTerm| match term.variable_name() {
+        Some(name) => bound.contains(name),
+        None => true,
+    };
+
+    let s = known(&pattern.subject);
+    let p = known(&pattern.predicate);
+    let o = known(&pattern.object);
+
+    (
+        AccessPath::for_bound(s, p, o),
+        s as usize + p as usize + o as usize,
+    )
+}
+
+/// Returns the access path for the right side of a merge join, or None if the patterns cannot be merge joined.
+///
+/// Any pattern with an unknown subject is answered through either the
+/// predicate index, the object index or a full scan, all of which
+/// return triples ordered by subject. Two such patterns on the same
+/// subject variable can therefore be merged, provided they share no
+/// other variables. Full scans are left to the nested loop join.
+fn merge_access(
+    first: &TriplePattern,
+    second: &TriplePattern,
+    first_access: AccessPath,
+) -> Option<AccessPath> {
+    let subject = first.subject.variable_name()?;
+    if second.subject.variable_name() != Some(subject)
+        || first.predicate.variable_name() == Some(subject)
+        || first.object.variable_name() == Some(subject)
+        || second.predicate.variable_name() == Some(subject)
+        || second.object.variable_name() == Some(subject)
+    {
+        return None;
+    }
+
+    let first_variables: HashSet<&str> = first
+        .terms()
+        .iter()
+        .filter_map(|t| t.variable_name())
+        .collect();
+    if [&second.predicate, &second.object]
+        .iter()
+        .filter_map(|t| t.variable_name())
+        .any(|v| first_variables.contains(v))
+    {
+        return None;
+    }
+
+    let (second_access, _) = access_for(second, &HashSet::new());
+    match (first_access, second_access) {
+        (AccessPath::All, _) | (_, AccessPath::All) => None,
+        (_, access) => Some(access),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Position {
+    Subject,
+    Predicate,
+    Object,
+}
+
+const POSITIONS: [Position; 3] = [Position::Subject, Position::Predicate, Position::Object];
+
+#[derive(Debug, Clone, Copy)]
+enum Slot {
+    Constant(u64),
+    Variable(usize),
+}
+
+/// A triple pattern with its constants resolved to ids and its variables to indexes.
+#[derive(Debug, Clone, Copy)]
+struct CompiledPattern {
+    slots: [Slot; 3],
+}
+
+pub(crate) type Partial = Vec<Option<BindingId>>;
+pub(crate) type PartialIterator<'a> = Box<dyn Iterator<Item = Partial> + Send + 'a>;
+
+impl CompiledPattern {
+    /// Compile a pattern, returning None if any of its constants are not known to the layer.
+    fn compile(layer: &dyn Layer, pattern: &TriplePattern, variables: &[String]) -> Option<Self> {
+        let mut slots = [Slot::Constant(0); 3];
+        for ((slot, term), position) in slots
+            .iter_mut()
+            .zip(pattern.terms().iter())
+            .zip(POSITIONS.iter())
+        {
+            *slot = match (term, position) {
+                (PatternTerm::Variable(name), _) => {
+                    Slot::Variable(variables.iter().position(|v| v == name).unwrap())
+                }
+                (PatternTerm::Id(id), _) => Slot::Constant(*id),
+                (PatternTerm::Node(node), Position::Subject) => {
+                    Slot::Constant(layer.subject_id(node)?)
+                }
+                (PatternTerm::Node(node), Position::Predicate) => {
+                    Slot::Constant(layer.predicate_id(node)?)
+                }
+                (PatternTerm::Node(node), Position::Object) => {
+                    Slot::Constant(layer.object_node_id(node)?)
+                }
+                (PatternTerm::Value(value), Position::Object) => {
+                    Slot::Constant(layer.object_value_id(value)?)
+                }
+                (PatternTerm::Value(_), _) => return None,
+            };
+        }
+
+        Some(CompiledPattern { slots })
+    }
+
+    /// Query the best index for the triples that can match this pattern, given a partial solution.
+    ///
+    /// The returned triples still have to be checked against the
+    /// pattern, as not all known positions are used for the lookup.
+    fn scan(
+        &self,
+        layer: &dyn Layer,
+        partial: &[Option<BindingId>],
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        let mut known = [None; 3];
+        for ((known, slot), position) in known
+            .iter_mut()
+            .zip(self.slots.iter())
+            .zip(POSITIONS.iter())
+        {
+            *known = match slot {
+                Slot::Constant(id) => Some(*id),
+                Slot::Variable(index) => match partial[*index] {
+                    None => None,
+                    Some(binding) => match binding.id_in_position(layer, *position) {
+                        Some(id) => Some(id),
+                        None => return Box::new(iter::empty()),
+                    },
+                },
+            };
+        }
+
+        match known {
+            [Some(s), Some(p), Some(o)] => {
+                if layer.triple_exists(s, p, o) {
+                    Box::new(iter::once(IdTriple::new(s, p, o)))
+                } else {
+                    Box::new(iter::empty())
+                }
+            }
+            [Some(s), Some(p), None] => layer.triples_sp(s, p),
+            [Some(s), None, _] => layer.triples_s(s),
+            [None, Some(p), Some(o)] => layer.triples_po(p, o),
+            [None, None, Some(o)] => layer.triples_o(o),
+            [None, Some(p), None] => layer.triples_p(p),
+            [None, None, None] => layer.triples(),
+        }
+    }
+
+    /// Extend a partial solution with the bindings from a triple, returning None if the triple does not match.
+    fn bind(
+        &self,
+        layer: &dyn Layer,
+        triple: IdTriple,
+        partial: &[Option<BindingId>],
+    ) -> Option<Partial> {
+        let mut result = partial.to_vec();
+        let values = [
+            BindingId::Object(triple.subject),
+            BindingId::Predicate(triple.predicate),
+            BindingId::Object(triple.object),
+        ];
+        for (slot, value) in self.slots.iter().zip(values.iter()) {
+            match (slot, value) {
+                (Slot::Constant(id), BindingId::Object(v))
+                | (Slot::Constant(id), BindingId::Predicate(v)) => {
+                    if id != v {
+                        return None;
+                    }
+                }
+                (Slot::Variable(index), value) => match result[*index] {
+                    None => result[*index] = Some(*value),
+                    Some(existing) => {
+                        if !bindings_equal(layer, existing, *value) {
+                            return None;
+                        }
+                    }
+                },
+            }
+        }
+
+        Some(result)
+    }
+}
+
+pub(crate) fn bindings_equal(layer: &dyn Layer, b1: BindingId, b2: BindingId) -> bool {
+    match (b1, b2) {
+        (BindingId::Object(id1), BindingId::Object(id2))
+        | (BindingId::Predicate(id1), BindingId::Predicate(id2)) => id1 == id2,
+        _ => match (b1.node_string(layer), b2.node_string(layer)) {
+            (Some(s1), Some(s2)) => s1 == s2,
+            _ => false,
+        },
+    }
+}
+
+/// A merge join of two triple streams that are sorted by subject.
+struct MergeJoin<'a> {
+    layer: &'a dyn Layer,
+    left_pattern: CompiledPattern,
+    right_pattern: CompiledPattern,
+    left: Peekable<Box<dyn Iterator<Item = IdTriple> + Send>>,
+    right: Peekable<Box<dyn Iterator<Item = IdTriple> + Send>>,
+    base: Partial,
+    pending: VecDeque<Partial>,
+}
+
+impl<'a> MergeJoin<'a> {
+    fn take_group(
+        iter: &mut Peekable<Box<dyn Iterator<Item = IdTriple> + Send>>,
+        subject: u64,
+    ) -> Vec<IdTriple> {
+        let mut group = Vec::new();
+        while let Some(triple) = iter.next_if(|t| t.subject == subject) {
+            group.push(triple);
+        }
+
+        group
+    }
+}
+
+impl<'a> Iterator for MergeJoin<'a> {
+    type Item = Partial;
+
+    fn next(&mut self) -> Option<Partial> {
+        loop {
+            if let Some(result) = self.pending.pop_front() {
+                return Some(result);
+            }
+
+            let left_subject = self.left.peek()?.subject;
+            let right_subject = self.right.peek()?.subject;
+            match left_subject.cmp(&right_subject) {
+                Ordering::Less => {
+                    self.left.next();
+                }
+                Ordering::Greater => {
+                    self.right.next();
+                }
+                Ordering::Equal => {
+                    let left = Self::take_group(&mut self.left, left_subject);
+                    let right = Self::take_group(&mut self.right, right_subject);
+                    for l in left {
+                        let partial = match self.left_pattern.bind(self.layer, l, &self.base) {
+                            Some(partial) => partial,
+                            None => continue,
+                        };
+                        for &r in right.iter() {
+                            if let Some(result) = self.right_pattern.bind(self.layer, r, &partial) {
+                                self.pending.push_back(result);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// An iterator over the solutions of a basic graph pattern.
+pub struct Solutions<'a> {
+    layer: &'a dyn Layer,
+    variables: Arc<[String]>,
+    inner: PartialIterator<'a>,
+}
+
+impl<'a> Solutions<'a> {
+    /// The variables bound by each solution.
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    /// Convert the solutions to maps from variable names to strings.
+    pub fn strings(self) -> impl Iterator<Item = HashMap<String, ObjectType>> + Send + 'a {
+        let layer = self.layer;
+        self.filter_map(move |solution| solution.to_strings(layer))
+    }
+}
+
+impl<'a> Iterator for Solutions<'a> {
+    type Item = Solution;
+
+    fn next(&mut self) -> Option<Solution> {
+        self.inner.next().map(|partial| Solution {
+            variables: self.variables.clone(),
+            bindings: partial
+                .into_iter()
+                .map(|binding| binding.expect("all variables are bound by some pattern"))
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdf::import_turtle;
+    use crate::store::{open_memory_store, StoreLayer};
+
+    async fn example_layer() -> StoreLayer {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        let document = r#"@prefix : <http://example.com/> .
+:cow a :Animal ; :says "moo" ; :likes :pig, :duck ; :name "Daisy" .
+:pig a :Animal ; :says "oink" ; :likes :cow .
+:duck a :Animal ; :says "quack" .
+:farmer a :Person ; :likes :cow ; :name "Old MacDonald" .
+:likes a :Relation .
+"#;
+        import_turtle(document.as_bytes(), &builder).unwrap();
+        builder.commit().await.unwrap()
+    }
+
+    fn ex(name: &str) -> PatternTerm {
+        PatternTerm::node(format!("http://example.com/{}", name))
+    }
+
+    fn rdf_type() -> PatternTerm {
+        PatternTerm::node("http://www.w3.org/1999/02/22-rdf-syntax-ns#type")
+    }
+
+    fn var(name: &str) -> PatternTerm {
+        PatternTerm::variable(name)
+    }
+
+    fn sorted_strings(
+        layer: &dyn Layer,
+        bgp: &BasicGraphPattern,
+        variables: &[&str],
+    ) -> Vec<Vec<String>> {
+        let mut result: Vec<Vec<String>> = bgp
+            .solutions(layer)
+            .strings()
+            .map(|mut solution| {
+                variables
+                    .iter()
+                    .map(|v| match solution.remove(*v).unwrap() {
+                        ObjectType::Node(node) => node
+                            .strip_prefix("http://example.com/")
+                            .unwrap_or(&node)
+                            .to_string(),
+                        ObjectType::Value(value) => value,
+                    })
+                    .collect()
+            })
+            .collect();
+        result.sort();
+
+        result
+    }
+
+    #[tokio::test]
+    async fn single_pattern_access_paths() {
+        let layer = example_layer().await;
+
+        let bgp =
+            BasicGraphPattern::new(vec![TriplePattern::new(var("x"), ex("likes"), ex("cow"))]);
+        assert_eq!(
+            vec![PlanStep {
+                pattern: 0,
+                access: AccessPath::Object,
+                join: JoinKind::Scan
+            }],
+            bgp.plan()
+        );
+        assert_eq!(
+            vec![vec!["farmer".to_string()], vec!["pig".to_string()]],
+            sorted_strings(&layer, &bgp, &["x"])
+        );
+
+        let bgp = BasicGraphPattern::new(vec![TriplePattern::new(ex("cow"), var("p"), var("o"))]);
+        assert_eq!(AccessPath::Subject, bgp.plan()[0].access);
+        assert_eq!(5, bgp.solutions(&layer).count());
+
+        let bgp = BasicGraphPattern::new(vec![TriplePattern::new(
+            ex("cow"),
+            ex("says"),
+            PatternTerm::value("\"moo\""),
+        )]);
+        assert_eq!(AccessPath::Exists, bgp.plan()[0].access);
+        let solutions: Vec<_> = bgp.solutions(&layer).collect();
+        assert_eq!(1, solutions.len());
+        assert!(solutions[0].variables().is_empty());
+
+        let bgp = BasicGraphPattern::new(vec![TriplePattern::new(var("s"), var("p"), var("o"))]);
+        assert_eq!(AccessPath::All, bgp.plan()[0].access);
+        assert_eq!(layer.triple_count(), bgp.solutions(&layer).count());
+    }
+
+    #[tokio::test]
+    async fn nested_loop_join() {
+        let layer = example_layer().await;
+
+        // animals liked by something that says moo
+        let bgp = BasicGraphPattern::new(vec![
+            TriplePattern::new(var("y"), rdf_type(), ex("Animal")),
+            TriplePattern::new(var("x"), ex("likes"), var("y")),
+            TriplePattern::new(var("x"), ex("says"), PatternTerm::value("\"moo\"")),
+        ]);
+        let plan = bgp.plan();
+        assert_eq!(0, plan[0].pattern);
+        assert_eq!(AccessPath::Object, plan[0].access);
+        assert_eq!(1, plan[1].pattern);
+        assert_eq!(AccessPath::Object, plan[1].access);
+        assert_eq!(JoinKind::NestedLoop, plan[1].join);
+        assert_eq!(AccessPath::Exists, plan[2].access);
+
+        assert_eq!(
+            vec![
+                vec!["cow".to_string(), "duck".to_string()],
+                vec!["cow".to_string(), "pig".to_string()]
+            ],
+            sorted_strings(&layer, &bgp, &["x", "y"])
+        );
+    }
+
+    #[tokio::test]
+    async fn merge_join_on_subject() {
+        let layer = example_layer().await;
+
+        let bgp = BasicGraphPattern::new(vec![
+            TriplePattern::new(var("x"), ex("likes"), var("y")),
+            TriplePattern::new(var("x"), ex("name"), var("name")),
+        ]);
+        let plan = bgp.plan();
+        assert_eq!(JoinKind::Merge, plan[1].join);
+        assert_eq!(AccessPath::Predicate, plan[1].access);
+
+        assert_eq!(
+            vec![
+                vec![
+                    "cow".to_string(),
+                    "duck".to_string(),
+                    "\"Daisy\"".to_string()
+                ],
+                vec![
+                    "cow".to_string(),
+                    "pig".to_string(),
+                    "\"Daisy\"".to_string()
+                ],
+                vec![
+                    "farmer".to_string(),
+                    "cow".to_string(),
+                    "\"Old MacDonald\"".to_string()
+                ],
+            ],
+            sorted_strings(&layer, &bgp, &["x", "y", "name"])
+        );
+
+        // sharing the object variable rules out the merge join
+        let bgp = BasicGraphPattern::new(vec![
+            TriplePattern::new(var("x"), ex("likes"), var("y")),
+            TriplePattern::new(var("y"), ex("likes"), var("x")),
+        ]);
+        assert_eq!(JoinKind::NestedLoop, bgp.plan()[1].join);
+        assert_eq!(
+            vec![
+                vec!["cow".to_string(), "pig".to_string()],
+                vec!["pig".to_string(), "cow".to_string()]
+            ],
+            sorted_strings(&layer, &bgp, &["x", "y"])
+        );
+    }
+
+    #[tokio::test]
+    async fn variables_across_id_spaces() {
+        let layer = example_layer().await;
+
+        // predicates that are themselves described in the graph
+        let bgp = BasicGraphPattern::new(vec![
+            TriplePattern::new(var("s"), var("p"), ex("pig")),
+            TriplePattern::new(var("p"), rdf_type(), ex("Relation")),
+        ]);
+        let solutions: Vec<_> = bgp.solutions(&layer).collect();
+        assert_eq!(1, solutions.len());
+        assert_eq!(
+            Some(ObjectType::Node("http://example.com/likes".to_string())),
+            solutions[0].get_string(&layer, "p")
+        );
+        assert_eq!(
+            Some(BindingId::Object(
+                layer.subject_id("http://example.com/cow").unwrap()
+            )),
+            solutions[0].get("s")
+        );
+
+        // a value can never be a subject
+        let bgp = BasicGraphPattern::new(vec![
+            TriplePattern::new(ex("cow"), ex("says"), var("x")),
+            TriplePattern::new(var("x"), var("p"), var("o")),
+        ]);
+        assert_eq!(0, bgp.solutions(&layer).count());
+    }
+
+    #[tokio::test]
+    async fn unknown_constants_give_no_solutions() {
+        let layer = example_layer().await;
+
+        let bgp = BasicGraphPattern::new(vec![
+            TriplePattern::new(var("x"), ex("likes"), var("y")),
+            TriplePattern::new(var("y"), ex("hates"), var("z")),
+        ]);
+        assert_eq!(0, bgp.solutions(&layer).count());
+
+        let bgp = BasicGraphPattern::new(vec![TriplePattern::new(
+            PatternTerm::value("\"moo\""),
+            var("p"),
+            var("o"),
+        )]);
+        assert_eq!(0, bgp.solutions(&layer).count());
+
+        let bgp = BasicGraphPattern::new(Vec::new());
+        assert_eq!(1, bgp.solutions(&layer).count());
+    }
+}
@@ -0,0 +1,28 @@
+//! Queries over layers.
+//!
+//! The building block for queries is the basic graph pattern: a
+//! conjunction of triple patterns whose positions are either fixed
+//! terms or variables. Evaluating a basic graph pattern against a
+//! layer gives every binding of its variables for which all triple
+//! patterns match a triple in the layer.
+//!
+//! Patterns are evaluated one at a time, in an order picked by a
+//! simple planner that prefers the most selective patterns. Each
+//! pattern is answered through the subject, subject-predicate,
+//! predicate or object index of the layer, depending on which of its
+//! positions are known at that point. Results are streamed, so large
+//! result sets never have to be held in memory.
+//!
+//! On top of basic graph patterns, a subset of SPARQL is supported
+//! for SELECT and ASK queries.
+//!
+//! For recursive queries, such as finding all ancestors of a class,
+//! layers can be traversed breadth-first along a set of predicates,
+//! or along a property path.
+mod bgp;
+mod path;
+mod sparql;
+
+pub use bgp::*;
+pub use path::*;
+pub use sparql::*;
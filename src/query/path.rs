@@ -0,0 +1,624 @@
+//! Graph traversal and property paths.
+//!
+//! Traversals walk the graph breadth-first from a start node, following
+//! triples forward from subject to object, or backward from object to
+//! subject. Every node is returned at most once, at the depth it was
+//! first reached, so cycles in the graph do not lead to endless
+//! iteration.
+//!
+//! Forward steps are answered through the subject-predicate index and
+//! backward steps through the object index.
+use crate::layer::{IdTriple, Layer};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::iter;
+use std::sync::Arc;
+
+/// The direction in which triples are followed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From subject to object.
+    Forward,
+    /// From object to subject.
+    Backward,
+}
+
+type NodeIterator = Box<dyn Iterator<Item = u64> + Send>;
+type Neighbors = Box<dyn Fn(u64) -> NodeIterator + Send + Sync>;
+
+/// A breadth-first walk over nodes, returning each node with its depth.
+struct BreadthFirst {
+    neighbors: Neighbors,
+    queue: VecDeque<(u64, usize)>,
+    visited: HashSet<u64>,
+    max_depth: Option<usize>,
+    include_start: bool,
+}
+
+impl BreadthFirst {
+    fn new(
+        start: u64,
+        neighbors: Neighbors,
+        max_depth: Option<usize>,
+        include_start: bool,
+    ) -> Self {
+        let mut visited = HashSet::new();
+        // if the start node is not returned at depth 0, it can still be
+        // returned later when it turns out to be part of a cycle.
+        if include_start {
+            visited.insert(start);
+        }
+
+        BreadthFirst {
+            neighbors,
+            queue: iter::once((start, 0)).collect(),
+            visited,
+            max_depth,
+            include_start,
+        }
+    }
+}
+
+impl Iterator for BreadthFirst {
+    type Item = (u64, usize);
+
+    fn next(&mut self) -> Option<(u64, usize)> {
+        loop {
+            let (node, depth) = self.queue.pop_front()?;
+            if self.max_depth.map(|max| depth < max).unwrap_or(true) {
+                for neighbor in (self.neighbors)(node) {
+                    if self.visited.insert(neighbor) {
+                        self.queue.push_back((neighbor, depth + 1));
+                    }
+                }
+            }
+
+            if depth == 0 && !self.include_start {
+                continue;
+            }
+
+            return Some((node, depth));
+        }
+    }
+}
+
+/// A traversal over the graph from a start node.
+///
+/// By default, a traversal follows all predicates forward, without a
+/// depth limit, and does not return the start node unless it can be
+/// reached from itself.
+#[derive(Clone)]
+pub struct Traversal {
+    layer: Arc<dyn Layer>,
+    start: u64,
+    predicates: Vec<u64>,
+    direction: Direction,
+    max_depth: Option<usize>,
+    include_start: bool,
+}
+
+impl Traversal {
+    /// Create a traversal over the given layer, starting at the given node id.
+    pub fn new(layer: Arc<dyn Layer>, start: u64) -> Self {
+        Traversal {
+            layer,
+            start,
+            predicates: Vec::new(),
+            direction: Direction::Forward,
+            max_depth: None,
+            include_start: false,
+        }
+    }
+
+    /// Only follow triples with the given predicate id.
+    ///
+    /// This can be called multiple times to follow several predicates.
+    pub fn predicate(mut self, predicate: u64) -> Self {
+        self.predicates.push(predicate);
+
+        self
+    }
+
+    /// Only follow triples with one of the given predicate ids.
+    pub fn predicates<I: IntoIterator<Item = u64>>(mut self, predicates: I) -> Self {
+        self.predicates.extend(predicates);
+
+        self
+    }
+
+    /// Follow triples in the given direction.
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+
+        self
+    }
+
+    /// Follow triples from object to subject.
+    pub fn backward(self) -> Self {
+        self.direction(Direction::Backward)
+    }
+
+    /// Do not follow more than the given amount of triples from the start node.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+
+        self
+    }
+
+    /// Return the start node itself at depth 0.
+    pub fn include_start(mut self, include_start: bool) -> Self {
+        self.include_start = include_start;
+
+        self
+    }
+
+    /// The triples that lead away from the given node.
+    fn edges(
+        layer: &dyn Layer,
+        predicates: &[u64],
+        direction: Direction,
+        node: u64,
+    ) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        match direction {
+            Direction::Forward if predicates.is_empty() => layer.triples_s(node),
+            Direction::Forward => {
+                let triples: Vec<_> = predicates
+                    .iter()
+                    .flat_map(|predicate| layer.triples_sp(node, *predicate))
+                    .collect();
+                Box::new(triples.into_iter())
+            }
+            Direction::Backward => {
+                let predicates = predicates.to_vec();
+                Box::new(
+                    layer.triples_o(node).filter(move |t| {
+                        predicates.is_empty() || predicates.contains(&t.predicate)
+                    }),
+                )
+            }
+        }
+    }
+
+    fn other_end(direction: Direction, triple: &IdTriple) -> u64 {
+        match direction {
+            Direction::Forward => triple.object,
+            Direction::Backward => triple.subject,
+        }
+    }
+
+    /// Stream all reachable nodes together with the amount of triples followed to reach them, in breadth-first order.
+    pub fn nodes_with_depth(self) -> impl Iterator<Item = (u64, usize)> + Send {
+        let Traversal {
+            layer,
+            start,
+            predicates,
+            direction,
+            max_depth,
+            include_start,
+        } = self;
+        let neighbors: Neighbors = Box::new(move |node| {
+            Box::new(
+                Self::edges(&*layer, &predicates, direction, node)
+                    .map(move |t| Self::other_end(direction, &t)),
+            )
+        });
+
+        BreadthFirst::new(start, neighbors, max_depth, include_start)
+    }
+
+    /// Stream all reachable node ids, in breadth-first order.
+    pub fn nodes(self) -> impl Iterator<Item = u64> + Send {
+        self.nodes_with_depth().map(|(node, _)| node)
+    }
+
+    /// Find a shortest path to the given node, returning the triples along the path.
+    ///
+    /// Returns None if the target cannot be reached. If the target is
+    /// the start node, the path is empty if the start node is
+    /// included, and otherwise is the shortest cycle through it.
+    pub fn shortest_path(self, target: u64) -> Option<Vec<IdTriple>> {
+        if self.include_start && target == self.start {
+            return Some(Vec::new());
+        }
+
+        let mut parents: HashMap<u64, IdTriple> = HashMap::new();
+        let mut queue: VecDeque<(u64, usize)> = iter::once((self.start, 0)).collect();
+        while let Some((node, depth)) = queue.pop_front() {
+            if self.max_depth.map(|max| depth >= max).unwrap_or(false) {
+                continue;
+            }
+            for triple in Self::edges(&*self.layer, &self.predicates, self.direction, node) {
+                let neighbor = Self::other_end(self.direction, &triple);
+                if parents.contains_key(&neighbor) || (neighbor == self.start && target != neighbor)
+                {
+                    continue;
+                }
+                parents.insert(neighbor, triple);
+                if neighbor == target {
+                    let mut path = vec![triple];
+                    let mut current = match self.direction {
+                        Direction::Forward => triple.subject,
+                        Direction::Backward => triple.object,
+                    };
+                    while current != self.start {
+                        let triple = parents[&current];
+                        path.push(triple);
+                        current = match self.direction {
+                            Direction::Forward => triple.subject,
+                            Direction::Backward => triple.object,
+                        };
+                    }
+                    path.reverse();
+
+                    return Some(path);
+                }
+                queue.push_back((neighbor, depth + 1));
+            }
+        }
+
+        None
+    }
+}
+
+/// A property path, describing how to get from one node to another.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PropertyPath {
+    /// Follow a triple with the given predicate id.
+    Predicate(u64),
+    /// Follow the inner path backward.
+    Inverse(Box<PropertyPath>),
+    /// Follow each of the paths in turn.
+    Sequence(Vec<PropertyPath>),
+    /// Follow any of the paths.
+    Alternative(Vec<PropertyPath>),
+    /// Follow the inner path any amount of times, including zero.
+    ZeroOrMore(Box<PropertyPath>),
+    /// Follow the inner path at least once.
+    OneOrMore(Box<PropertyPath>),
+    /// Follow the inner path once or not at all.
+    ZeroOrOne(Box<PropertyPath>),
+}
+
+/// Remove duplicates from a stream of nodes.
+fn distinct(nodes: NodeIterator) -> NodeIterator {
+    let mut seen = HashSet::new();
+    Box::new(nodes.filter(move |node| seen.insert(*node)))
+}
+
+impl PropertyPath {
+    /// A path following a single predicate.
+    pub fn predicate(predicate: u64) -> Self {
+        PropertyPath::Predicate(predicate)
+    }
+
+    /// This path, followed backward.
+    pub fn inverse(self) -> Self {
+        PropertyPath::Inverse(Box::new(self))
+    }
+
+    /// This path, followed by another path.
+    pub fn then(self, next: PropertyPath) -> Self {
+        match self {
+            PropertyPath::Sequence(mut paths) => {
+                paths.push(next);
+                PropertyPath::Sequence(paths)
+            }
+            path => PropertyPath::Sequence(vec![path, next]),
+        }
+    }
+
+    /// Either this path or another path.
+    pub fn or(self, other: PropertyPath) -> Self {
+        match self {
+            PropertyPath::Alternative(mut paths) => {
+                paths.push(other);
+                PropertyPath::Alternative(paths)
+            }
+            path => PropertyPath::Alternative(vec![path, other]),
+        }
+    }
+
+    /// This path, repeated any amount of times.
+    pub fn zero_or_more(self) -> Self {
+        PropertyPath::ZeroOrMore(Box::new(self))
+    }
+
+    /// This path, repeated at least once.
+    pub fn one_or_more(self) -> Self {
+        PropertyPath::OneOrMore(Box::new(self))
+    }
+
+    /// This path, or nothing.
+    pub fn zero_or_one(self) -> Self {
+        PropertyPath::ZeroOrOne(Box::new(self))
+    }
+
+    /// Stream the distinct nodes reachable from the start node through this path.
+    pub fn targets(&self, layer: Arc<dyn Layer>, start: u64) -> impl Iterator<Item = u64> + Send {
+        distinct(self.follow(layer, start, false))
+    }
+
+    fn follow(&self, layer: Arc<dyn Layer>, start: u64, inverse: bool) -> NodeIterator {
+        match self {
+            PropertyPath::Predicate(predicate) => {
+                let predicate = *predicate;
+                if inverse {
+                    Box::new(
+                        layer
+                            .triples_o(start)
+                            .filter(move |t| t.predicate == predicate)
+                            .map(|t| t.subject),
+                    )
+                } else {
+                    Box::new(layer.triples_sp(start, predicate).map(|t| t.object))
+                }
+            }
+            PropertyPath::Inverse(path) => path.follow(layer, start, !inverse),
+            PropertyPath::Sequence(paths) => {
+                let mut paths = paths.clone();
+                if inverse {
+                    paths.reverse();
+                }
+                let mut nodes: NodeIterator = Box::new(iter::once(start));
+                for path in paths {
+                    let layer = layer.clone();
+                    nodes = distinct(Box::new(
+                        nodes.flat_map(move |node| path.follow(layer.clone(), node, inverse)),
+                    ));
+                }
+
+                nodes
+            }
+            PropertyPath::Alternative(paths) => {
+                let paths = paths.clone();
+                distinct(Box::new(paths.into_iter().flat_map(move |path| {
+                    path.follow(layer.clone(), start, inverse)
+                })))
+            }
+            PropertyPath::ZeroOrOne(path) => distinct(Box::new(
+                iter::once(start).chain(path.follow(layer, start, inverse)),
+            )),
+            PropertyPath::ZeroOrMore(path) | PropertyPath::OneOrMore(path) => {
+                let path = path.clone();
+                let neighbors: Neighbors =
+                    Box::new(move |node| path.follow(layer.clone(), node, inverse));
+                let include_start = matches!(self, PropertyPath::ZeroOrMore(_));
+                Box::new(
+                    BreadthFirst::new(start, neighbors, None, include_start).map(|(node, _)| node),
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdf::import_turtle;
+    use crate::store::{open_memory_store, StoreLayer};
+
+    async fn example_layer() -> StoreLayer {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        let document = r#"@prefix : <http://example.com/> .
+:Cow :subClassOf :Mammal .
+:Mammal :subClassOf :Animal .
+:Bird :subClassOf :Animal .
+:Animal :subClassOf :Thing .
+:Thing :subClassOf :Thing .
+:alice :manages :bob, :carol .
+:bob :manages :dave .
+:dave :manages :alice .
+:carol :worksOn :website .
+:dave :worksOn :database .
+:database :dependsOn :website .
+"#;
+        import_turtle(document.as_bytes(), &builder).unwrap();
+        builder.commit().await.unwrap()
+    }
+
+    fn id(layer: &dyn Layer, name: &str) -> u64 {
+        layer
+            .subject_id(&format!("http://example.com/{}", name))
+            .or_else(|| layer.object_node_id(&format!("http://example.com/{}", name)))
+            .unwrap()
+    }
+
+    fn predicate(layer: &dyn Layer, name: &str) -> u64 {
+        layer
+            .predicate_id(&format!("http://example.com/{}", name))
+            .unwrap()
+    }
+
+    fn names(layer: &dyn Layer, nodes: impl Iterator<Item = u64>) -> Vec<String> {
+        nodes
+            .map(|node| {
+                layer
+                    .id_subject(node)
+                    .unwrap_or_else(|| match layer.id_object(node) {
+                        Some(crate::layer::ObjectType::Node(node)) => node,
+                        _ => panic!("not a node"),
+                    })["http://example.com/".len()..]
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn forward_and_backward_closure() {
+        let layer = example_layer().await;
+        let sub_class_of = predicate(&layer, "subClassOf");
+
+        let ancestors: Vec<_> = layer
+            .traverse(id(&layer, "Cow"))
+            .predicate(sub_class_of)
+            .nodes_with_depth()
+            .collect();
+        assert_eq!(
+            vec![
+                (id(&layer, "Mammal"), 1),
+                (id(&layer, "Animal"), 2),
+                (id(&layer, "Thing"), 3)
+            ],
+            ancestors
+        );
+
+        assert_eq!(
+            vec!["Cow", "Mammal"],
+            names(
+                &layer,
+                layer
+                    .traverse(id(&layer, "Cow"))
+                    .predicate(sub_class_of)
+                    .include_start(true)
+                    .max_depth(1)
+                    .nodes()
+            )
+        );
+
+        let mut descendants = names(
+            &layer,
+            layer
+                .traverse(id(&layer, "Animal"))
+                .predicate(sub_class_of)
+                .backward()
+                .nodes(),
+        );
+        descendants.sort();
+        assert_eq!(vec!["Bird", "Cow", "Mammal"], descendants);
+
+        // following any predicate
+        let mut reachable = names(&layer, layer.traverse(id(&layer, "carol")).nodes());
+        reachable.sort();
+        assert_eq!(vec!["website"], reachable);
+    }
+
+    #[tokio::test]
+    async fn cycles_are_visited_once() {
+        let layer = example_layer().await;
+        let manages = predicate(&layer, "manages");
+
+        assert_eq!(
+            vec!["bob", "carol", "dave", "alice"],
+            names(
+                &layer,
+                layer
+                    .traverse(id(&layer, "alice"))
+                    .predicate(manages)
+                    .nodes()
+            )
+        );
+        assert_eq!(
+            vec!["Thing"],
+            names(
+                &layer,
+                layer
+                    .traverse(id(&layer, "Thing"))
+                    .predicates(vec![predicate(&layer, "subClassOf")])
+                    .nodes()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn shortest_paths() {
+        let layer = example_layer().await;
+        let manages = predicate(&layer, "manages");
+        let works_on = predicate(&layer, "worksOn");
+
+        let path = layer
+            .traverse(id(&layer, "alice"))
+            .predicates(vec![manages, works_on])
+            .shortest_path(id(&layer, "database"))
+            .unwrap();
+        assert_eq!(
+            vec![
+                IdTriple::new(id(&layer, "alice"), manages, id(&layer, "bob")),
+                IdTriple::new(id(&layer, "bob"), manages, id(&layer, "dave")),
+                IdTriple::new(id(&layer, "dave"), works_on, id(&layer, "database")),
+            ],
+            path
+        );
+
+        assert_eq!(
+            None,
+            layer
+                .traverse(id(&layer, "alice"))
+                .predicates(vec![manages, works_on])
+                .max_depth(2)
+                .shortest_path(id(&layer, "database"))
+        );
+
+        // backward paths are returned as stored, from start to target
+        let path = layer
+            .traverse(id(&layer, "website"))
+            .backward()
+            .shortest_path(id(&layer, "carol"))
+            .unwrap();
+        assert_eq!(
+            vec![IdTriple::new(
+                id(&layer, "carol"),
+                works_on,
+                id(&layer, "website")
+            )],
+            path
+        );
+
+        // the shortest cycle through the start node
+        assert_eq!(
+            3,
+            layer
+                .traverse(id(&layer, "alice"))
+                .predicate(manages)
+                .shortest_path(id(&layer, "alice"))
+                .unwrap()
+                .len()
+        );
+        assert_eq!(
+            Some(Vec::new()),
+            layer
+                .traverse(id(&layer, "alice"))
+                .include_start(true)
+                .shortest_path(id(&layer, "alice"))
+        );
+    }
+
+    #[tokio::test]
+    async fn property_paths() {
+        let layer = example_layer().await;
+        let manages = PropertyPath::predicate(predicate(&layer, "manages"));
+        let works_on = PropertyPath::predicate(predicate(&layer, "worksOn"));
+        let depends_on = PropertyPath::predicate(predicate(&layer, "dependsOn"));
+
+        // what the reports of bob work on, and what that depends on
+        let path = manages
+            .clone()
+            .then(works_on.clone())
+            .then(depends_on.clone().zero_or_more());
+        let mut targets = names(&layer, layer.path_targets(id(&layer, "bob"), &path));
+        targets.sort();
+        assert_eq!(vec!["database", "website"], targets);
+
+        // who works on the website, or on something depending on it
+        let path = works_on.clone().then(depends_on.zero_or_more()).inverse();
+        let mut targets = names(&layer, layer.path_targets(id(&layer, "website"), &path));
+        targets.sort();
+        assert_eq!(vec!["carol", "dave"], targets);
+
+        let mut targets = names(
+            &layer,
+            layer.path_targets(id(&layer, "alice"), &manages.clone().one_or_more()),
+        );
+        targets.sort();
+        assert_eq!(vec!["alice", "bob", "carol", "dave"], targets);
+
+        let mut targets = names(
+            &layer,
+            layer.path_targets(
+                id(&layer, "carol"),
+                &works_on.or(manages.inverse()).zero_or_one(),
+            ),
+        );
+        targets.sort();
+        assert_eq!(vec!["alice", "carol", "website"], targets);
+    }
+}
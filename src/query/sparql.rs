@@ -0,0 +1,1246 @@
+//! A subset of SPARQL.
+//!
+//! SELECT and ASK queries are supported, with basic graph patterns,
+//! FILTER, OPTIONAL, UNION, DISTINCT, ORDER BY, LIMIT and OFFSET.
+//! Filters can use `=`, `!=`, `&&`, `||`, `!`, and the functions
+//! `regex`, `bound` and `str`.
+//!
+//! Query terms are mapped onto the strings in a layer in the same way
+//! as in the `rdf` module: IRIs match nodes, and literals match values
+//! in their canonical N-Triples form. Blank nodes in a query act as
+//! variables that are left out of `SELECT *`.
+//!
+//! Solutions are streamed, except when ORDER BY is used, as all
+//! solutions then have to be known before the first one is returned.
+use super::bgp::*;
+use crate::layer::{Layer, ObjectType};
+use crate::rdf::cursor::Cursor;
+use crate::rdf::{format_literal, parse_stored_literal, RdfParseError, Term, TurtleState};
+use regex::{Regex, RegexBuilder};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::iter;
+use std::sync::Arc;
+
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+const XSD: &str = "http://www.w3.org/2001/XMLSchema#";
+const XSD_BOOLEAN: &str = "http://www.w3.org/2001/XMLSchema#boolean";
+const NUMERIC_TYPES: [&str; 15] = [
+    "integer",
+    "decimal",
+    "double",
+    "float",
+    "long",
+    "int",
+    "short",
+    "byte",
+    "nonNegativeInteger",
+    "positiveInteger",
+    "nonPositiveInteger",
+    "negativeInteger",
+    "unsignedLong",
+    "unsignedInt",
+    "unsignedShort",
+];
+
+/// An error encountered while parsing a SPARQL query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparqlError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SparqlError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            formatter,
+            "invalid query at line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl Error for SparqlError {}
+
+impl From<RdfParseError> for SparqlError {
+    fn from(err: RdfParseError) -> SparqlError {
+        SparqlError {
+            line: err.line,
+            column: err.column,
+            message: err.message,
+        }
+    }
+}
+
+impl From<SparqlError> for io::Error {
+    fn from(err: SparqlError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expression {
+    Variable(usize),
+    Constant(ObjectType),
+    Equal(Box<Expression>, Box<Expression>),
+    NotEqual(Box<Expression>, Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    Bound(usize),
+    Str(Box<Expression>),
+    Regex(Box<Expression>, Regex),
+}
+
+fn boolean(b: bool) -> ObjectType {
+    ObjectType::Value(format_literal(
+        if b { "true" } else { "false" },
+        None,
+        Some(XSD_BOOLEAN),
+    ))
+}
+
+/// Split a stored value into its lexical form and datatype.
+///
+/// Values that are not literals in N-Triples syntax are treated as plain strings.
+fn literal_parts(value: &str) -> (String, Option<String>, Option<String>) {
+    match parse_stored_literal(value) {
+        Some(Term::Literal {
+            value,
+            language,
+            datatype,
+        }) => (value, language, datatype),
+        _ => (value.to_string(), None, None),
+    }
+}
+
+fn numeric_value(object: &ObjectType) -> Option<f64> {
+    match object {
+        ObjectType::Value(value) => {
+            let (lexical, _, datatype) = literal_parts(value);
+            let local = datatype.as_deref()?.strip_prefix(XSD)?;
+            if NUMERIC_TYPES.contains(&local) {
+                lexical.parse().ok()
+            } else {
+                None
+            }
+        }
+        ObjectType::Node(_) => None,
+    }
+}
+
+fn terms_equal(o1: &ObjectType, o2: &ObjectType) -> bool {
+    match (numeric_value(o1), numeric_value(o2)) {
+        (Some(n1), Some(n2)) => n1 == n2,
+        _ => o1 == o2,
+    }
+}
+
+/// The effective boolean value of a term, or None if it has none.
+fn effective_boolean_value(object: &ObjectType) -> Option<bool> {
+    let value = match object {
+        ObjectType::Value(value) => value,
+        ObjectType::Node(_) => return None,
+    };
+    let (lexical, language, datatype) = literal_parts(value);
+    match datatype.as_deref() {
+        _ if language.is_some() => Some(!lexical.is_empty()),
+        None => Some(!lexical.is_empty()),
+        Some(XSD_BOOLEAN) => Some(lexical == "true" || lexical == "1"),
+        Some(_) => numeric_value(object).map(|n| n != 0.0 && !n.is_nan()),
+    }
+}
+
+impl Expression {
+    /// Evaluate this expression, returning None on an error such as an unbound variable.
+    fn evaluate(&self, layer: &dyn Layer, partial: &[Option<BindingId>]) -> Option<ObjectType> {
+        match self {
+            Expression::Variable(index) => partial[*index]?.to_object(layer),
+            Expression::Constant(object) => Some(object.clone()),
+            Expression::Equal(e1, e2) => {
+                let o1 = e1.evaluate(layer, partial)?;
+                let o2 = e2.evaluate(layer, partial)?;
+                Some(boolean(terms_equal(&o1, &o2)))
+            }
+            Expression::NotEqual(e1, e2) => {
+                let o1 = e1.evaluate(layer, partial)?;
+                let o2 = e2.evaluate(layer, partial)?;
+                Some(boolean(!terms_equal(&o1, &o2)))
+            }
+            Expression::And(e1, e2) => {
+                match (e1.matches(layer, partial), e2.matches(layer, partial)) {
+                    (Some(false), _) | (_, Some(false)) => Some(boolean(false)),
+                    (Some(true), Some(true)) => Some(boolean(true)),
+                    _ => None,
+                }
+            }
+            Expression::Or(e1, e2) => {
+                match (e1.matches(layer, partial), e2.matches(layer, partial)) {
+                    (Some(true), _) | (_, Some(true)) => Some(boolean(true)),
+                    (Some(false), Some(false)) => Some(boolean(false)),
+                    _ => None,
+                }
+            }
+            Expression::Not(e) => e.matches(layer, partial).map(|b| boolean(!b)),
+            Expression::Bound(index) => Some(boolean(partial[*index].is_some())),
+            Expression::Str(e) => match e.evaluate(layer, partial)? {
+                ObjectType::Node(node) if node.starts_with("_:") => None,
+                ObjectType::Node(node) => {
+                    Some(ObjectType::Value(format_literal(&node, None, None)))
+                }
+                ObjectType::Value(value) => Some(ObjectType::Value(format_literal(
+                    &literal_parts(&value).0,
+                    None,
+                    None,
+                ))),
+            },
+            Expression::Regex(e, regex) => match e.evaluate(layer, partial)? {
+                ObjectType::Value(value) => Some(boolean(regex.is_match(&literal_parts(&value).0))),
+                ObjectType::Node(_) => None,
+            },
+        }
+    }
+
+    /// The effective boolean value of this expression, or None on an error.
+    fn matches(&self, layer: &dyn Layer, partial: &[Option<BindingId>]) -> Option<bool> {
+        self.evaluate(layer, partial)
+            .and_then(|object| effective_boolean_value(&object))
+    }
+}
+
+/// Compare two terms for ORDER BY.
+///
+/// Unbound values come first, then blank nodes, then IRIs, then
+/// literals. Numeric literals are compared by value, and other
+/// literals by their lexical form.
+fn compare_terms(o1: &Option<ObjectType>, o2: &Option<ObjectType>) -> Ordering {
+    fn rank(object: &Option<ObjectType>) -> u8 {
+        match object {
+            None => 0,
+            Some(ObjectType::Node(node)) if node.starts_with("_:") => 1,
+            Some(ObjectType::Node(_)) => 2,
+            Some(ObjectType::Value(_)) => 3,
+        }
+    }
+
+    match (o1, o2) {
+        (Some(v1 @ ObjectType::Value(s1)), Some(v2 @ ObjectType::Value(s2))) => {
+            match (numeric_value(v1), numeric_value(v2)) {
+                (Some(n1), Some(n2)) => n1.partial_cmp(&n2).unwrap_or(Ordering::Equal),
+                _ => literal_parts(s1)
+                    .0
+                    .cmp(&literal_parts(s2).0)
+                    .then_with(|| s1.cmp(s2)),
+            }
+        }
+        (Some(ObjectType::Node(n1)), Some(ObjectType::Node(n2))) if rank(o1) == rank(o2) => {
+            n1.cmp(n2)
+        }
+        _ => rank(o1).cmp(&rank(o2)),
+    }
+}
+
+#[derive(Debug, Clone)]
+enum GroupElement {
+    Basic(BasicGraphPattern),
+    Optional(Arc<Group>),
+    Union(Vec<Arc<Group>>),
+    Group(Arc<Group>),
+}
+
+/// A group graph pattern, with the filters that apply to it.
+#[derive(Debug, Clone, Default)]
+struct Group {
+    elements: Vec<GroupElement>,
+    filters: Vec<Expression>,
+}
+
+impl Group {
+    /// The variables that are bound in every solution of this group.
+    fn certain_variables(&self) -> HashSet<String> {
+        let mut result = HashSet::new();
+        for element in self.elements.iter() {
+            match element {
+                GroupElement::Basic(bgp) => result.extend(bgp.variables().iter().cloned()),
+                GroupElement::Optional(_) => {}
+                GroupElement::Union(groups) => {
+                    let mut branches = groups.iter().map(|g| g.certain_variables());
+                    if let Some(first) = branches.next() {
+                        result.extend(branches.fold(first, |acc, b| &acc & &b));
+                    }
+                }
+                GroupElement::Group(group) => result.extend(group.certain_variables()),
+            }
+        }
+
+        result
+    }
+}
+
+#[derive(Clone)]
+struct Context<'a> {
+    layer: &'a dyn Layer,
+    variables: Arc<[String]>,
+}
+
+impl<'a> Context<'a> {
+    fn bound_in(&self, partial: &[Option<BindingId>]) -> HashSet<String> {
+        self.variables
+            .iter()
+            .zip(partial.iter())
+            .filter(|(_, binding)| binding.is_some())
+            .map(|(variable, _)| variable.clone())
+            .collect()
+    }
+
+    fn evaluate_group(
+        &self,
+        group: &Arc<Group>,
+        bound: &HashSet<String>,
+        input: PartialIterator<'a>,
+    ) -> PartialIterator<'a> {
+        let mut bound = bound.clone();
+        let mut stream = input;
+        for element in group.elements.iter() {
+            match element {
+                GroupElement::Basic(bgp) => {
+                    let known = bound.iter().map(String::as_str).collect();
+                    stream = bgp.extend(self.layer, &self.variables, &known, stream);
+                    bound.extend(bgp.variables().iter().cloned());
+                }
+                GroupElement::Optional(optional) => {
+                    let context = self.clone();
+                    let optional = optional.clone();
+                    stream = Box::new(stream.flat_map(move |partial| {
+                        let row_bound = context.bound_in(&partial);
+                        let mut results = context
+                            .evaluate_group(
+                                &optional,
+                                &row_bound,
+                                Box::new(iter::once(partial.clone())),
+                            )
+                            .peekable();
+                        if results.peek().is_none() {
+                            Box::new(iter::once(partial)) as PartialIterator<'a>
+                        } else {
+                            Box::new(results)
+                        }
+                    }));
+                }
+                GroupElement::Union(groups) => {
+                    let context = self.clone();
+                    let groups = groups.clone();
+                    stream = Box::new(stream.flat_map(move |partial| {
+                        let context = context.clone();
+                        let row_bound = context.bound_in(&partial);
+                        groups.clone().into_iter().flat_map(move |group| {
+                            context.evaluate_group(
+                                &group,
+                                &row_bound,
+                                Box::new(iter::once(partial.clone())),
+                            )
+                        })
+                    }));
+                    bound.extend(element.certain_variables());
+                }
+                GroupElement::Group(inner) => {
+                    stream = self.evaluate_group(inner, &bound, stream);
+                    bound.extend(inner.certain_variables());
+                }
+            }
+        }
+
+        for filter in group.filters.iter() {
+            let layer = self.layer;
+            let filter = filter.clone();
+            stream = Box::new(
+                stream.filter(move |partial| filter.matches(layer, partial) == Some(true)),
+            );
+        }
+
+        stream
+    }
+}
+
+impl GroupElement {
+    fn certain_variables(&self) -> HashSet<String> {
+        Group {
+            elements: vec![self.clone()],
+            filters: Vec::new(),
+        }
+        .certain_variables()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum QueryForm {
+    Select(Vec<usize>),
+    Ask,
+}
+
+/// A parsed SPARQL query.
+#[derive(Debug, Clone)]
+pub struct SparqlQuery {
+    form: QueryForm,
+    variables: Arc<[String]>,
+    pattern: Arc<Group>,
+    distinct: bool,
+    order_by: Vec<(Expression, bool)>,
+    limit: Option<usize>,
+    offset: usize,
+}
+
+/// The result of a SPARQL query.
+pub enum SparqlResult<'a> {
+    /// The answer to an ASK query.
+    Boolean(bool),
+    /// The solutions of a SELECT query.
+    Solutions(SparqlSolutions<'a>),
+}
+
+/// An iterator over the solutions of a SELECT query.
+///
+/// Each solution has a value for every selected variable, in the
+/// order of `variables`. Variables that are not bound in a solution
+/// have no value.
+pub struct SparqlSolutions<'a> {
+    variables: Vec<String>,
+    inner: Box<dyn Iterator<Item = Vec<Option<ObjectType>>> + Send + 'a>,
+}
+
+impl<'a> SparqlSolutions<'a> {
+    /// The selected variables.
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+}
+
+impl<'a> Iterator for SparqlSolutions<'a> {
+    type Item = Vec<Option<ObjectType>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+impl SparqlQuery {
+    /// Parse a SPARQL query.
+    pub fn parse(query: &str) -> Result<Self, SparqlError> {
+        SparqlParser::new(query).parse_query()
+    }
+
+    /// Returns true if this is an ASK query, and false if it is a SELECT query.
+    pub fn is_ask(&self) -> bool {
+        matches!(self.form, QueryForm::Ask)
+    }
+
+    /// The variables selected by this query. This is empty for ASK queries.
+    pub fn selected_variables(&self) -> Vec<String> {
+        match &self.form {
+            QueryForm::Select(indexes) => indexes
+                .iter()
+                .map(|index| self.variables[*index].clone())
+                .collect(),
+            QueryForm::Ask => Vec::new(),
+        }
+    }
+
+    /// Run this query against the given layer.
+    pub fn execute<'a>(&self, layer: &'a dyn Layer) -> SparqlResult<'a> {
+        let context = Context {
+            layer,
+            variables: self.variables.clone(),
+        };
+        let mut stream = context.evaluate_group(
+            &self.pattern,
+            &HashSet::new(),
+            Box::new(iter::once(vec![None; self.variables.len()])),
+        );
+
+        let selected = match &self.form {
+            QueryForm::Ask => return SparqlResult::Boolean(stream.next().is_some()),
+            QueryForm::Select(selected) => selected.clone(),
+        };
+
+        if !self.order_by.is_empty() {
+            let mut keyed: Vec<_> = stream
+                .map(|partial| {
+                    let key: Vec<_> = self
+                        .order_by
+                        .iter()
+                        .map(|(expression, _)| expression.evaluate(layer, &partial))
+                        .collect();
+                    (key, partial)
+                })
+                .collect();
+            let descending: Vec<bool> = self.order_by.iter().map(|(_, d)| *d).collect();
+            keyed.sort_by(|(k1, _), (k2, _)| {
+                k1.iter()
+                    .zip(k2.iter())
+                    .zip(descending.iter())
+                    .map(|((o1, o2), descending)| {
+                        let ordering = compare_terms(o1, o2);
+                        if *descending {
+                            ordering.reverse()
+                        } else {
+                            ordering
+                        }
+                    })
+                    .find(|ordering| *ordering != Ordering::Equal)
+                    .unwrap_or(Ordering::Equal)
+            });
+            stream = Box::new(keyed.into_iter().map(|(_, partial)| partial));
+        }
+
+        let mut rows: Box<dyn Iterator<Item = Vec<Option<ObjectType>>> + Send + 'a> =
+            Box::new(stream.map(move |partial| {
+                selected
+                    .iter()
+                    .map(|index| partial[*index].and_then(|binding| binding.to_object(layer)))
+                    .collect()
+            }));
+        if self.distinct {
+            let mut seen = HashSet::new();
+            rows = Box::new(rows.filter(move |row| seen.insert(row.clone())));
+        }
+        rows = Box::new(rows.skip(self.offset));
+        if let Some(limit) = self.limit {
+            rows = Box::new(rows.take(limit));
+        }
+
+        SparqlResult::Solutions(SparqlSolutions {
+            variables: self.selected_variables(),
+            inner: rows,
+        })
+    }
+}
+
+/// Parse the given SPARQL query and run it against a layer.
+pub fn query_sparql<'a>(
+    layer: &'a dyn Layer,
+    query: &str,
+) -> Result<SparqlResult<'a>, SparqlError> {
+    Ok(SparqlQuery::parse(query)?.execute(layer))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Position {
+    Subject,
+    Predicate,
+    Object,
+}
+
+struct SparqlParser<'a> {
+    cursor: Cursor<'a>,
+    state: TurtleState,
+    variables: Vec<String>,
+    anonymous_count: usize,
+}
+
+impl<'a> SparqlParser<'a> {
+    fn new(query: &'a str) -> Self {
+        SparqlParser {
+            cursor: Cursor::new(query, 1),
+            state: TurtleState::new(),
+            variables: Vec::new(),
+            anonymous_count: 0,
+        }
+    }
+
+    fn variable_index(&mut self, name: &str) -> usize {
+        match self.variables.iter().position(|v| v == name) {
+            Some(index) => index,
+            None => {
+                self.variables.push(name.to_string());
+                self.variables.len() - 1
+            }
+        }
+    }
+
+    /// Consume the given keyword if it comes next, ignoring case.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        self.cursor.skip_whitespace();
+        let rest = self.cursor.rest();
+        let matched = rest.len() >= keyword.len()
+            && rest.is_char_boundary(keyword.len())
+            && rest[..keyword.len()].eq_ignore_ascii_case(keyword)
+            && !matches!(rest[keyword.len()..].chars().next(),
+                         Some(c) if c.is_alphanumeric() || c == '_' || c == ':' || c == '-');
+        if matched {
+            for _ in 0..keyword.len() {
+                self.cursor.bump();
+            }
+        }
+
+        matched
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), SparqlError> {
+        self.cursor.skip_whitespace();
+        Ok(self.cursor.expect(c)?)
+    }
+
+    fn error<S: Into<String>>(&self, message: S) -> SparqlError {
+        self.cursor.error(message).into()
+    }
+
+    fn parse_query(mut self) -> Result<SparqlQuery, SparqlError> {
+        loop {
+            if self.keyword("BASE") {
+                self.cursor.skip_whitespace();
+                let iri = self.state.parse_iriref(&mut self.cursor)?;
+                self.state.base = Some(iri);
+            } else if self.keyword("PREFIX") {
+                self.cursor.skip_whitespace();
+                let prefix = self.state.parse_pname_ns(&mut self.cursor)?;
+                self.cursor.skip_whitespace();
+                let iri = self.state.parse_iriref(&mut self.cursor)?;
+                self.state.prefixes.insert(prefix, iri);
+            } else {
+                break;
+            }
+        }
+
+        let mut distinct = false;
+        let mut ask = false;
+        let selected = if self.keyword("SELECT") {
+            if self.keyword("DISTINCT") {
+                distinct = true;
+            } else {
+                // duplicates may be kept, so REDUCED is the same as no modifier
+                self.keyword("REDUCED");
+            }
+            self.cursor.skip_whitespace();
+            if self.cursor.eat('*') {
+                None
+            } else {
+                let mut selected = Vec::new();
+                loop {
+                    self.cursor.skip_whitespace();
+                    match self.cursor.peek() {
+                        Some('?') | Some('$') => {
+                            let name = self.parse_variable_name()?;
+                            selected.push(self.variable_index(&name));
+                        }
+                        _ => break,
+                    }
+                }
+                if selected.is_empty() {
+                    return Err(self.error("expected variables or '*'"));
+                }
+                Some(selected)
+            }
+        } else if self.keyword("ASK") {
+            ask = true;
+            None
+        } else {
+            return Err(self.error("expected SELECT or ASK"));
+        };
+
+        self.keyword("WHERE");
+        let pattern = self.parse_group()?;
+
+        let mut order_by = Vec::new();
+        if self.keyword("ORDER") {
+            if !self.keyword("BY") {
+                return Err(self.error("expected BY"));
+            }
+            loop {
+                let descending = if self.keyword("ASC") {
+                    false
+                } else if self.keyword("DESC") {
+                    true
+                } else {
+                    self.cursor.skip_whitespace();
+                    match self.cursor.peek() {
+                        Some('?') | Some('$') => {
+                            let name = self.parse_variable_name()?;
+                            let index = self.variable_index(&name);
+                            order_by.push((Expression::Variable(index), false));
+                            continue;
+                        }
+                        _ => break,
+                    }
+                };
+                self.expect('(')?;
+                let expression = self.parse_expression()?;
+                self.expect(')')?;
+                order_by.push((expression, descending));
+            }
+            if order_by.is_empty() {
+                return Err(self.error("expected order condition"));
+            }
+        }
+
+        let mut limit = None;
+        let mut offset = 0;
+        loop {
+            if self.keyword("LIMIT") {
+                limit = Some(self.parse_integer()?);
+            } else if self.keyword("OFFSET") {
+                offset = self.parse_integer()?;
+            } else {
+                break;
+            }
+        }
+
+        self.cursor.skip_whitespace();
+        if !self.cursor.is_at_end() {
+            return Err(self.error("unexpected input after query"));
+        }
+
+        let form = match selected {
+            _ if ask => QueryForm::Ask,
+            Some(selected) => QueryForm::Select(selected),
+            None => QueryForm::Select(
+                self.variables
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, v)| !v.starts_with("_:"))
+                    .map(|(index, _)| index)
+                    .collect(),
+            ),
+        };
+
+        Ok(SparqlQuery {
+            form,
+            variables: self.variables.into(),
+            pattern: Arc::new(pattern),
+            distinct,
+            order_by,
+            limit,
+            offset,
+        })
+    }
+
+    fn parse_integer(&mut self) -> Result<usize, SparqlError> {
+        self.cursor.skip_whitespace();
+        let mut digits = String::new();
+        while let Some(c) = self.cursor.peek().filter(char::is_ascii_digit) {
+            self.cursor.bump();
+            digits.push(c);
+        }
+
+        digits
+            .parse()
+            .map_err(|_| self.error("expected a non-negative integer"))
+    }
+
+    fn parse_variable_name(&mut self) -> Result<String, SparqlError> {
+        self.cursor.bump();
+        let mut name = String::new();
+        while let Some(c) = self
+            .cursor
+            .peek()
+            .filter(|c| c.is_alphanumeric() || *c == '_')
+        {
+            self.cursor.bump();
+            name.push(c);
+        }
+        if name.is_empty() {
+            return Err(self.error("expected variable name"));
+        }
+
+        Ok(name)
+    }
+
+    fn parse_group(&mut self) -> Result<Group, SparqlError> {
+        self.expect('{')?;
+        let mut group = Group::default();
+        let mut patterns = Vec::new();
+        loop {
+            self.cursor.skip_whitespace();
+            if self.cursor.eat('}') {
+                break;
+            } else if self.cursor.is_at_end() {
+                return Err(self.error("expected '}'"));
+            } else if self.cursor.eat('.') {
+                continue;
+            } else if self.keyword("FILTER") {
+                let filter = self.parse_primary()?;
+                group.filters.push(filter);
+                continue;
+            }
+
+            if self.keyword("OPTIONAL") {
+                Self::flush_patterns(&mut group, &mut patterns);
+                let optional = self.parse_group()?;
+                group
+                    .elements
+                    .push(GroupElement::Optional(Arc::new(optional)));
+            } else if self.cursor.peek() == Some('{') {
+                Self::flush_patterns(&mut group, &mut patterns);
+                let mut branches = vec![Arc::new(self.parse_group()?)];
+                while self.keyword("UNION") {
+                    branches.push(Arc::new(self.parse_group()?));
+                }
+                if branches.len() == 1 {
+                    group
+                        .elements
+                        .push(GroupElement::Group(branches.pop().unwrap()));
+                } else {
+                    group.elements.push(GroupElement::Union(branches));
+                }
+            } else {
+                self.parse_triples(&mut patterns)?;
+            }
+        }
+        Self::flush_patterns(&mut group, &mut patterns);
+
+        Ok(group)
+    }
+
+    fn flush_patterns(group: &mut Group, patterns: &mut Vec<TriplePattern>) {
+        if !patterns.is_empty() {
+            let bgp = BasicGraphPattern::new(std::mem::take(patterns));
+            group.elements.push(GroupElement::Basic(bgp));
+        }
+    }
+
+    fn parse_triples(&mut self, patterns: &mut Vec<TriplePattern>) -> Result<(), SparqlError> {
+        let subject = self.parse_pattern_term(Position::Subject)?;
+        loop {
+            let predicate = self.parse_pattern_term(Position::Predicate)?;
+            loop {
+                let object = self.parse_pattern_term(Position::Object)?;
+                patterns.push(TriplePattern::new(
+                    subject.clone(),
+                    predicate.clone(),
+                    object,
+                ));
+                self.cursor.skip_whitespace();
+                if !self.cursor.eat(',') {
+                    break;
+                }
+            }
+
+            self.cursor.skip_whitespace();
+            if !self.cursor.eat(';') {
+                break;
+            }
+            loop {
+                self.cursor.skip_whitespace();
+                if !self.cursor.eat(';') {
+                    break;
+                }
+            }
+            if matches!(self.cursor.peek(), Some('.') | Some('}')) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_pattern_term(&mut self, position: Position) -> Result<PatternTerm, SparqlError> {
+        self.cursor.skip_whitespace();
+        let term = match self.cursor.peek() {
+            Some('?') | Some('$') => {
+                let name = self.parse_variable_name()?;
+                self.variable_index(&name);
+                PatternTerm::Variable(name)
+            }
+            Some('_') if self.cursor.peek_nth(1) == Some(':') => {
+                let label = self.cursor.parse_blank_node_label()?;
+                let name = format!("_:{}", label);
+                self.variable_index(&name);
+                PatternTerm::Variable(name)
+            }
+            Some('[') => {
+                self.cursor.bump();
+                self.expect(']')?;
+                let name = format!("_:anon{}", self.anonymous_count);
+                self.anonymous_count += 1;
+                self.variable_index(&name);
+                PatternTerm::Variable(name)
+            }
+            _ if position == Position::Predicate && self.keyword("a") => {
+                PatternTerm::node(RDF_TYPE)
+            }
+            _ => match self.parse_constant()? {
+                ObjectType::Node(node) => PatternTerm::Node(node),
+                ObjectType::Value(value) => PatternTerm::Value(value),
+            },
+        };
+
+        Ok(term)
+    }
+
+    /// Parse an IRI, prefixed name or literal.
+    fn parse_constant(&mut self) -> Result<ObjectType, SparqlError> {
+        self.cursor.skip_whitespace();
+        let object = match self.cursor.peek() {
+            Some('<') => ObjectType::Node(self.state.parse_iriref(&mut self.cursor)?),
+            Some('"') | Some('\'') => self.state.parse_literal(&mut self.cursor)?.into_object(),
+            Some(c) if c.is_ascii_digit() || c == '+' || c == '-' || c == '.' => self
+                .state
+                .parse_numeric_literal(&mut self.cursor)?
+                .into_object(),
+            _ if self.keyword("true") => boolean(true),
+            _ if self.keyword("false") => boolean(false),
+            _ => ObjectType::Node(self.state.parse_iri(&mut self.cursor)?),
+        };
+
+        Ok(object)
+    }
+
+    fn parse_expression(&mut self) -> Result<Expression, SparqlError> {
+        let mut expression = self.parse_and()?;
+        loop {
+            self.cursor.skip_whitespace();
+            if !self.cursor.starts_with("||") {
+                break;
+            }
+            self.cursor.bump();
+            self.cursor.bump();
+            let right = self.parse_and()?;
+            expression = Expression::Or(Box::new(expression), Box::new(right));
+        }
+
+        Ok(expression)
+    }
+
+    fn parse_and(&mut self) -> Result<Expression, SparqlError> {
+        let mut expression = self.parse_relational()?;
+        loop {
+            self.cursor.skip_whitespace();
+            if !self.cursor.starts_with("&&") {
+                break;
+            }
+            self.cursor.bump();
+            self.cursor.bump();
+            let right = self.parse_relational()?;
+            expression = Expression::And(Box::new(expression), Box::new(right));
+        }
+
+        Ok(expression)
+    }
+
+    fn parse_relational(&mut self) -> Result<Expression, SparqlError> {
+        let left = self.parse_unary()?;
+        self.cursor.skip_whitespace();
+        if self.cursor.starts_with("!=") {
+            self.cursor.bump();
+            self.cursor.bump();
+            let right = self.parse_unary()?;
+            Ok(Expression::NotEqual(Box::new(left), Box::new(right)))
+        } else if self.cursor.eat('=') {
+            let right = self.parse_unary()?;
+            Ok(Expression::Equal(Box::new(left), Box::new(right)))
+        } else {
+            Ok(left)
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, SparqlError> {
+        self.cursor.skip_whitespace();
+        if self.cursor.peek() == Some('!') && self.cursor.peek_nth(1) != Some('=') {
+            self.cursor.bump();
+            let expression = self.parse_unary()?;
+            Ok(Expression::Not(Box::new(expression)))
+        } else {
+            self.parse_primary()
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression, SparqlError> {
+        self.cursor.skip_whitespace();
+        match self.cursor.peek() {
+            Some('(') => {
+                self.cursor.bump();
+                let expression = self.parse_expression()?;
+                self.expect(')')?;
+                Ok(expression)
+            }
+            Some('?') | Some('$') => {
+                let name = self.parse_variable_name()?;
+                Ok(Expression::Variable(self.variable_index(&name)))
+            }
+            _ if self.keyword("REGEX") => {
+                self.expect('(')?;
+                let text = self.parse_expression()?;
+                self.expect(',')?;
+                let pattern = self.parse_string_argument()?;
+                let mut builder = RegexBuilder::new(&pattern);
+                self.cursor.skip_whitespace();
+                if self.cursor.eat(',') {
+                    for flag in self.parse_string_argument()?.chars() {
+                        match flag {
+                            'i' => builder.case_insensitive(true),
+                            's' => builder.dot_matches_new_line(true),
+                            'm' => builder.multi_line(true),
+                            'x' => builder.ignore_whitespace(true),
+                            _ => return Err(self.error(format!("unknown regex flag '{}'", flag))),
+                        };
+                    }
+                }
+                let regex = builder
+                    .build()
+                    .map_err(|e| self.error(format!("invalid regex: {}", e)))?;
+                self.expect(')')?;
+                Ok(Expression::Regex(Box::new(text), regex))
+            }
+            _ if self.keyword("BOUND") => {
+                self.expect('(')?;
+                self.cursor.skip_whitespace();
+                if !matches!(self.cursor.peek(), Some('?') | Some('$')) {
+                    return Err(self.error("expected variable"));
+                }
+                let name = self.parse_variable_name()?;
+                let index = self.variable_index(&name);
+                self.expect(')')?;
+                Ok(Expression::Bound(index))
+            }
+            _ if self.keyword("STR") => {
+                self.expect('(')?;
+                let expression = self.parse_expression()?;
+                self.expect(')')?;
+                Ok(Expression::Str(Box::new(expression)))
+            }
+            _ => Ok(Expression::Constant(self.parse_constant()?)),
+        }
+    }
+
+    fn parse_string_argument(&mut self) -> Result<String, SparqlError> {
+        self.cursor.skip_whitespace();
+        match self.state.parse_literal(&mut self.cursor)? {
+            Term::Literal { value, .. } => Ok(value),
+            _ => Err(self.error("expected string")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdf::import_turtle;
+    use crate::store::{open_memory_store, StoreLayer};
+
+    async fn example_layer() -> StoreLayer {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        let document = r#"@prefix : <http://example.com/> .
+:cow a :Animal ; :says "moo" ; :likes :pig, :duck ; :name "Daisy" ; :legs 4 .
+:pig a :Animal ; :says "oink" ; :likes :cow ; :legs 4 .
+:duck a :Animal ; :says "quack" ; :legs 2 .
+:farmer a :Person ; :likes :cow ; :name "Old MacDonald"@en ; :legs 2 .
+"#;
+        import_turtle(document.as_bytes(), &builder).unwrap();
+        builder.commit().await.unwrap()
+    }
+
+    const PREFIXES: &str = "PREFIX : <http://example.com/>\n";
+
+    fn select(layer: &dyn Layer, query: &str) -> Vec<Vec<Option<String>>> {
+        let query = format!("{}{}", PREFIXES, query);
+        match query_sparql(layer, &query).unwrap() {
+            SparqlResult::Solutions(solutions) => solutions
+                .map(|row| {
+                    row.into_iter()
+                        .map(|object| {
+                            object.map(|object| match object {
+                                ObjectType::Node(node) => node
+                                    .strip_prefix("http://example.com/")
+                                    .unwrap_or(&node)
+                                    .to_string(),
+                                ObjectType::Value(value) => value,
+                            })
+                        })
+                        .collect()
+                })
+                .collect(),
+            SparqlResult::Boolean(_) => panic!("expected solutions"),
+        }
+    }
+
+    fn ask(layer: &dyn Layer, query: &str) -> bool {
+        let query = format!("{}{}", PREFIXES, query);
+        match query_sparql(layer, &query).unwrap() {
+            SparqlResult::Boolean(b) => b,
+            SparqlResult::Solutions(_) => panic!("expected boolean"),
+        }
+    }
+
+    fn row(values: &[&str]) -> Vec<Option<String>> {
+        values
+            .iter()
+            .map(|v| {
+                if v.is_empty() {
+                    None
+                } else {
+                    Some(v.to_string())
+                }
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn select_with_modifiers() {
+        let layer = example_layer().await;
+
+        let query = SparqlQuery::parse(
+            "SELECT * WHERE { ?x a <http://example.com/Animal> ; <http://example.com/says> ?sound }",
+        )
+        .unwrap();
+        assert!(!query.is_ask());
+        assert_eq!(
+            vec!["x".to_string(), "sound".to_string()],
+            query.selected_variables()
+        );
+
+        assert_eq!(
+            vec![
+                row(&["cow", "\"moo\""]),
+                row(&["duck", "\"quack\""]),
+                row(&["pig", "\"oink\""])
+            ],
+            select(
+                &layer,
+                "SELECT ?x ?sound { ?x a :Animal ; :says ?sound } ORDER BY ?x"
+            )
+        );
+        assert_eq!(
+            vec![row(&["duck"]), row(&["cow"])],
+            select(
+                &layer,
+                "SELECT ?x { ?x a :Animal } ORDER BY DESC(?x) LIMIT 2 OFFSET 1"
+            )
+        );
+
+        // numeric literals are ordered by value
+        assert_eq!(
+            vec![
+                row(&["\"2\"^^<http://www.w3.org/2001/XMLSchema#integer>"]),
+                row(&["\"4\"^^<http://www.w3.org/2001/XMLSchema#integer>"])
+            ],
+            select(
+                &layer,
+                "SELECT DISTINCT ?legs { ?x :legs ?legs } ORDER BY ?legs"
+            )
+        );
+        assert_eq!(
+            4,
+            select(&layer, "SELECT REDUCED ?legs { ?x :legs ?legs }").len()
+        );
+    }
+
+    #[tokio::test]
+    async fn filters() {
+        let layer = example_layer().await;
+
+        assert_eq!(
+            vec![row(&["duck"]), row(&["farmer"])],
+            select(
+                &layer,
+                "SELECT ?x { ?x :legs ?l FILTER(?l = 2.0) } ORDER BY ?x"
+            )
+        );
+        assert_eq!(
+            vec![row(&["cow"]), row(&["pig"])],
+            select(
+                &layer,
+                "SELECT ?x { ?x :legs ?l . FILTER(?l != 2 && ?x != :farmer) } ORDER BY ?x"
+            )
+        );
+        assert_eq!(
+            vec![row(&["duck"]), row(&["pig"])],
+            select(
+                &layer,
+                "SELECT ?x { ?x :says ?s FILTER regex(?s, \"^[OQ]\", \"i\") } ORDER BY ?x"
+            )
+        );
+        assert_eq!(
+            vec![row(&["farmer"])],
+            select(
+                &layer,
+                "SELECT ?x { ?x :name ?n FILTER (regex(?n, \"Mac\") || ?n = \"nothing\") }"
+            )
+        );
+        assert_eq!(
+            vec![row(&["cow"])],
+            select(
+                &layer,
+                "SELECT ?x { ?x :likes ?y FILTER(regex(str(?y), \"duck$\")) }"
+            )
+        );
+        // regex on an IRI is an error, which filters out the solution
+        assert!(select(
+            &layer,
+            "SELECT ?x { ?x :likes ?y FILTER regex(?y, \"duck\") }"
+        )
+        .is_empty());
+    }
+
+    #[tokio::test]
+    async fn optional_and_union() {
+        let layer = example_layer().await;
+
+        assert_eq!(
+            vec![
+                row(&["cow", "\"Daisy\""]),
+                row(&["duck", ""]),
+                row(&["pig", ""])
+            ],
+            select(
+                &layer,
+                "SELECT ?x ?n { ?x a :Animal OPTIONAL { ?x :name ?n } } ORDER BY ?x"
+            )
+        );
+        assert_eq!(
+            vec![row(&["duck"]), row(&["pig"])],
+            select(
+                &layer,
+                "SELECT ?x { ?x a :Animal OPTIONAL { ?x :name ?n } FILTER(!bound(?n)) } ORDER BY ?x"
+            )
+        );
+        assert_eq!(
+            vec![
+                row(&["cow", "pig"]),
+                row(&["duck", ""]),
+                row(&["farmer", "cow"]),
+                row(&["pig", "cow"])
+            ],
+            select(
+                &layer,
+                "SELECT ?x ?y {
+                   { ?x :likes ?y FILTER(?y != :duck) }
+                   UNION
+                   { ?x :says \"quack\" }
+                 } ORDER BY ?x"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn ask_queries() {
+        let layer = example_layer().await;
+
+        assert!(ask(&layer, "ASK { :cow :likes ?x . ?x :likes :cow }"));
+        assert!(!ask(&layer, "ASK WHERE { :duck :likes ?x }"));
+        assert!(ask(
+            &layer,
+            "ASK { _:b :likes :cow ; :name \"Old MacDonald\"@EN }"
+        ));
+        assert!(!ask(&layer, "ASK { :cow :unknown ?x }"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = SparqlQuery::parse("SELECT ?x WHERE {\n  ?x ex:foo ?y }").unwrap_err();
+        assert_eq!((2, 6), (err.line, err.column));
+        assert_eq!("undefined prefix 'ex:'", err.message);
+
+        let err = SparqlQuery::parse("CONSTRUCT { ?x ?y ?z }").unwrap_err();
+        assert_eq!("expected SELECT or ASK", err.message);
+
+        let err = SparqlQuery::parse("SELECT ?x { ?x ?y ?z FILTER regex(?x, \"(\") }").unwrap_err();
+        assert!(err.message.starts_with("invalid regex"));
+
+        let err = SparqlQuery::parse("SELECT ?x { ?x ?y ?z } LIMIT 10 extra").unwrap_err();
+        assert_eq!("unexpected input after query", err.message);
+    }
+}
@@ -0,0 +1,320 @@
+//! Low-level lexing of the syntax shared by N-Triples, N-Quads and Turtle.
+use super::RdfParseError;
+
+/// A cursor over some RDF text, keeping track of the current line and column for error reporting.
+pub(crate) struct Cursor<'a> {
+    input: &'a str,
+    pos: usize,
+    line: usize,
+    line_start: usize,
+    first_column: usize,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(input: &'a str, line: usize) -> Self {
+        Self::with_column(input, line, 1)
+    }
+
+    /// Create a cursor over input which starts at the given line and column.
+    pub fn with_column(input: &'a str, line: usize, column: usize) -> Self {
+        Cursor {
+            input,
+            pos: 0,
+            line,
+            line_start: 0,
+            first_column: column,
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        let column = self.input[self.line_start..self.pos].chars().count();
+        if self.line_start == 0 {
+            column + self.first_column
+        } else {
+            column + 1
+        }
+    }
+
+    /// The byte offset of this cursor in its input.
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn error<S: Into<String>>(&self, message: S) -> RdfParseError {
+        RdfParseError::new(self.line, self.column(), message)
+    }
+
+    pub fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.pos == self.input.len()
+    }
+
+    pub fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    pub fn peek_nth(&self, n: usize) -> Option<char> {
+        self.rest().chars().nth(n)
+    }
+
+    pub fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.line_start = self.pos;
+        }
+
+        Some(c)
+    }
+
+    pub fn starts_with(&self, s: &str) -> bool {
+        self.rest().starts_with(s)
+    }
+
+    /// Consume the given character if it is next, returning whether it was.
+    pub fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn expect(&mut self, c: char) -> Result<(), RdfParseError> {
+        match self.peek() {
+            Some(found) if found == c => {
+                self.bump();
+                Ok(())
+            }
+            Some(found) => Err(self.error(format!("expected '{}', found '{}'", c, found))),
+            None => Err(self.error(format!("expected '{}', found end of input", c))),
+        }
+    }
+
+    /// Skip spaces and tabs.
+    pub fn skip_blanks(&mut self) {
+        while let Some(' ') | Some('\t') = self.peek() {
+            self.bump();
+        }
+    }
+
+    /// Skip the rest of the current line if it starts a comment.
+    pub fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.bump();
+            }
+        }
+    }
+
+    /// Skip whitespace, including newlines, and comments.
+    pub fn skip_whitespace(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ') | Some('\t') | Some('\n') | Some('\r') => {
+                    self.bump();
+                }
+                Some('#') => self.skip_comment(),
+                _ => break,
+            }
+        }
+    }
+
+    fn parse_hex(&mut self, digits: usize) -> Result<char, RdfParseError> {
+        let mut code = 0;
+        for _ in 0..digits {
+            let digit = self
+                .peek()
+                .and_then(|c| c.to_digit(16))
+                .ok_or_else(|| self.error("invalid hexadecimal digit in unicode escape"))?;
+            self.bump();
+            code = code * 16 + digit;
+        }
+
+        std::char::from_u32(code)
+            .ok_or_else(|| self.error("unicode escape is not a valid character"))
+    }
+
+    /// Parse a `\uXXXX` or `\UXXXXXXXX` escape, after the backslash.
+    pub fn parse_uchar(&mut self) -> Result<char, RdfParseError> {
+        match self.bump() {
+            Some('u') => self.parse_hex(4),
+            Some('U') => self.parse_hex(8),
+            _ => Err(self.error("invalid escape sequence")),
+        }
+    }
+
+    /// Parse a string or unicode escape, after the backslash.
+    pub fn parse_escape(&mut self) -> Result<char, RdfParseError> {
+        let c = match self.peek() {
+            Some('t') => '\t',
+            Some('b') => '\u{8}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('f') => '\u{c}',
+            Some('"') => '"',
+            Some('\'') => '\'',
+            Some('\\') => '\\',
+            _ => return self.parse_uchar(),
+        };
+        self.bump();
+
+        Ok(c)
+    }
+
+    /// Parse an IRI enclosed in angle brackets, returning it without the brackets and with escapes resolved.
+    pub fn parse_iriref(&mut self) -> Result<String, RdfParseError> {
+        self.expect('<')?;
+        let mut iri = String::new();
+        loop {
+            let c = match self.peek() {
+                None | Some('\n') => return Err(self.error("unterminated IRI")),
+                Some(c) => c,
+            };
+            self.bump();
+            match c {
+                '>' => break,
+                '\\' => {
+                    let c = self.parse_uchar()?;
+                    iri.push(c);
+                }
+                c if c <= ' ' || matches!(c, '<' | '"' | '{' | '}' | '|' | '^' | '`') => {
+                    return Err(self.error(format!("invalid character {:?} in IRI", c)))
+                }
+                c => iri.push(c),
+            }
+        }
+
+        Ok(iri)
+    }
+
+    /// Parse a blank node label, returning the label without the `_:` prefix.
+    pub fn parse_blank_node_label(&mut self) -> Result<String, RdfParseError> {
+        if !self.starts_with("_:") {
+            return Err(self.error("expected blank node"));
+        }
+        self.bump();
+        self.bump();
+
+        let mut label = String::new();
+        match self.peek() {
+            Some(c) if is_pn_chars_u(c) || c.is_ascii_digit() => {
+                self.bump();
+                label.push(c);
+            }
+            _ => return Err(self.error("invalid blank node label")),
+        }
+        loop {
+            match self.peek() {
+                Some(c) if is_pn_chars(c) => {
+                    self.bump();
+                    label.push(c);
+                }
+                // a dot is allowed inside a label, but not at its end
+                Some('.') if matches!(self.peek_nth(1), Some(c) if is_pn_chars_or_dot(c)) => {
+                    self.bump();
+                    label.push('.');
+                }
+                _ => break,
+            }
+        }
+        if label.ends_with('.') {
+            return Err(self.error("blank node label cannot end with '.'"));
+        }
+
+        Ok(label)
+    }
+
+    /// Parse a single-line string literal delimited by the given quote character.
+    pub fn parse_quoted_string(&mut self, quote: char) -> Result<String, RdfParseError> {
+        self.expect(quote)?;
+        let mut value = String::new();
+        loop {
+            let c = match self.peek() {
+                None | Some('\n') | Some('\r') => return Err(self.error("unterminated string")),
+                Some(c) => c,
+            };
+            self.bump();
+            match c {
+                '\\' => {
+                    let c = self.parse_escape()?;
+                    value.push(c);
+                }
+                c if c == quote => break,
+                c => value.push(c),
+            }
+        }
+
+        Ok(value)
+    }
+
+    /// Parse a language tag, after the `@`.
+    pub fn parse_langtag(&mut self) -> Result<String, RdfParseError> {
+        let mut tag = String::new();
+        while let Some(c) = self.peek().filter(|c| c.is_ascii_alphabetic()) {
+            self.bump();
+            tag.push(c);
+        }
+        if tag.is_empty() {
+            return Err(self.error("invalid language tag"));
+        }
+        while self.peek() == Some('-') {
+            self.bump();
+            tag.push('-');
+            let len = tag.len();
+            while let Some(c) = self.peek().filter(|c| c.is_ascii_alphanumeric()) {
+                self.bump();
+                tag.push(c);
+            }
+            if tag.len() == len {
+                return Err(self.error("invalid language tag"));
+            }
+        }
+
+        Ok(tag)
+    }
+}
+
+pub(crate) fn is_pn_chars_base(c: char) -> bool {
+    matches!(c,
+        'A'..='Z'
+        | 'a'..='z'
+        | '\u{00C0}'..='\u{00D6}'
+        | '\u{00D8}'..='\u{00F6}'
+        | '\u{00F8}'..='\u{02FF}'
+        | '\u{0370}'..='\u{037D}'
+        | '\u{037F}'..='\u{1FFF}'
+        | '\u{200C}'..='\u{200D}'
+        | '\u{2070}'..='\u{218F}'
+        | '\u{2C00}'..='\u{2FEF}'
+        | '\u{3001}'..='\u{D7FF}'
+        | '\u{F900}'..='\u{FDCF}'
+        | '\u{FDF0}'..='\u{FFFD}'
+        | '\u{10000}'..='\u{EFFFF}')
+}
+
+pub(crate) fn is_pn_chars_u(c: char) -> bool {
+    c == '_' || is_pn_chars_base(c)
+}
+
+pub(crate) fn is_pn_chars(c: char) -> bool {
+    is_pn_chars_u(c)
+        || c == '-'
+        || c.is_ascii_digit()
+        || c == '\u{00B7}'
+        || ('\u{0300}'..='\u{036F}').contains(&c)
+        || ('\u{203F}'..='\u{2040}').contains(&c)
+}
+
+fn is_pn_chars_or_dot(c: char) -> bool {
+    c == '.' || is_pn_chars(c)
+}
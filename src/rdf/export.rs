@@ -0,0 +1,456 @@
+//! N-Triples and Turtle export.
+//!
+//! Exporting reverses the mapping used on import. Nodes of the form
+//! `_:label` are written as blank nodes and all other nodes as IRIs.
+//! Values that are literals in N-Triples syntax are written as such,
+//! and any other value is written as a plain string literal.
+//!
+//! Triples are written in the order the layer returns them, which is
+//! by subject, predicate and object id. Exporting the same layer will
+//! therefore always give the same output.
+use super::cursor::*;
+use super::ntriples::{is_stored_blank_node, parse_stored_literal};
+use super::*;
+use crate::layer::{IdTriple, Layer, StringTriple};
+use crate::store::StoreLayer;
+use std::io::{BufWriter, Write};
+
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+
+/// Which triples of a layer to export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportSelection {
+    /// All triples in the layer, including those inherited from its ancestors.
+    All,
+    /// Only the triples that were added in this layer.
+    Additions,
+    /// Only the triples that were removed in this layer.
+    Removals,
+}
+
+impl ExportSelection {
+    async fn triples(
+        self,
+        layer: &StoreLayer,
+    ) -> io::Result<Box<dyn Iterator<Item = IdTriple> + Send>> {
+        match self {
+            ExportSelection::All => Ok(layer.triples()),
+            ExportSelection::Additions => layer.triple_additions().await,
+            ExportSelection::Removals => layer.triple_removals().await,
+        }
+    }
+}
+
+fn is_valid_prefix(prefix: &str) -> bool {
+    let mut chars = prefix.chars();
+    match chars.next() {
+        None => return true,
+        Some(c) if is_pn_chars_base(c) => {}
+        _ => return false,
+    }
+
+    !prefix.ends_with('.') && chars.all(|c| is_pn_chars(c) || c == '.')
+}
+
+/// Returns true if the given string can be written as the local part of a prefixed name without escaping.
+fn is_valid_local_name(local: &str) -> bool {
+    let mut chars = local.chars();
+    match chars.next() {
+        None => return true,
+        Some(c) if is_pn_chars_u(c) || c == ':' || c.is_ascii_digit() => {}
+        _ => return false,
+    }
+
+    !local.ends_with('.') && chars.all(|c| is_pn_chars(c) || c == ':' || c == '.')
+}
+
+struct TermFormatter<'a> {
+    prefixes: &'a [(&'a str, &'a str)],
+}
+
+impl<'a> TermFormatter<'a> {
+    fn push_iri(&self, out: &mut String, iri: &str) {
+        let compacted = self
+            .prefixes
+            .iter()
+            .filter_map(|(prefix, namespace)| {
+                iri.strip_prefix(namespace)
+                    .filter(|local| is_valid_local_name(local))
+                    .map(|local| (prefix, namespace.len(), local))
+            })
+            .max_by_key(|(_, len, _)| *len);
+        if let Some((prefix, _, local)) = compacted {
+            out.push_str(prefix);
+            out.push(':');
+            out.push_str(local);
+            return;
+        }
+
+        out.push('<');
+        for c in iri.chars() {
+            if c <= ' ' || matches!(c, '<' | '>' | '"' | '{' | '}' | '|' | '^' | '`' | '\\') {
+                out.push_str(&format!("\\u{:04X}", c as u32));
+            } else {
+                out.push(c);
+            }
+        }
+        out.push('>');
+    }
+
+    fn push_node(&self, out: &mut String, node: &str) {
+        if is_stored_blank_node(node) {
+            out.push_str(node);
+        } else {
+            self.push_iri(out, node);
+        }
+    }
+
+    fn push_value(&self, out: &mut String, value: &str) {
+        match parse_stored_literal(value) {
+            Some(Term::Literal {
+                value,
+                language,
+                datatype,
+            }) => {
+                out.push_str(&format_literal(&value, language.as_deref(), None));
+                match datatype {
+                    Some(datatype) if language.is_none() && datatype != XSD_STRING => {
+                        out.push_str("^^");
+                        self.push_iri(out, &datatype);
+                    }
+                    _ => {}
+                }
+            }
+            _ => out.push_str(&format_literal(value, None, None)),
+        }
+    }
+
+    fn push_object(&self, out: &mut String, object: &ObjectType) {
+        match object {
+            ObjectType::Node(node) => self.push_node(out, node),
+            ObjectType::Value(value) => self.push_value(out, value),
+        }
+    }
+}
+
+/// Format an IRI the way it is written in N-Triples.
+pub(crate) fn format_iri(iri: &str) -> String {
+    let mut out = String::with_capacity(iri.len() + 2);
+    TermFormatter { prefixes: &[] }.push_iri(&mut out, iri);
+
+    out
+}
+
+fn triple_to_string(layer: &dyn Layer, triple: &IdTriple) -> io::Result<StringTriple> {
+    layer.id_triple_to_string(triple).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("triple {:?} refers to an unknown id", triple),
+        )
+    })
+}
+
+/// Write the given triples of a layer as an N-Triples document.
+///
+/// Returns the amount of triples that were written.
+pub fn write_ntriples<W: Write, I: Iterator<Item = IdTriple>>(
+    layer: &dyn Layer,
+    triples: I,
+    writer: W,
+) -> io::Result<usize> {
+    let formatter = TermFormatter { prefixes: &[] };
+    let mut writer = BufWriter::new(writer);
+    let mut line = String::new();
+    let mut count = 0;
+    for triple in triples {
+        let triple = triple_to_string(layer, &triple)?;
+        line.clear();
+        formatter.push_node(&mut line, &triple.subject);
+        line.push(' ');
+        formatter.push_iri(&mut line, &triple.predicate);
+        line.push(' ');
+        formatter.push_object(&mut line, &triple.object);
+        line.push_str(" .\n");
+        writer.write_all(line.as_bytes())?;
+        count += 1;
+    }
+    writer.flush()?;
+
+    Ok(count)
+}
+
+/// Write the given triples of a layer as a Turtle document.
+///
+/// Each prefix is given as a pair of prefix name and namespace
+/// IRI. IRIs that start with one of the namespaces are written as
+/// prefixed names. Consecutive triples which share a subject, or a
+/// subject and predicate, are grouped together.
+///
+/// Returns the amount of triples that were written.
+pub fn write_turtle<W: Write, I: Iterator<Item = IdTriple>>(
+    layer: &dyn Layer,
+    triples: I,
+    prefixes: &[(&str, &str)],
+    writer: W,
+) -> io::Result<usize> {
+    if let Some((prefix, _)) = prefixes.iter().find(|(p, _)| !is_valid_prefix(p)) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid prefix name '{}'", prefix),
+        ));
+    }
+
+    let formatter = TermFormatter { prefixes };
+    let mut writer = BufWriter::new(writer);
+    let mut out = String::new();
+    for (prefix, namespace) in prefixes {
+        out.clear();
+        out.push_str("@prefix ");
+        out.push_str(prefix);
+        out.push_str(": ");
+        out.push_str(&format_iri(namespace));
+        out.push_str(" .\n");
+        writer.write_all(out.as_bytes())?;
+    }
+    if !prefixes.is_empty() {
+        writer.write_all(b"\n")?;
+    }
+
+    let mut last: Option<(u64, u64)> = None;
+    let mut count = 0;
+    for triple in triples {
+        let string_triple = triple_to_string(layer, &triple)?;
+        out.clear();
+        match last {
+            Some((s, p)) if s == triple.subject && p == triple.predicate => out.push_str(", "),
+            _ => {
+                match last {
+                    Some((s, _)) if s == triple.subject => out.push_str(" ;\n    "),
+                    _ => {
+                        if last.is_some() {
+                            out.push_str(" .\n\n");
+                        }
+                        formatter.push_node(&mut out, &string_triple.subject);
+                        out.push(' ');
+                    }
+                }
+                if string_triple.predicate == RDF_TYPE {
+                    out.push('a');
+                } else {
+                    formatter.push_iri(&mut out, &string_triple.predicate);
+                }
+                out.push(' ');
+            }
+        }
+        formatter.push_object(&mut out, &string_triple.object);
+        writer.write_all(out.as_bytes())?;
+
+        last = Some((triple.subject, triple.predicate));
+        count += 1;
+    }
+    if last.is_some() {
+        writer.write_all(b" .\n")?;
+    }
+    writer.flush()?;
+
+    Ok(count)
+}
+
+/// Write the selected triples of a layer to the writer as an N-Triples document.
+///
+/// Exporting the additions or removals of a layer gives a patch that
+/// can be applied to its parent.
+///
+/// Returns the amount of triples that were written.
+pub async fn export_ntriples<W: Write + Send>(
+    layer: &StoreLayer,
+    selection: ExportSelection,
+    writer: W,
+) -> io::Result<usize> {
+    let triples = selection.triples(layer).await?;
+
+    write_ntriples(layer, triples, writer)
+}
+
+/// Write the selected triples of a layer to the writer as a Turtle document.
+///
+/// See `write_turtle` for how prefixes are used.
+///
+/// Returns the amount of triples that were written.
+pub async fn export_turtle<W: Write + Send>(
+    layer: &StoreLayer,
+    selection: ExportSelection,
+    prefixes: &[(&str, &str)],
+    writer: W,
+) -> io::Result<usize> {
+    let triples = selection.triples(layer).await?;
+
+    write_turtle(layer, triples, prefixes, writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::open_memory_store;
+
+    const DOCUMENT: &str = r#"@prefix ex: <http://example.com/> .
+ex:cow a ex:Animal ;
+    ex:says "moo", "mooo"@en ;
+    ex:weight 500 ;
+    ex:likes [ ex:name "duck" ], <http://other.org/a> .
+"#;
+
+    async fn example_layer() -> StoreLayer {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        import_turtle(DOCUMENT.as_bytes(), &builder).unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value(
+                "http://example.com/cow",
+                "http://example.com/note",
+                "not a literal",
+            ))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node(
+                "http://example.com/cow",
+                "http://example.com/likes",
+                "http://other.org/a b",
+            ))
+            .unwrap();
+
+        builder.commit().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn export_ntriples_roundtrip() {
+        let layer = example_layer().await;
+        let mut output = Vec::new();
+        let count = export_ntriples(&layer, ExportSelection::All, &mut output)
+            .await
+            .unwrap();
+        assert_eq!(9, count);
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(
+            "<http://example.com/cow> <http://example.com/likes> <http://other.org/a\\u0020b> .\n"
+        ));
+        assert!(output
+            .contains("<http://example.com/cow> <http://example.com/note> \"not a literal\" .\n"));
+
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        import_ntriples(output.as_bytes(), &builder).unwrap();
+        let reimported = builder.commit().await.unwrap();
+
+        let mut expected: Vec<_> = layer
+            .triples()
+            .map(|t| layer.id_triple_to_string(&t).unwrap())
+            .collect();
+        let mut actual: Vec<_> = reimported
+            .triples()
+            .map(|t| reimported.id_triple_to_string(&t).unwrap())
+            .collect();
+        // the non-literal value is exported as a plain literal
+        for triple in expected.iter_mut() {
+            if triple.predicate == "http://example.com/note" {
+                triple.object = ObjectType::Value("\"not a literal\"".to_string());
+            }
+        }
+        expected.sort();
+        actual.sort();
+        assert_eq!(expected, actual);
+
+        let mut again = Vec::new();
+        export_ntriples(&layer, ExportSelection::All, &mut again)
+            .await
+            .unwrap();
+        assert_eq!(output.as_bytes(), &again[..]);
+    }
+
+    #[tokio::test]
+    async fn export_turtle_with_prefixes() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        import_turtle(DOCUMENT.as_bytes(), &builder).unwrap();
+        let layer = builder.commit().await.unwrap();
+
+        let mut output = Vec::new();
+        let prefixes = [
+            ("ex", "http://example.com/"),
+            ("xsd", "http://www.w3.org/2001/XMLSchema#"),
+        ];
+        export_turtle(&layer, ExportSelection::All, &prefixes, &mut output)
+            .await
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert_eq!(
+            r#"@prefix ex: <http://example.com/> .
+@prefix xsd: <http://www.w3.org/2001/XMLSchema#> .
+
+_:genid0 ex:name "duck" .
+
+ex:cow ex:likes _:genid0, <http://other.org/a> ;
+    ex:says "moo", "mooo"@en ;
+    ex:weight "500"^^xsd:integer ;
+    a ex:Animal .
+"#,
+            output
+        );
+
+        let reimported: Vec<_> = TurtleParser::new(output.as_bytes())
+            .map(|t| t.unwrap())
+            .collect();
+        assert_eq!(7, reimported.len());
+        // on import, labels in the document are kept apart from generated labels
+        let unescape = |label: String| label.replacen("_:genid_", "_:genid", 1);
+        for mut triple in reimported {
+            triple.subject = unescape(triple.subject);
+            if let ObjectType::Node(node) = triple.object {
+                triple.object = ObjectType::Node(unescape(node));
+            }
+            assert!(layer.string_triple_exists(&triple));
+        }
+    }
+
+    #[tokio::test]
+    async fn export_patch() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        import_ntriples("<a> <b> <c> .\n<a> <b> <d> .\n".as_bytes(), &builder).unwrap();
+        let base = builder.commit().await.unwrap();
+
+        let builder = base.open_write().await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_node("a", "b", "c"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("a", "b", "\"e\""))
+            .unwrap();
+        let child = builder.commit().await.unwrap();
+
+        let mut additions = Vec::new();
+        export_ntriples(&child, ExportSelection::Additions, &mut additions)
+            .await
+            .unwrap();
+        let mut removals = Vec::new();
+        export_ntriples(&child, ExportSelection::Removals, &mut removals)
+            .await
+            .unwrap();
+
+        assert_eq!(b"<a> <b> \"e\" .\n", &additions[..]);
+        assert_eq!(b"<a> <b> <c> .\n", &removals[..]);
+    }
+
+    #[test]
+    fn invalid_prefixes_are_rejected() {
+        assert!(is_valid_prefix(""));
+        assert!(is_valid_prefix("ex.a"));
+        assert!(!is_valid_prefix("ex."));
+        assert!(!is_valid_prefix("1ex"));
+        assert!(is_valid_local_name("a.b"));
+        assert!(!is_valid_local_name("a."));
+        assert!(!is_valid_local_name("a/b"));
+    }
+}
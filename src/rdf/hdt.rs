@@ -0,0 +1,1367 @@
+//! Reading and writing of HDT (Header-Dictionary-Triples) files.
+//!
+//! HDT is a compact binary format for RDF. An HDT file consists of a
+//! header with metadata, a dictionary which maps each term onto an
+//! id, and the triples as a sorted list of id triples. The structures
+//! used are very similar to what this crate uses for layers. The
+//! dictionary is made up of plain front-coded string sections, and
+//! the triples are stored as bitmap triples, which is an adjacency
+//! list from subjects to predicates and from subject-predicate pairs
+//! to objects.
+//!
+//! Only the format produced by the reference implementations is
+//! supported. That is, a four section dictionary (shared, subjects,
+//! predicates and objects) using plain front coding, and bitmap
+//! triples in subject-predicate-object order.
+//!
+//! HDT stores terms in the same form as the RDF importers do: IRIs
+//! without angle brackets, blank nodes as `_:label`, and literals in
+//! quotes with their language tag or datatype. Terms are therefore
+//! moved between a layer and an HDT file as is.
+use super::export::format_iri;
+use super::ntriples::parse_stored_literal;
+use super::*;
+use crate::layer::{BaseLayerFileBuilder, IdTriple, Layer, LayerBuilder, StringTriple};
+use crate::storage::{BaseLayerFiles, FileLoad, FileStore};
+use crate::structure::vbyte;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::io::Write;
+
+const COOKIE: &[u8] = b"$HDT";
+
+const CONTROL_GLOBAL: u8 = 1;
+const CONTROL_HEADER: u8 = 2;
+const CONTROL_DICTIONARY: u8 = 3;
+const CONTROL_TRIPLES: u8 = 4;
+
+const HDT_CONTAINER: &str = "<http://purl.org/HDT/hdt#HDTv1>";
+const DICTIONARY_FOUR: &str = "<http://purl.org/HDT/hdt#dictionaryFour>";
+const TRIPLES_BITMAP: &str = "<http://purl.org/HDT/hdt#triplesBitmap>";
+
+const SECTION_PFC: u8 = 2;
+const SEQUENCE_LOG64: u8 = 1;
+const BITMAP_PLAIN: u8 = 1;
+const ORDER_SPO: &str = "1";
+
+const PFC_BLOCK_SIZE: u64 = 16;
+
+/// An error encountered while reading an HDT file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HdtError {
+    pub message: String,
+}
+
+impl HdtError {
+    fn new<S: Into<String>>(message: S) -> Self {
+        HdtError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for HdtError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "invalid HDT file: {}", self.message)
+    }
+}
+
+impl Error for HdtError {}
+
+impl From<HdtError> for io::Error {
+    fn from(err: HdtError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in data {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    crc
+}
+
+lazy_static! {
+    static ref CRC32C_TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut crc = i as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0x82F6_3B78
+                } else {
+                    crc >> 1
+                };
+            }
+            *entry = crc;
+        }
+
+        table
+    };
+}
+
+/// Continue a CRC32C checksum, from the state before its final inversion.
+fn crc32c_update(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc = (crc >> 8) ^ CRC32C_TABLE[((crc ^ *byte as u32) & 0xFF) as usize];
+    }
+
+    crc
+}
+
+fn crc32c(data: &[u8]) -> u32 {
+    !crc32c_update(!0, data)
+}
+
+/// A sequence of fixed-width integers, packed little-endian.
+struct LogSequence<'a> {
+    width: u8,
+    len: u64,
+    data: &'a [u8],
+}
+
+impl<'a> LogSequence<'a> {
+    fn get(&self, index: u64) -> u64 {
+        let width = self.width as u64;
+        let start = index * width;
+        let mut result = 0;
+        let mut read = 0;
+        while read < width {
+            let pos = start + read;
+            let offset = pos % 8;
+            let take = std::cmp::min(8 - offset, width - read);
+            let bits = (self.data[(pos / 8) as usize] >> offset) as u64 & ((1 << take) - 1);
+            result |= bits << read;
+            read += take;
+        }
+
+        result
+    }
+}
+
+/// A plain front-coded dictionary section.
+struct PfcSection<'a> {
+    len: u64,
+    block_size: u64,
+    text: &'a [u8],
+}
+
+impl<'a> PfcSection<'a> {
+    /// Iterate over the strings in this section, checking that they are in order.
+    fn iter(&self) -> PfcIterator<'a> {
+        PfcIterator {
+            text: self.text,
+            block_size: self.block_size,
+            index: 0,
+            len: self.len,
+            last: Vec::new(),
+        }
+    }
+
+    /// Check that this section decodes into exactly its amount of strings, and that these are sorted and unique.
+    ///
+    /// With `allow_literals` false, the section may not contain literals either.
+    fn validate(&self, allow_literals: bool) -> Result<(), HdtError> {
+        let mut iter = self.iter();
+        for string in &mut iter {
+            let string = string?;
+            if !allow_literals && string.starts_with('"') {
+                return Err(HdtError::new("literal in a section of subjects"));
+            }
+        }
+        if !iter.text.is_empty() {
+            return Err(HdtError::new("trailing data in dictionary section"));
+        }
+
+        Ok(())
+    }
+}
+
+struct PfcIterator<'a> {
+    text: &'a [u8],
+    block_size: u64,
+    index: u64,
+    len: u64,
+    last: Vec<u8>,
+}
+
+impl<'a> PfcIterator<'a> {
+    fn next_string(&mut self) -> Result<String, HdtError> {
+        let mut string = if self.index.is_multiple_of(self.block_size) {
+            Vec::new()
+        } else {
+            let (prefix, len) = vbyte::decode(self.text)
+                .map_err(|_| HdtError::new("invalid prefix length in dictionary"))?;
+            self.text = &self.text[len..];
+            if prefix as usize > self.last.len() {
+                return Err(HdtError::new("invalid prefix length in dictionary"));
+            }
+            self.last[..prefix as usize].to_vec()
+        };
+        let end = self
+            .text
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(|| HdtError::new("unterminated string in dictionary"))?;
+        string.extend_from_slice(&self.text[..end]);
+        self.text = &self.text[end + 1..];
+
+        if self.index != 0 && string <= self.last {
+            return Err(HdtError::new("dictionary section is not sorted"));
+        }
+        self.index += 1;
+        self.last.clear();
+        self.last.extend_from_slice(&string);
+
+        String::from_utf8(string).map_err(|_| HdtError::new("invalid utf-8 in dictionary"))
+    }
+}
+
+impl<'a> Iterator for PfcIterator<'a> {
+    type Item = Result<String, HdtError>;
+
+    fn next(&mut self) -> Option<Result<String, HdtError>> {
+        if self.index == self.len {
+            None
+        } else {
+            Some(self.next_string())
+        }
+    }
+}
+
+struct ControlInformation {
+    format: String,
+    properties: HashMap<String, String>,
+}
+
+/// A cursor over the raw bytes of an HDT file.
+struct HdtReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> HdtReader<'a> {
+    fn take(&mut self, len: u64) -> Result<&'a [u8], HdtError> {
+        if len > (self.data.len() - self.pos) as u64 {
+            return Err(HdtError::new("unexpected end of file"));
+        }
+        let result = &self.data[self.pos..self.pos + len as usize];
+        self.pos += len as usize;
+
+        Ok(result)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, HdtError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_vbyte(&mut self) -> Result<u64, HdtError> {
+        let (num, len) = vbyte::decode(&self.data[self.pos..])
+            .map_err(|_| HdtError::new("invalid variable-byte integer"))?;
+        self.pos += len;
+
+        Ok(num)
+    }
+
+    fn read_cstring(&mut self) -> Result<String, HdtError> {
+        let len = self.data[self.pos..]
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(|| HdtError::new("unterminated string"))?;
+        let bytes = self.take(len as u64)?;
+        self.pos += 1;
+
+        String::from_utf8(bytes.to_vec()).map_err(|_| HdtError::new("invalid utf-8"))
+    }
+
+    fn check_crc8(&mut self, start: usize) -> Result<(), HdtError> {
+        let crc = crc8(&self.data[start..self.pos]);
+        if self.read_u8()? != crc {
+            return Err(HdtError::new("checksum mismatch"));
+        }
+
+        Ok(())
+    }
+
+    fn check_crc32(&mut self, start: usize) -> Result<(), HdtError> {
+        let crc = crc32c(&self.data[start..self.pos]);
+        let stored = self.take(4)?;
+        if stored != crc.to_le_bytes() {
+            return Err(HdtError::new("checksum mismatch"));
+        }
+
+        Ok(())
+    }
+
+    fn read_control_information(&mut self, kind: u8) -> Result<ControlInformation, HdtError> {
+        let start = self.pos;
+        if self.take(4)? != COOKIE {
+            return Err(HdtError::new("missing $HDT cookie"));
+        }
+        if self.read_u8()? != kind {
+            return Err(HdtError::new("unexpected section"));
+        }
+        let format = self.read_cstring()?;
+        let properties = self
+            .read_cstring()?
+            .split(';')
+            .filter_map(|property| property.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        let crc = crc16(&self.data[start..self.pos]);
+        if self.take(2)? != crc.to_le_bytes() {
+            return Err(HdtError::new("checksum mismatch in control information"));
+        }
+
+        Ok(ControlInformation { format, properties })
+    }
+
+    fn read_log_sequence(&mut self) -> Result<LogSequence<'a>, HdtError> {
+        let start = self.pos;
+        if self.read_u8()? != SEQUENCE_LOG64 {
+            return Err(HdtError::new("unsupported sequence type"));
+        }
+        let width = self.read_u8()?;
+        if width > 64 {
+            return Err(HdtError::new("invalid sequence width"));
+        }
+        let len = self.read_vbyte()?;
+        self.check_crc8(start)?;
+
+        let data_len = (len as u128 * width as u128).div_ceil(8);
+        if data_len > (self.data.len() - self.pos) as u128 {
+            return Err(HdtError::new("unexpected end of file"));
+        }
+        let start = self.pos;
+        let data = self.take(data_len as u64)?;
+        self.check_crc32(start)?;
+
+        Ok(LogSequence { width, len, data })
+    }
+
+    fn read_bitmap(&mut self) -> Result<LogSequence<'a>, HdtError> {
+        let start = self.pos;
+        if self.read_u8()? != BITMAP_PLAIN {
+            return Err(HdtError::new("unsupported bitmap type"));
+        }
+        let len = self.read_vbyte()?;
+        self.check_crc8(start)?;
+
+        let start = self.pos;
+        let data = self.take(len.div_ceil(8))?;
+        self.check_crc32(start)?;
+
+        Ok(LogSequence {
+            width: 1,
+            len,
+            data,
+        })
+    }
+
+    fn read_pfc_section(&mut self) -> Result<PfcSection<'a>, HdtError> {
+        let start = self.pos;
+        if self.read_u8()? != SECTION_PFC {
+            return Err(HdtError::new("unsupported dictionary section type"));
+        }
+        let len = self.read_vbyte()?;
+        let text_len = self.read_vbyte()?;
+        let block_size = self.read_vbyte()?;
+        self.check_crc8(start)?;
+        if block_size == 0 {
+            return Err(HdtError::new("invalid dictionary block size"));
+        }
+
+        // The block offsets allow random access, but we only ever read sections sequentially.
+        self.read_log_sequence()?;
+
+        let start = self.pos;
+        let text = self.take(text_len)?;
+        self.check_crc32(start)?;
+
+        Ok(PfcSection {
+            len,
+            block_size,
+            text,
+        })
+    }
+}
+
+/// The parts of an HDT file needed to build a layer.
+struct HdtDocument<'a> {
+    shared: PfcSection<'a>,
+    subjects: PfcSection<'a>,
+    predicates: PfcSection<'a>,
+    objects: PfcSection<'a>,
+
+    bitmap_y: LogSequence<'a>,
+    bitmap_z: LogSequence<'a>,
+    sequence_y: LogSequence<'a>,
+    sequence_z: LogSequence<'a>,
+}
+
+impl<'a> HdtDocument<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, HdtError> {
+        let mut reader = HdtReader { data, pos: 0 };
+
+        let global = reader.read_control_information(CONTROL_GLOBAL)?;
+        if global.format != HDT_CONTAINER {
+            return Err(HdtError::new(format!(
+                "unsupported container format {}",
+                global.format
+            )));
+        }
+
+        let header = reader.read_control_information(CONTROL_HEADER)?;
+        let header_len = header
+            .properties
+            .get("length")
+            .and_then(|len| len.parse().ok())
+            .ok_or_else(|| HdtError::new("header has no length"))?;
+        reader.take(header_len)?;
+
+        let dictionary = reader.read_control_information(CONTROL_DICTIONARY)?;
+        if dictionary.format != DICTIONARY_FOUR {
+            return Err(HdtError::new(format!(
+                "unsupported dictionary format {}",
+                dictionary.format
+            )));
+        }
+        let shared = reader.read_pfc_section()?;
+        let subjects = reader.read_pfc_section()?;
+        let predicates = reader.read_pfc_section()?;
+        let objects = reader.read_pfc_section()?;
+        // the dictionaries of a layer are built from these sections
+        // without further checks, so they have to be valid
+        shared.validate(false)?;
+        subjects.validate(false)?;
+        predicates.validate(true)?;
+        objects.validate(true)?;
+
+        let triples = reader.read_control_information(CONTROL_TRIPLES)?;
+        if triples.format != TRIPLES_BITMAP {
+            return Err(HdtError::new(format!(
+                "unsupported triples format {}",
+                triples.format
+            )));
+        }
+        if matches!(triples.properties.get("order"), Some(order) if order != ORDER_SPO) {
+            return Err(HdtError::new(
+                "triples are not in subject-predicate-object order",
+            ));
+        }
+        let bitmap_y = reader.read_bitmap()?;
+        let bitmap_z = reader.read_bitmap()?;
+        let sequence_y = reader.read_log_sequence()?;
+        let sequence_z = reader.read_log_sequence()?;
+        if bitmap_y.len != sequence_y.len || bitmap_z.len != sequence_z.len {
+            return Err(HdtError::new(
+                "triple bitmaps and sequences differ in length",
+            ));
+        }
+
+        Ok(HdtDocument {
+            shared,
+            subjects,
+            predicates,
+            objects,
+            bitmap_y,
+            bitmap_z,
+            sequence_y,
+            sequence_z,
+        })
+    }
+
+    /// Iterate over the id triples, as numbered in the HDT dictionary, in order.
+    fn for_each_triple<E: From<HdtError>, C: FnMut(u64, u64, u64) -> Result<(), E>>(
+        &self,
+        mut callback: C,
+    ) -> Result<(), E> {
+        let mut subject = 1;
+        let mut z = 0;
+        for y in 0..self.sequence_y.len {
+            let predicate = self.sequence_y.get(y);
+            loop {
+                if z == self.sequence_z.len {
+                    return Err(HdtError::new("triple bitmaps are inconsistent").into());
+                }
+                callback(subject, predicate, self.sequence_z.get(z))?;
+                let last = self.bitmap_z.get(z) == 1;
+                z += 1;
+                if last {
+                    break;
+                }
+            }
+            if self.bitmap_y.get(y) == 1 {
+                subject += 1;
+            }
+        }
+        if z != self.sequence_z.len {
+            return Err(HdtError::new("triple bitmaps are inconsistent").into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Strings from a dictionary section, together with their index in the HDT id space.
+type NodeSource<'a> = Box<dyn Iterator<Item = Result<(usize, String), HdtError>> + Send + 'a>;
+
+/// Build a base layer from the dictionary and triples of an HDT file.
+///
+/// Terms are merged into the node, predicate and value dictionaries
+/// of the layer while keeping track of how HDT ids map onto layer ids.
+/// The triples are then translated through this mapping, without any
+/// string lookups.
+pub(crate) async fn build_base_layer_from_hdt<F: 'static + FileLoad + FileStore + Clone>(
+    hdt: &[u8],
+    files: BaseLayerFiles<F>,
+) -> io::Result<()> {
+    let document = HdtDocument::parse(hdt)?;
+    let shared_len = document.shared.len as usize;
+    let mut builder = BaseLayerFileBuilder::from_files(&files);
+
+    // HDT ids of subjects and objects both start with the shared
+    // section, so we keep one mapping for each which share that part.
+    let mut subject_ids = vec![0; shared_len + document.subjects.len as usize];
+    let mut object_ids = vec![0; shared_len + document.objects.len as usize];
+
+    // nodes come from the shared and subject sections and from the
+    // non-literal part of the objects section. Each of these is
+    // sorted, so a merge gives us all nodes in order.
+    let mut sources: Vec<NodeSource> = vec![
+        Box::new(
+            document
+                .shared
+                .iter()
+                .enumerate()
+                .map(|(i, s)| s.map(|s| (i, s))),
+        ),
+        Box::new(
+            document
+                .subjects
+                .iter()
+                .enumerate()
+                .map(move |(i, s)| s.map(|s| (shared_len + i, s))),
+        ),
+        Box::new(
+            document
+                .objects
+                .iter()
+                .enumerate()
+                .map(move |(i, s)| s.map(|s| (shared_len + i, s)))
+                .filter(|s| !matches!(s, Ok((_, s)) if s.starts_with('"'))),
+        ),
+    ];
+    let mut heads = Vec::with_capacity(sources.len());
+    for source in sources.iter_mut() {
+        heads.push(source.next().transpose()?);
+    }
+    while let Some(node) = heads
+        .iter()
+        .filter_map(|head| head.as_ref().map(|(_, s)| s))
+        .min()
+        .cloned()
+    {
+        // every term may only appear in one section
+        if heads
+            .iter()
+            .filter(|head| matches!(head, Some((_, s)) if *s == node))
+            .count()
+            > 1
+        {
+            return Err(HdtError::new("term appears in more than one dictionary section").into());
+        }
+        let id = builder.add_node(&node).await?;
+        for (source_index, source) in sources.iter_mut().enumerate() {
+            if let Some((index, _)) = heads[source_index].as_ref().filter(|(_, s)| *s == node) {
+                match source_index {
+                    0 => {
+                        subject_ids[*index] = id;
+                        object_ids[*index] = id;
+                    }
+                    1 => subject_ids[*index] = id,
+                    _ => object_ids[*index] = id,
+                }
+                heads[source_index] = source.next().transpose()?;
+            }
+        }
+    }
+    let node_count = subject_ids
+        .iter()
+        .chain(object_ids.iter())
+        .copied()
+        .max()
+        .unwrap_or(0);
+
+    // predicates are a single sorted section, so their ids map directly
+    for predicate in document.predicates.iter() {
+        builder.add_predicate(&predicate?).await?;
+    }
+
+    for (index, object) in document.objects.iter().enumerate() {
+        let object = object?;
+        if object.starts_with('"') {
+            let id = builder.add_value(&object).await?;
+            object_ids[shared_len + index] = node_count + id;
+        }
+    }
+
+    let mut builder = builder.into_phase2().await?;
+
+    let predicate_count = document.predicates.len;
+    let mut triples = Vec::with_capacity(document.sequence_z.len as usize);
+    document.for_each_triple(|subject, predicate, object| -> Result<(), HdtError> {
+        let subject = subject_ids.get(subject as usize - 1);
+        let object = object
+            .checked_sub(1)
+            .and_then(|object| object_ids.get(object as usize));
+        match (subject, object) {
+            (Some(subject), Some(object)) if predicate != 0 && predicate <= predicate_count => {
+                triples.push(IdTriple::new(*subject, predicate, *object));
+                Ok(())
+            }
+            _ => Err(HdtError::new("triple refers to an unknown id")),
+        }
+    })?;
+    triples.par_sort_unstable();
+    triples.dedup();
+
+    builder.add_id_triples(triples).await?;
+    builder.finalize().await
+}
+
+/// The amount of bytes of a section that are buffered before writing them out.
+const WRITE_BUFFER_SIZE: usize = 1 << 16;
+
+/// Add all triples of an HDT file to the given builder, as string triples.
+///
+/// This goes through strings, so it is slower than
+/// `build_base_layer_from_hdt`, but it works with any builder.
+pub(crate) fn add_hdt_triples(hdt: &[u8], builder: &mut dyn LayerBuilder) -> io::Result<()> {
+    let document = HdtDocument::parse(hdt)?;
+    let strings = |section: &PfcSection| section.iter().collect::<Result<Vec<_>, _>>();
+    let shared = strings(&document.shared)?;
+    let subjects = strings(&document.subjects)?;
+    let predicates = strings(&document.predicates)?;
+    let objects = strings(&document.objects)?;
+
+    // subject and object ids both start with the shared section
+    let term = |section: &'_ [String], id: u64| -> Option<String> {
+        let index = id.checked_sub(1)? as usize;
+        match index.checked_sub(shared.len()) {
+            None => Some(shared[index].clone()),
+            Some(index) => section.get(index).cloned(),
+        }
+    };
+    document.for_each_triple(|subject, predicate, object| -> Result<(), HdtError> {
+        let subject = term(&subjects, subject);
+        let predicate = predicate
+            .checked_sub(1)
+            .and_then(|predicate| predicates.get(predicate as usize));
+        let object = term(&objects, object);
+        match (subject, predicate, object) {
+            (Some(subject), Some(predicate), Some(object)) => {
+                let object = if object.starts_with('"') {
+                    ObjectType::Value(object)
+                } else {
+                    ObjectType::Node(object)
+                };
+                builder.add_string_triple(StringTriple {
+                    subject,
+                    predicate: predicate.clone(),
+                    object,
+                });
+                Ok(())
+            }
+            _ => Err(HdtError::new("triple refers to an unknown id")),
+        }
+    })?;
+
+    Ok(())
+}
+
+fn write_vbyte(out: &mut Vec<u8>, num: u64) {
+    out.extend_from_slice(&vbyte::encode_vec(num));
+}
+
+/// Writes the data of a section, followed by its CRC32C checksum.
+struct ChecksummedWriter<'a, W: Write> {
+    writer: &'a mut W,
+    crc: u32,
+    buf: Vec<u8>,
+}
+
+impl<'a, W: Write> ChecksummedWriter<'a, W> {
+    fn new(writer: &'a mut W) -> Self {
+        ChecksummedWriter {
+            writer,
+            crc: !0,
+            buf: Vec::with_capacity(WRITE_BUFFER_SIZE),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= WRITE_BUFFER_SIZE {
+            self.write_buffer()?;
+        }
+
+        Ok(())
+    }
+
+    fn write_buffer(&mut self) -> io::Result<()> {
+        self.crc = crc32c_update(self.crc, &self.buf);
+        self.writer.write_all(&self.buf)?;
+        self.buf.clear();
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.write_buffer()?;
+        self.writer.write_all(&(!self.crc).to_le_bytes())
+    }
+}
+
+fn write_control_information<W: Write>(
+    writer: &mut W,
+    kind: u8,
+    format: &str,
+    properties: &[(&str, String)],
+) -> io::Result<()> {
+    let mut out = Vec::new();
+    out.extend_from_slice(COOKIE);
+    out.push(kind);
+    out.extend_from_slice(format.as_bytes());
+    out.push(0);
+    for (key, value) in properties {
+        out.extend_from_slice(format!("{}={};", key, value).as_bytes());
+    }
+    out.push(0);
+    let crc = crc16(&out);
+    out.extend_from_slice(&crc.to_le_bytes());
+
+    writer.write_all(&out)
+}
+
+/// Write the preamble of a sequence or bitmap, which ends in a CRC8 checksum.
+fn write_preamble<W: Write>(writer: &mut W, mut preamble: Vec<u8>) -> io::Result<()> {
+    preamble.push(crc8(&preamble));
+
+    writer.write_all(&preamble)
+}
+
+/// Pack the given values into `width` bits each, and write them as checksummed data.
+fn write_packed<W: Write, I: Iterator<Item = u64>>(
+    writer: &mut W,
+    values: I,
+    width: u8,
+) -> io::Result<()> {
+    let mut data = ChecksummedWriter::new(writer);
+    let mut bits: u128 = 0;
+    let mut bit_count = 0;
+    for value in values {
+        bits |= (value as u128) << bit_count;
+        bit_count += width as u32;
+        while bit_count >= 8 {
+            data.write(&[bits as u8])?;
+            bits >>= 8;
+            bit_count -= 8;
+        }
+    }
+    if bit_count > 0 {
+        data.write(&[bits as u8])?;
+    }
+
+    data.finish()
+}
+
+fn write_log_sequence<W: Write, I: Iterator<Item = u64> + Clone>(
+    writer: &mut W,
+    values: I,
+) -> io::Result<()> {
+    let (len, max) = values.clone().fold((0, 0), |(len, max), value| {
+        (len + 1, std::cmp::max(max, value))
+    });
+    let width = (64 - max.leading_zeros()) as u8;
+
+    let mut preamble = vec![SEQUENCE_LOG64, width];
+    write_vbyte(&mut preamble, len);
+    write_preamble(writer, preamble)?;
+
+    write_packed(writer, values, width)
+}
+
+fn write_bitmap<W: Write, I: Iterator<Item = u64> + Clone>(
+    writer: &mut W,
+    bits: I,
+) -> io::Result<()> {
+    let mut preamble = vec![BITMAP_PLAIN];
+    write_vbyte(&mut preamble, bits.clone().count() as u64);
+    write_preamble(writer, preamble)?;
+
+    write_packed(writer, bits, 1)
+}
+
+fn write_pfc_section<W: Write>(writer: &mut W, strings: &[String]) -> io::Result<()> {
+    // the length of every string once front-coded, without the string itself
+    let prefix = |index: usize| -> usize {
+        if (index as u64).is_multiple_of(PFC_BLOCK_SIZE) {
+            0
+        } else {
+            strings[index - 1]
+                .as_bytes()
+                .iter()
+                .zip(strings[index].as_bytes().iter())
+                .take_while(|(a, b)| a == b)
+                .count()
+        }
+    };
+    let encoded_len = |index: usize| -> u64 {
+        let prefix = prefix(index);
+        let prefix_len = if (index as u64).is_multiple_of(PFC_BLOCK_SIZE) {
+            0
+        } else {
+            vbyte::encoding_len(prefix as u64)
+        };
+
+        (prefix_len + strings[index].len() - prefix + 1) as u64
+    };
+
+    let mut blocks = Vec::new();
+    let mut text_len = 0;
+    for index in 0..strings.len() {
+        if (index as u64).is_multiple_of(PFC_BLOCK_SIZE) {
+            blocks.push(text_len);
+        }
+        text_len += encoded_len(index);
+    }
+    blocks.push(text_len);
+
+    let mut preamble = vec![SECTION_PFC];
+    write_vbyte(&mut preamble, strings.len() as u64);
+    write_vbyte(&mut preamble, text_len);
+    write_vbyte(&mut preamble, PFC_BLOCK_SIZE);
+    write_preamble(writer, preamble)?;
+
+    write_log_sequence(writer, blocks.into_iter())?;
+
+    let mut text = ChecksummedWriter::new(writer);
+    for (index, string) in strings.iter().enumerate() {
+        let string = string.as_bytes();
+        if (index as u64).is_multiple_of(PFC_BLOCK_SIZE) {
+            text.write(string)?;
+        } else {
+            let prefix = prefix(index);
+            text.write(&vbyte::encode_vec(prefix as u64))?;
+            text.write(&string[prefix..])?;
+        }
+        text.write(&[0])?;
+    }
+
+    text.finish()
+}
+
+fn object_to_hdt_string(object: ObjectType) -> String {
+    match object {
+        ObjectType::Node(node) => node,
+        ObjectType::Value(value) => {
+            if parse_stored_literal(&value).is_some() {
+                value
+            } else {
+                format_literal(&value, None, None)
+            }
+        }
+    }
+}
+
+fn unknown_id(id: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("layer refers to unknown id {}", id),
+    )
+}
+
+/// Sort terms by their string, returning the strings and a map from layer ids to positions.
+fn sort_terms(mut terms: Vec<(String, u64)>) -> (Vec<String>, HashMap<u64, u64>) {
+    terms.par_sort_unstable();
+    let map = terms
+        .iter()
+        .enumerate()
+        .map(|(index, (_, id))| (*id, index as u64))
+        .collect();
+    let strings = terms.into_iter().map(|(string, _)| string).collect();
+
+    (strings, map)
+}
+
+/// Write all triples in a layer as an HDT file.
+///
+/// The layer is written as it appears when queried, so for a child
+/// layer this includes everything inherited from its ancestors. The
+/// base IRI is recorded as the subject of the metadata in the HDT
+/// header.
+///
+/// The terms for the dictionary are collected from the layer's
+/// indexes, after which the triples are read once to translate them
+/// into HDT ids. Every section is written out as soon as it is ready.
+pub fn write_hdt<W: Write>(layer: &dyn Layer, base_iri: &str, mut writer: W) -> io::Result<()> {
+    // all of these are distinct and in ascending order
+    let subject_ids: Vec<u64> = layer.subjects().collect();
+    let predicate_ids: Vec<u64> = layer.predicates().collect();
+    let object_ids: Vec<u64> = layer.objects().collect();
+
+    // a node that is used both as subject and object goes into the shared section
+    let mut shared = Vec::new();
+    let mut subjects = Vec::new();
+    let mut objects = Vec::new();
+    let mut objects_iter = object_ids.iter().peekable();
+    for subject in subject_ids.iter() {
+        while let Some(object) = objects_iter.next_if(|o| *o < subject) {
+            objects.push(*object);
+        }
+        if objects_iter.next_if_eq(&subject).is_some() {
+            shared.push(*subject);
+        } else {
+            subjects.push(*subject);
+        }
+    }
+    objects.extend(objects_iter);
+
+    let node_terms = |ids: Vec<u64>| -> io::Result<Vec<(String, u64)>> {
+        ids.into_iter()
+            .map(|id| {
+                layer
+                    .id_subject(id)
+                    .map(|node| (node, id))
+                    .ok_or_else(|| unknown_id(id))
+            })
+            .collect()
+    };
+    let (shared, shared_map) = sort_terms(node_terms(shared)?);
+    let (subjects, subject_map) = sort_terms(node_terms(subjects)?);
+    let (objects, object_map) = sort_terms(
+        objects
+            .into_iter()
+            .map(|id| {
+                layer
+                    .id_object(id)
+                    .map(|object| (object_to_hdt_string(object), id))
+                    .ok_or_else(|| unknown_id(id))
+            })
+            .collect::<io::Result<_>>()?,
+    );
+    let (predicates, predicate_map) = sort_terms(
+        predicate_ids
+            .into_iter()
+            .map(|id| {
+                layer
+                    .id_predicate(id)
+                    .map(|predicate| (predicate, id))
+                    .ok_or_else(|| unknown_id(id))
+            })
+            .collect::<io::Result<_>>()?,
+    );
+    if shared
+        .iter()
+        .chain(subjects.iter())
+        .chain(predicates.iter())
+        .chain(objects.iter())
+        .any(|s| s.contains('\0'))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "HDT dictionaries cannot contain strings with NUL characters",
+        ));
+    }
+
+    let shared_len = shared.len() as u64;
+    let subject_id = |id: u64| match shared_map.get(&id) {
+        Some(index) => index + 1,
+        None => shared_len + subject_map[&id] + 1,
+    };
+    let object_id = |id: u64| match shared_map.get(&id) {
+        Some(index) => index + 1,
+        None => shared_len + object_map[&id] + 1,
+    };
+    let mut triples: Vec<(u64, u64, u64)> = layer
+        .triples()
+        .map(|t| {
+            (
+                subject_id(t.subject),
+                predicate_map[&t.predicate] + 1,
+                object_id(t.object),
+            )
+        })
+        .collect();
+    triples.par_sort_unstable();
+
+    let dictionary = HdtDictionary {
+        shared,
+        subjects,
+        predicates,
+        objects,
+    };
+    write_hdt_document(&mut writer, base_iri, &dictionary, &triples)?;
+
+    writer.flush()
+}
+
+/// The four sections of an HDT dictionary, each sorted.
+struct HdtDictionary {
+    shared: Vec<String>,
+    subjects: Vec<String>,
+    predicates: Vec<String>,
+    objects: Vec<String>,
+}
+
+/// Write an HDT file with the given dictionary and sorted triples, in HDT ids.
+fn write_hdt_document<W: Write>(
+    writer: &mut W,
+    base_iri: &str,
+    dictionary: &HdtDictionary,
+    triples: &[(u64, u64, u64)],
+) -> io::Result<()> {
+    let HdtDictionary {
+        shared,
+        subjects,
+        predicates,
+        objects,
+    } = dictionary;
+    let triple_count = triples.len();
+
+    write_control_information(
+        writer,
+        CONTROL_GLOBAL,
+        HDT_CONTAINER,
+        &[
+            ("BaseUri", base_iri.to_string()),
+            (
+                "Software",
+                format!("terminus-store {}", env!("CARGO_PKG_VERSION")),
+            ),
+        ],
+    )?;
+
+    let mut header = String::new();
+    let mut header_triple = |predicate: &str, object: &str| {
+        header.push_str(&format_iri(base_iri));
+        header.push(' ');
+        header.push_str(&format_iri(predicate));
+        header.push(' ');
+        header.push_str(object);
+        header.push_str(" .\n");
+    };
+    header_triple(
+        "http://www.w3.org/1999/02/22-rdf-syntax-ns#type",
+        "<http://purl.org/HDT/hdt#Dataset>",
+    );
+    header_triple(
+        "http://www.w3.org/1999/02/22-rdf-syntax-ns#type",
+        "<http://rdfs.org/ns/void#Dataset>",
+    );
+    header_triple(
+        "http://rdfs.org/ns/void#triples",
+        &format_literal(&triple_count.to_string(), None, None),
+    );
+    header_triple(
+        "http://rdfs.org/ns/void#properties",
+        &format_literal(&predicates.len().to_string(), None, None),
+    );
+    header_triple(
+        "http://rdfs.org/ns/void#distinctSubjects",
+        &format_literal(&(shared.len() + subjects.len()).to_string(), None, None),
+    );
+    header_triple(
+        "http://rdfs.org/ns/void#distinctObjects",
+        &format_literal(&(shared.len() + objects.len()).to_string(), None, None),
+    );
+    write_control_information(
+        writer,
+        CONTROL_HEADER,
+        "ntriples",
+        &[("length", header.len().to_string())],
+    )?;
+    writer.write_all(header.as_bytes())?;
+
+    let string_size: usize = shared
+        .iter()
+        .chain(subjects.iter())
+        .chain(predicates.iter())
+        .chain(objects.iter())
+        .map(|s| s.len())
+        .sum();
+    let element_count = shared.len() + subjects.len() + predicates.len() + objects.len();
+    write_control_information(
+        writer,
+        CONTROL_DICTIONARY,
+        DICTIONARY_FOUR,
+        &[
+            ("mapping", "1".to_string()),
+            ("sizeStrings", string_size.to_string()),
+            ("elements", element_count.to_string()),
+        ],
+    )?;
+    write_pfc_section(writer, shared)?;
+    write_pfc_section(writer, subjects)?;
+    write_pfc_section(writer, predicates)?;
+    write_pfc_section(writer, objects)?;
+
+    // a triple is the last of its subject-predicate pair, or of its
+    // subject, if the next triple differs in those
+    let last_object = |index: usize| {
+        let (subject, predicate, _) = triples[index];
+        !matches!(triples.get(index + 1), Some((s, p, _)) if *s == subject && *p == predicate)
+    };
+    let last_predicate = |index: usize| {
+        let (subject, _, _) = triples[index];
+        !matches!(triples.get(index + 1), Some((s, _, _)) if *s == subject)
+    };
+    let pair_ends = (0..triples.len()).filter(|index| last_object(*index));
+
+    write_control_information(
+        writer,
+        CONTROL_TRIPLES,
+        TRIPLES_BITMAP,
+        &[("order", ORDER_SPO.to_string())],
+    )?;
+    write_bitmap(
+        writer,
+        pair_ends.clone().map(|index| last_predicate(index) as u64),
+    )?;
+    write_bitmap(
+        writer,
+        (0..triples.len()).map(|index| last_object(index) as u64),
+    )?;
+    write_log_sequence(writer, pair_ends.map(|index| triples[index].1))?;
+    write_log_sequence(writer, triples.iter().map(|(_, _, object)| *object))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryLayerStore;
+    use crate::storage::LayerStore;
+    use crate::store::{open_memory_store, StoreLayer};
+
+    #[test]
+    fn checksums_match_reference_values() {
+        assert_eq!(0xF4, crc8(b"123456789"));
+        assert_eq!(0xBB3D, crc16(b"123456789"));
+        assert_eq!(0xE306_9283, crc32c(b"123456789"));
+    }
+
+    #[test]
+    fn log_sequence_roundtrip() {
+        let values: Vec<u64> = (0..100).map(|i| i * 37 % 1000).collect();
+        let mut out = Vec::new();
+        write_log_sequence(&mut out, values.iter().copied()).unwrap();
+
+        let mut reader = HdtReader { data: &out, pos: 0 };
+        let sequence = reader.read_log_sequence().unwrap();
+        assert_eq!(out.len(), reader.pos);
+        assert_eq!(10, sequence.width);
+        let read: Vec<u64> = (0..sequence.len).map(|i| sequence.get(i)).collect();
+        assert_eq!(values, read);
+    }
+
+    fn string_triples(layer: &StoreLayer) -> Vec<StringTriple> {
+        let mut triples: Vec<_> = layer
+            .triples()
+            .map(|t| layer.id_triple_to_string(&t).unwrap())
+            .collect();
+        triples.sort();
+
+        triples
+    }
+
+    async fn example_layer() -> StoreLayer {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        let document = r#"@prefix ex: <http://example.com/> .
+ex:cow a ex:Animal ; ex:says "moo", "mooo"@en ; ex:weight 500 ; ex:likes ex:pig, [ ex:name "duck" ] .
+ex:pig a ex:Animal ; ex:says "oink" ; ex:likes ex:cow .
+"#;
+        import_turtle(document.as_bytes(), &builder).unwrap();
+        let base = builder.commit().await.unwrap();
+
+        let builder = base.open_write().await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value(
+                "http://example.com/pig",
+                "http://example.com/says",
+                "\"oink\"",
+            ))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value(
+                "http://example.com/horse",
+                "http://example.com/says",
+                "\"neigh\"",
+            ))
+            .unwrap();
+
+        builder.commit().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn hdt_roundtrip() {
+        let layer = example_layer().await;
+        let mut hdt = Vec::new();
+        write_hdt(&layer, "http://example.com/dataset", &mut hdt).unwrap();
+
+        let document = HdtDocument::parse(&hdt).unwrap();
+        let shared: Vec<_> = document.shared.iter().map(|s| s.unwrap()).collect();
+        assert_eq!(
+            vec![
+                "_:genid0".to_string(),
+                "http://example.com/cow".to_string(),
+                "http://example.com/pig".to_string()
+            ],
+            shared
+        );
+        assert_eq!(1, document.subjects.len);
+        assert_eq!(5, document.predicates.len);
+        assert_eq!(6, document.objects.len);
+        let mut count = 0;
+        document
+            .for_each_triple(|_, _, _| -> Result<(), HdtError> {
+                count += 1;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(layer.triple_count(), count);
+
+        let store = open_memory_store();
+        let imported = store.create_base_layer_from_hdt(hdt).await.unwrap();
+        assert!(imported.parent().await.unwrap().is_none());
+        assert_eq!(string_triples(&layer), string_triples(&imported));
+    }
+
+    async fn build_from_dictionary(dictionary: HdtDictionary) -> io::Result<()> {
+        let mut hdt = Vec::new();
+        write_hdt_document(
+            &mut hdt,
+            "http://example.com/dataset",
+            &dictionary,
+            &[(1, 1, 1)],
+        )
+        .unwrap();
+
+        let store = open_memory_store();
+        store.create_base_layer_from_hdt(hdt).await.map(|_| ())
+    }
+
+    fn strings(strings: &[&str]) -> Vec<String> {
+        strings.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn malformed_dictionary_sections_are_rejected() {
+        let valid = || HdtDictionary {
+            shared: strings(&["a", "b"]),
+            subjects: strings(&["c"]),
+            predicates: strings(&["p", "q"]),
+            objects: strings(&["\"x\"", "d"]),
+        };
+        build_from_dictionary(valid()).await.unwrap();
+
+        let unsorted = HdtDictionary {
+            shared: strings(&["b", "a"]),
+            ..valid()
+        };
+        let duplicate = HdtDictionary {
+            predicates: strings(&["p", "p"]),
+            ..valid()
+        };
+        let shared_with_subjects = HdtDictionary {
+            subjects: strings(&["a"]),
+            ..valid()
+        };
+        let shared_with_objects = HdtDictionary {
+            objects: strings(&["\"x\"", "b"]),
+            ..valid()
+        };
+        let literal_subject = HdtDictionary {
+            subjects: strings(&["\"c\""]),
+            ..valid()
+        };
+        for dictionary in [
+            unsorted,
+            duplicate,
+            shared_with_subjects,
+            shared_with_objects,
+            literal_subject,
+        ] {
+            let err = build_from_dictionary(dictionary).await.err().unwrap();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+    }
+
+    #[tokio::test]
+    async fn add_hdt_triples_to_builder() {
+        let layer = example_layer().await;
+        let mut hdt = Vec::new();
+        write_hdt(&layer, "http://example.com/dataset", &mut hdt).unwrap();
+
+        let layer_store = MemoryLayerStore::new();
+        let mut builder = layer_store.create_base_layer().await.unwrap();
+        add_hdt_triples(&hdt, &mut *builder).unwrap();
+        let name = builder.name();
+        builder.commit_boxed().await.unwrap();
+
+        let imported = layer_store.get_layer(name).await.unwrap().unwrap();
+        let mut imported_triples: Vec<_> = imported
+            .triples()
+            .map(|t| imported.id_triple_to_string(&t).unwrap())
+            .collect();
+        imported_triples.sort();
+        assert_eq!(string_triples(&layer), imported_triples);
+    }
+
+    #[tokio::test]
+    async fn corrupt_hdt_is_rejected() {
+        let layer = example_layer().await;
+        let mut hdt = Vec::new();
+        write_hdt(&layer, "http://example.com/dataset", &mut hdt).unwrap();
+
+        let store = open_memory_store();
+        let len = hdt.len();
+        hdt[len - 10] ^= 0xFF;
+        let err = store.create_base_layer_from_hdt(hdt).await.err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        let err = store
+            .create_base_layer_from_hdt(b"$HDT".to_vec())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+}
@@ -0,0 +1,206 @@
+//! Resolution of relative IRI references, as described in RFC 3986 section 5.2.
+
+struct IriParts<'a> {
+    scheme: Option<&'a str>,
+    authority: Option<&'a str>,
+    path: &'a str,
+    query: Option<&'a str>,
+    fragment: Option<&'a str>,
+}
+
+fn split_scheme(iri: &str) -> (Option<&str>, &str) {
+    let mut chars = iri.char_indices();
+    match chars.next() {
+        Some((_, c)) if c.is_ascii_alphabetic() => {}
+        _ => return (None, iri),
+    }
+    for (i, c) in chars {
+        match c {
+            ':' => return (Some(&iri[..i]), &iri[i + 1..]),
+            c if c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.' => {}
+            _ => break,
+        }
+    }
+
+    (None, iri)
+}
+
+fn parse(iri: &str) -> IriParts<'_> {
+    let (scheme, rest) = split_scheme(iri);
+    let (rest, fragment) = match rest.find('#') {
+        Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+        None => (rest, None),
+    };
+    let (rest, query) = match rest.find('?') {
+        Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+        None => (rest, None),
+    };
+    let (authority, path) = if let Some(rest) = rest.strip_prefix("//") {
+        match rest.find('/') {
+            Some(i) => (Some(&rest[..i]), &rest[i..]),
+            None => (Some(rest), ""),
+        }
+    } else {
+        (None, rest)
+    };
+
+    IriParts {
+        scheme,
+        authority,
+        path,
+        query,
+        fragment,
+    }
+}
+
+/// Returns true if the given IRI has a scheme, meaning it is not a relative reference.
+pub fn is_absolute_iri(iri: &str) -> bool {
+    split_scheme(iri).0.is_some()
+}
+
+fn remove_dot_segments(path: &str) -> String {
+    let mut input = path;
+    let mut output: Vec<&str> = Vec::new();
+    // output segments all start with a '/', except possibly the first one
+    while !input.is_empty() {
+        if let Some(rest) = input.strip_prefix("../") {
+            input = rest;
+        } else if let Some(rest) = input.strip_prefix("./") {
+            input = rest;
+        } else if input.starts_with("/./") {
+            input = &input[2..];
+        } else if input == "/." {
+            input = "/";
+        } else if input.starts_with("/../") {
+            input = &input[3..];
+            output.pop();
+        } else if input == "/.." {
+            input = "/";
+            output.pop();
+        } else if input == "." || input == ".." {
+            input = "";
+        } else {
+            let start = if input.starts_with('/') { 1 } else { 0 };
+            let end = input[start..]
+                .find('/')
+                .map(|i| i + start)
+                .unwrap_or_else(|| input.len());
+            output.push(&input[..end]);
+            input = &input[end..];
+        }
+    }
+
+    output.concat()
+}
+
+fn merge(base: &IriParts, path: &str) -> String {
+    if base.authority.is_some() && base.path.is_empty() {
+        format!("/{}", path)
+    } else {
+        match base.path.rfind('/') {
+            Some(i) => format!("{}{}", &base.path[..=i], path),
+            None => path.to_string(),
+        }
+    }
+}
+
+/// Resolve a reference against a base IRI.
+pub fn resolve_iri(base: &str, reference: &str) -> String {
+    let r = parse(reference);
+    let b = parse(base);
+
+    let (scheme, authority, path, query) = if r.scheme.is_some() {
+        (r.scheme, r.authority, remove_dot_segments(r.path), r.query)
+    } else if r.authority.is_some() {
+        (b.scheme, r.authority, remove_dot_segments(r.path), r.query)
+    } else if r.path.is_empty() {
+        (
+            b.scheme,
+            b.authority,
+            b.path.to_string(),
+            r.query.or(b.query),
+        )
+    } else if r.path.starts_with('/') {
+        (b.scheme, b.authority, remove_dot_segments(r.path), r.query)
+    } else {
+        (
+            b.scheme,
+            b.authority,
+            remove_dot_segments(&merge(&b, r.path)),
+            r.query,
+        )
+    };
+
+    let mut result = String::with_capacity(base.len() + reference.len());
+    if let Some(scheme) = scheme {
+        result.push_str(scheme);
+        result.push(':');
+    }
+    if let Some(authority) = authority {
+        result.push_str("//");
+        result.push_str(authority);
+    }
+    result.push_str(&path);
+    if let Some(query) = query {
+        result.push('?');
+        result.push_str(query);
+    }
+    if let Some(fragment) = r.fragment {
+        result.push('#');
+        result.push_str(fragment);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_rfc3986_examples() {
+        let base = "http://a/b/c/d;p?q";
+        let examples = [
+            ("g:h", "g:h"),
+            ("g", "http://a/b/c/g"),
+            ("./g", "http://a/b/c/g"),
+            ("g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            ("//g", "http://g"),
+            ("?y", "http://a/b/c/d;p?y"),
+            ("g?y", "http://a/b/c/g?y"),
+            ("#s", "http://a/b/c/d;p?q#s"),
+            ("g#s", "http://a/b/c/g#s"),
+            (";x", "http://a/b/c/;x"),
+            ("", "http://a/b/c/d;p?q"),
+            (".", "http://a/b/c/"),
+            ("./", "http://a/b/c/"),
+            ("..", "http://a/b/"),
+            ("../g", "http://a/b/g"),
+            ("../..", "http://a/"),
+            ("../../g", "http://a/g"),
+            ("../../../g", "http://a/g"),
+            ("/./g", "http://a/g"),
+            ("/../g", "http://a/g"),
+            ("g.", "http://a/b/c/g."),
+            ("..g", "http://a/b/c/..g"),
+            ("./../g", "http://a/b/g"),
+            ("g/./h", "http://a/b/c/g/h"),
+            ("g/../h", "http://a/b/c/h"),
+            ("g;x=1/../y", "http://a/b/c/y"),
+        ];
+
+        for (reference, expected) in examples.iter() {
+            assert_eq!(*expected, resolve_iri(base, reference), "{}", reference);
+        }
+    }
+
+    #[test]
+    fn detect_absolute_iris() {
+        assert!(is_absolute_iri("http://example.com/"));
+        assert!(is_absolute_iri("urn:isbn:123"));
+        assert!(!is_absolute_iri("foo/bar"));
+        assert!(!is_absolute_iri("#frag"));
+        assert!(!is_absolute_iri("1a:b"));
+    }
+}
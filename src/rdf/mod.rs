@@ -0,0 +1,176 @@
+//! Import and export of RDF data.
+//!
+//! terminus-store makes no assumptions about the strings it stores,
+//! so loading RDF requires a mapping from RDF terms onto nodes and
+//! values. This module uses the following mapping:
+//!
+//! - IRIs are stored as nodes, without the surrounding angle brackets.
+//! - Blank nodes are stored as nodes, in the form `_:label`.
+//! - Literals are stored as values, in their canonical N-Triples
+//!   form. For example, `"moo"`, `"moo"@en` or
+//!   `"42"^^<http://www.w3.org/2001/XMLSchema#integer>`. Language
+//!   tags are lowercased, and the `xsd:string` datatype is left out
+//!   as it is implied for simple literals.
+//!
+//! Supported formats are N-Triples, N-Quads and Turtle. In Turtle
+//! documents, prefixed names are expanded and relative IRIs are
+//! resolved against the base IRI, so that only full IRIs are stored.
+//!
+//! Importing streams triples into a `StoreLayerBuilder`, which keeps
+//! them in memory until commit. For large imports, use a builder with
+//! a memory limit, as created by
+//! `Store::create_base_layer_with_memory_limit`.
+//!
+//! Layers can be exported as N-Triples or Turtle, either completely
+//! or as a patch consisting of the additions or removals of a layer.
+//! They can also be exchanged with other tools as HDT files.
+pub(crate) mod cursor;
+mod export;
+mod hdt;
+mod iri;
+mod ntriples;
+mod turtle;
+
+pub use export::*;
+pub use hdt::*;
+pub use iri::*;
+pub use ntriples::*;
+pub use turtle::*;
+
+use crate::layer::ObjectType;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+const XSD_STRING: &str = "http://www.w3.org/2001/XMLSchema#string";
+
+/// An RDF term.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Term {
+    Iri(String),
+    BlankNode(String),
+    Literal {
+        value: String,
+        language: Option<String>,
+        datatype: Option<String>,
+    },
+}
+
+impl Term {
+    /// Returns true if this term is a literal, and false otherwise.
+    pub fn is_literal(&self) -> bool {
+        matches!(self, Term::Literal { .. })
+    }
+
+    /// Convert this term to the node string it is stored as, or None if this term is a literal.
+    pub fn into_node(self) -> Option<String> {
+        match self {
+            Term::Iri(iri) => Some(iri),
+            Term::BlankNode(label) => Some(format!("_:{}", label)),
+            Term::Literal { .. } => None,
+        }
+    }
+
+    /// Convert this term to the object it is stored as.
+    pub fn into_object(self) -> ObjectType {
+        match self {
+            Term::Literal {
+                value,
+                language,
+                datatype,
+            } => ObjectType::Value(format_literal(
+                &value,
+                language.as_deref(),
+                datatype.as_deref(),
+            )),
+            term => ObjectType::Node(term.into_node().unwrap()),
+        }
+    }
+}
+
+/// Format a literal in its canonical N-Triples form, which is the form it is stored as.
+pub fn format_literal(value: &str, language: Option<&str>, datatype: Option<&str>) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+
+    match (language, datatype) {
+        (Some(language), _) => {
+            result.push('@');
+            result.push_str(&language.to_lowercase());
+        }
+        (None, Some(datatype)) if datatype != XSD_STRING => {
+            result.push_str("^^<");
+            result.push_str(datatype);
+            result.push('>');
+        }
+        _ => {}
+    }
+
+    result
+}
+
+/// An error encountered while parsing an RDF document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RdfParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl RdfParseError {
+    pub fn new<S: Into<String>>(line: usize, column: usize, message: S) -> Self {
+        RdfParseError {
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for RdfParseError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            formatter,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl Error for RdfParseError {}
+
+impl From<RdfParseError> for io::Error {
+    fn from(err: RdfParseError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_literals() {
+        assert_eq!("\"moo\"", format_literal("moo", None, None));
+        assert_eq!("\"moo\"", format_literal("moo", None, Some(XSD_STRING)));
+        assert_eq!("\"moo\"@en-gb", format_literal("moo", Some("en-GB"), None));
+        assert_eq!(
+            "\"4\\\"2\\n\"^^<http://www.w3.org/2001/XMLSchema#integer>",
+            format_literal(
+                "4\"2\n",
+                None,
+                Some("http://www.w3.org/2001/XMLSchema#integer")
+            )
+        );
+    }
+}
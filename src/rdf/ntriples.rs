@@ -0,0 +1,371 @@
+//! N-Triples and N-Quads parsing and import.
+//!
+//! Both formats are line based, so parsing happens one line at a
+//! time, allowing arbitrarily large documents to be streamed from a
+//! reader.
+use super::cursor::*;
+use super::*;
+use crate::layer::StringTriple;
+use crate::store::StoreLayerBuilder;
+use std::io::BufRead;
+
+impl<'a> Cursor<'a> {
+    fn parse_ntriples_literal(&mut self) -> Result<Term, RdfParseError> {
+        let value = self.parse_quoted_string('"')?;
+        let mut language = None;
+        let mut datatype = None;
+        if self.eat('@') {
+            language = Some(self.parse_langtag()?);
+        } else if self.starts_with("^^") {
+            self.bump();
+            self.bump();
+            datatype = Some(self.parse_iriref()?);
+        }
+
+        Ok(Term::Literal {
+            value,
+            language,
+            datatype,
+        })
+    }
+
+    fn parse_ntriples_term(&mut self) -> Result<Term, RdfParseError> {
+        match self.peek() {
+            Some('<') => Ok(Term::Iri(self.parse_iriref()?)),
+            Some('_') => Ok(Term::BlankNode(self.parse_blank_node_label()?)),
+            Some('"') => self.parse_ntriples_literal(),
+            Some(c) => Err(self.error(format!("unexpected character '{}'", c))),
+            None => Err(self.error("unexpected end of line")),
+        }
+    }
+}
+
+/// Parse a value in the form the importers store literals in, or None if it is not in that form.
+pub(crate) fn parse_stored_literal(value: &str) -> Option<Term> {
+    let mut cursor = Cursor::new(value, 1);
+    let term = cursor.parse_ntriples_literal().ok()?;
+    if cursor.is_at_end() {
+        Some(term)
+    } else {
+        None
+    }
+}
+
+/// Returns true if the given node is a blank node in the form `_:label`.
+pub(crate) fn is_stored_blank_node(node: &str) -> bool {
+    let mut cursor = Cursor::new(node, 1);
+    cursor.parse_blank_node_label().is_ok() && cursor.is_at_end()
+}
+
+/// A triple together with the graph it belongs to.
+///
+/// The graph is None for triples in the default graph. Otherwise it
+/// is the node string of the graph name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quad {
+    pub triple: StringTriple,
+    pub graph: Option<String>,
+}
+
+fn parse_line(line: &str, line_number: usize, quads: bool) -> Result<Option<Quad>, RdfParseError> {
+    let mut cursor = Cursor::new(line, line_number);
+    cursor.skip_blanks();
+    cursor.skip_comment();
+    if cursor.is_at_end() {
+        return Ok(None);
+    }
+
+    let subject = cursor.parse_ntriples_term()?;
+    if subject.is_literal() {
+        return Err(RdfParseError::new(
+            line_number,
+            1,
+            "subject cannot be a literal",
+        ));
+    }
+    cursor.skip_blanks();
+    let predicate = match cursor.peek() {
+        Some('<') => cursor.parse_iriref()?,
+        _ => return Err(cursor.error("predicate must be an IRI")),
+    };
+    cursor.skip_blanks();
+    let object = cursor.parse_ntriples_term()?;
+    cursor.skip_blanks();
+
+    let mut graph = None;
+    if quads && matches!(cursor.peek(), Some('<') | Some('_') | Some('"')) {
+        let term = cursor.parse_ntriples_term()?;
+        if term.is_literal() {
+            return Err(cursor.error("graph label cannot be a literal"));
+        }
+        graph = term.into_node();
+        cursor.skip_blanks();
+    }
+
+    cursor.expect('.')?;
+    cursor.skip_blanks();
+    cursor.skip_comment();
+    if !cursor.is_at_end() {
+        return Err(cursor.error("unexpected content after '.'"));
+    }
+
+    Ok(Some(Quad {
+        triple: StringTriple {
+            subject: subject.into_node().unwrap(),
+            predicate,
+            object: object.into_object(),
+        },
+        graph,
+    }))
+}
+
+/// A streaming parser for N-Triples and N-Quads documents.
+///
+/// This is an iterator over the quads in the document. Triples in
+/// N-Triples documents are all returned as part of the default graph.
+pub struct NQuadsParser<R: BufRead> {
+    reader: R,
+    quads: bool,
+    line_number: usize,
+    buf: Vec<u8>,
+}
+
+impl<R: BufRead> NQuadsParser<R> {
+    /// Create a parser for an N-Triples document.
+    pub fn ntriples(reader: R) -> Self {
+        NQuadsParser {
+            reader,
+            quads: false,
+            line_number: 0,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Create a parser for an N-Quads document.
+    pub fn nquads(reader: R) -> Self {
+        NQuadsParser {
+            reader,
+            quads: true,
+            line_number: 0,
+            buf: Vec::new(),
+        }
+    }
+
+    fn next_quad(&mut self) -> io::Result<Option<Quad>> {
+        loop {
+            self.buf.clear();
+            if self.reader.read_until(b'\n', &mut self.buf)? == 0 {
+                return Ok(None);
+            }
+            self.line_number += 1;
+
+            let line = std::str::from_utf8(&self.buf)
+                .map_err(|_| RdfParseError::new(self.line_number, 1, "invalid utf-8"))?;
+            let line = line.trim_end_matches(&['\n', '\r'][..]);
+            if let Some(quad) = parse_line(line, self.line_number, self.quads)? {
+                return Ok(Some(quad));
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for NQuadsParser<R> {
+    type Item = io::Result<Quad>;
+
+    fn next(&mut self) -> Option<io::Result<Quad>> {
+        self.next_quad().transpose()
+    }
+}
+
+/// Which graphs to import from an N-Quads document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphFilter {
+    /// Only import triples in the default graph.
+    Default,
+    /// Only import triples in the graph with the given name.
+    ///
+    /// The name is given in the form it would be stored as a node.
+    Named(String),
+    /// Import triples from all graphs.
+    All,
+}
+
+impl GraphFilter {
+    fn matches(&self, graph: Option<&str>) -> bool {
+        match self {
+            GraphFilter::Default => graph.is_none(),
+            GraphFilter::Named(name) => graph == Some(name.as_str()),
+            GraphFilter::All => true,
+        }
+    }
+}
+
+/// Add all triples in the given N-Triples document to the builder.
+///
+/// Returns the amount of triples that were added.
+pub fn import_ntriples<R: BufRead>(reader: R, builder: &StoreLayerBuilder) -> io::Result<usize> {
+    let mut count = 0;
+    for quad in NQuadsParser::ntriples(reader) {
+        builder.add_string_triple(quad?.triple)?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Add all triples in the given N-Quads document that match the graph filter to the builder.
+///
+/// Returns the amount of triples that were added.
+pub fn import_nquads<R: BufRead>(
+    reader: R,
+    builder: &StoreLayerBuilder,
+    filter: &GraphFilter,
+) -> io::Result<usize> {
+    let mut count = 0;
+    for quad in NQuadsParser::nquads(reader) {
+        let quad = quad?;
+        if filter.matches(quad.graph.as_deref()) {
+            builder.add_string_triple(quad.triple)?;
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::Layer;
+    use crate::store::open_memory_store;
+
+    fn parse(document: &str) -> io::Result<Vec<Quad>> {
+        NQuadsParser::nquads(document.as_bytes()).collect()
+    }
+
+    fn parse_error(document: &str) -> RdfParseError {
+        let err = parse(document).unwrap_err();
+        err.into_inner()
+            .unwrap()
+            .downcast::<RdfParseError>()
+            .map(|e| *e)
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_terms() {
+        let quads = parse(
+            r#"# a comment
+<http://example.com/cow> <http://example.com/says> "moo" .
+_:b0 <http://example.com/says> "quack"@en-GB .
+
+<http://example.com/pig>	<http://example.com/weight> "120"^^<http://www.w3.org/2001/XMLSchema#integer> . # trailing comment
+<http://example.com/pig> <http://example.com/likes> _:b0 <http://example.com/graph> .
+_:b.1 <http://example.com/says> "\"esc\\aped\"\n\u00e9\U0001F600" _:g .
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                Quad {
+                    triple: StringTriple::new_value(
+                        "http://example.com/cow",
+                        "http://example.com/says",
+                        "\"moo\""
+                    ),
+                    graph: None
+                },
+                Quad {
+                    triple: StringTriple::new_value(
+                        "_:b0",
+                        "http://example.com/says",
+                        "\"quack\"@en-gb"
+                    ),
+                    graph: None
+                },
+                Quad {
+                    triple: StringTriple::new_value(
+                        "http://example.com/pig",
+                        "http://example.com/weight",
+                        "\"120\"^^<http://www.w3.org/2001/XMLSchema#integer>"
+                    ),
+                    graph: None
+                },
+                Quad {
+                    triple: StringTriple::new_node(
+                        "http://example.com/pig",
+                        "http://example.com/likes",
+                        "_:b0"
+                    ),
+                    graph: Some("http://example.com/graph".to_string())
+                },
+                Quad {
+                    triple: StringTriple::new_value(
+                        "_:b.1",
+                        "http://example.com/says",
+                        "\"\\\"esc\\\\aped\\\"\\né😀\""
+                    ),
+                    graph: Some("_:g".to_string())
+                },
+            ],
+            quads
+        );
+    }
+
+    #[test]
+    fn ntriples_parser_rejects_graph_labels() {
+        let result: io::Result<Vec<_>> =
+            NQuadsParser::ntriples("<a> <b> <c> <d> .\n".as_bytes()).collect();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn errors_have_line_and_column() {
+        assert_eq!(
+            RdfParseError::new(2, 5, "predicate must be an IRI"),
+            parse_error("<a> <b> <c> .\n<a> _:b <c> .\n")
+        );
+        assert_eq!(
+            RdfParseError::new(1, 1, "subject cannot be a literal"),
+            parse_error("\"a\" <b> <c> .")
+        );
+        assert_eq!(
+            RdfParseError::new(1, 12, "expected '.', found end of input"),
+            parse_error("<a> <b> <c>")
+        );
+        assert_eq!(3, parse_error("\n\n<a> <b> \"c .").line);
+        assert_eq!(1, parse_error("<a b> <b> <c> .").line);
+        assert_eq!(1, parse_error("<a> <b> \"c\"@ .").line);
+        assert_eq!(1, parse_error("<a> <b> _:c. .").line);
+        assert_eq!(1, parse_error("<a> <b> \"\\q\" .").line);
+        assert_eq!(1, parse_error("<a> <b> <c> . <d>").line);
+    }
+
+    #[tokio::test]
+    async fn import_into_builder() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        let document = "<cow> <says> \"moo\" .\n<pig> <says> \"oink\" <farm> .\n<duck> <likes> <cow> <zoo> .\n";
+
+        let count = import_nquads(
+            document.as_bytes(),
+            &builder,
+            &GraphFilter::Named("farm".to_string()),
+        )
+        .unwrap();
+        assert_eq!(1, count);
+        let count = import_nquads(document.as_bytes(), &builder, &GraphFilter::Default).unwrap();
+        assert_eq!(1, count);
+        let count = import_ntriples("<horse> <says> \"neigh\" .".as_bytes(), &builder).unwrap();
+        assert_eq!(1, count);
+
+        let layer = builder.commit().await.unwrap();
+
+        assert_eq!(3, layer.triple_count());
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "\"moo\"")));
+        assert!(layer.string_triple_exists(&StringTriple::new_value("pig", "says", "\"oink\"")));
+        assert!(layer.string_triple_exists(&StringTriple::new_value("horse", "says", "\"neigh\"")));
+        assert!(!layer.string_triple_exists(&StringTriple::new_node("duck", "likes", "cow")));
+    }
+}
@@ -0,0 +1,1081 @@
+//! Turtle parsing and import.
+//!
+//! Turtle statements may span multiple lines. The parser reads lines
+//! into a buffer until a complete statement can be parsed, then
+//! discards the parsed part of the buffer. This way, memory use is
+//! bounded by the size of the largest statement rather than the size
+//! of the document. The lines are scanned once for places where a
+//! statement might end, and parsing is only attempted once such a
+//! place was read, so a statement isn't parsed again for every line
+//! it spans.
+//!
+//! Blank nodes that are written as `[]`, as a `[ ... ]` property
+//! list, or that are created for the elements of a collection, are
+//! given generated labels of the form `_:genid<n>`, numbered in order
+//! of appearance. Importing the same document twice will therefore
+//! result in the same labels. To keep labels from the document apart
+//! from generated ones, labels in the document that start with
+//! `genid` get an underscore inserted after it, so `_:genid0` in the
+//! document becomes `_:genid_0`.
+use super::cursor::*;
+use super::iri::*;
+use super::*;
+use crate::layer::StringTriple;
+use crate::store::StoreLayerBuilder;
+use std::collections::{HashMap, VecDeque};
+use std::io::BufRead;
+
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+const RDF_FIRST: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#first";
+const RDF_REST: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#rest";
+const RDF_NIL: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#nil";
+const XSD_BOOLEAN: &str = "http://www.w3.org/2001/XMLSchema#boolean";
+const XSD_INTEGER: &str = "http://www.w3.org/2001/XMLSchema#integer";
+const XSD_DECIMAL: &str = "http://www.w3.org/2001/XMLSchema#decimal";
+const XSD_DOUBLE: &str = "http://www.w3.org/2001/XMLSchema#double";
+
+/// Parser state that carries over between statements.
+///
+/// This is shared with the SPARQL parser, which uses the same syntax
+/// for IRIs, prefixed names and literals.
+pub(crate) struct TurtleState {
+    pub(crate) base: Option<String>,
+    pub(crate) prefixes: HashMap<String, String>,
+    blank_node_prefix: String,
+    blank_node_count: usize,
+}
+
+fn is_pn_local_escapable(c: char) -> bool {
+    matches!(
+        c,
+        '_' | '~'
+            | '.'
+            | '-'
+            | '!'
+            | '$'
+            | '&'
+            | '\''
+            | '('
+            | ')'
+            | '*'
+            | '+'
+            | ','
+            | ';'
+            | '='
+            | '/'
+            | '?'
+            | '#'
+            | '@'
+            | '%'
+    )
+}
+
+/// Returns true if a keyword like `a` or `true` is followed by something that ends it.
+pub(crate) fn ends_keyword(c: Option<char>) -> bool {
+    match c {
+        None => true,
+        Some(c) => !(is_pn_chars(c) || c == ':' || c == '.'),
+    }
+}
+
+impl TurtleState {
+    pub(crate) fn new() -> Self {
+        TurtleState {
+            base: None,
+            prefixes: HashMap::new(),
+            blank_node_prefix: String::new(),
+            blank_node_count: 0,
+        }
+    }
+
+    fn blank_node(&self, label: &str) -> String {
+        match label.strip_prefix("genid") {
+            Some(rest) => format!("_:{}genid_{}", self.blank_node_prefix, rest),
+            None => format!("_:{}{}", self.blank_node_prefix, label),
+        }
+    }
+
+    fn generate_blank_node(&mut self) -> String {
+        let node = format!("_:{}genid{}", self.blank_node_prefix, self.blank_node_count);
+        self.blank_node_count += 1;
+
+        node
+    }
+
+    fn resolve(&self, iri: String) -> String {
+        match &self.base {
+            Some(base) if !is_absolute_iri(&iri) => resolve_iri(base, &iri),
+            _ => iri,
+        }
+    }
+
+    pub(crate) fn parse_iriref(&self, cursor: &mut Cursor) -> Result<String, RdfParseError> {
+        let iri = cursor.parse_iriref()?;
+
+        Ok(self.resolve(iri))
+    }
+
+    /// Parse a prefix name including its trailing colon, returning the prefix without the colon.
+    pub(crate) fn parse_pname_ns(&self, cursor: &mut Cursor) -> Result<String, RdfParseError> {
+        let mut prefix = String::new();
+        if let Some(c) = cursor.peek().filter(|c| is_pn_chars_base(*c)) {
+            cursor.bump();
+            prefix.push(c);
+            loop {
+                match cursor.peek() {
+                    Some(c) if is_pn_chars(c) => {
+                        cursor.bump();
+                        prefix.push(c);
+                    }
+                    Some('.') if matches!(cursor.peek_nth(1), Some(c) if is_pn_chars(c) || c == '.') =>
+                    {
+                        cursor.bump();
+                        prefix.push('.');
+                    }
+                    _ => break,
+                }
+            }
+        }
+        if prefix.ends_with('.') {
+            return Err(cursor.error("prefix cannot end with '.'"));
+        }
+        cursor.expect(':')?;
+
+        Ok(prefix)
+    }
+
+    fn parse_pn_local_char(
+        &self,
+        cursor: &mut Cursor,
+        local: &mut String,
+    ) -> Result<bool, RdfParseError> {
+        match cursor.peek() {
+            Some(c) if is_pn_chars(c) || c == ':' => {
+                cursor.bump();
+                local.push(c);
+            }
+            Some('%') => {
+                cursor.bump();
+                local.push('%');
+                for _ in 0..2 {
+                    match cursor.peek() {
+                        Some(c) if c.is_ascii_hexdigit() => {
+                            cursor.bump();
+                            local.push(c);
+                        }
+                        _ => return Err(cursor.error("invalid percent encoding")),
+                    }
+                }
+            }
+            Some('\\') => {
+                cursor.bump();
+                match cursor.peek() {
+                    Some(c) if is_pn_local_escapable(c) => {
+                        cursor.bump();
+                        local.push(c);
+                    }
+                    _ => return Err(cursor.error("invalid escape in local name")),
+                }
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    fn parse_prefixed_name(&self, cursor: &mut Cursor) -> Result<String, RdfParseError> {
+        let (line, column) = (cursor.line(), cursor.column());
+        let prefix = self.parse_pname_ns(cursor)?;
+        let namespace = self.prefixes.get(&prefix).ok_or_else(|| {
+            RdfParseError::new(line, column, format!("undefined prefix '{}:'", prefix))
+        })?;
+
+        let mut local = String::new();
+        match cursor.peek() {
+            Some(c) if c.is_ascii_digit() || c == '_' => {
+                cursor.bump();
+                local.push(c);
+            }
+            Some('.') => {}
+            _ => {
+                self.parse_pn_local_char(cursor, &mut local)?;
+            }
+        }
+        if !local.is_empty() {
+            loop {
+                if cursor.peek() == Some('.') {
+                    // a dot may appear inside a local name, but not at its end
+                    let mut lookahead = 1;
+                    while cursor.peek_nth(lookahead) == Some('.') {
+                        lookahead += 1;
+                    }
+                    match cursor.peek_nth(lookahead) {
+                        Some(c) if is_pn_chars(c) || c == ':' || c == '%' || c == '\\' => {
+                            for _ in 0..lookahead {
+                                cursor.bump();
+                                local.push('.');
+                            }
+                        }
+                        _ => break,
+                    }
+                }
+                if !self.parse_pn_local_char(cursor, &mut local)? {
+                    break;
+                }
+            }
+        }
+
+        Ok(format!("{}{}", namespace, local))
+    }
+
+    pub(crate) fn parse_iri(&self, cursor: &mut Cursor) -> Result<String, RdfParseError> {
+        if cursor.peek() == Some('<') {
+            self.parse_iriref(cursor)
+        } else {
+            self.parse_prefixed_name(cursor)
+        }
+    }
+
+    fn parse_string(&self, cursor: &mut Cursor) -> Result<String, RdfParseError> {
+        let quote = match cursor.peek() {
+            Some(c) if c == '"' || c == '\'' => c,
+            _ => return Err(cursor.error("expected string")),
+        };
+        let long_quote = quote.to_string().repeat(3);
+        if !cursor.starts_with(&long_quote) {
+            return cursor.parse_quoted_string(quote);
+        }
+
+        let (line, column) = (cursor.line(), cursor.column());
+        for _ in 0..3 {
+            cursor.bump();
+        }
+        let mut value = String::new();
+        loop {
+            if cursor.starts_with(&long_quote) {
+                for _ in 0..3 {
+                    cursor.bump();
+                }
+                // a long string may end in up to two quotes
+                while cursor.peek() == Some(quote) {
+                    cursor.bump();
+                    value.push(quote);
+                }
+                if value.ends_with(&long_quote) {
+                    return Err(cursor.error("unescaped quotes at end of long string"));
+                }
+                break;
+            }
+            match cursor.bump() {
+                None => return Err(RdfParseError::new(line, column, "unterminated string")),
+                Some('\\') => {
+                    let c = cursor.parse_escape()?;
+                    value.push(c);
+                }
+                Some(c) => value.push(c),
+            }
+        }
+
+        Ok(value)
+    }
+
+    pub(crate) fn parse_literal(&self, cursor: &mut Cursor) -> Result<Term, RdfParseError> {
+        let value = self.parse_string(cursor)?;
+        let mut language = None;
+        let mut datatype = None;
+        if cursor.eat('@') {
+            language = Some(cursor.parse_langtag()?);
+        } else if cursor.starts_with("^^") {
+            cursor.bump();
+            cursor.bump();
+            datatype = Some(self.parse_iri(cursor)?);
+        }
+
+        Ok(Term::Literal {
+            value,
+            language,
+            datatype,
+        })
+    }
+
+    pub(crate) fn parse_numeric_literal(&self, cursor: &mut Cursor) -> Result<Term, RdfParseError> {
+        let mut value = String::new();
+        let mut datatype = XSD_INTEGER;
+        if let Some(c) = cursor.peek().filter(|c| *c == '+' || *c == '-') {
+            cursor.bump();
+            value.push(c);
+        }
+        let mut digits = 0;
+        while let Some(c) = cursor.peek().filter(char::is_ascii_digit) {
+            cursor.bump();
+            value.push(c);
+            digits += 1;
+        }
+        if cursor.peek() == Some('.') && matches!(cursor.peek_nth(1), Some(c) if c.is_ascii_digit())
+        {
+            cursor.bump();
+            value.push('.');
+            datatype = XSD_DECIMAL;
+            while let Some(c) = cursor.peek().filter(char::is_ascii_digit) {
+                cursor.bump();
+                value.push(c);
+                digits += 1;
+            }
+        }
+        if digits == 0 {
+            return Err(cursor.error("invalid number"));
+        }
+        if let Some(e) = cursor.peek().filter(|c| *c == 'e' || *c == 'E') {
+            cursor.bump();
+            value.push(e);
+            datatype = XSD_DOUBLE;
+            if let Some(c) = cursor.peek().filter(|c| *c == '+' || *c == '-') {
+                cursor.bump();
+                value.push(c);
+            }
+            let len = value.len();
+            while let Some(c) = cursor.peek().filter(char::is_ascii_digit) {
+                cursor.bump();
+                value.push(c);
+            }
+            if value.len() == len {
+                return Err(cursor.error("invalid exponent"));
+            }
+        }
+
+        Ok(Term::Literal {
+            value,
+            language: None,
+            datatype: Some(datatype.to_string()),
+        })
+    }
+
+    fn parse_collection(
+        &mut self,
+        cursor: &mut Cursor,
+        triples: &mut Vec<StringTriple>,
+    ) -> Result<String, RdfParseError> {
+        cursor.expect('(')?;
+        let mut head = RDF_NIL.to_string();
+        let mut last: Option<String> = None;
+        loop {
+            cursor.skip_whitespace();
+            if cursor.eat(')') {
+                break;
+            }
+            let object = self.parse_object(cursor, triples)?;
+            let node = self.generate_blank_node();
+            match last {
+                None => head = node.clone(),
+                Some(last) => triples.push(StringTriple {
+                    subject: last,
+                    predicate: RDF_REST.to_string(),
+                    object: ObjectType::Node(node.clone()),
+                }),
+            }
+            triples.push(StringTriple {
+                subject: node.clone(),
+                predicate: RDF_FIRST.to_string(),
+                object,
+            });
+            last = Some(node);
+        }
+        if let Some(last) = last {
+            triples.push(StringTriple::new_node(&last, RDF_REST, RDF_NIL));
+        }
+
+        Ok(head)
+    }
+
+    fn parse_blank_node_property_list(
+        &mut self,
+        cursor: &mut Cursor,
+        triples: &mut Vec<StringTriple>,
+    ) -> Result<String, RdfParseError> {
+        cursor.expect('[')?;
+        let node = self.generate_blank_node();
+        cursor.skip_whitespace();
+        if !cursor.eat(']') {
+            self.parse_predicate_object_list(cursor, &node, triples)?;
+            cursor.skip_whitespace();
+            cursor.expect(']')?;
+        }
+
+        Ok(node)
+    }
+
+    fn parse_object(
+        &mut self,
+        cursor: &mut Cursor,
+        triples: &mut Vec<StringTriple>,
+    ) -> Result<ObjectType, RdfParseError> {
+        let object = match cursor.peek() {
+            Some('<') => ObjectType::Node(self.parse_iriref(cursor)?),
+            Some('_') if cursor.starts_with("_:") => {
+                ObjectType::Node(self.blank_node(&cursor.parse_blank_node_label()?))
+            }
+            Some('[') => ObjectType::Node(self.parse_blank_node_property_list(cursor, triples)?),
+            Some('(') => ObjectType::Node(self.parse_collection(cursor, triples)?),
+            Some('"') | Some('\'') => self.parse_literal(cursor)?.into_object(),
+            Some(c) if c.is_ascii_digit() || c == '+' || c == '-' || c == '.' => {
+                self.parse_numeric_literal(cursor)?.into_object()
+            }
+            Some('t') if cursor.starts_with("true") && ends_keyword(cursor.peek_nth(4)) => {
+                for _ in 0..4 {
+                    cursor.bump();
+                }
+                ObjectType::Value(format_literal("true", None, Some(XSD_BOOLEAN)))
+            }
+            Some('f') if cursor.starts_with("false") && ends_keyword(cursor.peek_nth(5)) => {
+                for _ in 0..5 {
+                    cursor.bump();
+                }
+                ObjectType::Value(format_literal("false", None, Some(XSD_BOOLEAN)))
+            }
+            Some(_) => ObjectType::Node(self.parse_prefixed_name(cursor)?),
+            None => return Err(cursor.error("expected object, found end of input")),
+        };
+
+        Ok(object)
+    }
+
+    fn parse_verb(&self, cursor: &mut Cursor) -> Result<String, RdfParseError> {
+        if cursor.peek() == Some('a') && ends_keyword(cursor.peek_nth(1)) {
+            cursor.bump();
+            Ok(RDF_TYPE.to_string())
+        } else {
+            self.parse_iri(cursor)
+        }
+    }
+
+    fn parse_predicate_object_list(
+        &mut self,
+        cursor: &mut Cursor,
+        subject: &str,
+        triples: &mut Vec<StringTriple>,
+    ) -> Result<(), RdfParseError> {
+        loop {
+            let predicate = self.parse_verb(cursor)?;
+            loop {
+                cursor.skip_whitespace();
+                let object = self.parse_object(cursor, triples)?;
+                triples.push(StringTriple {
+                    subject: subject.to_string(),
+                    predicate: predicate.clone(),
+                    object,
+                });
+                cursor.skip_whitespace();
+                if !cursor.eat(',') {
+                    break;
+                }
+            }
+
+            // any amount of semicolons may follow, and the last may be followed by nothing
+            let mut semicolons = false;
+            while cursor.eat(';') {
+                semicolons = true;
+                cursor.skip_whitespace();
+            }
+            if !semicolons || matches!(cursor.peek(), Some('.') | Some(']') | None) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_triples(
+        &mut self,
+        cursor: &mut Cursor,
+        triples: &mut Vec<StringTriple>,
+    ) -> Result<(), RdfParseError> {
+        let subject = match cursor.peek() {
+            Some('<') => self.parse_iriref(cursor)?,
+            Some('_') if cursor.starts_with("_:") => {
+                self.blank_node(&cursor.parse_blank_node_label()?)
+            }
+            Some('(') => self.parse_collection(cursor, triples)?,
+            Some('[') => {
+                let subject = self.parse_blank_node_property_list(cursor, triples)?;
+                cursor.skip_whitespace();
+                if cursor.eat('.') {
+                    return Ok(());
+                }
+                subject
+            }
+            Some('"') | Some('\'') => return Err(cursor.error("subject cannot be a literal")),
+            _ => self.parse_prefixed_name(cursor)?,
+        };
+        cursor.skip_whitespace();
+        self.parse_predicate_object_list(cursor, &subject, triples)?;
+        cursor.skip_whitespace();
+        cursor.expect('.')
+    }
+
+    fn parse_keyword(cursor: &mut Cursor, keyword: &str, case_sensitive: bool) -> bool {
+        let rest = cursor.rest();
+        let matched = rest.len() >= keyword.len()
+            && rest.is_char_boundary(keyword.len())
+            && if case_sensitive {
+                &rest[..keyword.len()] == keyword
+            } else {
+                rest[..keyword.len()].eq_ignore_ascii_case(keyword)
+            }
+            && matches!(rest[keyword.len()..].chars().next(), Some(c) if c.is_whitespace() || c == '<' || c == ':' || c == '#');
+        if matched {
+            for _ in 0..keyword.chars().count() {
+                cursor.bump();
+            }
+        }
+
+        matched
+    }
+
+    /// Parse a single statement, collecting the triples it contains.
+    fn parse_statement(
+        &mut self,
+        cursor: &mut Cursor,
+        triples: &mut Vec<StringTriple>,
+    ) -> Result<(), RdfParseError> {
+        if Self::parse_keyword(cursor, "@prefix", true) {
+            cursor.skip_whitespace();
+            let prefix = self.parse_pname_ns(cursor)?;
+            cursor.skip_whitespace();
+            let iri = self.parse_iriref(cursor)?;
+            cursor.skip_whitespace();
+            cursor.expect('.')?;
+            self.prefixes.insert(prefix, iri);
+        } else if Self::parse_keyword(cursor, "@base", true) {
+            cursor.skip_whitespace();
+            let iri = self.parse_iriref(cursor)?;
+            cursor.skip_whitespace();
+            cursor.expect('.')?;
+            self.base = Some(iri);
+        } else if Self::parse_keyword(cursor, "prefix", false) {
+            cursor.skip_whitespace();
+            let prefix = self.parse_pname_ns(cursor)?;
+            cursor.skip_whitespace();
+            let iri = self.parse_iriref(cursor)?;
+            self.prefixes.insert(prefix, iri);
+        } else if Self::parse_keyword(cursor, "base", false) {
+            cursor.skip_whitespace();
+            let iri = self.parse_iriref(cursor)?;
+            self.base = Some(iri);
+        } else {
+            self.parse_triples(cursor, triples)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What the scan for statement ends is in the middle of.
+#[derive(Clone, Copy, PartialEq)]
+enum ScanState {
+    Normal,
+    Iri,
+    String { quote: u8, long: bool },
+    Comment,
+}
+
+/// Finds the places in the buffer where a statement might end.
+///
+/// A statement ends in a `.` outside of IRIs, literals, comments and
+/// brackets, which doesn't continue a name or a number. This is only
+/// used to avoid parsing a statement before it can be complete, so it
+/// is fine to find a place that turns out not to end a statement.
+struct StatementEnds {
+    scanned: usize,
+    state: ScanState,
+    escaped: bool,
+    depth: usize,
+    ends: VecDeque<usize>,
+}
+
+impl StatementEnds {
+    fn new() -> Self {
+        StatementEnds {
+            scanned: 0,
+            state: ScanState::Normal,
+            escaped: false,
+            depth: 0,
+            ends: VecDeque::new(),
+        }
+    }
+
+    /// Scan the part of the buffer that wasn't scanned yet.
+    fn scan(&mut self, buffer: &str) {
+        let bytes = buffer.as_bytes();
+        let mut i = self.scanned;
+        while i < bytes.len() {
+            let c = bytes[i];
+            i += 1;
+            if self.escaped {
+                self.escaped = false;
+                continue;
+            }
+            match self.state {
+                ScanState::Normal => match c {
+                    b'#' => self.state = ScanState::Comment,
+                    b'<' => self.state = ScanState::Iri,
+                    b'"' | b'\'' => {
+                        let long = bytes[i..].starts_with(&[c, c]);
+                        if long {
+                            i += 2;
+                        }
+                        self.state = ScanState::String { quote: c, long };
+                    }
+                    b'[' | b'(' => self.depth += 1,
+                    b']' | b')' => self.depth = self.depth.saturating_sub(1),
+                    b'\\' => self.escaped = true,
+                    b'.' if self.depth == 0 => {
+                        let continues = matches!(buffer[i..].chars().next(), Some(n) if is_pn_chars(n) || matches!(n, ':' | '.' | '%' | '\\'));
+                        if !continues {
+                            self.ends.push_back(i - 1);
+                        }
+                    }
+                    _ => {}
+                },
+                ScanState::Iri => match c {
+                    b'>' => self.state = ScanState::Normal,
+                    b'\\' => self.escaped = true,
+                    _ => {}
+                },
+                ScanState::String { quote, long } => {
+                    if c == b'\\' {
+                        self.escaped = true;
+                    } else if c == quote {
+                        // a long string ends in the last three quotes of a run
+                        let mut run = 1;
+                        while bytes.get(i) == Some(&quote) {
+                            i += 1;
+                            run += 1;
+                        }
+                        if !long || run >= 3 {
+                            self.state = ScanState::Normal;
+                        }
+                    }
+                }
+                ScanState::Comment => {
+                    if c == b'\n' {
+                        self.state = ScanState::Normal;
+                    }
+                }
+            }
+        }
+        self.scanned = i;
+    }
+
+    /// Forget about everything before the given position in the buffer, as it was removed.
+    fn shift(&mut self, pos: usize) {
+        self.scanned -= pos;
+        while matches!(self.ends.front(), Some(&end) if end < pos) {
+            self.ends.pop_front();
+        }
+        for end in self.ends.iter_mut() {
+            *end -= pos;
+        }
+    }
+}
+
+/// A streaming parser for Turtle documents.
+///
+/// This is an iterator over the triples in the document.
+pub struct TurtleParser<R: BufRead> {
+    reader: R,
+    state: TurtleState,
+    buffer: String,
+    /// The start of the part of the buffer that wasn't parsed yet.
+    start: usize,
+    statement_ends: StatementEnds,
+    line: usize,
+    column: usize,
+    eof: bool,
+    pending: VecDeque<StringTriple>,
+}
+
+impl<R: BufRead> TurtleParser<R> {
+    pub fn new(reader: R) -> Self {
+        TurtleParser {
+            reader,
+            state: TurtleState::new(),
+            buffer: String::new(),
+            start: 0,
+            statement_ends: StatementEnds::new(),
+            line: 1,
+            column: 1,
+            eof: false,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Resolve relative IRIs against the given base IRI, until the document sets its own base.
+    pub fn with_base<S: Into<String>>(mut self, base: S) -> Self {
+        self.state.base = Some(base.into());
+
+        self
+    }
+
+    /// Prepend the given prefix to the labels of all blank nodes.
+    ///
+    /// This is useful to keep the blank nodes of several documents
+    /// loaded into the same layer apart.
+    pub fn with_blank_node_prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.state.blank_node_prefix = prefix.into();
+
+        self
+    }
+
+    fn read_line(&mut self) -> io::Result<()> {
+        // drop the parsed part of the buffer
+        if self.start > 0 {
+            self.buffer.drain(..self.start);
+            self.statement_ends.shift(self.start);
+            self.start = 0;
+        }
+
+        let mut buf = Vec::new();
+        if self.reader.read_until(b'\n', &mut buf)? == 0 {
+            self.eof = true;
+            return Ok(());
+        }
+
+        let s = String::from_utf8(buf).map_err(|_| {
+            let line = self.line + self.buffer.matches('\n').count();
+            RdfParseError::new(line, 1, "invalid utf-8")
+        })?;
+        self.buffer.push_str(&s);
+        self.statement_ends.scan(&self.buffer);
+
+        Ok(())
+    }
+
+    /// Parse the next statement into the pending triples, returning false at the end of the document.
+    fn parse_next_statement(&mut self) -> io::Result<bool> {
+        let mut triples = Vec::new();
+        loop {
+            let mut cursor =
+                Cursor::with_column(&self.buffer[self.start..], self.line, self.column);
+            cursor.skip_whitespace();
+            if cursor.is_at_end() {
+                if self.eof {
+                    return Ok(false);
+                }
+                self.read_line()?;
+                continue;
+            }
+            if self.statement_ends.ends.is_empty() && !self.eof {
+                // the statement can't be complete yet
+                self.read_line()?;
+                continue;
+            }
+
+            let blank_node_count = self.state.blank_node_count;
+            match self.state.parse_statement(&mut cursor, &mut triples) {
+                Ok(()) => {
+                    let (pos, line, column) = (cursor.pos(), cursor.line(), cursor.column());
+                    self.start += pos;
+                    while matches!(self.statement_ends.ends.front(), Some(&end) if end < self.start)
+                    {
+                        self.statement_ends.ends.pop_front();
+                    }
+                    self.line = line;
+                    self.column = column;
+                    self.pending.extend(triples);
+
+                    return Ok(true);
+                }
+                Err(_) if cursor.is_at_end() && !self.eof => {
+                    // the statement continues past every end found so far
+                    self.state.blank_node_count = blank_node_count;
+                    self.statement_ends.ends.clear();
+                    triples.clear();
+                    self.read_line()?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for TurtleParser<R> {
+    type Item = io::Result<StringTriple>;
+
+    fn next(&mut self) -> Option<io::Result<StringTriple>> {
+        loop {
+            if let Some(triple) = self.pending.pop_front() {
+                return Some(Ok(triple));
+            }
+            match self.parse_next_statement() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => {
+                    // don't keep on returning the same error
+                    self.eof = true;
+                    self.buffer.clear();
+                    self.start = 0;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Add all triples in the given Turtle document to the builder.
+///
+/// Returns the amount of triples that were added.
+pub fn import_turtle<R: BufRead>(reader: R, builder: &StoreLayerBuilder) -> io::Result<usize> {
+    let mut count = 0;
+    for triple in TurtleParser::new(reader) {
+        builder.add_string_triple(triple?)?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::Layer;
+    use crate::store::open_memory_store;
+
+    fn parse(document: &str) -> io::Result<Vec<StringTriple>> {
+        TurtleParser::new(document.as_bytes()).collect()
+    }
+
+    fn parse_error(document: &str) -> RdfParseError {
+        let err = parse(document).unwrap_err();
+        err.into_inner()
+            .unwrap()
+            .downcast::<RdfParseError>()
+            .map(|e| *e)
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_prefixes_and_shorthand() {
+        let triples = parse(
+            r#"@prefix ex: <http://example.com/> .
+PREFIX : <http://example.com/default#>
+@base <http://example.com/base/> .
+
+ex:cow a ex:Animal ;
+    ex:says "moo", 'mooo'@en ;
+    ex:likes <pig>, :duck ;
+    .
+:duck ex:weight 1.5, -3, 4e10, +2.0E-3 ; ex:flies false .
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                StringTriple::new_node(
+                    "http://example.com/cow",
+                    RDF_TYPE,
+                    "http://example.com/Animal"
+                ),
+                StringTriple::new_value(
+                    "http://example.com/cow",
+                    "http://example.com/says",
+                    "\"moo\""
+                ),
+                StringTriple::new_value(
+                    "http://example.com/cow",
+                    "http://example.com/says",
+                    "\"mooo\"@en"
+                ),
+                StringTriple::new_node(
+                    "http://example.com/cow",
+                    "http://example.com/likes",
+                    "http://example.com/base/pig"
+                ),
+                StringTriple::new_node(
+                    "http://example.com/cow",
+                    "http://example.com/likes",
+                    "http://example.com/default#duck"
+                ),
+                StringTriple::new_value(
+                    "http://example.com/default#duck",
+                    "http://example.com/weight",
+                    &format_literal("1.5", None, Some(XSD_DECIMAL))
+                ),
+                StringTriple::new_value(
+                    "http://example.com/default#duck",
+                    "http://example.com/weight",
+                    &format_literal("-3", None, Some(XSD_INTEGER))
+                ),
+                StringTriple::new_value(
+                    "http://example.com/default#duck",
+                    "http://example.com/weight",
+                    &format_literal("4e10", None, Some(XSD_DOUBLE))
+                ),
+                StringTriple::new_value(
+                    "http://example.com/default#duck",
+                    "http://example.com/weight",
+                    &format_literal("+2.0E-3", None, Some(XSD_DOUBLE))
+                ),
+                StringTriple::new_value(
+                    "http://example.com/default#duck",
+                    "http://example.com/flies",
+                    &format_literal("false", None, Some(XSD_BOOLEAN))
+                ),
+            ],
+            triples
+        );
+    }
+
+    #[test]
+    fn parse_blank_nodes_and_collections() {
+        let triples = parse(
+            r#"@prefix ex: <http://example.com/> .
+_:a ex:knows [ ex:name "b" ], [] .
+[ ex:name "c" ] ex:list ( 1 _:a ) .
+ex:empty ex:list () .
+"#,
+        )
+        .unwrap();
+
+        let integer = |i: &str| format_literal(i, None, Some(XSD_INTEGER));
+        assert_eq!(
+            vec![
+                StringTriple::new_value("_:genid0", "http://example.com/name", "\"b\""),
+                StringTriple::new_node("_:a", "http://example.com/knows", "_:genid0"),
+                StringTriple::new_node("_:a", "http://example.com/knows", "_:genid1"),
+                StringTriple::new_value("_:genid2", "http://example.com/name", "\"c\""),
+                StringTriple::new_value("_:genid3", RDF_FIRST, &integer("1")),
+                StringTriple::new_node("_:genid3", RDF_REST, "_:genid4"),
+                StringTriple::new_node("_:genid4", RDF_FIRST, "_:a"),
+                StringTriple::new_node("_:genid4", RDF_REST, RDF_NIL),
+                StringTriple::new_node("_:genid2", "http://example.com/list", "_:genid3"),
+                StringTriple::new_node(
+                    "http://example.com/empty",
+                    "http://example.com/list",
+                    RDF_NIL
+                ),
+            ],
+            triples
+        );
+    }
+
+    #[test]
+    fn blank_node_labels_are_stable_across_line_breaks() {
+        let on_one_line = parse("<s> <p> [ <q> ( 1 2 ) ] .").unwrap();
+        let on_many_lines = parse("<s>\n<p>\n[\n<q> (\n1\n2\n)\n]\n.\n").unwrap();
+        assert_eq!(on_one_line, on_many_lines);
+
+        let prefixed: Vec<_> = TurtleParser::new("_:x <p> [] .".as_bytes())
+            .with_blank_node_prefix("doc1_")
+            .map(|t| t.unwrap())
+            .collect();
+        assert_eq!(
+            vec![StringTriple::new_node("_:doc1_x", "p", "_:doc1_genid0")],
+            prefixed
+        );
+    }
+
+    #[test]
+    fn document_labels_dont_collide_with_generated_labels() {
+        let triples = parse("<a> <p> _:genid0 .\n[ <q> <c> ] <r> _:genid_0 .\n").unwrap();
+        assert_eq!(
+            vec![
+                StringTriple::new_node("a", "p", "_:genid_0"),
+                StringTriple::new_node("_:genid0", "q", "c"),
+                StringTriple::new_node("_:genid0", "r", "_:genid__0"),
+            ],
+            triples
+        );
+    }
+
+    #[test]
+    fn statements_end_only_outside_literals_and_brackets() {
+        let triples = parse(
+            "<a> <p> \"\"\"x .\n\"\"\"\" . <a> <p> [\n<q> \"y .\" ; <r> <c.d>\n] . <a> <p> 1.5 # c .\n.\n",
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                StringTriple::new_value("a", "p", "\"x .\\n\\\"\""),
+                StringTriple::new_value("_:genid0", "q", "\"y .\""),
+                StringTriple::new_node("_:genid0", "r", "c.d"),
+                StringTriple::new_node("a", "p", "_:genid0"),
+                StringTriple::new_value("a", "p", &format_literal("1.5", None, Some(XSD_DECIMAL))),
+            ],
+            triples
+        );
+    }
+
+    #[test]
+    fn parse_strings_and_escapes() {
+        let triples = parse(
+            "@prefix ex: <http://example.com/> .\nex:a ex:b \"\"\"multi\nline \"quoted\" \\u00e9\"\"\", '''it''s''', \"x\"^^ex:type ; ex:c ex:with\\.dots.and\\~escapes .\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                StringTriple::new_value(
+                    "http://example.com/a",
+                    "http://example.com/b",
+                    "\"multi\\nline \\\"quoted\\\" é\""
+                ),
+                StringTriple::new_value(
+                    "http://example.com/a",
+                    "http://example.com/b",
+                    "\"it''s\""
+                ),
+                StringTriple::new_value(
+                    "http://example.com/a",
+                    "http://example.com/b",
+                    "\"x\"^^<http://example.com/type>"
+                ),
+                StringTriple::new_node(
+                    "http://example.com/a",
+                    "http://example.com/c",
+                    "http://example.com/with.dots.and~escapes"
+                ),
+            ],
+            triples
+        );
+    }
+
+    #[test]
+    fn errors_have_line_and_column() {
+        assert_eq!(
+            RdfParseError::new(2, 1, "undefined prefix 'ex:'"),
+            parse_error("<a> <b> <c> .\nex:a <b> <c> .")
+        );
+        assert_eq!(
+            RdfParseError::new(1, 15, "subject cannot be a literal"),
+            parse_error("<a> <b> <c> . \"a\" <b> <c> .")
+        );
+        assert_eq!(3, parse_error("<a>\n<b>\n<c> <d> .").line);
+        assert_eq!(1, parse_error("<a> <b> \"unterminated .\n").line);
+        assert_eq!(
+            2,
+            parse_error("<a> <b> <c> .\n<a> <b> \"\"\"unterminated .\n").line
+        );
+    }
+
+    #[tokio::test]
+    async fn import_into_builder() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        let document = "@prefix : <http://example.com/> .\n:cow :says \"moo\" ; :likes :pig .\n";
+
+        assert_eq!(2, import_turtle(document.as_bytes(), &builder).unwrap());
+        let layer = builder.commit().await.unwrap();
+
+        assert!(layer.string_triple_exists(&StringTriple::new_value(
+            "http://example.com/cow",
+            "http://example.com/says",
+            "\"moo\""
+        )));
+        assert!(layer.string_triple_exists(&StringTriple::new_node(
+            "http://example.com/cow",
+            "http://example.com/likes",
+            "http://example.com/pig"
+        )));
+    }
+}
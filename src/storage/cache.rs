@@ -148,6 +148,46 @@ impl LayerStore for CachedLayerStore {
         self.inner.create_child_layer_with_cache(parent, cache)
     }
 
+    fn create_spilling_base_layer(
+        &self,
+        memory_limit: usize,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        self.inner.create_spilling_base_layer(memory_limit)
+    }
+
+    fn create_spilling_child_layer(
+        &self,
+        parent: [u32; 5],
+        memory_limit: usize,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        self.inner
+            .create_spilling_child_layer_with_cache(parent, memory_limit, self.cache.clone())
+    }
+
+    fn create_spilling_child_layer_with_cache(
+        &self,
+        parent: [u32; 5],
+        memory_limit: usize,
+        cache: Arc<dyn LayerCache>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        self.inner
+            .create_spilling_child_layer_with_cache(parent, memory_limit, cache)
+    }
+
+    fn create_base_layer_from_hdt(
+        &self,
+        hdt: Vec<u8>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        self.inner.create_base_layer_from_hdt(hdt)
+    }
+
+    fn squash(
+        &self,
+        layer: Arc<InternalLayer>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        self.inner.squash(layer)
+    }
+
     fn perform_rollup(
         &self,
         layer: Arc<InternalLayer>,
@@ -174,6 +214,23 @@ impl LayerStore for CachedLayerStore {
             .perform_rollup_upto_with_cache(layer, upto, self.cache.clone())
     }
 
+    fn perform_compacted_rollup(
+        &self,
+        layer: Arc<InternalLayer>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        self.inner.perform_compacted_rollup(layer)
+    }
+
+    fn perform_compacted_rollup_upto_with_cache(
+        &self,
+        layer: Arc<InternalLayer>,
+        upto: [u32; 5],
+        cache: Arc<dyn LayerCache>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        self.inner
+            .perform_compacted_rollup_upto_with_cache(layer, upto, cache)
+    }
+
     fn register_rollup(
         &self,
         layer: [u32; 5],
@@ -192,6 +249,36 @@ impl LayerStore for CachedLayerStore {
         })
     }
 
+    fn layer_rollup(
+        &self,
+        layer: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<[u32; 5]>>> + Send>> {
+        self.inner.layer_rollup(layer)
+    }
+
+    fn layer_is_compacted_rollup(
+        &self,
+        layer: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+        self.inner.layer_is_compacted_rollup(layer)
+    }
+
+    fn unregister_rollup(
+        &self,
+        layer: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+        // like when registering, the cached version has to go
+        let cache = self.cache.clone();
+        let unregister_rollup = self.inner.unregister_rollup(layer);
+
+        Box::pin(async move {
+            let removed = unregister_rollup.await?;
+            cache.invalidate(layer);
+
+            Ok(removed)
+        })
+    }
+
     fn rollup_upto(
         self: Arc<Self>,
         layer: Arc<InternalLayer>,
@@ -420,6 +507,42 @@ impl LayerStore for CachedLayerStore {
         self.inner.triple_removals_o(layer, object)
     }
 
+    fn triple_additions_po(
+        &self,
+        layer: [u32; 5],
+        predicate: u64,
+        object: u64,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn Iterator<Item = IdTriple> + Send>>> + Send>>
+    {
+        if let Some(cached) = self.cache.get_layer_from_cache(layer) {
+            if !cached.is_rollup() {
+                return Box::pin(future::ok(
+                    cached.internal_triple_additions_po(predicate, object),
+                ));
+            }
+        }
+
+        self.inner.triple_additions_po(layer, predicate, object)
+    }
+
+    fn triple_removals_po(
+        &self,
+        layer: [u32; 5],
+        predicate: u64,
+        object: u64,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn Iterator<Item = IdTriple> + Send>>> + Send>>
+    {
+        if let Some(cached) = self.cache.get_layer_from_cache(layer) {
+            if !cached.is_rollup() {
+                return Box::pin(future::ok(
+                    cached.internal_triple_removals_po(predicate, object),
+                ));
+            }
+        }
+
+        self.inner.triple_removals_po(layer, predicate, object)
+    }
+
     fn triple_layer_addition_count(
         &self,
         layer: [u32; 5],
@@ -453,6 +576,21 @@ impl LayerStore for CachedLayerStore {
         // Note: Doesn't use cache, but does pointer chasing on disk anyhow
         self.inner.retrieve_layer_stack_names(name)
     }
+
+    fn layer_change_statistics(
+        &self,
+        layer: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<LayerChangeStatistics>>> + Send>> {
+        self.inner.layer_change_statistics(layer)
+    }
+
+    fn write_layer_change_statistics(
+        &self,
+        layer: [u32; 5],
+        statistics: LayerChangeStatistics,
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        self.inner.write_layer_change_statistics(layer, statistics)
+    }
 }
 
 #[cfg(test)]
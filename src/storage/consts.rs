@@ -84,6 +84,9 @@ pub struct Filenames {
 
     pub parent: &'static str,
     pub rollup: &'static str,
+    pub compacted: &'static str,
+    pub statistics: &'static str,
+    pub triple_filter: &'static str,
 }
 
 pub const FILENAMES: Filenames = Filenames {
@@ -180,4 +183,7 @@ pub const FILENAMES: Filenames = Filenames {
 
     parent: "parent.hex",
     rollup: "rollup.hex",
+    compacted: "compacted.txt",
+    statistics: "statistics.txt",
+    triple_filter: "triple_filter.bitarray",
 };
@@ -15,7 +15,9 @@ use tar::Archive;
 use tokio::fs::{self, *};
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
 
+use super::consts::FILENAMES;
 use super::*;
+use crate::structure::PfcDict;
 
 const PREFIX_DIR_SIZE: usize = 3;
 
@@ -121,18 +123,26 @@ impl PersistentLayerStore for DirectoryLayerStore {
     fn directories(&self) -> Pin<Box<dyn Future<Output = io::Result<Vec<[u32; 5]>>> + Send>> {
         let path = self.path.clone();
         Box::pin(async move {
-            let mut stream = fs::read_dir(path).await?;
+            let mut prefix_stream = fs::read_dir(path).await?;
             let mut result = Vec::new();
-            while let Some(direntry) = stream.next_entry().await? {
-                if direntry.file_type().await?.is_dir() {
-                    let os_name = direntry.file_name();
-                    let name = os_name.to_str().ok_or_else(|| {
-                        io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            "unexpected non-utf8 directory name",
-                        )
-                    })?;
-                    result.push(string_to_name(name)?);
+            // layer directories are grouped in directories named after their prefix
+            while let Some(prefix_entry) = prefix_stream.next_entry().await? {
+                if !prefix_entry.file_type().await?.is_dir() {
+                    continue;
+                }
+
+                let mut stream = fs::read_dir(prefix_entry.path()).await?;
+                while let Some(direntry) = stream.next_entry().await? {
+                    if direntry.file_type().await?.is_dir() {
+                        let os_name = direntry.file_name();
+                        let name = os_name.to_str().ok_or_else(|| {
+                            io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                "unexpected non-utf8 directory name",
+                            )
+                        })?;
+                        result.push(string_to_name(name)?);
+                    }
                 }
             }
 
@@ -203,6 +213,20 @@ impl PersistentLayerStore for DirectoryLayerStore {
         })
     }
 
+    fn remove_file(
+        &self,
+        directory: [u32; 5],
+        file: &str,
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        let mut p = self.path.clone();
+        let dir_name = name_to_string(directory);
+        p.push(&dir_name[0..PREFIX_DIR_SIZE]);
+        p.push(dir_name);
+        p.push(file);
+
+        Box::pin(async move { fs::remove_file(p).await })
+    }
+
     fn export_layers(&self, layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> Vec<u8> {
         let path = &self.path;
         let mut enc = GzEncoder::new(Vec::new(), Compression::default());
@@ -239,6 +263,10 @@ impl PersistentLayerStore for DirectoryLayerStore {
         // In terminus-server we are currently already doing this validation. Due to time constraints, we're not implementing it here.
         //
         // This should definitely be done in the future though, to make this part of the library independently usable in a safe manner.
+        //
+        // For now, we only check the dictionaries, as their blocks aren't validated when a layer is loaded.
+        validate_pack_dictionaries(pack, &layer_id_set)?;
+
         for e in archive.entries()? {
             let mut entry = e?;
             let path = entry.path()?;
@@ -249,6 +277,8 @@ impl PersistentLayerStore for DirectoryLayerStore {
                 let mut path: PathBuf = (&self.path).into();
                 let prefix = &layer_id[0..PREFIX_DIR_SIZE];
                 path.push(prefix);
+                // unpacking refuses a destination that doesn't exist yet
+                std::fs::create_dir_all(&path)?;
 
                 // extract!
                 entry.unpack_in(path)?;
@@ -259,6 +289,54 @@ impl PersistentLayerStore for DirectoryLayerStore {
     }
 }
 
+/// Check the encoding of the dictionaries of the given layers in a pack.
+fn validate_pack_dictionaries(pack: &[u8], layer_id_set: &HashSet<String>) -> io::Result<()> {
+    let dictionaries = [
+        (
+            FILENAMES.node_dictionary_blocks,
+            FILENAMES.node_dictionary_offsets,
+        ),
+        (
+            FILENAMES.predicate_dictionary_blocks,
+            FILENAMES.predicate_dictionary_offsets,
+        ),
+        (
+            FILENAMES.value_dictionary_blocks,
+            FILENAMES.value_dictionary_offsets,
+        ),
+    ];
+
+    let mut archive = Archive::new(GzDecoder::new(io::Cursor::new(pack)));
+    let mut files: HashMap<(String, String), Bytes> = HashMap::new();
+    for e in archive.entries()? {
+        let mut entry = e?;
+        let path = entry.path()?.into_owned();
+        let mut components = path.iter().map(|p| p.to_str().unwrap_or(""));
+        let layer_id = components.next().unwrap_or("");
+        let file_name = components.next().unwrap_or("");
+        let is_dictionary = dictionaries
+            .iter()
+            .any(|(blocks, offsets)| file_name == *blocks || file_name == *offsets);
+        if layer_id_set.contains(layer_id) && is_dictionary && components.next().is_none() {
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            files.insert((layer_id.to_string(), file_name.to_string()), data.into());
+        }
+    }
+
+    for layer_id in layer_id_set {
+        for (blocks, offsets) in dictionaries.iter() {
+            let blocks = files.get(&(layer_id.clone(), blocks.to_string()));
+            let offsets = files.get(&(layer_id.clone(), offsets.to_string()));
+            if let (Some(blocks), Some(offsets)) = (blocks, offsets) {
+                PfcDict::parse(blocks.clone(), offsets.clone())?.validate()?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
 #[derive(Clone)]
 pub struct DirectoryLabelStore {
     path: PathBuf,
@@ -427,6 +505,7 @@ impl LabelStore for DirectoryLabelStore {
 #[derive(Debug)]
 pub enum PackError {
     LayerNotFound,
+    InvalidPath(PathBuf),
     Io(io::Error),
     Utf8Error(std::str::Utf8Error),
 }
@@ -459,17 +538,14 @@ pub fn pack_layer_parents<R: io::Read>(
 
     for e in archive.entries()? {
         let mut entry = e?;
-        let path = entry.path()?;
+        let path = entry.path()?.into_owned();
 
-        let id = string_to_name(
-            path.iter()
-                .next()
-                .expect("expected path to have at least one component")
-                .to_str()
-                .expect("expected proper unicode path"),
-        )?;
+        let id = match path.iter().next().and_then(|p| p.to_str()) {
+            Some(id_str) => string_to_name(id_str)?,
+            None => return Err(PackError::InvalidPath(path)),
+        };
 
-        if path.file_name().expect("expected path to have a filename") == "parent.hex" {
+        if path.file_name() == Some("parent.hex".as_ref()) {
             // this is an element we want to know the parent of
             // lets read it
             let mut parent_id_bytes = [0u8; 40];
@@ -762,4 +838,71 @@ mod tests {
             !rolled_layer.string_triple_exists(&StringTriple::new_value("duck", "says", "quack"))
         );
     }
+
+    #[test]
+    fn pack_layer_parents_of_garbage_errors() {
+        assert!(pack_layer_parents(io::Cursor::new(b"not a pack")).is_err());
+    }
+
+    #[test]
+    fn pack_layer_parents_with_invalid_layer_name_errors() {
+        let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+        {
+            let mut tar = tar::Builder::new(&mut enc);
+            let mut header = tar::Header::new_gnu();
+            header.set_size(3);
+            header.set_cksum();
+            tar.append_data(&mut header, "not_a_layer/parent.hex", &b"foo"[..])
+                .unwrap();
+            tar.finish().unwrap();
+        }
+        let pack = enc.finish().unwrap();
+
+        assert!(pack_layer_parents(io::Cursor::new(pack)).is_err());
+    }
+
+    #[tokio::test]
+    async fn import_pack_with_malformed_dictionary_errors() {
+        let dir = tempdir().unwrap();
+        let store = DirectoryLayerStore::new(dir.path());
+        let mut builder = store.create_base_layer().await.unwrap();
+        let name = builder.name();
+        builder.add_string_triple(StringTriple::new_value("cow", "says", "moo"));
+        builder.commit_boxed().await.unwrap();
+        let pack = LayerStore::export_layers(&store, Box::new(vec![name].into_iter()));
+
+        // copy the pack, replacing the first string of the node dictionary with invalid utf8
+        let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+        {
+            let mut tar = tar::Builder::new(&mut enc);
+            let mut archive = Archive::new(GzDecoder::new(io::Cursor::new(&pack)));
+            for e in archive.entries().unwrap() {
+                let mut entry = e.unwrap();
+                let mut header = entry.header().clone();
+                let path = entry.path().unwrap().into_owned();
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                if path.ends_with(FILENAMES.node_dictionary_blocks) {
+                    data[0] = 0xff;
+                }
+                tar.append_data(&mut header, path, &data[..]).unwrap();
+            }
+            tar.finish().unwrap();
+        }
+        let malformed_pack = enc.finish().unwrap();
+
+        let dir = tempdir().unwrap();
+        let store = DirectoryLayerStore::new(dir.path());
+        assert!(LayerStore::import_layers(
+            &store,
+            &malformed_pack,
+            Box::new(vec![name].into_iter())
+        )
+        .is_err());
+        assert!(!store.directory_exists(name).await.unwrap());
+
+        LayerStore::import_layers(&store, &pack, Box::new(vec![name].into_iter())).unwrap();
+        let layer = store.get_layer(name).await.unwrap().unwrap();
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo")));
+    }
 }
@@ -3,10 +3,11 @@
 use bytes::Bytes;
 use futures::future::{self, Future};
 use futures::io;
+use std::convert::TryFrom;
 use std::pin::Pin;
 use tokio::io::{AsyncRead, AsyncWrite};
 
-use crate::structure::{AdjacencyList, BitIndex};
+use crate::structure::{AdjacencyList, AdjacencyListError, BitIndex, BitIndexError};
 
 pub trait SyncableFile: AsyncWrite + Unpin + Send {
     fn sync_all(self) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
@@ -83,6 +84,8 @@ pub struct BaseLayerFiles<F: 'static + FileLoad + FileStore> {
     pub o_ps_adjacency_list_files: AdjacencyListFiles<F>,
 
     pub predicate_wavelet_tree_files: BitIndexFiles<F>,
+
+    pub triple_filter_file: F,
 }
 
 #[derive(Clone)]
@@ -102,6 +105,8 @@ pub struct BaseLayerMaps {
     pub o_ps_adjacency_list_maps: AdjacencyListMaps,
 
     pub predicate_wavelet_tree_maps: BitIndexMaps,
+
+    pub triple_filter_map: Option<Bytes>,
 }
 
 impl<F: FileLoad + FileStore> BaseLayerFiles<F> {
@@ -121,6 +126,8 @@ impl<F: FileLoad + FileStore> BaseLayerFiles<F> {
 
         let predicate_wavelet_tree_maps = self.predicate_wavelet_tree_files.map_all().await?;
 
+        let triple_filter_map = self.triple_filter_file.map_if_exists().await?;
+
         Ok(BaseLayerMaps {
             node_dictionary_maps,
             predicate_dictionary_maps,
@@ -136,6 +143,8 @@ impl<F: FileLoad + FileStore> BaseLayerFiles<F> {
             o_ps_adjacency_list_maps,
 
             predicate_wavelet_tree_maps,
+
+            triple_filter_map,
         })
     }
 }
@@ -162,6 +171,8 @@ pub struct ChildLayerFiles<F: 'static + FileLoad + FileStore + Clone + Send + Sy
 
     pub pos_predicate_wavelet_tree_files: BitIndexFiles<F>,
     pub neg_predicate_wavelet_tree_files: BitIndexFiles<F>,
+
+    pub triple_filter_file: F,
 }
 
 #[derive(Clone)]
@@ -186,6 +197,8 @@ pub struct ChildLayerMaps {
 
     pub pos_predicate_wavelet_tree_maps: BitIndexMaps,
     pub neg_predicate_wavelet_tree_maps: BitIndexMaps,
+
+    pub triple_filter_map: Option<Bytes>,
 }
 
 impl<F: FileLoad + FileStore + Clone> ChildLayerFiles<F> {
@@ -214,6 +227,8 @@ impl<F: FileLoad + FileStore + Clone> ChildLayerFiles<F> {
         let neg_predicate_wavelet_tree_maps =
             self.neg_predicate_wavelet_tree_files.map_all().await?;
 
+        let triple_filter_map = self.triple_filter_file.map_if_exists().await?;
+
         Ok(ChildLayerMaps {
             node_dictionary_maps,
             predicate_dictionary_maps,
@@ -235,6 +250,8 @@ impl<F: FileLoad + FileStore + Clone> ChildLayerFiles<F> {
 
             pos_predicate_wavelet_tree_maps,
             neg_predicate_wavelet_tree_maps,
+
+            triple_filter_map,
         })
     }
 }
@@ -295,9 +312,11 @@ pub struct BitIndexMaps {
     pub sblocks_map: Bytes,
 }
 
-impl Into<BitIndex> for BitIndexMaps {
-    fn into(self) -> BitIndex {
-        BitIndex::from_maps(self.bits_map, self.blocks_map, self.sblocks_map)
+impl TryFrom<BitIndexMaps> for BitIndex {
+    type Error = BitIndexError;
+
+    fn try_from(maps: BitIndexMaps) -> Result<BitIndex, BitIndexError> {
+        BitIndex::from_maps(maps.bits_map, maps.blocks_map, maps.sblocks_map)
     }
 }
 
@@ -336,13 +355,15 @@ pub struct AdjacencyListMaps {
     pub nums_map: Bytes,
 }
 
-impl Into<AdjacencyList> for AdjacencyListMaps {
-    fn into(self) -> AdjacencyList {
+impl TryFrom<AdjacencyListMaps> for AdjacencyList {
+    type Error = AdjacencyListError;
+
+    fn try_from(maps: AdjacencyListMaps) -> Result<AdjacencyList, AdjacencyListError> {
         AdjacencyList::parse(
-            self.nums_map,
-            self.bitindex_maps.bits_map,
-            self.bitindex_maps.blocks_map,
-            self.bitindex_maps.sblocks_map,
+            maps.nums_map,
+            maps.bitindex_maps.bits_map,
+            maps.bitindex_maps.blocks_map,
+            maps.bitindex_maps.sblocks_map,
         )
     }
 }
@@ -2,11 +2,14 @@ use super::cache::*;
 use super::consts::FILENAMES;
 use super::file::*;
 use crate::layer::{
-    delta_rollup, delta_rollup_upto, layer_triple_exists, BaseLayer, ChildLayer, IdTriple,
-    InternalLayer, InternalLayerImpl, InternalLayerTripleObjectIterator,
-    InternalLayerTriplePredicateIterator, InternalLayerTripleSubjectIterator, LayerBuilder,
-    OptInternalLayerTriplePredicateIterator, RollupLayer, SimpleLayerBuilder,
+    compacted_delta_rollup, compacted_delta_rollup_upto, delta_rollup, delta_rollup_upto,
+    layer_triple_exists, squash_layer, BaseLayer, ChildLayer, IdTriple, InternalLayer,
+    InternalLayerImpl, InternalLayerTripleObjectIterator, InternalLayerTriplePredicateIterator,
+    InternalLayerTripleSubjectIterator, InternalTriplePredicateObjectIterator, LayerBuilder,
+    LayerChangeStatistics, OptInternalLayerTriplePredicateIterator, RollupLayer,
+    SimpleLayerBuilder, SpillingLayerBuilder,
 };
+use crate::rdf::{add_hdt_triples, build_base_layer_from_hdt};
 use crate::structure::bitarray::bitarray_len_from_file;
 use crate::structure::logarray::logarray_file_get_length_and_width;
 use crate::structure::{AdjacencyList, LogArray, MonotonicLogArray, WaveletTree};
@@ -48,6 +51,86 @@ pub trait LayerStore: 'static + Send + Sync {
         self.create_child_layer_with_cache(parent, NOCACHE.clone())
     }
 
+    /// Create a base layer builder which spills to temporary files
+    /// once roughly `memory_limit` bytes of triple data are buffered.
+    ///
+    /// Stores that don't support spilling fall back to a regular builder.
+    fn create_spilling_base_layer(
+        &self,
+        _memory_limit: usize,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        self.create_base_layer()
+    }
+    /// Create a child layer builder which spills to temporary files
+    /// once roughly `memory_limit` bytes of triple data are buffered.
+    ///
+    /// Stores that don't support spilling fall back to a regular builder.
+    fn create_spilling_child_layer_with_cache(
+        &self,
+        parent: [u32; 5],
+        _memory_limit: usize,
+        cache: Arc<dyn LayerCache>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        self.create_child_layer_with_cache(parent, cache)
+    }
+    fn create_spilling_child_layer(
+        &self,
+        parent: [u32; 5],
+        memory_limit: usize,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        self.create_spilling_child_layer_with_cache(parent, memory_limit, NOCACHE.clone())
+    }
+
+    /// Create a new base layer from the contents of an HDT file, returning the name of the new layer.
+    ///
+    /// By default, the triples of the HDT file are added to a base
+    /// layer builder as strings. Stores that can build the layer files
+    /// directly from the HDT dictionary should do so instead.
+    fn create_base_layer_from_hdt(
+        &self,
+        hdt: Vec<u8>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        let builder = self.create_base_layer();
+        Box::pin(async move {
+            let mut builder = builder.await?;
+            add_hdt_triples(&hdt, &mut *builder)?;
+            let name = builder.name();
+            builder.commit_boxed().await?;
+
+            Ok(name)
+        })
+    }
+
+    /// Create a new base layer with all triples of the given layer, returning the name of the new layer.
+    ///
+    /// By default, the triples of the layer are converted to strings
+    /// and added to a base layer builder. Stores that can merge the
+    /// dictionaries of the layer stack directly should do so instead.
+    fn squash(
+        &self,
+        layer: Arc<InternalLayer>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        use crate::layer::Layer;
+
+        let builder = self.create_base_layer();
+        Box::pin(async move {
+            let mut builder = builder.await?;
+            for triple in layer.triples() {
+                let triple = layer.id_triple_to_string(&triple).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "triple of squashed layer has no string representation",
+                    )
+                })?;
+                builder.add_string_triple(triple);
+            }
+            let name = builder.name();
+            builder.commit_boxed().await?;
+
+            Ok(name)
+        })
+    }
+
     fn perform_rollup(
         &self,
         layer: Arc<InternalLayer>,
@@ -65,12 +148,81 @@ pub trait LayerStore: 'static + Send + Sync {
     ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
         self.perform_rollup_upto_with_cache(layer, upto, NOCACHE.clone())
     }
+
+    /// Roll up the given layer into a layer whose dictionaries only hold the strings still in use.
+    ///
+    /// By default, this returns an error.
+    fn perform_compacted_rollup(
+        &self,
+        _layer: Arc<InternalLayer>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        Box::pin(future::err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this layer store can't perform compacted rollups",
+        )))
+    }
+
+    /// Roll up the given layer up to the given ancestor into a layer whose dictionaries only hold the strings still in use.
+    ///
+    /// By default, this returns an error.
+    fn perform_compacted_rollup_upto_with_cache(
+        &self,
+        _layer: Arc<InternalLayer>,
+        _upto: [u32; 5],
+        _cache: Arc<dyn LayerCache>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        Box::pin(future::err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this layer store can't perform compacted rollups",
+        )))
+    }
+
     fn register_rollup(
         &self,
         layer: [u32; 5],
         rollup: [u32; 5],
     ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
 
+    /// Returns the rollup registered for the given layer, or None if it has none.
+    ///
+    /// By default, this returns an error, as the store can't tell.
+    fn layer_rollup(
+        &self,
+        _layer: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<[u32; 5]>>> + Send>> {
+        Box::pin(future::err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this layer store can't look up rollups",
+        )))
+    }
+
+    /// Returns whether the given layer was made by a compacted rollup.
+    ///
+    /// By default, this returns an error, as the store can't tell.
+    fn layer_is_compacted_rollup(
+        &self,
+        _layer: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+        Box::pin(future::err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this layer store can't tell compacted rollups apart",
+        )))
+    }
+
+    /// Stop using the rollup registered for the given layer, returning false if it had none.
+    ///
+    /// The rollup layer itself is left in place. By default, this
+    /// returns an error.
+    fn unregister_rollup(
+        &self,
+        _layer: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+        Box::pin(future::err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this layer store can't unregister rollups",
+        )))
+    }
+
     /// Create a new rollup layer which rolls up all triples in the given layer, as well as all its ancestors.
     ///
     /// It is a good idea to keep layer stacks small, meaning, to only
@@ -123,6 +275,50 @@ pub trait LayerStore: 'static + Send + Sync {
         self.rollup_upto_with_cache(layer, upto, NOCACHE.clone())
     }
 
+    /// Create a new rollup layer like `rollup`, leaving out all dictionary entries that are no longer used by any triple.
+    ///
+    /// The ids of all strings that are still in use stay the same. Any
+    /// layer that was built on top of the given layer may use one of
+    /// the strings that are left out, and would lose it once it is
+    /// loaded on top of the rollup. A compacted rollup can therefore
+    /// only be made for layers without children, like the head of a
+    /// graph, and an error is returned for any other layer. Finding
+    /// out whether a layer has children means going over every layer
+    /// in the store.
+    fn compacted_rollup(
+        self: Arc<Self>,
+        layer: Arc<InternalLayer>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        Box::pin(async move {
+            let name = layer.name();
+            ensure_no_descendants(&*self, name).await?;
+            let rollup = self.perform_compacted_rollup(layer).await?;
+            self.register_rollup(name, rollup).await?;
+
+            Ok(rollup)
+        })
+    }
+
+    /// Create a new rollup layer like `rollup_upto`, leaving out all dictionary entries of the rolled up layers that are no longer used by any triple.
+    ///
+    /// The same caveat as for `compacted_rollup` applies.
+    fn compacted_rollup_upto(
+        self: Arc<Self>,
+        layer: Arc<InternalLayer>,
+        upto: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        Box::pin(async move {
+            let name = layer.name();
+            ensure_no_descendants(&*self, name).await?;
+            let rollup = self
+                .perform_compacted_rollup_upto_with_cache(layer, upto, NOCACHE.clone())
+                .await?;
+            self.register_rollup(name, rollup).await?;
+
+            Ok(rollup)
+        })
+    }
+
     /// Export the given layers by creating a pack, a Vec<u8> that can later be used with `import_layers` on a different store.
     fn export_layers(&self, layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> Vec<u8>;
 
@@ -219,6 +415,38 @@ pub trait LayerStore: 'static + Send + Sync {
         predicate: u64,
     ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn Iterator<Item = IdTriple> + Send>>> + Send>>;
 
+    fn triple_additions_po(
+        &self,
+        layer: [u32; 5],
+        predicate: u64,
+        object: u64,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn Iterator<Item = IdTriple> + Send>>> + Send>>
+    {
+        let additions = self.triple_additions_o(layer, object);
+        Box::pin(async move {
+            Ok(
+                Box::new(additions.await?.filter(move |t| t.predicate == predicate))
+                    as Box<dyn Iterator<Item = _> + Send>,
+            )
+        })
+    }
+
+    fn triple_removals_po(
+        &self,
+        layer: [u32; 5],
+        predicate: u64,
+        object: u64,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn Iterator<Item = IdTriple> + Send>>> + Send>>
+    {
+        let removals = self.triple_removals_o(layer, object);
+        Box::pin(async move {
+            Ok(
+                Box::new(removals.await?.filter(move |t| t.predicate == predicate))
+                    as Box<dyn Iterator<Item = _> + Send>,
+            )
+        })
+    }
+
     fn triple_layer_addition_count(
         &self,
         layer: [u32; 5],
@@ -233,6 +461,45 @@ pub trait LayerStore: 'static + Send + Sync {
         &self,
         name: [u32; 5],
     ) -> Pin<Box<dyn Future<Output = io::Result<Vec<[u32; 5]>>> + Send>>;
+
+    /// Returns the statistics stored for the given layer, or None if none were stored.
+    ///
+    /// By default, no statistics are stored, so they get computed
+    /// every time they are needed.
+    fn layer_change_statistics(
+        &self,
+        _layer: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<LayerChangeStatistics>>> + Send>> {
+        Box::pin(future::ok(None))
+    }
+
+    /// Store statistics for the given layer, replacing any previously stored statistics.
+    ///
+    /// By default, the statistics are discarded.
+    fn write_layer_change_statistics(
+        &self,
+        _layer: [u32; 5],
+        _statistics: LayerChangeStatistics,
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        Box::pin(future::ok(()))
+    }
+}
+
+/// Return an error if any layer in the store is built on top of the given layer.
+async fn ensure_no_descendants<S: LayerStore + ?Sized>(
+    store: &S,
+    layer: [u32; 5],
+) -> io::Result<()> {
+    for other in store.layers().await? {
+        if other != layer && store.layer_is_ancestor_of(other, layer).await? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a compacted rollup can only be made for a layer without children",
+            ));
+        }
+    }
+
+    Ok(())
 }
 
 pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
@@ -260,6 +527,20 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
         directory: [u32; 5],
         file: &str,
     ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>>;
+    /// Remove the given file from the given directory.
+    ///
+    /// By default, this returns an error, so stores that never remove
+    /// files don't have to implement it.
+    fn remove_file(
+        &self,
+        _directory: [u32; 5],
+        _file: &str,
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        Box::pin(future::err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this layer store can't remove files",
+        )))
+    }
 
     fn layer_has_rollup(
         &self,
@@ -313,6 +594,7 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
                 FILENAMES.base_predicate_wavelet_tree_bits,
                 FILENAMES.base_predicate_wavelet_tree_bit_index_blocks,
                 FILENAMES.base_predicate_wavelet_tree_bit_index_sblocks,
+                FILENAMES.triple_filter,
             ];
 
             let mut files = Vec::with_capacity(filenames.len());
@@ -380,6 +662,8 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
                     blocks_file: files[27].clone(),
                     sblocks_file: files[28].clone(),
                 },
+
+                triple_filter_file: files[29].clone(),
             })
         })
     }
@@ -438,6 +722,7 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
                 FILENAMES.neg_predicate_wavelet_tree_bits,
                 FILENAMES.neg_predicate_wavelet_tree_bit_index_blocks,
                 FILENAMES.neg_predicate_wavelet_tree_bit_index_sblocks,
+                FILENAMES.triple_filter,
             ];
 
             let mut files = Vec::with_capacity(filenames.len());
@@ -535,6 +820,8 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
                     blocks_file: files[44].clone(),
                     sblocks_file: files[45].clone(),
                 },
+
+                triple_filter_file: files[46].clone(),
             })
         })
     }
@@ -597,6 +884,24 @@ pub trait PersistentLayerStore: 'static + Send + Sync + Clone {
         })
     }
 
+    /// Mark the given layer as made by a compacted rollup.
+    fn write_compacted_file(
+        &self,
+        dir_name: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        let get_file = self.get_file(dir_name, FILENAMES.compacted);
+        Box::pin(async move {
+            let file = get_file.await?;
+            let mut writer = file.open_write();
+
+            writer.write_all(b"1\n").await?;
+            writer.flush().await?;
+            writer.sync_all().await?;
+
+            Ok(())
+        })
+    }
+
     fn read_rollup_file(
         &self,
         dir_name: [u32; 5],
@@ -1284,6 +1589,12 @@ pub fn string_to_name(string: &str) -> Result<[u32; 5], std::io::Error> {
     if string.len() != 40 {
         return Err(io::Error::new(io::ErrorKind::Other, "string not len 40"));
     }
+    if !string.is_ascii() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "string not ascii",
+        ));
+    }
     let n1 = u32::from_str_radix(&string[..8], 16)
         .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
     let n2 = u32::from_str_radix(&string[8..16], 16)
@@ -1506,6 +1817,68 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         })
     }
 
+    fn create_spilling_base_layer(
+        &self,
+        memory_limit: usize,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            let dir_name = self_.create_directory().await?;
+            let files = self_.base_layer_files(dir_name).await?;
+            Ok(
+                Box::new(SpillingLayerBuilder::new(dir_name, files, memory_limit))
+                    as Box<dyn LayerBuilder>,
+            )
+        })
+    }
+
+    fn create_spilling_child_layer_with_cache(
+        &self,
+        parent: [u32; 5],
+        memory_limit: usize,
+        cache: Arc<dyn LayerCache>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        let create_files = self.create_child_layer_files_with_cache(parent, cache);
+        Box::pin(async move {
+            let (layer_dir, parent_layer, child_layer_files) = create_files.await?;
+
+            Ok(Box::new(SpillingLayerBuilder::from_parent(
+                layer_dir,
+                parent_layer,
+                child_layer_files,
+                memory_limit,
+            )) as Box<dyn LayerBuilder>)
+        })
+    }
+
+    fn create_base_layer_from_hdt(
+        &self,
+        hdt: Vec<u8>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            let dir_name = self_.create_directory().await?;
+            let files = self_.base_layer_files(dir_name).await?;
+            build_base_layer_from_hdt(&hdt, files).await?;
+
+            Ok(dir_name)
+        })
+    }
+
+    fn squash(
+        &self,
+        layer: Arc<InternalLayer>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            let dir_name = self_.create_directory().await?;
+            let files = self_.base_layer_files(dir_name).await?;
+            squash_layer(&layer, files).await?;
+
+            Ok(dir_name)
+        })
+    }
+
     fn perform_rollup(
         &self,
         layer: Arc<InternalLayer>,
@@ -1573,6 +1946,46 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         })
     }
 
+    fn perform_compacted_rollup(
+        &self,
+        layer: Arc<InternalLayer>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        // unlike a normal rollup, a base layer can still be compacted,
+        // and an existing rollup can't be reused as it may not be compacted.
+        let self_ = self.clone();
+        Box::pin(async move {
+            let dir_name = self_.create_directory().await?;
+            let files = self_.base_layer_files(dir_name).await?;
+            compacted_delta_rollup(&layer, files).await?;
+            self_.write_compacted_file(dir_name).await?;
+
+            Ok(dir_name)
+        })
+    }
+
+    fn perform_compacted_rollup_upto_with_cache(
+        &self,
+        layer: Arc<InternalLayer>,
+        upto: [u32; 5],
+        cache: Arc<dyn LayerCache>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        if layer.name() == upto {
+            // there is nothing to roll up.
+            return Box::pin(future::ok(layer.name()));
+        }
+
+        let self_ = self.clone();
+        Box::pin(async move {
+            let (layer_dir, _parent_layer, child_layer_files) = self_
+                .create_child_layer_files_with_cache(upto, cache)
+                .await?;
+            compacted_delta_rollup_upto(&layer, upto, child_layer_files).await?;
+            self_.write_compacted_file(layer_dir).await?;
+
+            Ok(layer_dir)
+        })
+    }
+
     fn register_rollup(
         &self,
         layer: [u32; 5],
@@ -1586,6 +1999,42 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         }
     }
 
+    fn layer_rollup(
+        &self,
+        layer: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<[u32; 5]>>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            if self_.layer_has_rollup(layer).await? {
+                Ok(Some(self_.read_rollup_file(layer).await?))
+            } else {
+                Ok(None)
+            }
+        })
+    }
+
+    fn layer_is_compacted_rollup(
+        &self,
+        layer: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+        self.file_exists(layer, FILENAMES.compacted)
+    }
+
+    fn unregister_rollup(
+        &self,
+        layer: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            if self_.layer_has_rollup(layer).await? {
+                self_.remove_file(layer, FILENAMES.rollup).await?;
+                Ok(true)
+            } else {
+                Ok(false)
+            }
+        })
+    }
+
     fn export_layers(&self, layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> Vec<u8> {
         Self::export_layers(self, layer_ids)
     }
@@ -1898,6 +2347,61 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
         })
     }
 
+    fn triple_additions_po(
+        &self,
+        layer: [u32; 5],
+        predicate: u64,
+        object: u64,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn Iterator<Item = IdTriple> + Send>>> + Send>>
+    {
+        let self_ = self.clone();
+        Box::pin(async move {
+            let (subjects_file, objects_file, o_ps_aj_files, s_p_aj_files) =
+                self_.triple_addition_files_by_object(layer).await?;
+
+            Ok(Box::new(
+                file_triple_iterator_by_predicate_object(
+                    subjects_file,
+                    objects_file,
+                    o_ps_aj_files,
+                    s_p_aj_files,
+                    predicate,
+                    object,
+                )
+                .await?,
+            ) as Box<dyn Iterator<Item = _> + Send>)
+        })
+    }
+
+    fn triple_removals_po(
+        &self,
+        layer: [u32; 5],
+        predicate: u64,
+        object: u64,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn Iterator<Item = IdTriple> + Send>>> + Send>>
+    {
+        let self_ = self.clone();
+        Box::pin(async move {
+            if let Some((subjects_file, objects_file, o_ps_aj_files, s_p_aj_files)) =
+                self_.triple_removal_files_by_object(layer).await?
+            {
+                Ok(Box::new(
+                    file_triple_iterator_by_predicate_object(
+                        subjects_file,
+                        objects_file,
+                        o_ps_aj_files,
+                        s_p_aj_files,
+                        predicate,
+                        object,
+                    )
+                    .await?,
+                ) as Box<dyn Iterator<Item = _> + Send>)
+            } else {
+                Ok(Box::new(std::iter::empty()) as Box<dyn Iterator<Item = _> + Send>)
+            }
+        })
+    }
+
     fn triple_layer_addition_count(
         &self,
         layer: [u32; 5],
@@ -1946,6 +2450,43 @@ impl<F: 'static + FileLoad + FileStore + Clone, T: 'static + PersistentLayerStor
             }
         })
     }
+
+    fn layer_change_statistics(
+        &self,
+        layer: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<LayerChangeStatistics>>> + Send>> {
+        let self_ = self.clone();
+        Box::pin(async move {
+            if !self_.file_exists(layer, FILENAMES.statistics).await? {
+                return Ok(None);
+            }
+
+            let file = self_.get_file(layer, FILENAMES.statistics).await?;
+            let mut reader = file.open_read();
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data).await?;
+
+            Ok(Some(LayerChangeStatistics::from_bytes(&data)?))
+        })
+    }
+
+    fn write_layer_change_statistics(
+        &self,
+        layer: [u32; 5],
+        statistics: LayerChangeStatistics,
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        let get_file = self.get_file(layer, FILENAMES.statistics);
+        Box::pin(async move {
+            let file = get_file.await?;
+            let mut writer = file.open_write();
+
+            writer.write_all(&statistics.to_bytes()).await?;
+            writer.flush().await?;
+            writer.sync_all().await?;
+
+            Ok(())
+        })
+    }
 }
 
 pub(crate) async fn file_triple_exists<F: FileLoad + FileStore>(
@@ -1962,9 +2503,10 @@ pub(crate) async fn file_triple_exists<F: FileLoad + FileStore>(
     let subjects: Option<MonotonicLogArray> = subjects_file
         .map_if_exists()
         .await?
-        .map(|l| LogArray::parse(l).unwrap().into());
-    let s_p_aj = s_p_maps.into();
-    let sp_o_aj = sp_o_maps.into();
+        .map(|l| LogArray::parse(l).map(MonotonicLogArray::from))
+        .transpose()?;
+    let s_p_aj: AdjacencyList = s_p_maps.try_into()?;
+    let sp_o_aj: AdjacencyList = sp_o_maps.try_into()?;
 
     Ok(layer_triple_exists(
         subjects.as_ref(),
@@ -1987,9 +2529,10 @@ pub(crate) async fn file_triple_iterator<F: FileLoad + FileStore>(
     let subjects: Option<MonotonicLogArray> = subjects_file
         .map_if_exists()
         .await?
-        .map(|l| LogArray::parse(l).unwrap().into());
-    let s_p_aj = s_p_maps.into();
-    let sp_o_aj = sp_o_maps.into();
+        .map(|l| LogArray::parse(l).map(MonotonicLogArray::from))
+        .transpose()?;
+    let s_p_aj: AdjacencyList = s_p_maps.try_into()?;
+    let sp_o_aj: AdjacencyList = sp_o_maps.try_into()?;
 
     Ok(InternalLayerTripleSubjectIterator::new(
         subjects, s_p_aj, sp_o_aj,
@@ -2010,13 +2553,14 @@ pub(crate) async fn file_triple_iterator_by_predicate<F: FileLoad + FileStore>(
     let subjects: Option<MonotonicLogArray> = subjects_file
         .map_if_exists()
         .await?
-        .map(|l| LogArray::parse(l).unwrap().into());
-    let s_p_aj: AdjacencyList = s_p_maps.into();
-    let sp_o_aj: AdjacencyList = sp_o_maps.into();
+        .map(|l| LogArray::parse(l).map(MonotonicLogArray::from))
+        .transpose()?;
+    let s_p_aj: AdjacencyList = s_p_maps.try_into()?;
+    let sp_o_aj: AdjacencyList = sp_o_maps.try_into()?;
 
     let width = s_p_aj.nums().width();
-    let wavelet_bits = predicate_wavelet_maps.into();
-    let wtree = WaveletTree::from_parts(wavelet_bits, width);
+    let wavelet_bits = predicate_wavelet_maps.try_into()?;
+    let wtree = WaveletTree::from_parts(wavelet_bits, width)?;
     Ok(match wtree.lookup(predicate) {
         Some(lookup) => OptInternalLayerTriplePredicateIterator(Some(
             InternalLayerTriplePredicateIterator::new(lookup, subjects, s_p_aj, sp_o_aj),
@@ -2035,16 +2579,18 @@ pub(crate) async fn file_triple_iterator_by_object<F: FileLoad + FileStore>(
     let subjects: Option<MonotonicLogArray> = subjects_file
         .map_if_exists()
         .await?
-        .map(|l| LogArray::parse(l).unwrap().into());
+        .map(|l| LogArray::parse(l).map(MonotonicLogArray::from))
+        .transpose()?;
     let objects: Option<MonotonicLogArray> = objects_file
         .map_if_exists()
         .await?
-        .map(|l| LogArray::parse(l).unwrap().into());
+        .map(|l| LogArray::parse(l).map(MonotonicLogArray::from))
+        .transpose()?;
 
     let o_ps_maps = o_ps_adjacency_list_files.map_all().await?;
     let s_p_maps = s_p_adjacency_list_files.map_all().await?;
-    let o_ps_aj: AdjacencyList = o_ps_maps.into();
-    let s_p_aj: AdjacencyList = s_p_maps.into();
+    let o_ps_aj: AdjacencyList = o_ps_maps.try_into()?;
+    let s_p_aj: AdjacencyList = s_p_maps.try_into()?;
 
     Ok(
         InternalLayerTripleObjectIterator::new(subjects, objects, o_ps_aj, s_p_aj)
@@ -2052,6 +2598,37 @@ pub(crate) async fn file_triple_iterator_by_object<F: FileLoad + FileStore>(
     )
 }
 
+pub(crate) async fn file_triple_iterator_by_predicate_object<F: FileLoad + FileStore>(
+    subjects_file: F,
+    objects_file: F,
+    o_ps_adjacency_list_files: AdjacencyListFiles<F>,
+    s_p_adjacency_list_files: AdjacencyListFiles<F>,
+    predicate: u64,
+    object: u64,
+) -> io::Result<impl Iterator<Item = IdTriple> + Send> {
+    let subjects: Option<MonotonicLogArray> = subjects_file
+        .map_if_exists()
+        .await?
+        .map(|l| LogArray::parse(l).map(MonotonicLogArray::from))
+        .transpose()?;
+    let objects: Option<MonotonicLogArray> = objects_file
+        .map_if_exists()
+        .await?
+        .map(|l| LogArray::parse(l).map(MonotonicLogArray::from))
+        .transpose()?;
+
+    let o_ps_maps = o_ps_adjacency_list_files.map_all().await?;
+    let s_p_maps = s_p_adjacency_list_files.map_all().await?;
+    let o_ps_aj: AdjacencyList = o_ps_maps.try_into()?;
+    let s_p_aj: AdjacencyList = s_p_maps.try_into()?;
+
+    Ok(InternalTriplePredicateObjectIterator::new(
+        InternalLayerTripleObjectIterator::new(subjects, objects, o_ps_aj, s_p_aj),
+        predicate,
+        object,
+    ))
+}
+
 pub(crate) async fn file_triple_layer_count<F: FileLoad + FileStore>(
     s_p_nums_file: F,
     sp_o_bits_file: F,
@@ -2063,8 +2640,8 @@ pub(crate) async fn file_triple_layer_count<F: FileLoad + FileStore>(
         .try_into()
         .unwrap();
     let predicate_wavelet_maps = predicate_wavelet_files.map_all().await?;
-    let wavelet_bits = predicate_wavelet_maps.into();
-    let wtree = WaveletTree::from_parts(wavelet_bits, width);
+    let wavelet_bits = predicate_wavelet_maps.try_into()?;
+    let wtree = WaveletTree::from_parts(wavelet_bits, width)?;
 
     Ok(bits_len - wtree.lookup(0).map(|l| l.len()).unwrap_or(0))
 }
@@ -2412,6 +2989,24 @@ mod tests {
 
         assert!(store.triple_additions_o(name, 42).await?.next().is_none());
 
+        let predicate = triples[0].predicate;
+        let with_predicate: Vec<_> = triples
+            .iter()
+            .filter(|t| t.predicate == predicate)
+            .cloned()
+            .collect();
+        let result: Vec<_> = store
+            .triple_additions_po(name, predicate, triples[0].object)
+            .await?
+            .collect();
+        assert_eq!(with_predicate, result);
+
+        assert!(store
+            .triple_additions_po(name, 42, triples[0].object)
+            .await?
+            .next()
+            .is_none());
+
         Ok(())
     }
 
@@ -2441,6 +3036,11 @@ mod tests {
             .is_none());
         assert!(store.triple_removals_p(name, 42).await?.next().is_none());
         assert!(store.triple_removals_o(name, 42).await?.next().is_none());
+        assert!(store
+            .triple_removals_po(name, 42, 42)
+            .await?
+            .next()
+            .is_none());
 
         Ok(())
     }
@@ -2648,6 +3248,24 @@ mod tests {
 
         assert!(store.triple_additions_o(name, 42).await?.next().is_none());
 
+        let predicate = triples[0].predicate;
+        let with_predicate: Vec<_> = triples
+            .iter()
+            .filter(|t| t.predicate == predicate)
+            .cloned()
+            .collect();
+        let result: Vec<_> = store
+            .triple_additions_po(name, predicate, triples[0].object)
+            .await?
+            .collect();
+        assert_eq!(with_predicate, result);
+
+        assert!(store
+            .triple_additions_po(name, 42, triples[0].object)
+            .await?
+            .next()
+            .is_none());
+
         Ok(())
     }
 
@@ -2851,6 +3469,24 @@ mod tests {
 
         assert!(store.triple_removals_o(name, 42).await?.next().is_none());
 
+        let predicate = triples[0].predicate;
+        let with_predicate: Vec<_> = triples
+            .iter()
+            .filter(|t| t.predicate == predicate)
+            .cloned()
+            .collect();
+        let result: Vec<_> = store
+            .triple_removals_po(name, predicate, triples[0].object)
+            .await?
+            .collect();
+        assert_eq!(with_predicate, result);
+
+        assert!(store
+            .triple_removals_po(name, 42, triples[0].object)
+            .await?
+            .next()
+            .is_none());
+
         Ok(())
     }
 
@@ -6,15 +6,17 @@ use bytes::Bytes;
 use futures::future::{self, Future};
 use futures::io;
 use futures::task::{Context, Poll};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::pin::Pin;
 use std::sync::{self, Arc, RwLock};
 
 use super::*;
 use crate::layer::{
-    delta_rollup, delta_rollup_upto, BaseLayer, ChildLayer, IdTriple, InternalLayer, LayerBuilder,
-    RollupLayer, SimpleLayerBuilder,
+    compacted_delta_rollup, compacted_delta_rollup_upto, delta_rollup, delta_rollup_upto,
+    squash_layer, BaseLayer, ChildLayer, IdTriple, InternalLayer, LayerBuilder,
+    LayerChangeStatistics, RollupLayer, SimpleLayerBuilder, SpillingLayerBuilder,
 };
+use crate::rdf::build_base_layer_from_hdt;
 
 pub struct MemoryBackedStoreWriter {
     vec: Arc<sync::RwLock<Vec<u8>>>,
@@ -171,6 +173,8 @@ pub struct MemoryLayerStore {
             ),
         >,
     >,
+    statistics: futures_locks::RwLock<HashMap<[u32; 5], LayerChangeStatistics>>,
+    compacted: futures_locks::RwLock<HashSet<[u32; 5]>>,
 }
 
 impl MemoryLayerStore {
@@ -544,6 +548,8 @@ pub fn base_layer_memory_files() -> BaseLayerFiles<MemoryBackedStore> {
             blocks_file: MemoryBackedStore::new(),
             sblocks_file: MemoryBackedStore::new(),
         },
+
+        triple_filter_file: MemoryBackedStore::new(),
     }
 }
 
@@ -638,6 +644,8 @@ pub fn child_layer_memory_files() -> ChildLayerFiles<MemoryBackedStore> {
             blocks_file: MemoryBackedStore::new(),
             sblocks_file: MemoryBackedStore::new(),
         },
+
+        triple_filter_file: MemoryBackedStore::new(),
     }
 }
 
@@ -946,6 +954,94 @@ impl LayerStore for MemoryLayerStore {
         })
     }
 
+    fn create_spilling_base_layer(
+        &self,
+        memory_limit: usize,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        let name = rand::random();
+        let blf = base_layer_memory_files();
+
+        let guard = self.layers.write();
+        Box::pin(async move {
+            let mut layers = guard.await;
+            layers.insert(name, (None, None, LayerFiles::Base(blf.clone())));
+            Ok(Box::new(SpillingLayerBuilder::new(name, blf, memory_limit))
+                as Box<dyn LayerBuilder>)
+        })
+    }
+
+    fn create_spilling_child_layer_with_cache(
+        &self,
+        parent: [u32; 5],
+        memory_limit: usize,
+        cache: Arc<dyn LayerCache>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn LayerBuilder>>> + Send>> {
+        let layers = self.layers.clone();
+        let get_layer_with_cache = self.get_layer_with_cache(parent, cache);
+        Box::pin(async move {
+            let parent_layer = match get_layer_with_cache.await? {
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "parent layer not found",
+                    ))
+                }
+                Some(parent_layer) => parent_layer,
+            };
+
+            let name = rand::random();
+            let clf = child_layer_memory_files();
+
+            layers
+                .write()
+                .await
+                .insert(name, (Some(parent), None, LayerFiles::Child(clf.clone())));
+            Ok(Box::new(SpillingLayerBuilder::from_parent(
+                name,
+                parent_layer,
+                clf,
+                memory_limit,
+            )) as Box<dyn LayerBuilder>)
+        })
+    }
+
+    fn create_base_layer_from_hdt(
+        &self,
+        hdt: Vec<u8>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        let name = rand::random();
+        let blf = base_layer_memory_files();
+
+        let guard = self.layers.write();
+        Box::pin(async move {
+            guard
+                .await
+                .insert(name, (None, None, LayerFiles::Base(blf.clone())));
+            build_base_layer_from_hdt(&hdt, blf).await?;
+
+            Ok(name)
+        })
+    }
+
+    fn squash(
+        &self,
+        layer: Arc<InternalLayer>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        let layers = self.layers.clone();
+        Box::pin(async move {
+            let name = rand::random();
+            let blf = base_layer_memory_files();
+
+            squash_layer(&layer, blf.clone()).await?;
+            layers
+                .write()
+                .await
+                .insert(name, (None, None, LayerFiles::Base(blf)));
+
+            Ok(name)
+        })
+    }
+
     fn perform_rollup(
         &self,
         layer: Arc<InternalLayer>,
@@ -1023,6 +1119,55 @@ impl LayerStore for MemoryLayerStore {
         })
     }
 
+    fn perform_compacted_rollup(
+        &self,
+        layer: Arc<InternalLayer>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        let layers = self.layers.clone();
+        let compacted = self.compacted.clone();
+        Box::pin(async move {
+            let name = rand::random();
+            let blf = base_layer_memory_files();
+
+            compacted_delta_rollup(&layer, blf.clone()).await?;
+            layers
+                .write()
+                .await
+                .insert(name, (None, None, LayerFiles::Base(blf)));
+            compacted.write().await.insert(name);
+
+            Ok(name)
+        })
+    }
+
+    fn perform_compacted_rollup_upto_with_cache(
+        &self,
+        layer: Arc<InternalLayer>,
+        upto: [u32; 5],
+        _cache: Arc<dyn LayerCache>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<[u32; 5]>> + Send>> {
+        if layer.name() == upto {
+            // there is nothing to roll up.
+            return Box::pin(future::ok(layer.name()));
+        }
+
+        let layers = self.layers.clone();
+        let compacted = self.compacted.clone();
+        Box::pin(async move {
+            let name = rand::random();
+            let clf = child_layer_memory_files();
+
+            compacted_delta_rollup_upto(&layer, upto, clf.clone()).await?;
+            layers
+                .write()
+                .await
+                .insert(name, (Some(upto), None, LayerFiles::Child(clf)));
+            compacted.write().await.insert(name);
+
+            Ok(name)
+        })
+    }
+
     fn register_rollup(
         &self,
         layer: [u32; 5],
@@ -1062,6 +1207,39 @@ impl LayerStore for MemoryLayerStore {
         })
     }
 
+    fn layer_rollup(
+        &self,
+        layer: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<[u32; 5]>>> + Send>> {
+        let guard = self.layers.read();
+        Box::pin(async move {
+            let layers = guard.await;
+            Ok(layers.get(&layer).and_then(|(_, rollup, _)| *rollup))
+        })
+    }
+
+    fn layer_is_compacted_rollup(
+        &self,
+        layer: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+        let guard = self.compacted.read();
+        Box::pin(async move { Ok(guard.await.contains(&layer)) })
+    }
+
+    fn unregister_rollup(
+        &self,
+        layer: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<bool>> + Send>> {
+        let guard = self.layers.write();
+        Box::pin(async move {
+            let mut layers = guard.await;
+            Ok(layers
+                .get_mut(&layer)
+                .map(|(_, rollup, _)| rollup.take().is_some())
+                .unwrap_or(false))
+        })
+    }
+
     fn export_layers(&self, _layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> Vec<u8> {
         unimplemented!();
     }
@@ -1371,6 +1549,61 @@ impl LayerStore for MemoryLayerStore {
         })
     }
 
+    fn triple_additions_po(
+        &self,
+        layer: [u32; 5],
+        predicate: u64,
+        object: u64,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn Iterator<Item = IdTriple> + Send>>> + Send>>
+    {
+        let self_ = self.clone();
+        Box::pin(async move {
+            let (subjects_file, objects_file, o_ps_aj_files, s_p_aj_files) =
+                self_.triple_addition_files_by_object(layer).await?;
+
+            Ok(Box::new(
+                file_triple_iterator_by_predicate_object(
+                    subjects_file,
+                    objects_file,
+                    o_ps_aj_files,
+                    s_p_aj_files,
+                    predicate,
+                    object,
+                )
+                .await?,
+            ) as Box<dyn Iterator<Item = _> + Send>)
+        })
+    }
+
+    fn triple_removals_po(
+        &self,
+        layer: [u32; 5],
+        predicate: u64,
+        object: u64,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn Iterator<Item = IdTriple> + Send>>> + Send>>
+    {
+        let self_ = self.clone();
+        Box::pin(async move {
+            if let Some((subjects_file, objects_file, o_ps_aj_files, s_p_aj_files)) =
+                self_.triple_removal_files_by_object(layer).await?
+            {
+                Ok(Box::new(
+                    file_triple_iterator_by_predicate_object(
+                        subjects_file,
+                        objects_file,
+                        o_ps_aj_files,
+                        s_p_aj_files,
+                        predicate,
+                        object,
+                    )
+                    .await?,
+                ) as Box<dyn Iterator<Item = _> + Send>)
+            } else {
+                Ok(Box::new(std::iter::empty()) as Box<dyn Iterator<Item = _> + Send>)
+            }
+        })
+    }
+
     fn triple_layer_addition_count(
         &self,
         layer: [u32; 5],
@@ -1423,6 +1656,27 @@ impl LayerStore for MemoryLayerStore {
             }
         })
     }
+
+    fn layer_change_statistics(
+        &self,
+        layer: [u32; 5],
+    ) -> Pin<Box<dyn Future<Output = io::Result<Option<LayerChangeStatistics>>> + Send>> {
+        let guard = self.statistics.read();
+        Box::pin(async move { Ok(guard.await.get(&layer).cloned()) })
+    }
+
+    fn write_layer_change_statistics(
+        &self,
+        layer: [u32; 5],
+        statistics: LayerChangeStatistics,
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>> {
+        let guard = self.statistics.write();
+        Box::pin(async move {
+            guard.await.insert(layer, statistics);
+
+            Ok(())
+        })
+    }
 }
 
 #[derive(Clone, Default)]
@@ -0,0 +1,291 @@
+//! Id-level differences between layers that share history.
+//!
+//! Two layers that were built on top of a common ancestor agree on
+//! the ids of all dictionary entries up to and including that
+//! ancestor, as rollups never change ids. The difference between two
+//! such layers can therefore be computed from the changes made after
+//! their common ancestor, with only the ids of entries added since
+//! needing a translation through their strings.
+use super::{Store, StoreLayer, StoreLayerBuilder};
+use crate::layer::{IdTriple, InternalLayerImpl, Layer, ObjectType};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::sync::Arc;
+
+impl Store {
+    /// Returns the net change of the given layers, applied in order, with true for additions and false for removals.
+    ///
+    /// Every layer is read by itself, so this works whether or not the
+    /// layers are replaced by a rollup.
+    pub(super) async fn net_changes(
+        &self,
+        layers: &[[u32; 5]],
+    ) -> io::Result<BTreeMap<IdTriple, bool>> {
+        let mut changes = BTreeMap::new();
+        for &name in layers {
+            for triple in self.layer_store.triple_removals(name).await? {
+                if changes.remove(&triple) != Some(true) {
+                    changes.insert(triple, false);
+                }
+            }
+            for triple in self.layer_store.triple_additions(name).await? {
+                if changes.remove(&triple) != Some(false) {
+                    changes.insert(triple, true);
+                }
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Returns the original stacks of the two given layers, along with the amount of layers they have in common, or None if they have no common ancestor.
+    async fn shared_history(
+        &self,
+        layer1: [u32; 5],
+        layer2: [u32; 5],
+    ) -> io::Result<Option<(Vec<[u32; 5]>, Vec<[u32; 5]>, usize)>> {
+        let stack1 = self.layer_store.retrieve_layer_stack_names(layer1).await?;
+        let stack2 = self.layer_store.retrieve_layer_stack_names(layer2).await?;
+        let shared = stack1
+            .iter()
+            .zip(stack2.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        if shared == 0 {
+            Ok(None)
+        } else {
+            Ok(Some((stack1, stack2, shared)))
+        }
+    }
+
+    /// Returns whether the given layer can be used for id-level work.
+    ///
+    /// A layer can't be used if a compacted rollup is part of its
+    /// stack, as such a rollup no longer knows the strings of some of
+    /// the ids it shares with its relatives. If the layer store can't
+    /// tell which rollups are compacted, the layer can't be used
+    /// either.
+    async fn layer_allows_id_diff(&self, layer: [u32; 5]) -> io::Result<bool> {
+        let layer = match self.layer_store.get_layer(layer).await? {
+            Some(layer) => layer,
+            None => return Ok(false),
+        };
+        let rolled_up: Vec<_> = layer
+            .immediate_layers()
+            .into_iter()
+            .filter(|l| l.is_rollup())
+            .map(InternalLayerImpl::name)
+            .collect();
+        for name in rolled_up {
+            let compacted = match self.layer_store.layer_rollup(name).await {
+                Ok(Some(rollup)) => self
+                    .layer_store
+                    .layer_is_compacted_rollup(rollup)
+                    .await
+                    .unwrap_or(true),
+                Ok(None) => false,
+                Err(_) => true,
+            };
+            if compacted {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// Translates triples from one layer into the ids of a layer that shares part of its history.
+///
+/// Ids up to the counts of the common ancestor are the same in both
+/// layers. Other ids are translated through their string, once each.
+struct IdTranslator<'a> {
+    from: &'a dyn Layer,
+    to: &'a dyn Layer,
+    shared_node_value_count: u64,
+    shared_predicate_count: u64,
+    node_values: HashMap<u64, Option<u64>>,
+    predicates: HashMap<u64, Option<u64>>,
+}
+
+impl<'a> IdTranslator<'a> {
+    fn new(from: &'a dyn Layer, to: &'a dyn Layer, ancestor: &dyn Layer) -> Self {
+        Self {
+            from,
+            to,
+            shared_node_value_count: ancestor.node_and_value_count() as u64,
+            shared_predicate_count: ancestor.predicate_count() as u64,
+            node_values: HashMap::new(),
+            predicates: HashMap::new(),
+        }
+    }
+
+    fn node_value(&mut self, id: u64) -> Option<u64> {
+        if id <= self.shared_node_value_count {
+            return Some(id);
+        }
+
+        let (from, to) = (self.from, self.to);
+        *self
+            .node_values
+            .entry(id)
+            .or_insert_with(|| match from.id_object(id)? {
+                ObjectType::Node(node) => to.object_node_id(&node),
+                ObjectType::Value(value) => to.object_value_id(&value),
+            })
+    }
+
+    fn predicate(&mut self, id: u64) -> Option<u64> {
+        if id <= self.shared_predicate_count {
+            return Some(id);
+        }
+
+        let (from, to) = (self.from, self.to);
+        *self
+            .predicates
+            .entry(id)
+            .or_insert_with(|| to.predicate_id(&from.id_predicate(id)?))
+    }
+
+    /// Returns the triple in the ids of the target layer, or None if one of its strings is unknown there.
+    fn translate(&mut self, triple: IdTriple) -> Option<IdTriple> {
+        Some(IdTriple::new(
+            self.node_value(triple.subject)?,
+            self.predicate(triple.predicate)?,
+            self.node_value(triple.object)?,
+        ))
+    }
+}
+
+impl StoreLayerBuilder {
+    /// Apply the changes from our parent to the given layer at the id level, returning false if this is not possible.
+    ///
+    /// This requires both layers to come from the same store, and to
+    /// share an ancestor. Neither of their stacks may contain a
+    /// compacted rollup.
+    pub(super) async fn apply_diff_by_id(&self, other: &StoreLayer) -> io::Result<bool> {
+        let parent = match self.parent() {
+            Some(parent) => parent,
+            None => return Ok(false),
+        };
+        if !Arc::ptr_eq(&self.store.layer_store, &other.store.layer_store) {
+            return Ok(false);
+        }
+        let (parent_stack, other_stack, shared) = match self
+            .store
+            .shared_history(parent.name(), other.name())
+            .await?
+        {
+            Some(history) => history,
+            None => return Ok(false),
+        };
+        if !self.store.layer_allows_id_diff(parent.name()).await?
+            || !self.store.layer_allows_id_diff(other.name()).await?
+        {
+            return Ok(false);
+        }
+        let ancestor = match self
+            .store
+            .layer_store
+            .get_layer(parent_stack[shared - 1])
+            .await?
+        {
+            Some(ancestor) => ancestor,
+            None => return Ok(false),
+        };
+
+        let parent_changes = self.store.net_changes(&parent_stack[shared..]).await?;
+        let other_changes = self.store.net_changes(&other_stack[shared..]).await?;
+
+        let mut translator = IdTranslator::new(other, &*parent, &*ancestor);
+        let mut other_additions = HashSet::new();
+        for (&triple, &added) in other_changes.iter() {
+            match (translator.translate(triple), added) {
+                (Some(translated), true) => {
+                    other_additions.insert(translated);
+                    self.add_id_triple(translated)?;
+                }
+                (None, true) => {
+                    // this triple uses strings our parent doesn't know about
+                    let triple = other.id_triple_to_string(&triple).ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, "triple has unknown ids")
+                    })?;
+                    self.add_string_triple(triple)?;
+                }
+                (Some(translated), false) => self.remove_id_triple(translated)?,
+                // a triple with strings our parent doesn't know about is not in our parent
+                (None, false) => {}
+            }
+        }
+
+        // changes that our parent made since the common ancestor, and which the other layer didn't make, have to be undone
+        for (&triple, &added) in parent_changes.iter() {
+            if added && !other_additions.contains(&triple) {
+                self.remove_id_triple(triple)?;
+            } else if !added && other_changes.get(&triple) != Some(&false) {
+                self.add_id_triple(triple)?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Apply the changes of the given layer at the id level, returning false if this is not possible.
+    ///
+    /// This requires both the delta and our parent to come from the
+    /// same store, and to share an ancestor. Neither of their stacks
+    /// may contain a compacted rollup.
+    pub(super) async fn apply_delta_by_id(&self, delta: &StoreLayer) -> io::Result<bool> {
+        let parent = match self.parent() {
+            Some(parent) => parent,
+            None => return Ok(false),
+        };
+        if !Arc::ptr_eq(&self.store.layer_store, &delta.store.layer_store) {
+            return Ok(false);
+        }
+        let (parent_stack, _, shared) = match self
+            .store
+            .shared_history(parent.name(), delta.name())
+            .await?
+        {
+            Some(history) => history,
+            None => return Ok(false),
+        };
+        if !self.store.layer_allows_id_diff(parent.name()).await?
+            || !self.store.layer_allows_id_diff(delta.name()).await?
+        {
+            return Ok(false);
+        }
+        let ancestor = match self
+            .store
+            .layer_store
+            .get_layer(parent_stack[shared - 1])
+            .await?
+        {
+            Some(ancestor) => ancestor,
+            None => return Ok(false),
+        };
+
+        let mut translator = IdTranslator::new(delta, &*parent, &*ancestor);
+        for triple in delta.triple_additions().await? {
+            match translator.translate(triple) {
+                Some(translated) => self.add_id_triple(translated)?,
+                None => {
+                    let triple = delta.id_triple_to_string(&triple).ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, "triple has unknown ids")
+                    })?;
+                    self.add_string_triple(triple)?;
+                }
+            }
+        }
+        for triple in delta.triple_removals().await? {
+            // a triple with strings our parent doesn't know about is not in our parent
+            if let Some(translated) = translator.translate(triple) {
+                self.remove_id_triple(translated)?;
+            }
+        }
+
+        Ok(true)
+    }
+}
@@ -1,17 +1,26 @@
 //! High-level API for working with terminus-store.
 //!
 //! It is expected that most users of this library will work exclusively with the types contained in this module.
+mod diff;
+mod rollup;
 pub mod sync;
 
+pub use rollup::*;
+
 use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
 
-use crate::layer::{IdTriple, Layer, LayerBuilder, LayerCounts, ObjectType, StringTriple};
+use crate::layer::{
+    IdTriple, Layer, LayerBuilder, LayerChangeStatistics, LayerCounts, LayerStatistics,
+    ObjectCursor, ObjectType, ScanToken, ScanTokenError, StringTriple, SubjectCursor,
+    TripleSampler, TripleStream,
+};
 use crate::storage::directory::{DirectoryLabelStore, DirectoryLayerStore};
 use crate::storage::memory::{MemoryLabelStore, MemoryLayerStore};
 use crate::storage::{CachedLayerStore, LabelStore, LayerStore, LockingHashMapLayerCache};
 
 use std::io;
+use std::ops::Range;
 use std::pin::Pin;
 
 use rayon::prelude::*;
@@ -23,6 +32,8 @@ use futures::future::Future;
 pub struct Store {
     label_store: Arc<dyn LabelStore>,
     layer_store: Arc<dyn LayerStore>,
+    rollup_policy: RollupPolicy,
+    background_rollups: Arc<BackgroundRollups>,
 }
 
 /// A wrapper over a SimpleLayerBuilder, providing a thread-safe sharable interface.
@@ -137,7 +148,12 @@ impl StoreLayerBuilder {
                 io::ErrorKind::InvalidData,
                 "builder has already been committed",
             )),
-            Some(builder) => builder.commit_boxed().await,
+            Some(builder) => {
+                builder.commit_boxed().await?;
+                self.store.layer_change_statistics(self.name).await?;
+
+                Ok(())
+            }
         }
     }
 
@@ -157,7 +173,16 @@ impl StoreLayerBuilder {
     ///
     /// This is a way to 'cherry-pick' a layer on top of another
     /// layer, without caring about its history.
+    ///
+    /// If the delta and the parent of this builder come from the same
+    /// store and share an ancestor, the changes are applied at the id
+    /// level, and only strings that were added since that ancestor are
+    /// looked up.
     pub async fn apply_delta(&self, delta: &StoreLayer) -> Result<(), io::Error> {
+        if self.apply_delta_by_id(delta).await? {
+            return Ok(());
+        }
+
         // create a child builder and use it directly
         // first check what dictionary entries we don't know about, add those
         let triple_additions = delta.triple_additions().await?;
@@ -183,38 +208,62 @@ impl StoreLayerBuilder {
     }
 
     /// Apply the changes required to change our parent layer into the given layer.
+    ///
+    /// Every triple of both layers is compared by its strings. Use
+    /// `apply_shared_diff` to compare layers that share history at the
+    /// id level.
     pub fn apply_diff(&self, other: &StoreLayer) -> Result<(), io::Error> {
         // create a child builder and use it directly
         // first check what dictionary entries we don't know about, add those
         rayon::join(
             || {
                 if let Some(this) = self.parent() {
-                    this.triples().par_bridge().for_each(|t| {
-                        if let Some(st) = this.id_triple_to_string(&t) {
-                            if !other.string_triple_exists(&st) {
-                                self.remove_string_triple(st).unwrap()
+                    this.triples_split(rayon::current_num_threads())
+                        .into_par_iter()
+                        .flat_map_iter(|triples| triples)
+                        .for_each(|t| {
+                            if let Some(st) = this.id_triple_to_string(&t) {
+                                if !other.string_triple_exists(&st) {
+                                    self.remove_string_triple(st).unwrap()
+                                }
                             }
-                        }
-                    })
+                        })
                 };
             },
             || {
-                other.triples().par_bridge().for_each(|t| {
-                    if let Some(st) = other.id_triple_to_string(&t) {
-                        if let Some(this) = self.parent() {
-                            if !this.string_triple_exists(&st) {
+                other
+                    .triples_split(rayon::current_num_threads())
+                    .into_par_iter()
+                    .flat_map_iter(|triples| triples)
+                    .for_each(|t| {
+                        if let Some(st) = other.id_triple_to_string(&t) {
+                            if let Some(this) = self.parent() {
+                                if !this.string_triple_exists(&st) {
+                                    self.add_string_triple(st).unwrap()
+                                }
+                            } else {
                                 self.add_string_triple(st).unwrap()
-                            }
-                        } else {
-                            self.add_string_triple(st).unwrap()
-                        };
-                    }
-                })
+                            };
+                        }
+                    })
             },
         );
 
         Ok(())
     }
+
+    /// Apply the changes required to change our parent layer into the given layer, like `apply_diff`.
+    ///
+    /// If both layers come from the same store and share an ancestor,
+    /// the difference is computed at the id level from the layers
+    /// since that ancestor. Otherwise, this falls back to `apply_diff`.
+    pub async fn apply_shared_diff(&self, other: &StoreLayer) -> Result<(), io::Error> {
+        if self.apply_diff_by_id(other).await? {
+            return Ok(());
+        }
+
+        self.apply_diff(other)
+    }
 }
 
 /// A layer that keeps track of the store it came out of, allowing the creation of a layer builder on top of this layer.
@@ -251,6 +300,22 @@ impl StoreLayer {
         Ok(StoreLayerBuilder::wrap(layer, self.store.clone()))
     }
 
+    /// Create a layer builder based on this layer, which spills to
+    /// temporary files once roughly `memory_limit` bytes of triple
+    /// data are buffered.
+    pub async fn open_write_with_memory_limit(
+        &self,
+        memory_limit: usize,
+    ) -> io::Result<StoreLayerBuilder> {
+        let layer = self
+            .store
+            .layer_store
+            .create_spilling_child_layer(self.layer.name(), memory_limit)
+            .await?;
+
+        Ok(StoreLayerBuilder::wrap(layer, self.store.clone()))
+    }
+
     /// Returns the parent of this layer, if any, or None if this layer has no parent.
     pub async fn parent(&self) -> io::Result<Option<StoreLayer>> {
         let parent_name = self.layer.parent_name();
@@ -276,14 +341,19 @@ impl StoreLayer {
     /// option if you do not care for history, as it throws away all
     /// data that you no longer need.
     pub async fn squash(&self) -> io::Result<StoreLayer> {
-        // TODO check if we already committed
-        let new_builder = self.store.create_base_layer().await?;
-        self.triples().par_bridge().for_each(|t| {
-            let st = self.id_triple_to_string(&t).unwrap();
-            new_builder.add_string_triple(st).unwrap()
-        });
+        let layer = self
+            .store
+            .layer_store
+            .get_layer(self.name())
+            .await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "layer not found"))?;
+        let name = self.store.layer_store.squash(layer).await?;
+        self.store.layer_change_statistics(name).await?;
 
-        new_builder.commit().await
+        self.store
+            .get_layer_from_id(name)
+            .await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "layer not found after squash"))
     }
 
     /// Create a new rollup layer which rolls up all triples in this layer, as well as all its ancestors.
@@ -322,6 +392,112 @@ impl StoreLayer {
         Ok(())
     }
 
+    /// Create a new rollup layer like `rollup`, leaving out all strings that are no longer used by any triple.
+    ///
+    /// Ids stay the same for all strings that are still in use. As
+    /// layers that were built on top of this layer might use the
+    /// strings that are left out, this returns an error for a layer
+    /// with children. Only layers without children, like the head of a
+    /// graph, can be rolled up this way.
+    pub async fn rollup_compacted(&self) -> io::Result<()> {
+        let layer = self
+            .store
+            .layer_store
+            .get_layer(self.name())
+            .await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "layer not found"))?;
+        self.store
+            .layer_store
+            .clone()
+            .compacted_rollup(layer)
+            .await?;
+        Ok(())
+    }
+
+    /// Create a new rollup layer like `rollup_upto`, leaving out all strings of the rolled up layers that are no longer used by any triple.
+    ///
+    /// The same caveat as for `rollup_compacted` applies.
+    pub async fn rollup_upto_compacted(&self, upto: &StoreLayer) -> io::Result<()> {
+        let layer = self
+            .store
+            .layer_store
+            .get_layer(self.name())
+            .await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "layer not found"))?;
+        self.store
+            .layer_store
+            .clone()
+            .compacted_rollup_upto(layer, upto.name())
+            .await?;
+        Ok(())
+    }
+
+    /// Roll up part of the stack of this layer if the given policy asks for it, returning the rollup that was done, if any.
+    pub async fn rollup_with_policy(
+        &self,
+        policy: RollupPolicy,
+    ) -> io::Result<Option<RollupAction>> {
+        if policy == RollupPolicy::Never {
+            return Ok(None);
+        }
+
+        let layer = self
+            .store
+            .layer_store
+            .get_layer(self.name())
+            .await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "layer not found"))?;
+        let stack = self
+            .store
+            .layer_store
+            .retrieve_layer_stack_names(self.name())
+            .await?;
+
+        let action = policy.decide(&layer, &stack);
+        match action {
+            None => {}
+            Some(RollupAction::Full) => {
+                self.store.layer_store.clone().rollup(layer).await?;
+            }
+            Some(RollupAction::Upto(upto)) => {
+                self.store
+                    .layer_store
+                    .clone()
+                    .rollup_upto(layer, upto)
+                    .await?;
+            }
+        }
+
+        Ok(action)
+    }
+
+    /// Returns statistics about this layer and all its ancestors, for use in query planning.
+    ///
+    /// The statistics of each layer in the stack are computed when it
+    /// is committed. Layers that were imported from elsewhere get
+    /// their statistics computed and stored the first time they are
+    /// needed.
+    pub async fn statistics(&self) -> io::Result<LayerStatistics> {
+        let layer = self
+            .store
+            .layer_store
+            .get_layer(self.name())
+            .await?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "layer not found"))?;
+        let names = self
+            .store
+            .layer_store
+            .retrieve_layer_stack_names(self.name())
+            .await?;
+
+        let mut changes = Vec::with_capacity(names.len());
+        for name in names {
+            changes.push(self.store.layer_change_statistics(name).await?);
+        }
+
+        LayerStatistics::combine(&changes, &layer)
+    }
+
     /// Returns a future that yields true if this triple has been added in this layer, or false if it doesn't.
     ///
     /// Since this operation will involve io when this layer is a
@@ -374,6 +550,47 @@ impl StoreLayer {
         self.store.layer_store.triple_removals(self.layer.name())
     }
 
+    /// Returns a stream over all layer additions.
+    ///
+    /// The triples are read on the blocking thread pool, and handed
+    /// over in batches. Dropping the stream stops the read.
+    pub async fn triple_additions_stream(&self) -> io::Result<TripleStream> {
+        Ok(TripleStream::new(self.triple_additions().await?))
+    }
+
+    /// Returns a stream over all layer removals.
+    ///
+    /// The triples are read on the blocking thread pool, and handed
+    /// over in batches. Dropping the stream stops the read.
+    pub async fn triple_removals_stream(&self) -> io::Result<TripleStream> {
+        Ok(TripleStream::new(self.triple_removals().await?))
+    }
+
+    /// Returns a stream over all triples in this layer.
+    pub fn triples_stream(&self) -> TripleStream {
+        TripleStream::new(self.triples())
+    }
+
+    /// Returns a stream over all triples in this layer that share a particular subject.
+    pub fn triples_s_stream(&self, subject: u64) -> TripleStream {
+        TripleStream::new(self.triples_s(subject))
+    }
+
+    /// Returns a stream over all triples in this layer that share a particular subject and predicate.
+    pub fn triples_sp_stream(&self, subject: u64, predicate: u64) -> TripleStream {
+        TripleStream::new(self.triples_sp(subject, predicate))
+    }
+
+    /// Returns a stream over all triples in this layer that share a particular predicate.
+    pub fn triples_p_stream(&self, predicate: u64) -> TripleStream {
+        TripleStream::new(self.triples_p(predicate))
+    }
+
+    /// Returns a stream over all triples in this layer that share a particular object.
+    pub fn triples_o_stream(&self, object: u64) -> TripleStream {
+        TripleStream::new(self.triples_o(object))
+    }
+
     /// Returns a future that yields an iterator over all layer additions that share a particular subject.
     ///
     /// Since this operation will involve io when this layer is a
@@ -488,6 +705,36 @@ impl StoreLayer {
             .triple_removals_o(self.layer.name(), object)
     }
 
+    /// Returns a future that yields an iterator over all layer additions that share a particular predicate and object.
+    ///
+    /// Since this operation will involve io when this layer is a
+    /// rollup layer, io errors may occur.
+    pub fn triple_additions_po(
+        &self,
+        predicate: u64,
+        object: u64,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn Iterator<Item = IdTriple> + Send>>> + Send>>
+    {
+        self.store
+            .layer_store
+            .triple_additions_po(self.layer.name(), predicate, object)
+    }
+
+    /// Returns a future that yields an iterator over all layer removals that share a particular predicate and object.
+    ///
+    /// Since this operation will involve io when this layer is a
+    /// rollup layer, io errors may occur.
+    pub fn triple_removals_po(
+        &self,
+        predicate: u64,
+        object: u64,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Box<dyn Iterator<Item = IdTriple> + Send>>> + Send>>
+    {
+        self.store
+            .layer_store
+            .triple_removals_po(self.layer.name(), predicate, object)
+    }
+
     /// Returns a future that yields the amount of triples that this layer adds.
     ///
     /// Since this operation will involve io when this layer is a
@@ -566,6 +813,34 @@ impl Layer for StoreLayer {
         self.layer.id_object(id)
     }
 
+    fn id_object_is_value(&self, id: u64) -> Option<bool> {
+        self.layer.id_object_is_value(id)
+    }
+
+    fn subject_ids(&self, subjects: &[&str]) -> Vec<Option<u64>> {
+        self.layer.subject_ids(subjects)
+    }
+
+    fn predicate_ids(&self, predicates: &[&str]) -> Vec<Option<u64>> {
+        self.layer.predicate_ids(predicates)
+    }
+
+    fn object_value_ids(&self, objects: &[&str]) -> Vec<Option<u64>> {
+        self.layer.object_value_ids(objects)
+    }
+
+    fn id_subjects(&self, ids: &[u64]) -> Vec<Option<String>> {
+        self.layer.id_subjects(ids)
+    }
+
+    fn id_predicates(&self, ids: &[u64]) -> Vec<Option<String>> {
+        self.layer.id_predicates(ids)
+    }
+
+    fn id_objects(&self, ids: &[u64]) -> Vec<Option<ObjectType>> {
+        self.layer.id_objects(ids)
+    }
+
     fn triple_exists(&self, subject: u64, predicate: u64, object: u64) -> bool {
         self.layer.triple_exists(subject, predicate, object)
     }
@@ -594,6 +869,57 @@ impl Layer for StoreLayer {
         self.layer.triples_o(object)
     }
 
+    fn triples_po(&self, predicate: u64, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.layer.triples_po(predicate, object)
+    }
+
+    fn count_s(&self, subject: u64) -> usize {
+        self.layer.count_s(subject)
+    }
+
+    fn count_sp(&self, subject: u64, predicate: u64) -> usize {
+        self.layer.count_sp(subject, predicate)
+    }
+
+    fn count_p(&self, predicate: u64) -> usize {
+        self.layer.count_p(predicate)
+    }
+
+    fn count_o(&self, object: u64) -> usize {
+        self.layer.count_o(object)
+    }
+
+    fn subject_cursor(&self) -> SubjectCursor {
+        self.layer.subject_cursor()
+    }
+
+    fn object_cursor(&self) -> ObjectCursor {
+        self.layer.object_cursor()
+    }
+
+    fn triples_after(
+        &self,
+        token: &ScanToken,
+    ) -> Result<Box<dyn Iterator<Item = IdTriple> + Send>, ScanTokenError> {
+        self.layer.triples_after(token)
+    }
+
+    fn subject_ranges(&self, parts: usize) -> Vec<Range<u64>> {
+        self.layer.subject_ranges(parts)
+    }
+
+    fn triple_sampler(&self) -> TripleSampler {
+        self.layer.triple_sampler()
+    }
+
+    fn triple_sampler_s(&self, subject: u64) -> TripleSampler {
+        self.layer.triple_sampler_s(subject)
+    }
+
+    fn triple_sampler_p(&self, predicate: u64) -> TripleSampler {
+        self.layer.triple_sampler_p(predicate)
+    }
+
     fn clone_boxed(&self) -> Box<dyn Layer> {
         Box::new(self.clone())
     }
@@ -679,6 +1005,7 @@ impl NamedGraph {
 
         if set_is_ok {
             self.store.label_store.set_label(&label, layer_name).await?;
+            self.spawn_rollup(layer);
         }
 
         Ok(set_is_ok)
@@ -692,11 +1019,34 @@ impl NamedGraph {
             None => Err(io::Error::new(io::ErrorKind::NotFound, "label not found")),
             Some(label) => {
                 self.store.label_store.set_label(&label, layer_name).await?;
+                self.spawn_rollup(layer);
 
                 Ok(true)
             }
         }
     }
+
+    /// Apply the rollup policy of the store to the new head in the background.
+    ///
+    /// Nothing is done if a rollup of this layer is already running.
+    /// Errors are kept for `Store::take_rollup_errors`.
+    fn spawn_rollup(&self, layer: &StoreLayer) {
+        let policy = self.store.rollup_policy;
+        if policy == RollupPolicy::Never {
+            return;
+        }
+
+        let in_flight = match self.store.background_rollups.start(layer.name()) {
+            Some(in_flight) => in_flight,
+            None => return,
+        };
+        let layer = layer.clone();
+        tokio::spawn(async move {
+            if let Err(e) = layer.rollup_with_policy(policy).await {
+                in_flight.failed(e);
+            }
+        });
+    }
 }
 
 impl Store {
@@ -708,9 +1058,23 @@ impl Store {
         Store {
             label_store: Arc::new(label_store),
             layer_store: Arc::new(layer_store),
+            rollup_policy: RollupPolicy::default(),
+            background_rollups: Arc::new(BackgroundRollups::default()),
         }
     }
 
+    /// Use the given policy to roll up layers automatically after they are set as the head of a named graph.
+    pub fn with_rollup_policy(mut self, policy: RollupPolicy) -> Store {
+        self.rollup_policy = policy;
+
+        self
+    }
+
+    /// Returns the policy used to roll up layers automatically.
+    pub fn rollup_policy(&self) -> RollupPolicy {
+        self.rollup_policy
+    }
+
     /// Create a new database with the given name.
     ///
     /// If the database already exists, this will return an error.
@@ -738,6 +1102,38 @@ impl Store {
         StoreLayerBuilder::new(self.clone()).await
     }
 
+    /// Create a base layer builder, unattached to any database label,
+    /// which spills to temporary files once roughly `memory_limit`
+    /// bytes of triple data are buffered.
+    ///
+    /// This is useful for bulk loading data sets that are too large
+    /// to build in memory.
+    pub async fn create_base_layer_with_memory_limit(
+        &self,
+        memory_limit: usize,
+    ) -> io::Result<StoreLayerBuilder> {
+        let builder = self
+            .layer_store
+            .create_spilling_base_layer(memory_limit)
+            .await?;
+
+        Ok(StoreLayerBuilder::wrap(builder, self.clone()))
+    }
+
+    /// Create a base layer from the contents of an HDT file, unattached to any database label.
+    ///
+    /// The dictionary and triples of the HDT file are converted
+    /// directly into layer structures. Use `set_head` on a
+    /// `NamedGraph` to attach the resulting layer.
+    pub async fn create_base_layer_from_hdt(&self, hdt: Vec<u8>) -> io::Result<StoreLayer> {
+        let name = self.layer_store.create_base_layer_from_hdt(hdt).await?;
+        self.layer_change_statistics(name).await?;
+
+        self.get_layer_from_id(name).await?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "layer not found after HDT import")
+        })
+    }
+
     /// Export the given layers by creating a pack, a Vec<u8> that can later be used with `import_layers` on a different store.
     pub fn export_layers(&self, layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> Vec<u8> {
         self.layer_store.export_layers(layer_ids)
@@ -755,6 +1151,39 @@ impl Store {
     ) -> Result<(), io::Error> {
         self.layer_store.import_layers(pack, layer_ids)
     }
+
+    /// Retrieve the stored statistics of the given layer, computing and storing them first if there are none.
+    async fn layer_change_statistics(&self, name: [u32; 5]) -> io::Result<LayerChangeStatistics> {
+        if let Some(statistics) = self.layer_store.layer_change_statistics(name).await? {
+            return Ok(statistics);
+        }
+
+        let not_found = || io::Error::new(io::ErrorKind::NotFound, "layer not found");
+        let layer = self
+            .layer_store
+            .get_layer(name)
+            .await?
+            .ok_or_else(not_found)?;
+        let parent = match layer.parent_name() {
+            Some(parent_name) => Some(
+                self.layer_store
+                    .get_layer(parent_name)
+                    .await?
+                    .ok_or_else(not_found)?,
+            ),
+            None => None,
+        };
+
+        let additions = self.layer_store.triple_additions(name).await?;
+        let removals = self.layer_store.triple_removals(name).await?;
+        let statistics =
+            LayerChangeStatistics::from_triples(additions, removals, &layer, parent.as_deref());
+        self.layer_store
+            .write_layer_change_statistics(name, statistics.clone())
+            .await?;
+
+        Ok(statistics)
+    }
 }
 
 /// Open a store that is entirely in memory.
@@ -826,6 +1255,86 @@ mod tests {
         create_and_manipulate_database(store).await;
     }
 
+    #[tokio::test]
+    async fn create_spilling_layers_in_directory_store() {
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+
+        let builder = store.create_base_layer_with_memory_limit(0).await.unwrap();
+        for i in 0..100 {
+            builder
+                .add_string_triple(StringTriple::new_value("cow", "says", &format!("moo{}", i)))
+                .unwrap();
+        }
+        let layer = builder.commit().await.unwrap();
+
+        let builder = layer.open_write_with_memory_limit(0).await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("cow", "says", "moo0"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("pig", "likes", "cow"))
+            .unwrap();
+        let layer = builder.commit().await.unwrap();
+
+        assert_eq!(100, layer.triple_count());
+        assert!(!layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo0")));
+        assert!(layer.string_triple_exists(&StringTriple::new_value("cow", "says", "moo99")));
+        assert!(layer.string_triple_exists(&StringTriple::new_node("pig", "likes", "cow")));
+    }
+
+    #[tokio::test]
+    async fn stream_layer_changes() {
+        use futures::stream::StreamExt;
+
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let base_layer = builder.commit().await.unwrap();
+
+        let builder = base_layer.open_write().await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("pig", "likes", "cow"))
+            .unwrap();
+        let layer = builder.commit().await.unwrap();
+
+        let to_strings = |triples: Vec<IdTriple>| -> Vec<StringTriple> {
+            triples
+                .into_iter()
+                .map(|t| layer.id_triple_to_string(&t).unwrap())
+                .collect()
+        };
+
+        let additions = layer.triple_additions_stream().await.unwrap();
+        assert_eq!(
+            vec![StringTriple::new_node("pig", "likes", "cow")],
+            to_strings(additions.collect().await)
+        );
+        let removals = layer.triple_removals_stream().await.unwrap();
+        assert_eq!(
+            vec![StringTriple::new_value("cow", "says", "moo")],
+            to_strings(removals.collect().await)
+        );
+
+        let says = layer.predicate_id("says").unwrap();
+        assert_eq!(
+            vec![StringTriple::new_value("pig", "says", "oink")],
+            to_strings(layer.triples_p_stream(says).collect().await)
+        );
+        assert_eq!(
+            layer.triples().collect::<Vec<_>>(),
+            layer.triples_stream().collect::<Vec<_>>().await
+        );
+    }
+
     #[tokio::test]
     async fn create_layer_and_retrieve_it_by_id() {
         let store = open_memory_store();
@@ -1054,4 +1563,342 @@ mod tests {
         let store = open_directory_store(dir.path());
         cached_layer_name_does_not_change_after_rollup_upto(store).await
     }
+
+    async fn compacted_rollup_drops_unused_strings(store: Store) {
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cat", "says", "meow"))
+            .unwrap();
+        let base_layer = builder.commit().await.unwrap();
+        let builder = base_layer.open_write().await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("cat", "says", "meow"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
+            .unwrap();
+        let child_layer = builder.commit().await.unwrap();
+        let cat = child_layer.subject_id("cat").unwrap();
+        let triples: Vec<_> = child_layer.triples().collect();
+
+        child_layer.rollup_compacted().await.unwrap();
+        let rolled_layer = store
+            .get_layer_from_id(child_layer.name())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(triples, rolled_layer.triples().collect::<Vec<_>>());
+        assert_eq!(None, rolled_layer.subject_id("cat"));
+        assert_eq!(None, rolled_layer.id_subject(cat));
+        assert_eq!(None, rolled_layer.object_value_id("meow"));
+        assert_eq!(
+            child_layer.node_and_value_count(),
+            rolled_layer.node_and_value_count()
+        );
+
+        // a new layer on top of the rollup gives 'cat' a new id
+        let builder = rolled_layer.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cat", "says", "purr"))
+            .unwrap();
+        let new_layer = builder.commit().await.unwrap();
+        let new_cat = new_layer.subject_id("cat").unwrap();
+        assert!(new_cat > cat);
+        assert_eq!(Some("cat".to_string()), new_layer.id_subject(new_cat));
+        assert!(new_layer.string_triple_exists(&StringTriple::new_value("cat", "says", "purr")));
+        assert!(new_layer.string_triple_exists(&StringTriple::new_value("dog", "says", "woof")));
+    }
+
+    async fn compacted_rollup_refuses_layer_with_children(store: Store) {
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cat", "says", "meow"))
+            .unwrap();
+        let base_layer = builder.commit().await.unwrap();
+        let builder = base_layer.open_write().await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("cat", "says", "meow"))
+            .unwrap();
+        let child_layer = builder.commit().await.unwrap();
+        let builder = child_layer.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cat", "says", "purr"))
+            .unwrap();
+        let grandchild_layer = builder.commit().await.unwrap();
+
+        let error = child_layer.rollup_compacted().await.unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+        let error = child_layer
+            .rollup_upto_compacted(&base_layer)
+            .await
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+        assert_eq!(None, store.rollup_info(child_layer.name()).await.unwrap());
+
+        let grandchild_layer = store
+            .get_layer_from_id(grandchild_layer.name())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(
+            grandchild_layer.string_triple_exists(&StringTriple::new_value("cat", "says", "purr"))
+        );
+
+        grandchild_layer.rollup_compacted().await.unwrap();
+        assert!(store
+            .rollup_info(grandchild_layer.name())
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn mem_compacted_rollup_refuses_layer_with_children() {
+        compacted_rollup_refuses_layer_with_children(open_memory_store()).await
+    }
+
+    #[tokio::test]
+    async fn dir_compacted_rollup_refuses_layer_with_children() {
+        let dir = tempdir().unwrap();
+        compacted_rollup_refuses_layer_with_children(open_directory_store(dir.path())).await
+    }
+
+    #[tokio::test]
+    async fn mem_compacted_rollup_drops_unused_strings() {
+        let store = open_memory_store();
+        compacted_rollup_drops_unused_strings(store).await
+    }
+
+    #[tokio::test]
+    async fn dir_compacted_rollup_drops_unused_strings() {
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+        compacted_rollup_drops_unused_strings(store).await
+    }
+
+    fn string_triples(layer: &StoreLayer) -> Vec<StringTriple> {
+        let mut triples: Vec<_> = layer
+            .triples()
+            .map(|t| layer.id_triple_to_string(&t).unwrap())
+            .collect();
+        triples.sort();
+
+        triples
+    }
+
+    async fn apply_diff_between_branches(store: Store) {
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cat", "says", "meow"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let base_layer = builder.commit().await.unwrap();
+
+        let builder = base_layer.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("duck", "says", "quack"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let ours = builder.commit().await.unwrap();
+
+        let builder = base_layer.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("cat", "says", "meow"))
+            .unwrap();
+        let theirs = builder.commit().await.unwrap();
+        let builder = theirs.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("horse", "says", "neigh"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node("horse", "likes", "cow"))
+            .unwrap();
+        let theirs = builder.commit().await.unwrap();
+        theirs.rollup().await.unwrap();
+        let theirs = store
+            .get_layer_from_id(theirs.name())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let builder = ours.open_write().await.unwrap();
+        assert!(builder.apply_diff_by_id(&theirs).await.unwrap());
+        let diffed = builder.commit().await.unwrap();
+
+        assert_eq!(string_triples(&theirs), string_triples(&diffed));
+    }
+
+    #[tokio::test]
+    async fn mem_apply_diff_between_branches() {
+        let store = open_memory_store();
+        apply_diff_between_branches(store).await
+    }
+
+    #[tokio::test]
+    async fn dir_apply_diff_between_branches() {
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+        apply_diff_between_branches(store).await
+    }
+
+    async fn apply_diff_from_compacted_branch(store: Store) {
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let base_layer = builder.commit().await.unwrap();
+
+        let builder = base_layer.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
+            .unwrap();
+        let ours = builder.commit().await.unwrap();
+
+        let builder = base_layer.open_write().await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let theirs = builder.commit().await.unwrap();
+        theirs.rollup_compacted().await.unwrap();
+        let theirs = store
+            .get_layer_from_id(theirs.name())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let builder = ours.open_write().await.unwrap();
+        assert!(!builder.apply_diff_by_id(&theirs).await.unwrap());
+        builder.apply_shared_diff(&theirs).await.unwrap();
+        let diffed = builder.commit().await.unwrap();
+
+        assert_eq!(string_triples(&theirs), string_triples(&diffed));
+
+        // the same goes for a compacted stack below the builder
+        let builder = theirs.open_write().await.unwrap();
+        assert!(!builder.apply_diff_by_id(&ours).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn mem_apply_diff_from_compacted_branch() {
+        let store = open_memory_store();
+        apply_diff_from_compacted_branch(store).await
+    }
+
+    #[tokio::test]
+    async fn dir_apply_diff_from_compacted_branch() {
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+        apply_diff_from_compacted_branch(store).await
+    }
+
+    #[tokio::test]
+    async fn apply_diff_to_unrelated_layer() {
+        let store = open_memory_store();
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cat", "says", "meow"))
+            .unwrap();
+        let ours = builder.commit().await.unwrap();
+
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cat", "says", "meow"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
+            .unwrap();
+        let theirs = builder.commit().await.unwrap();
+
+        let builder = ours.open_write().await.unwrap();
+        assert!(!builder.apply_diff_by_id(&theirs).await.unwrap());
+        builder.apply_shared_diff(&theirs).await.unwrap();
+        let diffed = builder.commit().await.unwrap();
+
+        assert_eq!(string_triples(&theirs), string_triples(&diffed));
+    }
+
+    async fn apply_delta_from_branch(store: Store) {
+        let builder = store.create_base_layer().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cow", "says", "moo"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("cat", "says", "meow"))
+            .unwrap();
+        let base_layer = builder.commit().await.unwrap();
+
+        let builder = base_layer.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
+            .unwrap();
+        let ours = builder.commit().await.unwrap();
+
+        let builder = base_layer.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let theirs = builder.commit().await.unwrap();
+        let builder = theirs.open_write().await.unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("dog", "says", "woof"))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value("horse", "says", "neigh"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("cat", "says", "meow"))
+            .unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value("pig", "says", "oink"))
+            .unwrap();
+        let delta = builder.commit().await.unwrap();
+
+        let builder = ours.open_write().await.unwrap();
+        assert!(builder.apply_delta_by_id(&delta).await.unwrap());
+        let rebased = builder.commit().await.unwrap();
+
+        assert_eq!(
+            vec![
+                StringTriple::new_value("cow", "says", "moo"),
+                StringTriple::new_value("dog", "says", "woof"),
+                StringTriple::new_value("horse", "says", "neigh"),
+            ],
+            string_triples(&rebased)
+        );
+    }
+
+    #[tokio::test]
+    async fn mem_apply_delta_from_branch() {
+        let store = open_memory_store();
+        apply_delta_from_branch(store).await
+    }
+
+    #[tokio::test]
+    async fn dir_apply_delta_from_branch() {
+        let dir = tempdir().unwrap();
+        let store = open_directory_store(dir.path());
+        apply_delta_from_branch(store).await
+    }
 }
@@ -0,0 +1,641 @@
+//! Rollup policies and management.
+//!
+//! Every layer on a stack makes queries a bit slower. A
+//! `RollupPolicy` decides, for a layer that just became the head of
+//! a named graph, whether part of its stack should be rolled up. The
+//! store applies its policy in a background task after `set_head`, so
+//! that committing is not held up by the rollup. Only one background
+//! rollup runs per layer at a time, and the errors of failed ones can
+//! be retrieved with `Store::take_rollup_errors`.
+//!
+//! Once registered, a rollup is used in place of the layers it
+//! covers whenever its layer is loaded. The `Store` methods in this
+//! module list the registered rollups, check them against the layers
+//! they replace, and remove the ones that should no longer be used.
+use super::Store;
+use crate::layer::{IdTriple, InternalLayer, InternalLayerImpl, Layer};
+use std::collections::{BTreeSet, HashSet};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// A policy for when to roll up the stack of a layer.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RollupPolicy {
+    /// Never roll up automatically. This is the default.
+    #[default]
+    Never,
+    /// Roll up the whole stack once more than the given amount of layers have to be visited for a query.
+    MaxDepth(usize),
+    /// Roll up the whole stack once the changes in the layers above
+    /// the bottom layer add up to more than the given fraction of the
+    /// triples in the bottom layer.
+    SizeRatio(f64),
+    /// Roll up in exponentially growing levels, like a log-structured merge tree.
+    ///
+    /// Every `factor` layers are rolled up into a single level 1
+    /// rollup, every `factor` level 1 rollups into a level 2 rollup,
+    /// and so on. The amount of layers to visit for a query stays
+    /// logarithmic in the height of the stack.
+    Levels(usize),
+}
+
+/// A rollup to perform, as decided by a `RollupPolicy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RollupAction {
+    /// Roll up the whole stack.
+    Full,
+    /// Roll up the stack up to (but not including) the given ancestor.
+    Upto([u32; 5]),
+}
+
+impl RollupPolicy {
+    /// Decide what to roll up for the given layer.
+    ///
+    /// `stack` contains the names of all the layers in the stack of
+    /// this layer as it was originally committed, starting at the base
+    /// layer and ending at the layer itself.
+    pub fn decide(&self, layer: &InternalLayer, stack: &[[u32; 5]]) -> Option<RollupAction> {
+        if layer.is_rollup() || layer.immediate_parent().is_none() {
+            return None;
+        }
+
+        let immediate = layer.immediate_layers();
+        match *self {
+            RollupPolicy::Never => None,
+            RollupPolicy::MaxDepth(depth) => {
+                if immediate.len() > depth {
+                    Some(RollupAction::Full)
+                } else {
+                    None
+                }
+            }
+            RollupPolicy::SizeRatio(ratio) => {
+                let bottom = immediate[0].internal_triple_layer_addition_count();
+                let changes: usize = immediate[1..]
+                    .iter()
+                    .map(|l| {
+                        l.internal_triple_layer_addition_count()
+                            + l.internal_triple_layer_removal_count()
+                    })
+                    .sum();
+
+                if changes as f64 > bottom as f64 * ratio {
+                    Some(RollupAction::Full)
+                } else {
+                    None
+                }
+            }
+            RollupPolicy::Levels(factor) => {
+                let factor = factor.max(2);
+                let height = stack.len();
+
+                // the largest power of the factor that divides the height is the span of layers to roll up
+                let mut span = 1;
+                while height.is_multiple_of(span * factor) {
+                    span *= factor;
+                }
+                if span == 1 {
+                    return None;
+                }
+                if span == height {
+                    return Some(RollupAction::Full);
+                }
+
+                // the layer below the span is the top of an earlier
+                // level, so it is still part of the stack. A manual
+                // rollup may have hidden it, in which case we leave
+                // the stack alone.
+                let upto = stack[height - span - 1];
+                if immediate
+                    .iter()
+                    .any(|l| InternalLayerImpl::name(*l) == upto)
+                {
+                    Some(RollupAction::Upto(upto))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Bookkeeping for the rollups that a store runs in the background.
+#[derive(Default)]
+pub(crate) struct BackgroundRollups {
+    in_flight: Mutex<HashSet<[u32; 5]>>,
+    errors: Mutex<Vec<([u32; 5], io::Error)>>,
+}
+
+impl BackgroundRollups {
+    /// Mark a rollup of the given layer as running, or return None if one already is.
+    ///
+    /// The rollup counts as running until the returned guard is dropped.
+    pub(crate) fn start(self: &Arc<Self>, layer: [u32; 5]) -> Option<InFlightRollup> {
+        if self.in_flight.lock().unwrap().insert(layer) {
+            Some(InFlightRollup {
+                rollups: self.clone(),
+                layer,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn is_running(&self, layer: [u32; 5]) -> bool {
+        self.in_flight.lock().unwrap().contains(&layer)
+    }
+}
+
+/// A background rollup that is running.
+pub(crate) struct InFlightRollup {
+    rollups: Arc<BackgroundRollups>,
+    layer: [u32; 5],
+}
+
+impl InFlightRollup {
+    /// Keep the error of a failed rollup, so that `Store::take_rollup_errors` can return it.
+    pub(crate) fn failed(&self, error: io::Error) {
+        self.rollups
+            .errors
+            .lock()
+            .unwrap()
+            .push((self.layer, error));
+    }
+}
+
+impl Drop for InFlightRollup {
+    fn drop(&mut self) {
+        self.rollups.in_flight.lock().unwrap().remove(&self.layer);
+    }
+}
+
+/// A rollup registered for a layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RollupInfo {
+    /// The layer that the rollup replaces.
+    pub layer: [u32; 5],
+    /// The rollup layer.
+    pub rollup: [u32; 5],
+    /// The ancestor the rollup was built on, or None if the rollup covers the whole stack.
+    pub upto: Option<[u32; 5]>,
+}
+
+/// The differences between a rollup and the layers it replaces.
+///
+/// Every triple in here is one that queries on the rolled up layer
+/// would get wrong.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RollupCheck {
+    /// Triples in the original layers, but not in the rollup.
+    pub missing: Vec<IdTriple>,
+    /// Triples in the rollup, but not in the original layers.
+    pub unexpected: Vec<IdTriple>,
+}
+
+impl RollupCheck {
+    /// Returns true if the rollup has the same triples as the layers it replaces.
+    pub fn is_consistent(&self) -> bool {
+        self.missing.is_empty() && self.unexpected.is_empty()
+    }
+}
+
+/// Returns the triples in `expected` that are not in `actual`, and the other way around.
+fn sorted_differences(
+    expected: &[IdTriple],
+    actual: &[IdTriple],
+) -> (Vec<IdTriple>, Vec<IdTriple>) {
+    let mut missing = Vec::new();
+    let mut unexpected = Vec::new();
+    let mut expected = expected.iter().peekable();
+    let mut actual = actual.iter().peekable();
+    loop {
+        match (expected.peek(), actual.peek()) {
+            (None, None) => break,
+            (Some(&&e), None) => {
+                missing.push(e);
+                expected.next();
+            }
+            (None, Some(&&a)) => {
+                unexpected.push(a);
+                actual.next();
+            }
+            (Some(&&e), Some(&&a)) => {
+                if e < a {
+                    missing.push(e);
+                    expected.next();
+                } else if a < e {
+                    unexpected.push(a);
+                    actual.next();
+                } else {
+                    expected.next();
+                    actual.next();
+                }
+            }
+        }
+    }
+
+    (missing, unexpected)
+}
+
+impl Store {
+    /// Returns true if a background rollup of the given layer is running.
+    pub fn rollup_in_progress(&self, layer: [u32; 5]) -> bool {
+        self.background_rollups.is_running(layer)
+    }
+
+    /// Returns the errors of the background rollups that failed since the last call, along with the layer each was for.
+    ///
+    /// A failed rollup leaves the stack of its layer as it was.
+    pub fn take_rollup_errors(&self) -> Vec<([u32; 5], io::Error)> {
+        std::mem::take(&mut *self.background_rollups.errors.lock().unwrap())
+    }
+
+    /// Returns all rollups registered in this store.
+    pub async fn rollups(&self) -> io::Result<Vec<RollupInfo>> {
+        let mut result = Vec::new();
+        for layer in self.layer_store.layers().await? {
+            if let Some(info) = self.rollup_info(layer).await? {
+                result.push(info);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Returns the rollup registered for the given layer, or None if it has none.
+    pub async fn rollup_info(&self, layer: [u32; 5]) -> io::Result<Option<RollupInfo>> {
+        let rollup = match self.layer_store.layer_rollup(layer).await? {
+            Some(rollup) => rollup,
+            None => return Ok(None),
+        };
+        let rollup_stack = self.layer_store.retrieve_layer_stack_names(rollup).await?;
+        let upto = if rollup_stack.len() > 1 {
+            Some(rollup_stack[rollup_stack.len() - 2])
+        } else {
+            None
+        };
+
+        Ok(Some(RollupInfo {
+            layer,
+            rollup,
+            upto,
+        }))
+    }
+
+    /// Stop using the rollup registered for the given layer, returning false if it had none.
+    ///
+    /// The layer is loaded from its original stack again. The rollup
+    /// layer itself is left in the store.
+    pub async fn remove_rollup(&self, layer: [u32; 5]) -> io::Result<bool> {
+        self.layer_store.unregister_rollup(layer).await
+    }
+
+    /// Compare the rollup registered for the given layer with the layers it replaces, or return None if it has no rollup.
+    ///
+    /// This compares all triples of the rollup with those of the
+    /// layers it replaces, so it takes about as long as building the
+    /// rollup did.
+    pub async fn check_rollup(&self, layer: [u32; 5]) -> io::Result<Option<RollupCheck>> {
+        let info = match self.rollup_info(layer).await? {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+
+        let stack = self.layer_store.retrieve_layer_stack_names(layer).await?;
+        let covered = match info.upto {
+            None => &stack[..],
+            Some(upto) => match stack.iter().position(|&name| name == upto) {
+                Some(pos) => &stack[pos + 1..],
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "rollup was not built on an ancestor of its layer",
+                    ))
+                }
+            },
+        };
+
+        let not_found = || io::Error::new(io::ErrorKind::NotFound, "layer not found");
+        // the layers below the rollup are shared, so the original
+        // triples are those of that shared part with the changes of
+        // the covered layers applied
+        let mut expected = BTreeSet::new();
+        if let Some(upto) = info.upto {
+            let upto_layer = self
+                .layer_store
+                .get_layer(upto)
+                .await?
+                .ok_or_else(not_found)?;
+            expected.extend(upto_layer.triples());
+        }
+        for (triple, added) in self.net_changes(covered).await? {
+            if added {
+                expected.insert(triple);
+            } else {
+                expected.remove(&triple);
+            }
+        }
+        let expected: Vec<_> = expected.into_iter().collect();
+
+        let rollup = self
+            .layer_store
+            .get_layer(info.rollup)
+            .await?
+            .ok_or_else(not_found)?;
+        let actual: Vec<_> = rollup.triples().collect();
+
+        let (missing, unexpected) = sorted_differences(&expected, &actual);
+
+        Ok(Some(RollupCheck {
+            missing,
+            unexpected,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::StringTriple;
+    use crate::store::*;
+
+    async fn commit_layer(store: &Store, parent: Option<&StoreLayer>, i: usize) -> StoreLayer {
+        let builder = match parent {
+            None => store.create_base_layer().await.unwrap(),
+            Some(parent) => parent.open_write().await.unwrap(),
+        };
+        builder
+            .add_string_triple(StringTriple::new_value(
+                &format!("s{}", i),
+                "p",
+                &format!("{}", i),
+            ))
+            .unwrap();
+        builder.commit().await.unwrap()
+    }
+
+    async fn immediate_depth(store: &Store, layer: &StoreLayer) -> usize {
+        let layer = store
+            .layer_store
+            .get_layer(layer.name())
+            .await
+            .unwrap()
+            .unwrap();
+
+        layer.immediate_layers().len()
+    }
+
+    #[tokio::test]
+    async fn levels_policy_keeps_stack_logarithmic() {
+        let store = open_memory_store();
+        let policy = RollupPolicy::Levels(2);
+        let mut layers: Vec<StoreLayer> = Vec::new();
+        let mut actions = Vec::new();
+        for i in 0..8 {
+            let layer = commit_layer(&store, layers.last(), i).await;
+            actions.push(layer.rollup_with_policy(policy).await.unwrap());
+            layers.push(layer);
+        }
+
+        assert_eq!(
+            vec![
+                None,
+                Some(RollupAction::Full),
+                None,
+                Some(RollupAction::Full),
+                None,
+                Some(RollupAction::Upto(layers[3].name())),
+                None,
+                Some(RollupAction::Full),
+            ],
+            actions
+        );
+
+        // 7 = 4 + 2 + 1
+        assert_eq!(3, immediate_depth(&store, &layers[6]).await);
+        assert_eq!(1, immediate_depth(&store, &layers[7]).await);
+
+        let head = store
+            .get_layer_from_id(layers[6].name())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(7, head.triple_count());
+        for i in 0..7 {
+            let s = head.subject_id(&format!("s{}", i)).unwrap();
+            assert_eq!(1, head.triples_s(s).count());
+        }
+    }
+
+    #[tokio::test]
+    async fn depth_and_size_policies() {
+        let store = open_memory_store();
+        let mut layers: Vec<StoreLayer> = Vec::new();
+        for i in 0..4 {
+            let layer = commit_layer(&store, layers.last(), i).await;
+            layers.push(layer);
+        }
+
+        assert_eq!(
+            None,
+            layers[3]
+                .rollup_with_policy(RollupPolicy::MaxDepth(4))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            None,
+            layers[3]
+                .rollup_with_policy(RollupPolicy::SizeRatio(3.0))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            Some(RollupAction::Full),
+            layers[2]
+                .rollup_with_policy(RollupPolicy::SizeRatio(1.5))
+                .await
+                .unwrap()
+        );
+        assert_eq!(1, immediate_depth(&store, &layers[2]).await);
+        assert_eq!(
+            Some(RollupAction::Full),
+            layers[3]
+                .rollup_with_policy(RollupPolicy::MaxDepth(1))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            None,
+            layers[3]
+                .rollup_with_policy(RollupPolicy::MaxDepth(1))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn set_head_rolls_up_in_background() {
+        let store = open_memory_store().with_rollup_policy(RollupPolicy::MaxDepth(2));
+        let graph = store.create("foo").await.unwrap();
+        let mut layers: Vec<StoreLayer> = Vec::new();
+        for i in 0..3 {
+            let layer = commit_layer(&store, layers.last(), i).await;
+            assert!(graph.set_head(&layer).await.unwrap());
+            layers.push(layer);
+        }
+
+        for _ in 0..100 {
+            if immediate_depth(&store, &layers[2]).await == 1 {
+                assert!(store.take_rollup_errors().is_empty());
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        panic!("head was not rolled up");
+    }
+
+    #[tokio::test]
+    async fn set_head_skips_layer_that_is_being_rolled_up() {
+        let store = open_memory_store().with_rollup_policy(RollupPolicy::MaxDepth(1));
+        let graph = store.create("foo").await.unwrap();
+        let base = commit_layer(&store, None, 0).await;
+        let child = commit_layer(&store, Some(&base), 1).await;
+
+        let in_flight = store.background_rollups.start(child.name()).unwrap();
+        assert!(store.rollup_in_progress(child.name()));
+        assert!(store.background_rollups.start(child.name()).is_none());
+        assert!(graph.set_head(&child).await.unwrap());
+
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(2, immediate_depth(&store, &child).await);
+
+        drop(in_flight);
+        assert!(!store.rollup_in_progress(child.name()));
+        assert!(graph.force_set_head(&child).await.unwrap());
+        for _ in 0..100 {
+            if immediate_depth(&store, &child).await == 1 {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        panic!("head was not rolled up");
+    }
+
+    async fn commit_changes(parent: &StoreLayer, i: usize) -> StoreLayer {
+        let builder = parent.open_write().await.unwrap();
+        builder
+            .remove_string_triple(StringTriple::new_value(
+                &format!("s{}", i - 1),
+                "p",
+                &format!("{}", i - 1),
+            ))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_value(
+                &format!("s{}", i),
+                "p",
+                &format!("{}", i),
+            ))
+            .unwrap();
+        builder
+            .add_string_triple(StringTriple::new_node(&format!("s{}", i), "q", "s0"))
+            .unwrap();
+        builder.commit().await.unwrap()
+    }
+
+    async fn manage_rollups(store: Store) {
+        let mut layers = vec![commit_layer(&store, None, 0).await];
+        for i in 1..4 {
+            let layer = commit_changes(layers.last().unwrap(), i).await;
+            layers.push(layer);
+        }
+        let names: Vec<_> = layers.iter().map(|l| l.name()).collect();
+        let expected: Vec<_> = layers[3].triples().collect();
+
+        layers[1].rollup().await.unwrap();
+        layers[3].rollup_upto(&layers[1]).await.unwrap();
+
+        let mut rollups = store.rollups().await.unwrap();
+        rollups.sort_by_key(|info| info.layer != names[1]);
+        assert_eq!(2, rollups.len());
+        assert_eq!(names[1], rollups[0].layer);
+        assert_eq!(None, rollups[0].upto);
+        assert_eq!(names[3], rollups[1].layer);
+        assert_eq!(Some(names[1]), rollups[1].upto);
+
+        assert_eq!(None, store.rollup_info(names[2]).await.unwrap());
+        assert_eq!(None, store.check_rollup(names[2]).await.unwrap());
+        assert!(store
+            .check_rollup(names[1])
+            .await
+            .unwrap()
+            .unwrap()
+            .is_consistent());
+        assert!(store
+            .check_rollup(names[3])
+            .await
+            .unwrap()
+            .unwrap()
+            .is_consistent());
+
+        assert!(store.remove_rollup(names[3]).await.unwrap());
+        assert!(!store.remove_rollup(names[3]).await.unwrap());
+        assert_eq!(None, store.rollup_info(names[3]).await.unwrap());
+        assert_eq!(1, store.rollups().await.unwrap().len());
+
+        let layer = store.get_layer_from_id(names[3]).await.unwrap().unwrap();
+        assert_eq!(expected, layer.triples().collect::<Vec<_>>());
+        assert_eq!(
+            4,
+            store
+                .layer_store
+                .get_layer(names[3])
+                .await
+                .unwrap()
+                .unwrap()
+                .immediate_layers()
+                .len()
+        );
+    }
+
+    #[tokio::test]
+    async fn mem_manage_rollups() {
+        manage_rollups(open_memory_store()).await
+    }
+
+    #[tokio::test]
+    async fn dir_manage_rollups() {
+        let dir = tempfile::tempdir().unwrap();
+        manage_rollups(open_directory_store(dir.path())).await
+    }
+
+    #[tokio::test]
+    async fn check_detects_mismatched_rollup() {
+        let store = open_memory_store();
+        let base = commit_layer(&store, None, 0).await;
+        let child = commit_changes(&base, 1).await;
+        let grandchild = commit_changes(&child, 2).await;
+
+        grandchild.rollup().await.unwrap();
+        let rollup = store
+            .rollup_info(grandchild.name())
+            .await
+            .unwrap()
+            .unwrap()
+            .rollup;
+        // pretend the rollup of the grandchild is the rollup of the child
+        store
+            .layer_store
+            .register_rollup(child.name(), rollup)
+            .await
+            .unwrap();
+
+        let check = store.check_rollup(child.name()).await.unwrap().unwrap();
+        assert!(!check.is_consistent());
+        assert_eq!(2, check.unexpected.len());
+        assert_eq!(1, check.missing.len());
+    }
+}
@@ -8,12 +8,18 @@
 use futures::Future;
 use tokio::runtime::Runtime;
 
-use std::io;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
 use std::path::PathBuf;
 
-use crate::layer::{IdTriple, Layer, LayerCounts, ObjectType, StringTriple};
+use crate::layer::{
+    IdTriple, Layer, LayerCounts, LayerStatistics, ObjectCursor, ObjectType, ScanToken,
+    ScanTokenError, StringTriple, SubjectCursor, TripleSampler,
+};
+use crate::rdf::{self, ExportSelection, GraphFilter};
 use crate::store::{
-    open_directory_store, open_memory_store, NamedGraph, Store, StoreLayer, StoreLayerBuilder,
+    open_directory_store, open_memory_store, NamedGraph, RollupAction, RollupCheck, RollupInfo,
+    RollupPolicy, Store, StoreLayer, StoreLayerBuilder,
 };
 
 lazy_static! {
@@ -98,8 +104,36 @@ impl SyncStoreLayerBuilder {
     }
 
     /// Apply the changes required to change our parent layer into the given layer.
+    ///
+    /// If both layers come from the same store and share an ancestor,
+    /// the difference is computed at the id level.
     pub fn apply_diff(&self, other: &SyncStoreLayer) -> Result<(), io::Error> {
-        self.inner.apply_diff(&other.inner)
+        task_sync(self.inner.apply_shared_diff(&other.inner))
+    }
+
+    /// Add all triples in the given N-Triples document to this builder.
+    ///
+    /// Returns the amount of triples that were added.
+    pub fn import_ntriples<R: BufRead>(&self, reader: R) -> Result<usize, io::Error> {
+        rdf::import_ntriples(reader, &self.inner)
+    }
+
+    /// Add all triples in the given N-Quads document that match the graph filter to this builder.
+    ///
+    /// Returns the amount of triples that were added.
+    pub fn import_nquads<R: BufRead>(
+        &self,
+        reader: R,
+        filter: &GraphFilter,
+    ) -> Result<usize, io::Error> {
+        rdf::import_nquads(reader, &self.inner, filter)
+    }
+
+    /// Add all triples in the given Turtle document to this builder.
+    ///
+    /// Returns the amount of triples that were added.
+    pub fn import_turtle<R: BufRead>(&self, reader: R) -> Result<usize, io::Error> {
+        rdf::import_turtle(reader, &self.inner)
     }
 }
 
@@ -132,6 +166,18 @@ impl SyncStoreLayer {
         inner.map(SyncStoreLayerBuilder::wrap)
     }
 
+    /// Create a layer builder based on this layer, which spills to
+    /// temporary files once roughly `memory_limit` bytes of triple
+    /// data are buffered.
+    pub fn open_write_with_memory_limit(
+        &self,
+        memory_limit: usize,
+    ) -> Result<SyncStoreLayerBuilder, io::Error> {
+        let inner = task_sync(self.inner.open_write_with_memory_limit(memory_limit));
+
+        inner.map(SyncStoreLayerBuilder::wrap)
+    }
+
     /// Returns the parent of this layer, if any, or None if this layer has no parent.
     pub fn parent(&self) -> Result<Option<SyncStoreLayer>, io::Error> {
         let inner = task_sync(self.inner.parent());
@@ -174,6 +220,70 @@ impl SyncStoreLayer {
         task_sync(self.inner.clone().rollup_upto(&upto.inner))
     }
 
+    /// Create a new rollup layer like `rollup`, leaving out all strings that are no longer used by any triple.
+    ///
+    /// Ids stay the same for all strings that are still in use. As
+    /// layers that were built on top of this layer might use the
+    /// strings that are left out, this returns an error for a layer
+    /// with children. Only layers without children, like the head of a
+    /// graph, can be rolled up this way.
+    pub fn rollup_compacted(&self) -> Result<(), io::Error> {
+        task_sync(self.inner.clone().rollup_compacted())
+    }
+
+    /// Create a new rollup layer like `rollup_upto`, leaving out all strings of the rolled up layers that are no longer used by any triple.
+    pub fn rollup_upto_compacted(&self, upto: &SyncStoreLayer) -> Result<(), io::Error> {
+        task_sync(self.inner.clone().rollup_upto_compacted(&upto.inner))
+    }
+
+    /// Roll up part of the stack of this layer if the given policy asks for it, returning the rollup that was done, if any.
+    pub fn rollup_with_policy(
+        &self,
+        policy: RollupPolicy,
+    ) -> Result<Option<RollupAction>, io::Error> {
+        task_sync(self.inner.rollup_with_policy(policy))
+    }
+
+    /// Returns statistics about this layer and all its ancestors, for use in query planning.
+    ///
+    /// The statistics of each layer in the stack are computed when it
+    /// is committed. Layers that were committed without loading them,
+    /// or that were imported from elsewhere, get their statistics
+    /// computed and stored the first time they are needed.
+    pub fn statistics(&self) -> io::Result<LayerStatistics> {
+        task_sync(self.inner.statistics())
+    }
+
+    /// Write the selected triples of this layer to the writer as an N-Triples document.
+    ///
+    /// Returns the amount of triples that were written.
+    pub fn export_ntriples<W: Write + Send>(
+        &self,
+        selection: ExportSelection,
+        writer: W,
+    ) -> Result<usize, io::Error> {
+        task_sync(rdf::export_ntriples(&self.inner, selection, writer))
+    }
+
+    /// Write the selected triples of this layer to the writer as a Turtle document, using the given prefixes.
+    ///
+    /// Returns the amount of triples that were written.
+    pub fn export_turtle<W: Write + Send>(
+        &self,
+        selection: ExportSelection,
+        prefixes: &[(&str, &str)],
+        writer: W,
+    ) -> Result<usize, io::Error> {
+        task_sync(rdf::export_turtle(&self.inner, selection, prefixes, writer))
+    }
+
+    /// Write all triples in this layer to the writer as an HDT file.
+    ///
+    /// The base IRI is used as the subject of the metadata in the HDT header.
+    pub fn export_hdt<W: Write>(&self, base_iri: &str, writer: W) -> Result<(), io::Error> {
+        rdf::write_hdt(&self.inner, base_iri, writer)
+    }
+
     /// Returns true if this triple has been added in this layer, or false if it doesn't.
     ///
     /// Since this operation will involve io when this layer is a
@@ -309,6 +419,30 @@ impl SyncStoreLayer {
         task_sync(self.inner.triple_removals_o(object))
     }
 
+    /// Returns an iterator over all layer additions that share a particular predicate and object.
+    ///
+    /// Since this operation will involve io when this layer is a
+    /// rollup layer, io errors may occur.
+    pub fn triple_additions_po(
+        &self,
+        predicate: u64,
+        object: u64,
+    ) -> io::Result<Box<dyn Iterator<Item = IdTriple> + Send>> {
+        task_sync(self.inner.triple_additions_po(predicate, object))
+    }
+
+    /// Returns an iterator over all layer removals that share a particular predicate and object.
+    ///
+    /// Since this operation will involve io when this layer is a
+    /// rollup layer, io errors may occur.
+    pub fn triple_removals_po(
+        &self,
+        predicate: u64,
+        object: u64,
+    ) -> io::Result<Box<dyn Iterator<Item = IdTriple> + Send>> {
+        task_sync(self.inner.triple_removals_po(predicate, object))
+    }
+
     /// Returns the amount of triples that this layer adds.
     ///
     /// Since this operation will involve io when this layer is a
@@ -376,6 +510,34 @@ impl Layer for SyncStoreLayer {
         self.inner.id_object(id)
     }
 
+    fn id_object_is_value(&self, id: u64) -> Option<bool> {
+        self.inner.id_object_is_value(id)
+    }
+
+    fn subject_ids(&self, subjects: &[&str]) -> Vec<Option<u64>> {
+        self.inner.subject_ids(subjects)
+    }
+
+    fn predicate_ids(&self, predicates: &[&str]) -> Vec<Option<u64>> {
+        self.inner.predicate_ids(predicates)
+    }
+
+    fn object_value_ids(&self, objects: &[&str]) -> Vec<Option<u64>> {
+        self.inner.object_value_ids(objects)
+    }
+
+    fn id_subjects(&self, ids: &[u64]) -> Vec<Option<String>> {
+        self.inner.id_subjects(ids)
+    }
+
+    fn id_predicates(&self, ids: &[u64]) -> Vec<Option<String>> {
+        self.inner.id_predicates(ids)
+    }
+
+    fn id_objects(&self, ids: &[u64]) -> Vec<Option<ObjectType>> {
+        self.inner.id_objects(ids)
+    }
+
     fn triple_exists(&self, subject: u64, predicate: u64, object: u64) -> bool {
         self.inner.triple_exists(subject, predicate, object)
     }
@@ -404,6 +566,57 @@ impl Layer for SyncStoreLayer {
         self.inner.triples_o(object)
     }
 
+    fn triples_po(&self, predicate: u64, object: u64) -> Box<dyn Iterator<Item = IdTriple> + Send> {
+        self.inner.triples_po(predicate, object)
+    }
+
+    fn count_s(&self, subject: u64) -> usize {
+        self.inner.count_s(subject)
+    }
+
+    fn count_sp(&self, subject: u64, predicate: u64) -> usize {
+        self.inner.count_sp(subject, predicate)
+    }
+
+    fn count_p(&self, predicate: u64) -> usize {
+        self.inner.count_p(predicate)
+    }
+
+    fn count_o(&self, object: u64) -> usize {
+        self.inner.count_o(object)
+    }
+
+    fn subject_cursor(&self) -> SubjectCursor {
+        self.inner.subject_cursor()
+    }
+
+    fn object_cursor(&self) -> ObjectCursor {
+        self.inner.object_cursor()
+    }
+
+    fn triples_after(
+        &self,
+        token: &ScanToken,
+    ) -> Result<Box<dyn Iterator<Item = IdTriple> + Send>, ScanTokenError> {
+        self.inner.triples_after(token)
+    }
+
+    fn subject_ranges(&self, parts: usize) -> Vec<Range<u64>> {
+        self.inner.subject_ranges(parts)
+    }
+
+    fn triple_sampler(&self) -> TripleSampler {
+        self.inner.triple_sampler()
+    }
+
+    fn triple_sampler_s(&self, subject: u64) -> TripleSampler {
+        self.inner.triple_sampler_s(subject)
+    }
+
+    fn triple_sampler_p(&self, predicate: u64) -> TripleSampler {
+        self.inner.triple_sampler_p(predicate)
+    }
+
     fn clone_boxed(&self) -> Box<dyn Layer> {
         Box::new(self.clone())
     }
@@ -506,6 +719,25 @@ impl SyncStore {
         inner.map(SyncStoreLayerBuilder::wrap)
     }
 
+    /// Create a base layer builder, unattached to any database label,
+    /// which spills to temporary files once roughly `memory_limit`
+    /// bytes of triple data are buffered.
+    pub fn create_base_layer_with_memory_limit(
+        &self,
+        memory_limit: usize,
+    ) -> Result<SyncStoreLayerBuilder, io::Error> {
+        let inner = task_sync(self.inner.create_base_layer_with_memory_limit(memory_limit));
+
+        inner.map(SyncStoreLayerBuilder::wrap)
+    }
+
+    /// Create a base layer from the contents of an HDT file, unattached to any database label.
+    pub fn create_base_layer_from_hdt(&self, hdt: Vec<u8>) -> Result<SyncStoreLayer, io::Error> {
+        let inner = task_sync(self.inner.create_base_layer_from_hdt(hdt));
+
+        inner.map(SyncStoreLayer::wrap)
+    }
+
     /// Export the given layers by creating a pack, a Vec<u8> that can later be used with `import_layers` on a different store.
     pub fn export_layers(&self, layer_ids: Box<dyn Iterator<Item = [u32; 5]>>) -> Vec<u8> {
         self.inner.layer_store.export_layers(layer_ids)
@@ -523,6 +755,26 @@ impl SyncStore {
     ) -> Result<(), io::Error> {
         self.inner.layer_store.import_layers(pack, layer_ids)
     }
+
+    /// Returns all rollups registered in this store.
+    pub fn rollups(&self) -> Result<Vec<RollupInfo>, io::Error> {
+        task_sync(self.inner.rollups())
+    }
+
+    /// Returns the rollup registered for the given layer, or None if it has none.
+    pub fn rollup_info(&self, layer: [u32; 5]) -> Result<Option<RollupInfo>, io::Error> {
+        task_sync(self.inner.rollup_info(layer))
+    }
+
+    /// Stop using the rollup registered for the given layer, returning false if it had none.
+    pub fn remove_rollup(&self, layer: [u32; 5]) -> Result<bool, io::Error> {
+        task_sync(self.inner.remove_rollup(layer))
+    }
+
+    /// Compare the rollup registered for the given layer with the layers it replaces, or return None if it has no rollup.
+    pub fn check_rollup(&self, layer: [u32; 5]) -> Result<Option<RollupCheck>, io::Error> {
+        task_sync(self.inner.check_rollup(layer))
+    }
 }
 
 /// Open a store that is entirely in memory.
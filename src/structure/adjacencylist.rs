@@ -13,6 +13,7 @@
 use std::convert::TryInto;
 use std::io;
 use std::pin::Pin;
+use std::{error, fmt};
 
 use bytes::Bytes;
 
@@ -30,6 +31,51 @@ pub struct AdjacencyList {
     pub bits: BitIndex,
 }
 
+/// An error that occurred while loading an adjacency list.
+#[derive(Debug, PartialEq)]
+pub enum AdjacencyListError {
+    LogArray(LogArrayError),
+    BitIndex(BitIndexError),
+    UnexpectedLength(usize, usize),
+    UnterminatedList,
+}
+
+impl fmt::Display for AdjacencyListError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use AdjacencyListError::*;
+        match self {
+            LogArray(err) => write!(f, "invalid nums: {}", err),
+            BitIndex(err) => write!(f, "invalid bits: {}", err),
+            UnexpectedLength(nums_len, bits_len) => write!(
+                f,
+                "expected nums length ({}) to equal bits length ({})",
+                nums_len, bits_len
+            ),
+            UnterminatedList => write!(f, "expected last bit to be set"),
+        }
+    }
+}
+
+impl error::Error for AdjacencyListError {}
+
+impl From<LogArrayError> for AdjacencyListError {
+    fn from(err: LogArrayError) -> AdjacencyListError {
+        AdjacencyListError::LogArray(err)
+    }
+}
+
+impl From<BitIndexError> for AdjacencyListError {
+    fn from(err: BitIndexError) -> AdjacencyListError {
+        AdjacencyListError::BitIndex(err)
+    }
+}
+
+impl From<AdjacencyListError> for io::Error {
+    fn from(err: AdjacencyListError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
 impl AdjacencyList {
     pub fn from_parts(nums: LogArray, bits: BitIndex) -> AdjacencyList {
         debug_assert_eq!(nums.len(), bits.len());
@@ -41,18 +87,24 @@ impl AdjacencyList {
         bits_slice: Bytes,
         bits_block_slice: Bytes,
         bits_sblock_slice: Bytes,
-    ) -> AdjacencyList {
-        let nums = LogArray::parse(nums_slice).unwrap();
-        let bit_array = BitArray::from_bits(bits_slice).unwrap();
-        let bits_block_array = LogArray::parse(bits_block_slice).unwrap();
-        let bits_sblock_array = LogArray::parse(bits_sblock_slice).unwrap();
-        let bits = BitIndex::from_parts(bit_array, bits_block_array, bits_sblock_array);
+    ) -> Result<AdjacencyList, AdjacencyListError> {
+        let nums = LogArray::parse(nums_slice)?;
+        let bits = BitIndex::from_maps(bits_slice, bits_block_slice, bits_sblock_slice)?;
+
+        if nums.len() != bits.len() {
+            return Err(AdjacencyListError::UnexpectedLength(nums.len(), bits.len()));
+        }
+
+        // every left-hand side is closed off by a 1-bit, so a non-empty list has to end in one.
+        if !bits.is_empty() && !bits.get(bits.len() as u64 - 1) {
+            return Err(AdjacencyListError::UnterminatedList);
+        }
 
-        Self::from_parts(nums, bits)
+        Ok(Self::from_parts(nums, bits))
     }
 
     pub fn left_count(&self) -> usize {
-        if self.bits.len() == 0 {
+        if self.bits.is_empty() {
             0
         } else {
             self.bits.rank1((self.bits.len() as u64) - 1) as usize
@@ -387,7 +439,8 @@ mod tests {
             bitfile_contents,
             bitindex_blocks_contents,
             bitindex_sblocks_contents,
-        );
+        )
+        .unwrap();
 
         let slice = adjacencylist.get(1);
         assert_eq!(2, slice.len());
@@ -451,7 +504,8 @@ mod tests {
             bitfile_contents,
             bitindex_blocks_contents,
             bitindex_sblocks_contents,
-        );
+        )
+        .unwrap();
 
         assert_eq!(0, adjacencylist.left_count());
     }
@@ -490,7 +544,8 @@ mod tests {
             bitfile_contents,
             bitindex_blocks_contents,
             bitindex_sblocks_contents,
-        );
+        )
+        .unwrap();
 
         let slice = adjacencylist.get(1);
         assert_eq!(1, slice.len());
@@ -555,7 +610,8 @@ mod tests {
             bitfile_contents,
             bitindex_blocks_contents,
             bitindex_sblocks_contents,
-        );
+        )
+        .unwrap();
 
         assert_eq!(
             vec![(1, 1), (1, 3), (2, 5), (7, 4)],
@@ -644,7 +700,8 @@ mod tests {
             bitfile_contents,
             bitindex_blocks_contents,
             bitindex_sblocks_contents,
-        );
+        )
+        .unwrap();
 
         let result: Vec<_> = (0..adjacencylist.right_count())
             .map(|i| adjacencylist.pair_at_pos(i as u64))
@@ -721,7 +778,8 @@ mod tests {
             bitfile_contents,
             bitindex_blocks_contents,
             bitindex_sblocks_contents,
-        );
+        )
+        .unwrap();
 
         let result: Vec<_> = (0..adjacencylist.right_count())
             .map(|i| adjacencylist.pair_at_pos(i as u64))
@@ -749,4 +807,54 @@ mod tests {
             result
         );
     }
+
+    #[test]
+    fn parse_adjacencylist_with_mismatched_lengths_errors() {
+        let bitfile = MemoryBackedStore::new();
+        let bitindex_blocks_file = MemoryBackedStore::new();
+        let bitindex_sblocks_file = MemoryBackedStore::new();
+        let nums_file = MemoryBackedStore::new();
+
+        let mut builder = AdjacencyListBuilder::new(
+            bitfile.clone(),
+            bitindex_blocks_file.open_write(),
+            bitindex_sblocks_file.open_write(),
+            nums_file.open_write(),
+            8,
+        );
+        block_on(async {
+            builder
+                .push_all(util::stream_iter_ok(vec![(1, 1), (1, 3), (2, 5)]))
+                .await?;
+            builder.finalize().await?;
+
+            Ok::<_, io::Error>(())
+        })
+        .unwrap();
+
+        let bitfile_contents = block_on(bitfile.map()).unwrap();
+        let bitindex_blocks_contents = block_on(bitindex_blocks_file.map()).unwrap();
+        let bitindex_sblocks_contents = block_on(bitindex_sblocks_file.map()).unwrap();
+
+        let short_nums = MemoryBackedStore::new();
+        let mut nums_builder = LogArrayFileBuilder::new(short_nums.open_write(), 8);
+        block_on(async {
+            nums_builder.push_vec(vec![1, 3]).await?;
+            nums_builder.finalize().await?;
+
+            Ok::<_, io::Error>(())
+        })
+        .unwrap();
+
+        assert_eq!(
+            Err(AdjacencyListError::UnexpectedLength(2, 3)),
+            AdjacencyList::parse(
+                block_on(short_nums.map()).unwrap(),
+                bitfile_contents,
+                bitindex_blocks_contents,
+                bitindex_sblocks_contents,
+            )
+            .map(|_| ())
+        );
+    }
 }
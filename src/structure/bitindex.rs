@@ -9,6 +9,7 @@ use crate::storage::SyncableFile;
 
 use futures::io;
 use futures::stream::StreamExt;
+use std::{error, fmt};
 use tokio::io::AsyncRead;
 
 // a block is 64 bit, which is the register size on modern architectures
@@ -25,13 +26,81 @@ pub struct BitIndex {
     sblocks: LogArray,
 }
 
-impl BitIndex {
-    pub fn from_maps(bitarray_map: Bytes, blocks_map: Bytes, sblocks_map: Bytes) -> BitIndex {
-        let bitarray = BitArray::from_bits(bitarray_map).unwrap();
-        let blocks_logarray = LogArray::parse(blocks_map).unwrap();
-        let sblocks_logarray = LogArray::parse(sblocks_map).unwrap();
+/// An error that occurred while loading a bit index.
+#[derive(Debug, PartialEq)]
+pub enum BitIndexError {
+    BitArray(BitArrayError),
+    LogArray(LogArrayError),
+    UnexpectedBlockCount(usize, usize),
+    UnexpectedSblockCount(usize, usize),
+    InvalidBlockRank(usize),
+    InvalidSblockRank(usize),
+}
+
+impl fmt::Display for BitIndexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use BitIndexError::*;
+        match self {
+            BitArray(err) => write!(f, "invalid bit array: {}", err),
+            LogArray(err) => write!(f, "invalid index: {}", err),
+            UnexpectedBlockCount(count, expected) => {
+                write!(f, "expected block count ({}) to be {}", count, expected)
+            }
+            UnexpectedSblockCount(count, expected) => {
+                write!(
+                    f,
+                    "expected superblock count ({}) to be {}",
+                    count, expected
+                )
+            }
+            InvalidBlockRank(index) => write!(f, "block {} has an invalid rank", index),
+            InvalidSblockRank(index) => write!(f, "superblock {} has an invalid rank", index),
+        }
+    }
+}
+
+impl error::Error for BitIndexError {}
+
+impl From<BitArrayError> for BitIndexError {
+    fn from(err: BitArrayError) -> BitIndexError {
+        BitIndexError::BitArray(err)
+    }
+}
+
+impl From<LogArrayError> for BitIndexError {
+    fn from(err: LogArrayError) -> BitIndexError {
+        BitIndexError::LogArray(err)
+    }
+}
 
-        BitIndex::from_parts(bitarray, blocks_logarray, sblocks_logarray)
+impl From<BitIndexError> for io::Error {
+    fn from(err: BitIndexError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+impl BitIndex {
+    /// Construct a `BitIndex` by parsing the buffers of a bitarray and its index.
+    ///
+    /// The index is checked against the bitarray, so that a corrupt
+    /// index results in an error rather than in wrong answers or
+    /// panics later on.
+    pub fn from_maps(
+        bitarray_map: Bytes,
+        blocks_map: Bytes,
+        sblocks_map: Bytes,
+    ) -> Result<BitIndex, BitIndexError> {
+        let bitarray = BitArray::from_bits(bitarray_map)?;
+        let blocks_logarray = LogArray::parse(blocks_map)?;
+        let sblocks_logarray = LogArray::parse(sblocks_map)?;
+
+        validate_index(&bitarray, &blocks_logarray, &sblocks_logarray)?;
+
+        Ok(BitIndex::from_parts(
+            bitarray,
+            blocks_logarray,
+            sblocks_logarray,
+        ))
     }
 
     pub fn from_parts(array: BitArray, blocks: LogArray, sblocks: LogArray) -> BitIndex {
@@ -56,6 +125,11 @@ impl BitIndex {
         self.array.len()
     }
 
+    /// Returns `true` if the underlying bitarray is empty.
+    pub fn is_empty(&self) -> bool {
+        self.array.is_empty()
+    }
+
     /// Returns the bit at the given index.
     pub fn get(&self, index: u64) -> bool {
         self.array.get(index as usize)
@@ -316,6 +390,59 @@ impl BitIndex {
     }
 }
 
+/// Check that the block and superblock ranks match the bitarray they index.
+///
+/// This recalculates the ranks the same way `build_bitindex` does.
+fn validate_index(
+    array: &BitArray,
+    blocks: &LogArray,
+    sblocks: &LogArray,
+) -> Result<(), BitIndexError> {
+    let expected_blocks = array.len().div_ceil(64);
+    if blocks.len() != expected_blocks {
+        return Err(BitIndexError::UnexpectedBlockCount(
+            blocks.len(),
+            expected_blocks,
+        ));
+    }
+
+    let expected_sblocks = expected_blocks.div_ceil(SBLOCK_SIZE);
+    if sblocks.len() != expected_sblocks {
+        return Err(BitIndexError::UnexpectedSblockCount(
+            sblocks.len(),
+            expected_sblocks,
+        ));
+    }
+
+    let bits = array.bits();
+    let mut sblock_rank = 0;
+    for sblock_index in 0..expected_sblocks {
+        let start = sblock_index * SBLOCK_SIZE;
+        let end = std::cmp::min(start + SBLOCK_SIZE, expected_blocks);
+        let block_ranks: Vec<u64> = (start..end)
+            .map(|block_index| {
+                BigEndian::read_u64(&bits[block_index * 8..block_index * 8 + 8]).count_ones() as u64
+            })
+            .collect();
+
+        let mut sblock_subrank: u64 = block_ranks.iter().sum();
+        sblock_rank += sblock_subrank;
+
+        for (block_index, block_rank) in (start..end).zip(block_ranks) {
+            if blocks.entry(block_index) != sblock_subrank {
+                return Err(BitIndexError::InvalidBlockRank(block_index));
+            }
+            sblock_subrank -= block_rank;
+        }
+
+        if sblocks.entry(sblock_index) != sblock_rank {
+            return Err(BitIndexError::InvalidSblockRank(sblock_index));
+        }
+    }
+
+    Ok(())
+}
+
 pub async fn build_bitindex<
     R: 'static + AsyncRead + Unpin + Send,
     W1: 'static + SyncableFile + Send,
@@ -392,7 +519,8 @@ mod tests {
             block_on(bits.map()).unwrap(),
             block_on(index_blocks.map()).unwrap(),
             block_on(index_sblocks.map()).unwrap(),
-        );
+        )
+        .unwrap();
 
         for i in 0..123456 {
             assert_eq!(i / 3 + 1, index.rank1(i));
@@ -426,7 +554,8 @@ mod tests {
             block_on(bits.map()).unwrap(),
             block_on(index_blocks.map()).unwrap(),
             block_on(index_sblocks.map()).unwrap(),
-        );
+        )
+        .unwrap();
 
         for i in 1..(123456 / 3) {
             assert_eq!((i - 1) * 3, index.select1(i).unwrap());
@@ -462,7 +591,8 @@ mod tests {
             block_on(bits.map()).unwrap(),
             block_on(index_blocks.map()).unwrap(),
             block_on(index_sblocks.map()).unwrap(),
-        );
+        )
+        .unwrap();
 
         assert_eq!(0, index.rank1_from_range(6, 6));
         assert_eq!(1, index.rank1_from_range(6, 7));
@@ -498,7 +628,8 @@ mod tests {
             block_on(bits.map()).unwrap(),
             block_on(index_blocks.map()).unwrap(),
             block_on(index_sblocks.map()).unwrap(),
-        );
+        )
+        .unwrap();
 
         assert_eq!(None, index.select1_from_range(0, 6, 6));
         assert_eq!(None, index.select1_from_range(0, 6, 7));
@@ -535,7 +666,8 @@ mod tests {
             block_on(bits.map()).unwrap(),
             block_on(index_blocks.map()).unwrap(),
             block_on(index_sblocks.map()).unwrap(),
-        );
+        )
+        .unwrap();
 
         for i in 0..123456 {
             assert_eq!(1 + i - (i / 3 + 1), index.rank0(i));
@@ -569,7 +701,8 @@ mod tests {
             block_on(bits.map()).unwrap(),
             block_on(index_blocks.map()).unwrap(),
             block_on(index_sblocks.map()).unwrap(),
-        );
+        )
+        .unwrap();
 
         for i in 1..=(123456 * 2 / 3) {
             assert_eq!(i + (i - 1) / 2, index.select0(i).unwrap());
@@ -605,7 +738,8 @@ mod tests {
             block_on(bits.map()).unwrap(),
             block_on(index_blocks.map()).unwrap(),
             block_on(index_sblocks.map()).unwrap(),
-        );
+        )
+        .unwrap();
 
         assert_eq!(0, index.rank0_from_range(5, 5));
         assert_eq!(1, index.rank0_from_range(5, 6));
@@ -642,7 +776,8 @@ mod tests {
             block_on(bits.map()).unwrap(),
             block_on(index_blocks.map()).unwrap(),
             block_on(index_sblocks.map()).unwrap(),
-        );
+        )
+        .unwrap();
 
         assert_eq!(None, index.select0_from_range(0, 6, 6));
         assert_eq!(Some(6), index.select0_from_range(0, 6, 7));
@@ -652,4 +787,64 @@ mod tests {
         assert_eq!(Some(10), index.select0_from_range(4, 5, 11));
         assert_eq!(None, index.select0_from_range(123456, 5, 10));
     }
+
+    #[test]
+    fn from_maps_with_corrupt_index_errors() {
+        let bits = MemoryBackedStore::new();
+        let mut ba_builder = BitArrayFileBuilder::new(bits.open_write());
+        let contents = (0..).map(|n| n % 3 == 0).take(1234);
+
+        block_on(async {
+            ba_builder.push_all(stream_iter_ok(contents)).await?;
+            ba_builder.finalize().await?;
+
+            Ok::<_, io::Error>(())
+        })
+        .unwrap();
+
+        let index_blocks = MemoryBackedStore::new();
+        let index_sblocks = MemoryBackedStore::new();
+        block_on(build_bitindex(
+            bits.open_read(),
+            index_blocks.open_write(),
+            index_sblocks.open_write(),
+        ))
+        .unwrap();
+
+        let bits_map = block_on(bits.map()).unwrap();
+        let blocks_map = block_on(index_blocks.map()).unwrap();
+        let sblocks_map = block_on(index_sblocks.map()).unwrap();
+
+        assert!(
+            BitIndex::from_maps(bits_map.clone(), blocks_map.clone(), sblocks_map.clone()).is_ok()
+        );
+
+        let mut corrupt_bits = bits_map.to_vec();
+        corrupt_bits[0] ^= 0b0100_0000;
+        assert_eq!(
+            Err(BitIndexError::InvalidBlockRank(0)),
+            BitIndex::from_maps(
+                Bytes::from(corrupt_bits),
+                blocks_map.clone(),
+                sblocks_map.clone()
+            )
+            .map(|_| ())
+        );
+
+        assert_eq!(
+            Err(BitIndexError::UnexpectedBlockCount(20, 0)),
+            BitIndex::from_maps(Bytes::from(vec![0u8; 8]), blocks_map.clone(), sblocks_map)
+                .map(|_| ())
+        );
+
+        assert_eq!(
+            Err(BitIndexError::UnexpectedSblockCount(0, 1)),
+            BitIndex::from_maps(
+                bits_map,
+                blocks_map,
+                Bytes::from(vec![0, 0, 0, 0, 64, 0, 0, 0])
+            )
+            .map(|_| ())
+        );
+    }
 }
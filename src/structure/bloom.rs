@@ -0,0 +1,160 @@
+//! A Bloom filter over 64-bit keys.
+//!
+//! A Bloom filter answers whether a key might be part of a set. It
+//! never gives a false negative, and gives a false positive for about
+//! one in a hundred keys that are not in the set.
+//!
+//! The filter is stored as a plain bit array. The amount of bits is
+//! chosen from the amount of keys the filter is built for, and the
+//! amount of hash functions is fixed, so the bit array is all there
+//! is to store.
+use super::bitarray::*;
+use super::util;
+use crate::storage::*;
+use bytes::Bytes;
+use std::io;
+use tokio::io::AsyncWriteExt;
+
+/// The amount of bits set for every key.
+const BLOOM_HASH_COUNT: u64 = 7;
+/// The amount of bits in the filter for every key, which together with `BLOOM_HASH_COUNT` gives a false positive rate of about 1%.
+const BLOOM_BITS_PER_KEY: u64 = 10;
+
+/// Mix the bits of the given number, using the finalizer of splitmix64.
+pub fn mix_u64(mut x: u64) -> u64 {
+    x ^= x >> 30;
+    x = x.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x ^= x >> 27;
+    x = x.wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^= x >> 31;
+
+    x
+}
+
+/// Returns the positions of the bits that are set for the given key, in a filter of `len` bits.
+fn bit_positions(key: u64, len: u64) -> impl Iterator<Item = u64> {
+    let h1 = mix_u64(key);
+    let h2 = mix_u64(h1) | 1;
+
+    (0..BLOOM_HASH_COUNT).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % len)
+}
+
+/// A Bloom filter, loaded from a bit array.
+#[derive(Clone)]
+pub struct BloomFilter {
+    bits: BitArray,
+}
+
+impl BloomFilter {
+    /// Parse a Bloom filter from a bit array buffer.
+    pub fn parse(buf: Bytes) -> Result<BloomFilter, BitArrayError> {
+        let bits = BitArray::from_bits(buf)?;
+
+        Ok(BloomFilter { bits })
+    }
+
+    /// Returns false if the given key is definitely not in the filter, and true if it might be.
+    pub fn may_contain(&self, key: u64) -> bool {
+        let len = self.bits.len() as u64;
+        if len == 0 {
+            return false;
+        }
+
+        bit_positions(key, len).all(|pos| self.bits.get(pos as usize))
+    }
+}
+
+/// A builder for a Bloom filter.
+///
+/// The size of the filter is chosen from the amount of keys it is
+/// going to hold, which has to be known up front. Adding more keys
+/// than that raises the false positive rate.
+pub struct BloomFilterBuilder {
+    len: u64,
+    words: Vec<u64>,
+    count: usize,
+}
+
+impl BloomFilterBuilder {
+    /// Create a builder for a filter holding the given amount of keys.
+    pub fn new(key_count: usize) -> Self {
+        // round up to whole words, so that a filter is never empty
+        let len = ((key_count as u64 * BLOOM_BITS_PER_KEY) / 64 + 1) * 64;
+
+        Self {
+            len,
+            words: vec![0; (len / 64) as usize],
+            count: 0,
+        }
+    }
+
+    /// Add the given key to the filter.
+    pub fn add(&mut self, key: u64) {
+        for pos in bit_positions(key, self.len) {
+            self.words[(pos / 64) as usize] |= 0x8000_0000_0000_0000 >> (pos % 64);
+        }
+        self.count += 1;
+    }
+
+    /// The amount of keys added so far.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Returns true if no keys were added yet.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Write the filter as a bit array to the given file.
+    pub async fn finalize<F: FileStore>(self, file: F) -> io::Result<()> {
+        let mut writer = file.open_write();
+        for word in self.words {
+            util::write_u64(&mut writer, word).await?;
+        }
+        // the control word of the bit array
+        util::write_u64(&mut writer, self.len).await?;
+        writer.flush().await?;
+        writer.sync_all().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::*;
+
+    #[tokio::test]
+    async fn filter_contains_added_keys() {
+        let mut builder = BloomFilterBuilder::new(1000);
+        for key in (0..1000).map(|i| i * 3) {
+            builder.add(key);
+        }
+        let file = MemoryBackedStore::new();
+        builder.finalize(file.clone()).await.unwrap();
+
+        let filter = BloomFilter::parse(file.map().await.unwrap()).unwrap();
+        for key in (0..1000).map(|i| i * 3) {
+            assert!(filter.may_contain(key));
+        }
+
+        let false_positives = (0..1000)
+            .map(|i| i * 3 + 1)
+            .filter(|key| filter.may_contain(*key))
+            .count();
+        assert!(false_positives < 30);
+    }
+
+    #[tokio::test]
+    async fn empty_filter_contains_nothing() {
+        let file = MemoryBackedStore::new();
+        BloomFilterBuilder::new(0)
+            .finalize(file.clone())
+            .await
+            .unwrap();
+
+        let filter = BloomFilter::parse(file.map().await.unwrap()).unwrap();
+        assert!(!filter.may_contain(0));
+        assert!(!filter.may_contain(42));
+    }
+}
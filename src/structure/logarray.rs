@@ -93,6 +93,7 @@ pub struct LogArray {
 pub enum LogArrayError {
     InputBufferTooSmall(usize),
     WidthTooLarge(u8),
+    WidthTooSmall(u32),
     UnexpectedInputBufferSize(u64, u64, u32, u8),
 }
 
@@ -109,7 +110,8 @@ impl LogArrayError {
 
     /// Validate the number of elements and bit width against the input buffer size.
     ///
-    /// The bit width should no greater than 64 since each word is 64 bits.
+    /// The bit width should no greater than 64 since each word is 64 bits. It should also be
+    /// greater than 0 if there are any elements, since elements cannot be decoded otherwise.
     ///
     /// The input buffer size should be the appropriate multiple of 8 to include the exact number
     /// of encoded elements plus the control word.
@@ -118,6 +120,10 @@ impl LogArrayError {
             return Err(LogArrayError::WidthTooLarge(width));
         }
 
+        if width == 0 && len != 0 {
+            return Err(LogArrayError::WidthTooSmall(len));
+        }
+
         // Calculate the expected input buffer size. This includes the control word.
         // To avoid overflow, convert `len: u32` to `u64` and do the addition in `u64`.
         let expected_buf_size = u64::from(len) * u64::from(width) + 127 >> 6 << 3;
@@ -144,6 +150,7 @@ impl fmt::Display for LogArrayError {
                 write!(f, "expected input buffer size ({}) >= 8", input_buf_size)
             }
             WidthTooLarge(width) => write!(f, "expected width ({}) <= 64", width),
+            WidthTooSmall(len) => write!(f, "expected width > 0 for {} elements", len),
             UnexpectedInputBufferSize(input_buf_size, expected_buf_size, len, width) => write!(
                 f,
                 "expected input buffer size ({}) to be {} for {} elements and width {}",
@@ -694,6 +701,10 @@ mod tests {
             "expected width (69) <= 64",
             LogArrayError::WidthTooLarge(69).to_string()
         );
+        assert_eq!(
+            "expected width > 0 for 3 elements",
+            LogArrayError::WidthTooSmall(3).to_string()
+        );
         assert_eq!(
             "expected input buffer size (9) to be 8 for 0 elements and width 17",
             LogArrayError::UnexpectedInputBufferSize(9, 8, 0, 17).to_string()
@@ -738,6 +749,8 @@ mod tests {
 
         // width: 0
         assert_eq!(err(0, 8, 0, 0), val(0, 0, 0));
+        assert_eq!(Ok(()), val(8, 0, 0));
+        assert_eq!(Err(LogArrayError::WidthTooSmall(1)), val(8, 1, 0));
 
         // width: 1
         assert_eq!(Ok(()), val(8, 0, 1));
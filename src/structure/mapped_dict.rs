@@ -1,14 +1,20 @@
-#![allow(unused)]
-
-use super::bitindex::*;
+//! Dictionaries with remapped ids, and the merging of dictionary stacks.
+//!
+//! Merging a stack of dictionaries results in a single sorted
+//! dictionary. The ids that entries had in the stack are generally not
+//! the same as their position in the merged dictionary, so a merge also
+//! returns the mapping between the two.
 use super::pfc::*;
 use super::util::*;
 use super::wavelettree::*;
 use crate::storage::*;
-use futures::future;
-use futures::prelude::*;
 use std::io;
 
+/// A dictionary whose ids are remapped through a wavelet tree.
+///
+/// The wavelet tree holds the id of every entry, in dictionary order,
+/// as returned by `merge_dictionary_stack`.
+#[derive(Clone)]
 pub struct MappedPfcDict {
     inner: PfcDict,
     id_wtree: Option<WaveletTree>,
@@ -26,6 +32,10 @@ impl MappedPfcDict {
         self.inner.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.inner.len() == 0
+    }
+
     pub fn get(&self, index: usize) -> Option<String> {
         if index < self.len() {
             let mapped_id = self
@@ -47,111 +57,56 @@ impl MappedPfcDict {
                 .unwrap_or(mapped_id)
         })
     }
+
+    /// Returns all entries in dictionary order, along with their id.
+    pub fn entries(&self) -> impl Iterator<Item = (u64, PfcDictEntry)> + Send {
+        let id_wtree = self.id_wtree.clone();
+        self.inner.entries().enumerate().map(move |(i, e)| {
+            let id = id_wtree
+                .as_ref()
+                .map(|wtree| wtree.decode_one(i))
+                .unwrap_or(i as u64);
+
+            (id, e)
+        })
+    }
 }
 
-pub fn merge_dictionary_stack<F: 'static + FileLoad + FileStore>(
-    stack: Vec<(F, Option<BitIndexFiles<F>>)>,
+/// Merge a stack of dictionaries into a single dictionary, leaving out the entries whose id is rejected by `keep`.
+///
+/// Every dictionary in the stack is given by its entries in sorted
+/// order, along with the id that each entry has in the stack. No entry
+/// may appear in more than one dictionary.
+///
+/// The result is the stack id of every entry in the merged dictionary,
+/// in dictionary order.
+pub async fn merge_dictionary_stack<
+    F: 'static + FileLoad + FileStore,
+    I: 'static + Iterator<Item = (u64, PfcDictEntry)> + Send,
+>(
+    stack: Vec<I>,
+    keep: impl Fn(u64) -> bool,
     dict_files: DictionaryFiles<F>,
-    wavelet_files: BitIndexFiles<F>,
-) -> impl Future<Item = (), Error = io::Error> + Send {
-    let dict_builder = PfcDictFileBuilder::new(
+) -> io::Result<Vec<u64>> {
+    let pick_fn = |vals: &[Option<&(u64, PfcDictEntry)>]| {
+        vals.iter()
+            .enumerate()
+            .filter(|(_, x)| x.is_some())
+            .min_by(|(_, x), (_, y)| x.unwrap().1.cmp(&y.unwrap().1))
+            .map(|x| x.0)
+    };
+    let (ids, entries): (Vec<_>, Vec<_>) = sorted_iterator(stack, pick_fn)
+        .filter(|(id, _)| keep(*id))
+        .unzip();
+
+    let mut builder = PfcDictFileBuilder::new(
         dict_files.blocks_file.open_write(),
         dict_files.offsets_file.open_write(),
     );
+    builder.add_all_entries(entries.into_iter()).await?;
+    builder.finalize().await?;
 
-    future::join_all(
-        stack
-            .clone()
-            .into_iter()
-            .map(|(f, _remap)| dict_file_get_count(f)),
-    )
-    .and_then(|counts: Vec<u64>| {
-        futures::stream::iter_ok(
-            counts
-                .into_iter()
-                .scan(0, |mut tally, c| {
-                    let prev = *tally;
-                    *tally += c;
-
-                    Some(prev)
-                })
-                .zip(stack.into_iter()),
-        )
-        .and_then(|(offset, (file, remap))| {
-            // TODO this is where we should possibly apply a remapping
-            match remap {
-                None => {
-                    let dict_stream = dict_reader_to_stream(file.open_read());
-                    let count_stream = futures::stream::unfold(offset, |c| Some(Ok((c, c + 1))));
-                    future::Either::A(future::ok(Box::new(count_stream.zip(dict_stream))
-                        as Box<dyn Stream<Item = (u64, String), Error = _> + Send>))
-                }
-                Some(remap) => {
-                    future::Either::B(
-                        dict_file_get_count(file.clone())
-                            .map(|count| (count as f32).log2().ceil() as u8)
-                            .and_then(move |width| {
-                                future::join_all(vec![
-                                    remap.bits_file.map(),
-                                    remap.blocks_file.map(),
-                                    remap.sblocks_file.map(),
-                                ])
-                                .map(|maps| {
-                                    BitIndex::from_maps(
-                                        maps[0].clone(),
-                                        maps[1].clone(),
-                                        maps[2].clone(),
-                                    )
-                                })
-                                .map(move |bi| WaveletTree::from_parts(bi, width))
-                            })
-                            .map(move |wtree| {
-                                let dict_stream = dict_reader_to_stream(file.open_read());
-                                //let count_stream = futures::stream::unfold(offset, |c| Some(Ok((c, c+1))));
-                                let count_stream = futures::stream::iter_ok(wtree.decode());
-                                Box::new(count_stream.zip(dict_stream))
-                                    as Box<dyn Stream<Item = (u64, String), Error = _> + Send>
-                            }),
-                    )
-                }
-            }
-
-            //dict_reader_to_indexed_stream(file.open_read(), offset)
-        })
-        .collect()
-    })
-    .map(|streams: Vec<_>| {
-        sorted_stream(streams, |results| {
-            results
-                .iter()
-                .enumerate()
-                .filter(|&(_, item)| item.is_some())
-                .min_by_key(|&(a, item)| &item.unwrap().1)
-                .map(|x| x.0)
-        })
-    })
-    .and_then(|stream| {
-        stream.fold(
-            (dict_builder, Vec::new()),
-            |(builder, mut indexes), (ix, s)| {
-                indexes.push(ix);
-                builder.add(&s).map(|(_, b)| (b, indexes))
-            },
-        )
-    })
-    .and_then(|(builder, indexes)| {
-        let f1 = builder.finalize();
-        let max = indexes.iter().max().map(|x| *x).unwrap_or(0) + 1;
-        let width = (max as f32).log2().ceil() as u8;
-        let f2 = build_wavelet_tree_from_iter(
-            width,
-            indexes.into_iter(),
-            wavelet_files.bits_file,
-            wavelet_files.blocks_file,
-            wavelet_files.sblocks_file,
-        );
-        f1.join(f2).map(|_| ())
-    })
+    Ok(ids)
 }
 
 #[cfg(test)]
@@ -160,255 +115,203 @@ mod tests {
     use crate::storage::memory::*;
     use crate::structure::bitindex::*;
 
-    #[test]
-    fn mapped_dict_that_wraps_normal_dict_without_mapping() {
-        let contents = vec![
-            "aaaaa",
-            "abcdefghijk",
-            "arf",
-            "bapofsi",
-            "berf",
-            "bzwas baraf",
-            "eadfpoicvu",
-            "faadsafdfaf sdfasdf",
-            "gahh",
-        ];
-
+    const CONTENTS1: [&str; 9] = [
+        "aaaaa",
+        "abcdefghijk",
+        "arf",
+        "bapofsi",
+        "berf",
+        "bzwas baraf",
+        "eadfpoicvu",
+        "faadsafdfaf sdfasdf",
+        "gahh",
+    ];
+
+    const CONTENTS2: [&str; 9] = [
+        "aaaaaaaaaa",
+        "aaaabbbbbb",
+        "addeeerafa",
+        "barf",
+        "boo boo boo boo",
+        "dradsfadfvbbb",
+        "eeeee ee e eee",
+        "frumps framps fremps",
+        "hai hai hai",
+    ];
+
+    async fn build_dict(contents: &'static [&'static str]) -> PfcDict {
         let blocks = MemoryBackedStore::new();
         let offsets = MemoryBackedStore::new();
-        let builder = PfcDictFileBuilder::new(blocks.open_write(), offsets.open_write());
+        let mut builder = PfcDictFileBuilder::new(blocks.open_write(), offsets.open_write());
+        builder.add_all(contents.iter().copied()).await.unwrap();
+        builder.finalize().await.unwrap();
 
-        builder
-            .add_all(contents.clone().into_iter().map(|s| s.to_string()))
-            .and_then(|(_, b)| b.finalize())
-            .wait()
-            .unwrap();
-
-        let dict =
-            PfcDict::parse(blocks.map().wait().unwrap(), offsets.map().wait().unwrap()).unwrap();
-
-        let mapped_dict = MappedPfcDict::from_parts(dict, None);
-
-        for i in 0..contents.len() {
-            let s = mapped_dict.get(i).unwrap();
-            assert_eq!(contents[i], s);
-            let id = mapped_dict.id(&s).unwrap();
-            assert_eq!(i as u64, id);
-        }
+        PfcDict::parse(blocks.map().await.unwrap(), offsets.map().await.unwrap()).unwrap()
     }
 
-    #[test]
-    fn create_and_query_mapped_dict() {
-        let contents1 = vec![
-            "aaaaa",
-            "abcdefghijk",
-            "arf",
-            "bapofsi",
-            "berf",
-            "bzwas baraf",
-            "eadfpoicvu",
-            "faadsafdfaf sdfasdf",
-            "gahh",
-        ];
-
-        let contents2 = vec![
-            "aaaaaaaaaa",
-            "aaaabbbbbb",
-            "addeeerafa",
-            "barf",
-            "boo boo boo boo",
-            "dradsfadfvbbb",
-            "eeeee ee e eee",
-            "frumps framps fremps",
-            "hai hai hai",
-        ];
-
-        let blocks1 = MemoryBackedStore::new();
-        let offsets1 = MemoryBackedStore::new();
-        let builder1 = PfcDictFileBuilder::new(blocks1.open_write(), offsets1.open_write());
-
-        builder1
-            .add_all(contents1.clone().into_iter().map(|s| s.to_string()))
-            .and_then(|(_, b)| b.finalize())
-            .wait()
-            .unwrap();
-
-        let blocks2 = MemoryBackedStore::new();
-        let offsets2 = MemoryBackedStore::new();
-        let builder2 = PfcDictFileBuilder::new(blocks2.open_write(), offsets2.open_write());
-
-        builder2
-            .add_all(contents2.clone().into_iter().map(|s| s.to_string()))
-            .and_then(|(_, b)| b.finalize())
-            .wait()
-            .unwrap();
-
-        let dict3_files = DictionaryFiles {
+    fn dictionary_files() -> DictionaryFiles<MemoryBackedStore> {
+        DictionaryFiles {
             blocks_file: MemoryBackedStore::new(),
             offsets_file: MemoryBackedStore::new(),
-        };
+        }
+    }
+
+    async fn load_mapped_dict(
+        dict_files: DictionaryFiles<MemoryBackedStore>,
+        ids: Vec<u64>,
+    ) -> MappedPfcDict {
+        let dict = PfcDict::parse(
+            dict_files.blocks_file.map().await.unwrap(),
+            dict_files.offsets_file.map().await.unwrap(),
+        )
+        .unwrap();
+
+        let width = calculate_width(ids.len() as u64);
         let wavelet_files = BitIndexFiles {
             bits_file: MemoryBackedStore::new(),
             blocks_file: MemoryBackedStore::new(),
             sblocks_file: MemoryBackedStore::new(),
         };
-
-        merge_dictionary_stack(
-            vec![(blocks1, None), (blocks2, None)],
-            dict3_files.clone(),
-            wavelet_files.clone(),
+        build_wavelet_tree_from_iter(
+            width,
+            ids.into_iter(),
+            wavelet_files.bits_file.clone(),
+            wavelet_files.blocks_file.clone(),
+            wavelet_files.sblocks_file.clone(),
         )
-        .wait()
+        .await
         .unwrap();
-
-        let dict = PfcDict::parse(
-            dict3_files.blocks_file.map().wait().unwrap(),
-            dict3_files.offsets_file.map().wait().unwrap(),
+        let wavelet_bitindex = BitIndex::from_maps(
+            wavelet_files.bits_file.map().await.unwrap(),
+            wavelet_files.blocks_file.map().await.unwrap(),
+            wavelet_files.sblocks_file.map().await.unwrap(),
         )
         .unwrap();
-        let wavelet_bitindex = BitIndex::from_maps(
-            wavelet_files.bits_file.map().wait().unwrap(),
-            wavelet_files.blocks_file.map().wait().unwrap(),
-            wavelet_files.sblocks_file.map().wait().unwrap(),
-        );
-        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 5);
+        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, width).unwrap();
 
-        let mapped_dict = MappedPfcDict::from_parts(dict, Some(wavelet_tree));
+        MappedPfcDict::from_parts(dict, Some(wavelet_tree))
+    }
 
-        let mut total_contents = Vec::with_capacity(contents1.len() + contents2.len());
-        total_contents.extend(contents1);
-        total_contents.extend(contents2);
+    #[tokio::test]
+    async fn mapped_dict_that_wraps_normal_dict_without_mapping() {
+        let dict = build_dict(&CONTENTS1).await;
+        let mapped_dict = MappedPfcDict::from_parts(dict, None);
 
-        for i in 0..total_contents.len() {
+        for (i, expected) in CONTENTS1.iter().enumerate() {
             let s = mapped_dict.get(i).unwrap();
-            assert_eq!(total_contents[i], s);
+            assert_eq!(*expected, s);
             let id = mapped_dict.id(&s).unwrap();
             assert_eq!(i as u64, id);
         }
     }
 
-    #[test]
-    fn create_from_mapped_dict_and_query_mapped_dict() {
-        let contents1 = vec![
-            "aaaaa",
-            "abcdefghijk",
-            "arf",
-            "bapofsi",
-            "berf",
-            "bzwas baraf",
-            "eadfpoicvu",
-            "faadsafdfaf sdfasdf",
-            "gahh",
-        ];
-
-        let contents2 = vec![
-            "aaaaaaaaaa",
-            "aaaabbbbbb",
-            "addeeerafa",
-            "barf",
-            "boo boo boo boo",
-            "dradsfadfvbbb",
-            "eeeee ee e eee",
-            "frumps framps fremps",
-            "hai hai hai",
-        ];
-
-        let contents3 = vec!["berlin", "dodo", "fragile"];
-
-        let blocks1 = MemoryBackedStore::new();
-        let offsets1 = MemoryBackedStore::new();
-        let builder1 = PfcDictFileBuilder::new(blocks1.open_write(), offsets1.open_write());
-
-        builder1
-            .add_all(contents1.clone().into_iter().map(|s| s.to_string()))
-            .and_then(|(_, b)| b.finalize())
-            .wait()
-            .unwrap();
-
-        let blocks2 = MemoryBackedStore::new();
-        let offsets2 = MemoryBackedStore::new();
-        let builder2 = PfcDictFileBuilder::new(blocks2.open_write(), offsets2.open_write());
-
-        builder2
-            .add_all(contents2.clone().into_iter().map(|s| s.to_string()))
-            .and_then(|(_, b)| b.finalize())
-            .wait()
-            .unwrap();
-
-        let blocks3 = MemoryBackedStore::new();
-        let offsets3 = MemoryBackedStore::new();
-        let builder3 = PfcDictFileBuilder::new(blocks3.open_write(), offsets3.open_write());
-
-        builder3
-            .add_all(contents3.clone().into_iter().map(|s| s.to_string()))
-            .and_then(|(_, b)| b.finalize())
-            .wait()
-            .unwrap();
-
-        let dict4_files = DictionaryFiles {
-            blocks_file: MemoryBackedStore::new(),
-            offsets_file: MemoryBackedStore::new(),
-        };
-        let wavelet4_files = BitIndexFiles {
-            bits_file: MemoryBackedStore::new(),
-            blocks_file: MemoryBackedStore::new(),
-            sblocks_file: MemoryBackedStore::new(),
-        };
+    #[tokio::test]
+    async fn create_and_query_mapped_dict() {
+        let dict1 = MappedPfcDict::from_parts(build_dict(&CONTENTS1).await, None);
+        let dict2 = MappedPfcDict::from_parts(build_dict(&CONTENTS2).await, None);
+        let offset = dict1.len() as u64;
 
-        merge_dictionary_stack(
-            vec![(blocks1, None), (blocks2, None)],
-            dict4_files.clone(),
-            wavelet4_files.clone(),
+        let dict3_files = dictionary_files();
+        let ids = merge_dictionary_stack(
+            vec![
+                Box::new(dict1.entries()) as Box<dyn Iterator<Item = _> + Send>,
+                Box::new(dict2.entries().map(move |(id, e)| (id + offset, e))),
+            ],
+            |_| true,
+            dict3_files.clone(),
         )
-        .wait()
+        .await
         .unwrap();
 
-        let dict5_files = DictionaryFiles {
-            blocks_file: MemoryBackedStore::new(),
-            offsets_file: MemoryBackedStore::new(),
-        };
-        let wavelet5_files = BitIndexFiles {
-            bits_file: MemoryBackedStore::new(),
-            blocks_file: MemoryBackedStore::new(),
-            sblocks_file: MemoryBackedStore::new(),
-        };
+        let mapped_dict = load_mapped_dict(dict3_files, ids).await;
 
-        merge_dictionary_stack(
+        let total_contents: Vec<_> = CONTENTS1.iter().chain(CONTENTS2.iter()).collect();
+        for (i, expected) in total_contents.into_iter().enumerate() {
+            let s = mapped_dict.get(i).unwrap();
+            assert_eq!(*expected, s);
+            let id = mapped_dict.id(&s).unwrap();
+            assert_eq!(i as u64, id);
+        }
+    }
+
+    #[tokio::test]
+    async fn create_from_mapped_dict_and_query_mapped_dict() {
+        const CONTENTS3: [&str; 3] = ["berlin", "dodo", "fragile"];
+
+        let dict1 = MappedPfcDict::from_parts(build_dict(&CONTENTS1).await, None);
+        let dict2 = MappedPfcDict::from_parts(build_dict(&CONTENTS2).await, None);
+        let dict3 = MappedPfcDict::from_parts(build_dict(&CONTENTS3).await, None);
+        let offset = dict1.len() as u64;
+
+        let dict4_files = dictionary_files();
+        let ids = merge_dictionary_stack(
             vec![
-                (dict4_files.blocks_file, Some(wavelet4_files)),
-                (blocks3, None),
+                Box::new(dict1.entries()) as Box<dyn Iterator<Item = _> + Send>,
+                Box::new(dict2.entries().map(move |(id, e)| (id + offset, e))),
             ],
-            dict5_files.clone(),
-            wavelet5_files.clone(),
+            |_| true,
+            dict4_files.clone(),
         )
-        .wait()
+        .await
         .unwrap();
+        let dict4 = load_mapped_dict(dict4_files, ids).await;
 
-        let dict = PfcDict::parse(
-            dict5_files.blocks_file.map().wait().unwrap(),
-            dict5_files.offsets_file.map().wait().unwrap(),
+        let offset = dict4.len() as u64;
+        let dict5_files = dictionary_files();
+        let ids = merge_dictionary_stack(
+            vec![
+                Box::new(dict4.entries()) as Box<dyn Iterator<Item = _> + Send>,
+                Box::new(dict3.entries().map(move |(id, e)| (id + offset, e))),
+            ],
+            |_| true,
+            dict5_files.clone(),
         )
+        .await
         .unwrap();
-        let wavelet_bitindex = BitIndex::from_maps(
-            wavelet5_files.bits_file.map().wait().unwrap(),
-            wavelet5_files.blocks_file.map().wait().unwrap(),
-            wavelet5_files.sblocks_file.map().wait().unwrap(),
-        );
-        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 5);
-
-        let mapped_dict = MappedPfcDict::from_parts(dict, Some(wavelet_tree));
-
-        let mut total_contents =
-            Vec::with_capacity(contents1.len() + contents2.len() + contents3.len());
-        total_contents.extend(contents1);
-        total_contents.extend(contents2);
-        total_contents.extend(contents3);
-
-        for i in 0..total_contents.len() {
+        let mapped_dict = load_mapped_dict(dict5_files, ids).await;
+
+        let total_contents: Vec<_> = CONTENTS1
+            .iter()
+            .chain(CONTENTS2.iter())
+            .chain(CONTENTS3.iter())
+            .collect();
+        for (i, expected) in total_contents.into_iter().enumerate() {
             let s = mapped_dict.get(i).unwrap();
-            assert_eq!(total_contents[i], s);
+            assert_eq!(*expected, s);
             let id = mapped_dict.id(&s).unwrap();
             assert_eq!(i as u64, id);
         }
     }
+
+    #[tokio::test]
+    async fn merge_leaves_out_rejected_entries() {
+        let dict1 = MappedPfcDict::from_parts(build_dict(&CONTENTS1).await, None);
+        let dict2 = MappedPfcDict::from_parts(build_dict(&CONTENTS2).await, None);
+        let offset = dict1.len() as u64;
+
+        let dict3_files = dictionary_files();
+        let ids = merge_dictionary_stack(
+            vec![
+                Box::new(dict1.entries()) as Box<dyn Iterator<Item = _> + Send>,
+                Box::new(dict2.entries().map(move |(id, e)| (id + offset, e))),
+            ],
+            |id| id % 2 == 0,
+            dict3_files.clone(),
+        )
+        .await
+        .unwrap();
+
+        let dict = PfcDict::parse(
+            dict3_files.blocks_file.map().await.unwrap(),
+            dict3_files.offsets_file.map().await.unwrap(),
+        )
+        .unwrap();
+        let total_contents: Vec<_> = CONTENTS1.iter().chain(CONTENTS2.iter()).collect();
+        assert_eq!(9, dict.len());
+        assert_eq!(9, ids.len());
+        for (i, id) in ids.into_iter().enumerate() {
+            assert_eq!(0, id % 2);
+            assert_eq!(*total_contents[id as usize], dict.get(i).unwrap());
+        }
+    }
 }
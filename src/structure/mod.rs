@@ -6,8 +6,9 @@ pub mod adjacencylist;
 pub mod bitarray;
 pub mod bitindex;
 pub mod bititer;
+pub mod bloom;
 pub mod logarray;
-//pub mod mapped_dict;
+pub mod mapped_dict;
 pub mod pfc;
 pub mod util;
 pub mod vbyte;
@@ -16,6 +17,8 @@ pub mod wavelettree;
 pub use adjacencylist::*;
 pub use bitarray::*;
 pub use bitindex::*;
+pub use bloom::*;
 pub use logarray::*;
+pub use mapped_dict::*;
 pub use pfc::*;
 pub use wavelettree::*;
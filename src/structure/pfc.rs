@@ -120,15 +120,59 @@ impl Iterator for PfcBlockIterator {
     }
 }
 
+/// Check that the first `n_strings` entries in `data` form a properly encoded block.
+///
+/// Every entry has to be nul-terminated, every shared prefix has to
+/// fit in the string before it, and every string has to be valid
+/// utf8. On success, this returns the amount of bytes the block
+/// takes up.
+fn validate_block(data: &[u8], n_strings: usize) -> Result<usize, PfcError> {
+    let mut pos = 0;
+    let mut string: Vec<u8> = Vec::new();
+    for count in 0..n_strings {
+        let common = if count == 0 {
+            0
+        } else {
+            let (common, common_len) =
+                vbyte::decode(&data[pos..]).map_err(|_| PfcError::InvalidCoding)?;
+            pos += common_len;
+
+            common
+        };
+
+        if common > string.len() as u64 {
+            return Err(PfcError::InvalidCoding);
+        }
+
+        let postfix_len = data[pos..]
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(PfcError::NotEnoughData)?;
+
+        string.truncate(common as usize);
+        string.extend_from_slice(&data[pos..pos + postfix_len]);
+        if std::str::from_utf8(&string).is_err() {
+            return Err(PfcError::InvalidCoding);
+        }
+
+        pos += postfix_len + 1;
+    }
+
+    Ok(pos)
+}
+
 impl PfcBlock {
     pub fn parse(data: Bytes) -> Result<PfcBlock, PfcError> {
-        Ok(PfcBlock {
-            encoded_strings: data,
-            n_strings: BLOCK_SIZE,
-        })
+        Self::parse_incomplete(data, BLOCK_SIZE)
     }
 
     pub fn parse_incomplete(data: Bytes, n_strings: usize) -> Result<PfcBlock, PfcError> {
+        if n_strings == 0 || n_strings > BLOCK_SIZE {
+            return Err(PfcError::InvalidCoding);
+        }
+
+        validate_block(&data, n_strings)?;
+
         Ok(PfcBlock {
             encoded_strings: data,
             n_strings,
@@ -249,11 +293,6 @@ impl Iterator for PfcDictBlockIterator {
         if self.block_index > self.dict.block_offsets.len() {
             None
         } else {
-            let block_offset = if self.block_index == 0 {
-                0
-            } else {
-                self.dict.block_offsets.entry(self.block_index - 1)
-            } as usize;
             let remainder = self.dict.n_strings as usize - self.block_index * BLOCK_SIZE;
 
             if remainder == 0 {
@@ -262,13 +301,7 @@ impl Iterator for PfcDictBlockIterator {
 
             self.block_index += 1;
 
-            let mut block = self.dict.blocks.clone();
-            block.advance(block_offset);
-            if remainder >= BLOCK_SIZE {
-                Some(PfcBlock::parse(block).unwrap())
-            } else {
-                Some(PfcBlock::parse_incomplete(block, remainder).unwrap())
-            }
+            Some(self.dict.block(self.block_index - 1))
         }
     }
 }
@@ -522,11 +555,39 @@ pub struct PfcDict {
 }
 
 impl PfcDict {
+    /// Construct a `PfcDict` by parsing its blocks and block offsets.
+    ///
+    /// This checks that the block offsets are consistent with the
+    /// amount of strings and the size of the data, but it does not
+    /// look inside the blocks. Use `validate` for dictionaries from an
+    /// untrusted source.
     pub fn parse(blocks: Bytes, offsets: Bytes) -> Result<PfcDict, PfcError> {
+        if blocks.len() < 8 {
+            return Err(PfcError::NotEnoughData);
+        }
+
         let n_strings = BigEndian::read_u64(&blocks.as_ref()[blocks.as_ref().len() - 8..]);
 
         let block_offsets = LogArray::parse(offsets)?;
 
+        let n_blocks = n_strings.div_ceil(BLOCK_SIZE as u64);
+        if block_offsets.len() as u64 != n_blocks.saturating_sub(1) {
+            return Err(PfcError::InvalidCoding);
+        }
+
+        let data_len = blocks.len() - 8;
+        let mut block_start = 0;
+        for block_end in block_offsets.iter() {
+            if block_end as usize <= block_start || block_end as usize >= data_len {
+                return Err(PfcError::InvalidCoding);
+            }
+
+            block_start = block_end as usize;
+        }
+        if n_blocks != 0 && block_start >= data_len {
+            return Err(PfcError::InvalidCoding);
+        }
+
         Ok(PfcDict {
             n_strings,
             block_offsets,
@@ -534,51 +595,84 @@ impl PfcDict {
         })
     }
 
-    pub fn len(&self) -> usize {
-        self.n_strings as usize
-    }
+    /// Check that every block of this dictionary is properly encoded.
+    ///
+    /// Lookups on a dictionary that passed validation cannot fail on
+    /// malformed data. This goes over all the data, so it is meant for
+    /// dictionaries from an untrusted source, like an imported pack.
+    pub fn validate(&self) -> Result<(), PfcError> {
+        let data = &self.blocks.as_ref()[..self.blocks.len() - 8];
+        let n_blocks = self.block_offsets.len() + 1;
+        for block_index in 0..n_blocks {
+            let remainder = self.n_strings as usize - block_index * BLOCK_SIZE;
+            if remainder == 0 {
+                break;
+            }
 
-    fn calculate_block_offset_index(&self, ix: usize) -> Option<(u64, usize)> {
-        if (ix as u64) < self.n_strings {
-            let block_index = ix / BLOCK_SIZE;
-            let block_offset = if block_index == 0 {
-                0
+            let block_start = self.block_offset(block_index);
+            let block_end = if block_index < self.block_offsets.len() {
+                self.block_offsets.entry(block_index) as usize
             } else {
-                self.block_offsets.entry(block_index - 1)
+                data.len()
             };
 
-            let index_in_block = ix % BLOCK_SIZE;
-            Some((block_offset, index_in_block))
+            let n_block_strings = std::cmp::min(remainder, BLOCK_SIZE);
+            let size = validate_block(&data[block_start..block_end], n_block_strings)?;
+            if block_index < self.block_offsets.len() && block_start + size != block_end {
+                return Err(PfcError::InvalidCoding);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.n_strings as usize
+    }
+
+    fn block_offset(&self, block_index: usize) -> usize {
+        if block_index == 0 {
+            0
         } else {
-            None
+            self.block_offsets.entry(block_index - 1) as usize
         }
     }
 
-    pub fn entry(&self, ix: usize) -> Option<PfcDictEntry> {
-        if let Some((block_offset, index_in_block)) = self.calculate_block_offset_index(ix) {
-            let mut block_bytes = self.blocks.clone();
-            block_bytes.advance(block_offset as usize);
+    /// Returns the block at the given index.
+    ///
+    /// This skips block validation, which is left to `validate`.
+    fn block(&self, block_index: usize) -> PfcBlock {
+        let mut block_bytes = self.blocks.clone();
+        block_bytes.advance(self.block_offset(block_index));
+        let remainder = self.n_strings as usize - block_index * BLOCK_SIZE;
+
+        PfcBlock {
+            encoded_strings: block_bytes,
+            n_strings: std::cmp::min(remainder, BLOCK_SIZE),
+        }
+    }
 
-            let block = PfcBlock::parse(block_bytes).unwrap();
-            block.entry(index_in_block)
+    pub fn entry(&self, ix: usize) -> Option<PfcDictEntry> {
+        if (ix as u64) < self.n_strings {
+            self.block(ix / BLOCK_SIZE).entry(ix % BLOCK_SIZE)
         } else {
             None
         }
     }
 
     pub fn get(&self, ix: usize) -> Option<String> {
-        if let Some((block_offset, index_in_block)) = self.calculate_block_offset_index(ix) {
-            let mut block_bytes = self.blocks.clone();
-            block_bytes.advance(block_offset as usize);
-
-            let block = PfcBlock::parse(block_bytes).unwrap();
-            block.get(index_in_block)
+        if (ix as u64) < self.n_strings {
+            self.block(ix / BLOCK_SIZE).get(ix % BLOCK_SIZE)
         } else {
             None
         }
     }
 
     pub fn id(&self, s: &str) -> Option<u64> {
+        if self.n_strings == 0 {
+            return None;
+        }
+
         let s_bytes = s.as_bytes();
         // let's binary search
         let mut min = 0;
@@ -588,16 +682,9 @@ impl PfcDict {
         while min <= max {
             mid = (min + max) / 2;
 
-            let block_offset = if mid == 0 {
-                0
-            } else {
-                self.block_offsets.entry(mid - 1) as usize
-            };
-            let block_slice = &self.blocks.as_ref()[block_offset..]; // this is probably more than one block, but we're only interested in the first string anyway
-            let head_end = block_slice.iter().position(|&b| b == 0).unwrap();
-            let head_slice = &block_slice[..head_end];
+            let head = self.block(mid).head();
 
-            match s_bytes.cmp(head_slice) {
+            match s_bytes.cmp(head.as_ref()) {
                 Ordering::Less => {
                     if mid == 0 {
                         // we checked the first block and determined that the string should be in the previous block, if it exists.
@@ -614,19 +701,7 @@ impl PfcDict {
         let found = max;
 
         // we found the block the string should be part of.
-        let block_start = if found == 0 {
-            0
-        } else {
-            self.block_offsets.entry(found - 1) as usize
-        };
-        let remainder = self.n_strings as usize - (found * BLOCK_SIZE);
-        let mut block = self.blocks.clone();
-        block.advance(block_start);
-        let block = if remainder >= BLOCK_SIZE {
-            PfcBlock::parse(block).unwrap()
-        } else {
-            PfcBlock::parse_incomplete(block, remainder as usize).unwrap()
-        };
+        let block = self.block(found);
 
         for (count, block_entry) in block.entries().enumerate() {
             if block_entry.buf_eq(s_bytes) {
@@ -637,6 +712,85 @@ impl PfcDict {
         None
     }
 
+    /// Look up the strings for many ids at once, returning them in the order of the given ids.
+    ///
+    /// The ids are resolved in sorted order, so that every block is
+    /// decoded at most once.
+    pub fn get_many(&self, ids: &[usize]) -> Vec<Option<String>> {
+        let mut result = vec![None; ids.len()];
+        let mut order: Vec<usize> = (0..ids.len())
+            .filter(|&index| (ids[index] as u64) < self.n_strings)
+            .collect();
+        order.sort_by_key(|&index| ids[index]);
+
+        let mut decoded: Option<(usize, Vec<String>)> = None;
+        for index in order {
+            let block_index = ids[index] / BLOCK_SIZE;
+            if decoded.as_ref().map(|(i, _)| *i) != Some(block_index) {
+                decoded = Some((block_index, self.block(block_index).strings().collect()));
+            }
+
+            let (_, strings) = decoded.as_ref().unwrap();
+            result[index] = Some(strings[ids[index] % BLOCK_SIZE].clone());
+        }
+
+        result
+    }
+
+    /// Find the last block, starting at the given block, whose head is less than or equal to the given string.
+    fn block_index_for(&self, s_bytes: &[u8], start: usize) -> Option<usize> {
+        let mut low = start;
+        let mut high = self.block_offsets.len() + 1;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.block(mid).head().as_ref() <= s_bytes {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+
+        low.checked_sub(1)
+    }
+
+    /// Look up the ids of many strings at once, returning them in the order of the given strings.
+    ///
+    /// The strings are resolved in sorted order, so that the search
+    /// for every next block can start at the block of the previous
+    /// string, and every block is decoded at most once.
+    pub fn id_many<S: AsRef<str>>(&self, strings: &[S]) -> Vec<Option<u64>> {
+        let mut result = vec![None; strings.len()];
+        if self.n_strings == 0 {
+            return result;
+        }
+
+        let mut order: Vec<usize> = (0..strings.len()).collect();
+        order.sort_by(|&a, &b| strings[a].as_ref().cmp(strings[b].as_ref()));
+
+        let mut decoded: Option<(usize, Vec<String>)> = None;
+        let mut start = 0;
+        for index in order {
+            let s = strings[index].as_ref();
+            let block_index = match self.block_index_for(s.as_bytes(), start) {
+                Some(block_index) => block_index,
+                // the string comes before the first block
+                None => continue,
+            };
+            start = block_index;
+
+            if decoded.as_ref().map(|(i, _)| *i) != Some(block_index) {
+                decoded = Some((block_index, self.block(block_index).strings().collect()));
+            }
+
+            let (_, block_strings) = decoded.as_ref().unwrap();
+            if let Ok(pos) = block_strings.binary_search_by(|b| b.as_str().cmp(s)) {
+                result[index] = Some((block_index * BLOCK_SIZE + pos) as u64);
+            }
+        }
+
+        result
+    }
+
     pub fn strings(&self) -> impl Iterator<Item = String> {
         let block_iterator = PfcDictBlockIterator::new(self.clone());
 
@@ -796,7 +950,8 @@ impl Decoder for PfcDecoder {
                     // this is the start of a block. we expect a 0-delimited cstring
                     let b = bytes.split_to(pos);
                     bytes.advance(1);
-                    let s = String::from_utf8(b.to_vec()).expect("expected utf8 string");
+                    let s = String::from_utf8(b.to_vec())
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
                     self.last = Some(b);
                     self.index += 1;
 
@@ -804,8 +959,12 @@ impl Decoder for PfcDecoder {
                 }
                 false => {
                     // This is in the middle of some block. we expect a vbyte followed by some 0-delimited cstring
-                    let last = self.last.as_ref().unwrap();
-                    let (prefix_len, vbyte_len) = vbyte::decode(&bytes).expect("expected vbyte");
+                    let last = self.last.as_ref().ok_or(PfcError::InvalidCoding)?;
+                    let (prefix_len, vbyte_len) =
+                        vbyte::decode(&bytes).map_err(|_| PfcError::InvalidCoding)?;
+                    if vbyte_len > pos || prefix_len > last.len() as u64 {
+                        return Err(PfcError::InvalidCoding.into());
+                    }
                     bytes.advance(vbyte_len);
                     let b = bytes.split_to(pos - vbyte_len);
                     bytes.advance(1);
@@ -813,7 +972,8 @@ impl Decoder for PfcDecoder {
                     full.extend_from_slice(&last[..prefix_len as usize]);
                     full.extend_from_slice(&b);
 
-                    let s = String::from_utf8(full.to_vec()).expect("expected utf8 string");
+                    let s = String::from_utf8(full.to_vec())
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
                     self.last = Some(full);
                     self.index += 1;
 
@@ -960,6 +1120,88 @@ mod tests {
         assert_eq!(None, p.get(10));
     }
 
+    #[test]
+    fn resolve_many_ids_and_strings() {
+        let contents = vec![
+            "string000",
+            "string002",
+            "string004",
+            "string006",
+            "string008",
+            "string010",
+            "string012",
+            "string014",
+            "string016",
+            "string018",
+            "string020",
+            "string022",
+            "string024",
+            "string026",
+            "string028",
+            "string030",
+            "string032",
+            "string034",
+            "string036",
+            "string038",
+            "string040",
+            "string042",
+            "string044",
+            "string046",
+            "string048",
+        ];
+
+        let blocks = MemoryBackedStore::new();
+        let offsets = MemoryBackedStore::new();
+        let mut builder = PfcDictFileBuilder::new(blocks.open_write(), offsets.open_write());
+
+        block_on(async {
+            builder.add_all(contents.into_iter()).await?;
+            builder.finalize().await?;
+
+            Ok::<_, io::Error>(())
+        })
+        .unwrap();
+
+        let p = PfcDict::parse(
+            block_on(blocks.map()).unwrap(),
+            block_on(offsets.map()).unwrap(),
+        )
+        .unwrap();
+
+        let lookups = vec![
+            "string048",
+            "string001",
+            "string000",
+            "aaa",
+            "string030",
+            "string016",
+            "string016",
+            "zzz",
+            "string031",
+        ];
+        let expected: Vec<_> = lookups.iter().map(|s| p.id(s)).collect();
+        assert_eq!(expected, p.id_many(&lookups));
+        assert_eq!(
+            vec![
+                Some(24),
+                None,
+                Some(0),
+                None,
+                Some(15),
+                Some(8),
+                Some(8),
+                None,
+                None
+            ],
+            p.id_many(&lookups)
+        );
+
+        let ids = vec![24, 0, 7, 8, 100, 15, 7];
+        let expected: Vec<_> = ids.iter().map(|&id| p.get(id)).collect();
+        assert_eq!(expected, p.get_many(&ids));
+        assert_eq!(None, p.get_many(&ids)[4]);
+    }
+
     #[test]
     fn retrieve_id_from_dict() {
         let contents = vec![
@@ -1338,4 +1580,123 @@ mod tests {
 
         assert!(entry1 > entry2);
     }
+
+    fn build_dict(contents: Vec<&'static str>) -> (Bytes, Bytes) {
+        let blocks = MemoryBackedStore::new();
+        let offsets = MemoryBackedStore::new();
+        let mut builder = PfcDictFileBuilder::new(blocks.open_write(), offsets.open_write());
+
+        block_on(async {
+            builder.add_all(contents.into_iter()).await?;
+            builder.finalize().await?;
+
+            Ok::<_, io::Error>(())
+        })
+        .unwrap();
+
+        (
+            block_on(blocks.map()).unwrap(),
+            block_on(offsets.map()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn parse_empty_dict() {
+        let (blocks, offsets) = build_dict(Vec::new());
+        let dict = PfcDict::parse(blocks, offsets).unwrap();
+
+        assert_eq!(0, dict.len());
+        assert_eq!(None, dict.get(0));
+        assert_eq!(None, dict.id(""));
+        assert_eq!(None, dict.id("foo"));
+    }
+
+    #[test]
+    fn parse_dict_with_too_little_data_errors() {
+        let (_, offsets) = build_dict(vec!["aaaaa", "aabbb"]);
+
+        assert!(PfcDict::parse(Bytes::from(vec![0, 0, 0]), offsets).is_err());
+    }
+
+    #[test]
+    fn parse_dict_with_wrong_count_errors() {
+        let (blocks, offsets) = build_dict(vec!["aaaaa", "aabbb", "ccccc"]);
+        let mut blocks = blocks.to_vec();
+        let len = blocks.len();
+        blocks[len - 1] = 42;
+
+        assert!(PfcDict::parse(Bytes::from(blocks), offsets).is_err());
+    }
+
+    const MULTI_BLOCK_STRINGS: [&str; 20] = [
+        "aa", "ab", "ac", "ad", "ae", "af", "ag", "ah", "ai", "aj", "ak", "al", "am", "an", "ao",
+        "ap", "aq", "ar", "as", "at",
+    ];
+
+    #[test]
+    fn parse_dict_with_block_offset_past_the_data_errors() {
+        let (blocks, _) = build_dict(MULTI_BLOCK_STRINGS.to_vec());
+        let offsets_file = MemoryBackedStore::new();
+        let mut offsets = LogArrayFileBuilder::new(offsets_file.open_write(), 16);
+        block_on(async {
+            offsets.push(10).await?;
+            offsets.push(blocks.len() as u64).await?;
+            offsets.finalize().await
+        })
+        .unwrap();
+        let offsets = block_on(offsets_file.map()).unwrap();
+
+        assert!(PfcDict::parse(blocks, offsets).is_err());
+    }
+
+    #[test]
+    fn validate_multi_block_dict() {
+        let (blocks, offsets) = build_dict(MULTI_BLOCK_STRINGS.to_vec());
+        let dict = PfcDict::parse(blocks, offsets).unwrap();
+
+        assert!(dict.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_dict_with_missing_terminator_errors() {
+        let (blocks, offsets) = build_dict(vec!["aaaaa", "aabbb", "ccccc"]);
+        let mut blocks = blocks.to_vec();
+        let len = blocks.len();
+        for b in blocks[..len - 8].iter_mut() {
+            *b = b'x';
+        }
+
+        let dict = PfcDict::parse(Bytes::from(blocks), offsets).unwrap();
+        assert!(matches!(dict.validate(), Err(PfcError::NotEnoughData)));
+    }
+
+    #[test]
+    fn validate_dict_with_overlong_prefix_errors() {
+        let (blocks, offsets) = build_dict(vec!["aaaaa", "aabbb", "ccccc"]);
+        let mut blocks = blocks.to_vec();
+        // the second entry starts right after the nul-terminated head, with a prefix length of 2.
+        assert_eq!(vbyte::encode_vec(2), &blocks[6..7]);
+        blocks[6] = vbyte::encode_vec(100)[0];
+
+        let dict = PfcDict::parse(Bytes::from(blocks), offsets).unwrap();
+        assert!(dict.validate().is_err());
+    }
+
+    #[test]
+    fn validate_dict_with_invalid_utf8_errors() {
+        let (blocks, offsets) = build_dict(vec!["aaaaa", "aabbb", "ccccc"]);
+        let mut blocks = blocks.to_vec();
+        blocks[0] = 0xff;
+
+        let dict = PfcDict::parse(Bytes::from(blocks), offsets).unwrap();
+        assert!(dict.validate().is_err());
+    }
+
+    #[test]
+    fn parse_block_without_enough_strings_errors() {
+        let (blocks, _) = build_dict(vec!["aaaaa", "aabbb", "ccccc"]);
+
+        assert!(PfcBlock::parse_incomplete(blocks.clone(), 3).is_ok());
+        assert!(PfcBlock::parse(blocks).is_err());
+    }
 }
@@ -7,7 +7,7 @@ use super::util;
 use crate::storage::*;
 
 use std::convert::TryInto;
-use std::io;
+use std::{error, fmt, io};
 
 /// A wavelet tree, encoding a u64 array for fast lookup of number positions.
 ///
@@ -21,6 +21,33 @@ pub struct WaveletTree {
     num_layers: u8,
 }
 
+/// An error that occurred while constructing a wavelet tree.
+#[derive(Debug, PartialEq)]
+pub enum WaveletTreeError {
+    UnexpectedBitCount(usize, u8),
+}
+
+impl fmt::Display for WaveletTreeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use WaveletTreeError::*;
+        match self {
+            UnexpectedBitCount(len, num_layers) => write!(
+                f,
+                "expected bit count ({}) to be a multiple of the number of layers ({})",
+                len, num_layers
+            ),
+        }
+    }
+}
+
+impl error::Error for WaveletTreeError {}
+
+impl From<WaveletTreeError> for io::Error {
+    fn from(err: WaveletTreeError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
 /// A lookup for all positions of a particular entry.
 ///
 /// This struct caches part of the calculation required to get
@@ -85,12 +112,12 @@ impl WaveletLookup {
 
 impl WaveletTree {
     /// Construct a wavelet tree from a bitindex and a layer count.
-    pub fn from_parts(bits: BitIndex, num_layers: u8) -> WaveletTree {
+    pub fn from_parts(bits: BitIndex, num_layers: u8) -> Result<WaveletTree, WaveletTreeError> {
         if num_layers != 0 && bits.len() % num_layers as usize != 0 {
-            panic!("the bitarray length is not a multiple of the number of layers");
+            return Err(WaveletTreeError::UnexpectedBitCount(bits.len(), num_layers));
         }
 
-        WaveletTree { bits, num_layers }
+        Ok(WaveletTree { bits, num_layers })
     }
 
     /// Returns the length of the encoded array.
@@ -350,8 +377,9 @@ mod tests {
         let wavelet_blocks = block_on(wavelet_blocks_file.map()).unwrap();
         let wavelet_sblocks = block_on(wavelet_sblocks_file.map()).unwrap();
 
-        let wavelet_bitindex = BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks);
-        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 5);
+        let wavelet_bitindex =
+            BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks).unwrap();
+        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 5).unwrap();
 
         assert_eq!(contents_len, wavelet_tree.len());
 
@@ -390,8 +418,9 @@ mod tests {
         let wavelet_blocks = block_on(wavelet_blocks_file.map()).unwrap();
         let wavelet_sblocks = block_on(wavelet_sblocks_file.map()).unwrap();
 
-        let wavelet_bitindex = BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks);
-        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 5);
+        let wavelet_bitindex =
+            BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks).unwrap();
+        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 5).unwrap();
 
         assert_eq!(contents_len, wavelet_tree.len());
 
@@ -420,8 +449,9 @@ mod tests {
         let wavelet_blocks = block_on(wavelet_blocks_file.map()).unwrap();
         let wavelet_sblocks = block_on(wavelet_sblocks_file.map()).unwrap();
 
-        let wavelet_bitindex = BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks);
-        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 4);
+        let wavelet_bitindex =
+            BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks).unwrap();
+        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 4).unwrap();
 
         let slice = wavelet_tree.lookup(8).unwrap();
         assert_eq!(vec![0, 2, 3, 8, 16], slice.iter().collect::<Vec<_>>());
@@ -455,8 +485,9 @@ mod tests {
         let wavelet_blocks = block_on(wavelet_blocks_file.map()).unwrap();
         let wavelet_sblocks = block_on(wavelet_sblocks_file.map()).unwrap();
 
-        let wavelet_bitindex = BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks);
-        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 4);
+        let wavelet_bitindex =
+            BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks).unwrap();
+        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 4).unwrap();
 
         assert!(wavelet_tree.lookup(3).is_none());
     }
@@ -483,8 +514,9 @@ mod tests {
         let wavelet_blocks = block_on(wavelet_blocks_file.map()).unwrap();
         let wavelet_sblocks = block_on(wavelet_sblocks_file.map()).unwrap();
 
-        let wavelet_bitindex = BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks);
-        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 4);
+        let wavelet_bitindex =
+            BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks).unwrap();
+        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 4).unwrap();
 
         assert!(wavelet_tree.lookup(100).is_none());
     }
@@ -511,8 +543,9 @@ mod tests {
         let wavelet_blocks = block_on(wavelet_blocks_file.map()).unwrap();
         let wavelet_sblocks = block_on(wavelet_sblocks_file.map()).unwrap();
 
-        let wavelet_bitindex = BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks);
-        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 4);
+        let wavelet_bitindex =
+            BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks).unwrap();
+        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 4).unwrap();
 
         assert_eq!(
             vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
@@ -544,8 +577,9 @@ mod tests {
         let wavelet_blocks = block_on(wavelet_blocks_file.map()).unwrap();
         let wavelet_sblocks = block_on(wavelet_sblocks_file.map()).unwrap();
 
-        let wavelet_bitindex = BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks);
-        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 4);
+        let wavelet_bitindex =
+            BitIndex::from_maps(wavelet_bits, wavelet_blocks, wavelet_sblocks).unwrap();
+        let wavelet_tree = WaveletTree::from_parts(wavelet_bitindex, 4).unwrap();
 
         assert_eq!(Some(3), wavelet_tree.lookup_one(1));
         assert_eq!(Some(2), wavelet_tree.lookup_one(2));